@@ -0,0 +1,63 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use realworld_axum_sqlx::db::fixtures;
+use realworld_axum_sqlx::http::slugify;
+
+// Micro-benchmarks for the CPU-bound parts of handling a request. Run with `cargo bench`.
+//
+// This is a plain `main()` rather than Criterion, which would be nicer (warmup, outlier detection,
+// comparing against the last run) but is a big dependency for two functions. If we ever have more
+// than a handful of these, it's probably worth switching.
+//
+// For the endpoints as a whole, see `examples/loadtest.rs`.
+
+/// Run `f` repeatedly for about `target`, and print the mean time per call.
+fn bench<T>(name: &str, target: Duration, mut f: impl FnMut() -> T) {
+    // Warm up caches and the branch predictor, and find out roughly how long one call takes.
+    let start = Instant::now();
+    let mut warmup = 0u32;
+
+    while start.elapsed() < target / 10 {
+        black_box(f());
+        warmup += 1;
+    }
+
+    let iterations = (warmup * 10).max(1);
+    let start = Instant::now();
+
+    for _ in 0..iterations {
+        black_box(f());
+    }
+
+    let mean = start.elapsed() / iterations;
+    println!(
+        "{:<40} {:>12.3?}/iter ({} iterations)",
+        name, mean, iterations
+    );
+}
+
+fn main() {
+    // `cargo test --benches` runs this too; one quick pass is enough to know it works.
+    let target = if std::env::args().any(|arg| arg == "--bench") {
+        Duration::from_secs(3)
+    } else {
+        Duration::from_millis(10)
+    };
+
+    bench("slugify (ascii)", target, || {
+        slugify(black_box(
+            "Converting to Rust from C: It's as Easy as 1, 2, 3!",
+        ))
+    });
+
+    bench("slugify (unicode)", target, || {
+        slugify(black_box("Überraschung: 日本語のタイトル 🦀"))
+    });
+
+    // This is deliberately slow; it's here so we notice if a change to the Argon2 parameters
+    // makes registering and logging in *much* slower, or suspiciously fast.
+    bench("argon2 hash_password", target, || {
+        fixtures::hash_password(black_box("hunter2hunter2")).unwrap()
+    });
+}
@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use hyper::body::Bytes;
+use hyper::client::HttpConnector;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Client, Method, Request, StatusCode};
+use serde_json::{json, Value};
+
+use realworld_axum_sqlx::db::fixtures::{Fixtures, DEMO_PASSWORD};
+
+// A load test for the hot endpoints, which reports the median and 99th percentile latency
+// of each. Use it to back up performance PRs with numbers, by running it before and after.
+//
+// It needs a running server with seeded data, and it's only meaningful with both built in
+// release mode:
+//
+// ```
+// $ cargo run --release -- seed --users 1000 --articles 20000 --follows 20000
+// $ cargo run --release &
+// $ cargo run --release --example loadtest -- --duration 30
+// ```
+//
+// It logs in as the first user that `seed` generated, which is why it needs to know the seed.
+//
+// This is a lot less sophisticated than a dedicated tool like `oha` or Goose; in particular,
+// every worker sends its next request as soon as the last one completes, which understates
+// tail latency when the server is saturated ("coordinated omission"). It's fine for comparing
+// two builds on the same machine, which is what it's for.
+
+#[derive(Parser)]
+struct Args {
+    /// The base URL of the running API.
+    #[clap(long, default_value = "http://localhost:8080")]
+    url: String,
+
+    /// How long to run for, in seconds.
+    #[clap(long, default_value = "10")]
+    duration: u64,
+
+    /// How many requests to keep in flight at once.
+    #[clap(long, default_value = "16")]
+    concurrency: usize,
+
+    /// The `--seed` that the database was seeded with.
+    #[clap(long, default_value = "0")]
+    seed: u64,
+}
+
+/// The endpoints we hit, in proportion to how often a frontend would: mostly reading listings
+/// and articles.
+const MIX: &[Endpoint] = &[
+    Endpoint::ListArticles,
+    Endpoint::ListArticles,
+    Endpoint::ListArticles,
+    Endpoint::ListByTag,
+    Endpoint::Feed,
+    Endpoint::Feed,
+    Endpoint::GetArticle,
+    Endpoint::GetArticle,
+    Endpoint::GetArticle,
+    Endpoint::GetComments,
+    Endpoint::GetTags,
+];
+
+#[derive(Debug, Copy, Clone)]
+enum Endpoint {
+    ListArticles,
+    ListByTag,
+    Feed,
+    GetArticle,
+    GetComments,
+    GetTags,
+}
+
+/// What every worker needs to make requests.
+struct Scenario {
+    client: Client<HttpConnector>,
+    url: String,
+    token: String,
+    /// Articles to fetch, taken from the first page of the listing.
+    slugs: Vec<String>,
+    tags: Vec<String>,
+}
+
+/// The latencies of the successful requests to one endpoint, and the number that failed.
+#[derive(Default)]
+struct Stats {
+    latencies: Vec<Duration>,
+    errors: u64,
+}
+
+impl Endpoint {
+    fn name(&self) -> &'static str {
+        match self {
+            Endpoint::ListArticles => "GET /api/articles",
+            Endpoint::ListByTag => "GET /api/articles?tag=",
+            Endpoint::Feed => "GET /api/articles/feed",
+            Endpoint::GetArticle => "GET /api/articles/:slug",
+            Endpoint::GetComments => "GET /api/articles/:slug/comments",
+            Endpoint::GetTags => "GET /api/tags",
+        }
+    }
+
+    /// The path to request, with `i` picking which article or tag.
+    fn path(&self, scenario: &Scenario, i: usize) -> String {
+        let slug = &scenario.slugs[i % scenario.slugs.len()];
+
+        match self {
+            Endpoint::ListArticles => "/api/articles".into(),
+            Endpoint::ListByTag => match scenario.tags.get(i % scenario.tags.len().max(1)) {
+                Some(tag) => format!("/api/articles?tag={}", tag),
+                None => "/api/articles?tag=rust".into(),
+            },
+            Endpoint::Feed => "/api/articles/feed".into(),
+            Endpoint::GetArticle => format!("/api/articles/{}", slug),
+            Endpoint::GetComments => format!("/api/articles/{}/comments", slug),
+            Endpoint::GetTags => "/api/tags".into(),
+        }
+    }
+}
+
+impl Scenario {
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> anyhow::Result<(StatusCode, Bytes)> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.url, path));
+
+        if !self.token.is_empty() {
+            req = req.header(AUTHORIZATION, format!("Token {}", self.token));
+        }
+
+        let req = match body {
+            Some(body) => req
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))?,
+            None => req.body(Body::empty())?,
+        };
+
+        let res = self.client.request(req).await?;
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await?;
+
+        Ok((status, bytes))
+    }
+
+    /// Fetch a JSON response, failing on anything but `200 OK`.
+    async fn get_json(&self, path: &str) -> anyhow::Result<Value> {
+        let (status, bytes) = self.send(Method::GET, path, None).await?;
+
+        if status != StatusCode::OK {
+            bail!("GET {} returned {}", path, status);
+        }
+
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let mut scenario = Scenario {
+        client: Client::new(),
+        url: args.url.trim_end_matches('/').to_string(),
+        token: String::new(),
+        slugs: vec![],
+        tags: vec![],
+    };
+
+    // The same user `seed` generated first.
+    let email = Fixtures::new(args.seed).user().build().email;
+
+    let (status, bytes) = scenario
+        .send(
+            Method::POST,
+            "/api/users/login",
+            Some(json!({ "user": { "email": email, "password": DEMO_PASSWORD } })),
+        )
+        .await
+        .with_context(|| format!("failed to connect to {}", args.url))?;
+
+    if status != StatusCode::OK {
+        bail!(
+            "failed to log in as {} ({}); did you run `seed --seed {}`?",
+            email,
+            status,
+            args.seed
+        );
+    }
+
+    let body: Value = serde_json::from_slice(&bytes)?;
+    scenario.token = body["user"]["token"]
+        .as_str()
+        .context("no token in login response")?
+        .to_string();
+
+    let body = scenario.get_json("/api/articles?limit=100").await?;
+    scenario.slugs = body["articles"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|article| Some(article["slug"].as_str()?.to_string()))
+        .collect();
+
+    if scenario.slugs.is_empty() {
+        bail!("there are no articles to fetch; seed the database first");
+    }
+
+    let body = scenario.get_json("/api/tags").await?;
+    scenario.tags = body["tags"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|tag| Some(tag.as_str()?.to_string()))
+        .collect();
+
+    println!(
+        "running for {}s with {} concurrent requests against {}",
+        args.duration, args.concurrency, args.url
+    );
+
+    let scenario = Arc::new(scenario);
+    let duration = Duration::from_secs(args.duration);
+    let deadline = Instant::now() + duration;
+
+    let workers: Vec<_> = (0..args.concurrency)
+        .map(|worker| {
+            let scenario = scenario.clone();
+
+            tokio::spawn(async move {
+                let mut stats = BTreeMap::<&'static str, Stats>::new();
+                // Start each worker at a different point in the mix, so they're not all hitting
+                // the same endpoint at the same time.
+                let mut i = worker;
+
+                while Instant::now() < deadline {
+                    let endpoint = MIX[i % MIX.len()];
+                    let path = endpoint.path(&scenario, i / MIX.len());
+                    i += 1;
+
+                    let start = Instant::now();
+                    let res = scenario.send(Method::GET, &path, None).await;
+                    let elapsed = start.elapsed();
+
+                    let stats = stats.entry(endpoint.name()).or_default();
+
+                    match res {
+                        Ok((status, _)) if status.is_success() => stats.latencies.push(elapsed),
+                        _ => stats.errors += 1,
+                    }
+                }
+
+                stats
+            })
+        })
+        .collect();
+
+    let mut totals = BTreeMap::<&'static str, Stats>::new();
+
+    for worker in workers {
+        for (name, stats) in worker.await? {
+            let total = totals.entry(name).or_default();
+            total.latencies.extend(stats.latencies);
+            total.errors += stats.errors;
+        }
+    }
+
+    println!(
+        "\n{:<34} {:>9} {:>7} {:>9} {:>10} {:>10} {:>10}",
+        "endpoint", "requests", "errors", "req/s", "p50", "p99", "max"
+    );
+
+    for (name, mut stats) in totals {
+        stats.latencies.sort_unstable();
+
+        let requests = stats.latencies.len() as u64 + stats.errors;
+
+        println!(
+            "{:<34} {:>9} {:>7} {:>9.1} {:>10.2?} {:>10.2?} {:>10.2?}",
+            name,
+            requests,
+            stats.errors,
+            requests as f64 / duration.as_secs_f64(),
+            percentile(&stats.latencies, 0.50),
+            percentile(&stats.latencies, 0.99),
+            stats.latencies.last().copied().unwrap_or_default(),
+        );
+    }
+
+    Ok(())
+}
+
+/// The `p`th percentile of `sorted`, by the nearest-rank method.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
@@ -0,0 +1,6 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    realworld_axum_sqlx::http::fuzz::request_bodies(data);
+});
@@ -0,0 +1,6 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|title: &str| {
+    realworld_axum_sqlx::http::fuzz::slugify(title);
+});
@@ -0,0 +1,21 @@
+-- An advisory lock on editing an article, so that two people editing it at once find out before one of them
+-- overwrites the other's changes. See `http::articles::locks` for how it's used.
+--
+-- This isn't a Postgres lock: it has to outlive any one transaction, since it's held for as long as someone has the
+-- editor open, which is why it's a row with an expiry. A client that crashes or loses its connection can't release
+-- its lock, so an expired lock is as good as no lock at all, and gets overwritten by the next person to take one.
+create table article_lock
+(
+    -- Only one lock per article, which is the whole point.
+    article_id uuid primary key references article (article_id) on delete cascade,
+
+    user_id    uuid        not null references "user" (user_id) on delete cascade,
+
+    -- Compared against the application's clock rather than `now()`, so tests can fast-forward past it.
+    expires_at timestamptz not null,
+
+    created_at timestamptz not null default now(),
+    updated_at timestamptz
+);
+
+select trigger_updated_at('article_lock');
@@ -0,0 +1,10 @@
+-- The user that articles and comments are reattributed to when their author's account is anonymized, rather than
+-- deleting them along with it. See `db::users::anonymize()`.
+--
+-- The username and email can't be registered through the API, since `[` and `]` aren't allowed in either, so this
+-- can't clash with a real user. The password hash isn't a hash of anything and the user is banned, so nobody can
+-- log in as it either; and since there's no way to log in, there's no way to follow anyone or post anything as it.
+--
+-- The nil UUID makes it easy to spot in the data, and is what `db::users::GHOST_USER_ID` refers to.
+insert into "user" (user_id, username, email, password_hash, banned_at)
+values ('00000000-0000-0000-0000-000000000000', '[deleted]', '[deleted]', '!', now());
@@ -0,0 +1,14 @@
+-- Tags each user has muted, whose articles are left out of their article listings and feed.
+-- See `http::articles::muted_tags`.
+create table muted_tag
+(
+    user_id    uuid        not null references "user" (user_id) on delete cascade,
+
+    -- Tags don't have a table of their own, they're just strings in `article.tag_list`, so this is too. A user can
+    -- mute a tag nobody has used yet.
+    tag        text        not null,
+
+    created_at timestamptz not null default now(),
+
+    primary key (user_id, tag)
+);
@@ -0,0 +1,29 @@
+-- A moderator's decision to take an article down, and the author's appeal against it if they've made one.
+-- See `http::articles::takedowns` for how it's used.
+--
+-- An article with a row in here is hidden from everyone but its author. It's a table of its own rather than columns
+-- on `article` because it's a record of a decision, with its own reason and history, and because hardly any articles
+-- will ever have one.
+create table article_takedown
+(
+    article_id        uuid primary key references article (article_id) on delete cascade,
+
+    -- Who took it down. Kept if they stop being a moderator, but not if their account goes.
+    moderator_user_id uuid        references "user" (user_id) on delete set null,
+
+    -- Shown to the author, so they know what to fix or what to argue against.
+    reason            text        not null,
+
+    -- The author's case for putting the article back up. Setting this reopens the case for the moderators, and taking
+    -- the article down again (i.e. upholding the decision) clears it.
+    appeal            text,
+    appealed_at       timestamptz,
+
+    created_at        timestamptz not null default now(),
+    updated_at        timestamptz
+);
+
+select trigger_updated_at('article_takedown');
+
+-- For moderators working through the open appeals, oldest first.
+create index on article_takedown (appealed_at) where appealed_at is not null;
@@ -0,0 +1,14 @@
+-- Where each user signed up from, so that `POST /api/users` can cap how many accounts are registered from the same
+-- IP address or device in a short time, see `Config::signups_per_ip_per_hour`.
+--
+-- Both are whatever the request said they were (see `http::extractor::ClientIp`), so they're good for spotting a
+-- script registering accounts in a loop and not much else. Either can be null: the IP address if
+-- `client_ip_header` isn't configured, and the device if the frontend didn't send a fingerprint.
+alter table "user"
+    add column signup_ip     text,
+    add column signup_device text;
+
+-- Only recent signups are ever counted, so these are on `created_at` too. Users who registered before this migration
+-- don't have either, and don't need to be in the indexes.
+create index on "user" (signup_ip, created_at) where signup_ip is not null;
+create index on "user" (signup_device, created_at) where signup_device is not null;
@@ -0,0 +1,26 @@
+-- The external links and images in each article's body, as of its last edit. See `http::articles::links` for how they
+-- get here.
+--
+-- These are for moderators, who can scan for spam and malware by domain without grepping every article body, and
+-- eventually for checking them for link rot.
+create type link_kind as enum ('link', 'image');
+
+create table article_link
+(
+    article_id uuid        not null references article (article_id) on delete cascade,
+
+    kind       link_kind   not null,
+    url        text        not null,
+
+    -- Lowercased, and without the port. Saves parsing `url` again to find every link to a given domain.
+    host       text        not null,
+
+    -- When the link was first added to the article. Editing the article keeps the rows for links that are still there,
+    -- so this doesn't change on every edit, and "links added since the last scan" is a range query.
+    created_at timestamptz not null default now(),
+
+    primary key (article_id, kind, url)
+);
+
+create index on article_link (host);
+create index on article_link (created_at);
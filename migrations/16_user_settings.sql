@@ -0,0 +1,24 @@
+-- Per-user state that isn't part of their profile and that frontends want to keep across devices, see
+-- `http::onboarding`.
+--
+-- A user only gets a row here once something needs storing, so anything reading it has to treat a missing row the
+-- same as one with all the defaults.
+create table user_settings
+(
+    user_id                 uuid primary key references "user" (user_id) on delete cascade,
+
+    -- When the user finished each step of onboarding, as reported by the frontend, or null if they haven't. Following
+    -- someone and writing an article also count without this (see `db::user_settings::onboarding()`), so these are
+    -- mostly for a user who went through the step and chose not to do anything.
+    picked_tags_at          timestamptz,
+    followed_authors_at     timestamptz,
+    wrote_first_article_at  timestamptz,
+
+    -- When the user finished or skipped onboarding as a whole, whichever steps they did.
+    onboarding_completed_at timestamptz,
+
+    created_at              timestamptz not null default now(),
+    updated_at              timestamptz
+);
+
+select trigger_updated_at('user_settings');
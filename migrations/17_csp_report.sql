@@ -0,0 +1,29 @@
+-- Content Security Policy violations reported by browsers, see `http::csp`.
+--
+-- A policy that's wrong gets a report from every browser that loads the page, so rather than a row per report we keep
+-- a row per distinct violation, with how many times it's been reported and the most recent report in full. That
+-- keeps the table small however much traffic there is, and it's what you want to look at anyway.
+create table csp_report
+(
+    -- The page the violation happened on, without its query string or fragment, so that every article page isn't
+    -- a separate row.
+    document_uri       text        not null,
+
+    -- The directive that was violated, e.g. `img-src`.
+    violated_directive text        not null,
+
+    -- What was blocked: a URL without its query string, or a keyword like `inline` or `eval`.
+    blocked_uri        text        not null,
+
+    -- How many times it's been reported, and the latest report as the browser sent it.
+    count              int8        not null default 1,
+    sample             jsonb       not null,
+
+    first_seen_at      timestamptz not null default now(),
+    last_seen_at       timestamptz not null default now(),
+
+    primary key (document_uri, violated_directive, blocked_uri)
+);
+
+-- For listing the latest violations first, and forgetting old ones.
+create index on csp_report (last_seen_at);
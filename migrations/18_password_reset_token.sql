@@ -0,0 +1,20 @@
+-- Tokens for resetting a forgotten password, see `http::users::forgot_password()`.
+--
+-- We only store a hash of each token, the same way we'd never store a password, so someone who can read this table
+-- (or a backup of it) still can't use it to take over an account. The tokens are random enough that a plain SHA-256
+-- does the job; unlike passwords, there's nothing to guess.
+create table password_reset_token
+(
+    token_hash bytea primary key,
+    user_id    uuid        not null references "user" (user_id) on delete cascade,
+
+    expires_at timestamptz not null,
+
+    -- Set when the token is used, after which it's no good. We keep the row rather than deleting it so that it still
+    -- counts towards how many emails the user has been sent recently.
+    used_at    timestamptz,
+
+    created_at timestamptz not null default now()
+);
+
+create index on password_reset_token (user_id, created_at);
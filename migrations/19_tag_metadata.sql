@@ -0,0 +1,19 @@
+-- A description and image for a tag, set by moderators, for frontends to show at the top of the tag's landing page.
+-- See `http::articles::tags`.
+--
+-- Tags themselves only exist in `article.tag_list`, so there's no foreign key here: a tag can have metadata before
+-- any article uses it, and keeps it after the last one stops. Merging tags moves it along, see
+-- `db::tags::merge_metadata()`.
+create table tag_metadata
+(
+    tag         text primary key,
+
+    -- Both optional; a moderator may only want to set one of them.
+    description text,
+    image       text,
+
+    created_at  timestamptz not null default now(),
+    updated_at  timestamptz
+);
+
+select trigger_updated_at('tag_metadata');
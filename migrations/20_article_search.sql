@@ -0,0 +1,15 @@
+-- The full-text search document for each article, for `GET /api/articles/search`. See `db::articles::search()`.
+--
+-- A generated column means Postgres keeps it up to date on every insert and update by itself, so there's no trigger to
+-- forget about, and the index below doesn't have to repeat the expression for queries to use it.
+--
+-- Matches in the title count for the most, then the description, then the body; `ts_rank()` uses these weights.
+-- Everything is stemmed as English, which is a guess, but a better one than `simple` for most Realworld content.
+alter table article
+    add column search tsvector not null generated always as (
+        setweight(to_tsvector('english', title), 'A') ||
+        setweight(to_tsvector('english', description), 'B') ||
+        setweight(to_tsvector('english', body), 'C')
+    ) stored;
+
+create index on article using gin (search);
@@ -0,0 +1,33 @@
+-- Co-authors of articles, and the invitations that make someone one. See `http::articles::authors`.
+--
+-- `article.user_id` stays the article's one and only author as far as the Realworld API is concerned: it's who the
+-- article is shown as by, whose followers get it in their feed, and the only one who can delete it or invite anyone
+-- else. Co-authors can edit it (and so take the editing lock, see `article_lock`), and that's all.
+create table article_author
+(
+    article_id uuid        not null references article (article_id) on delete cascade,
+    user_id    uuid        not null references "user" (user_id) on delete cascade,
+
+    created_at timestamptz not null default now(),
+
+    primary key (article_id, user_id)
+);
+
+-- Nobody becomes a co-author without saying so: the author invites them, which puts a row in here, and they accept
+-- (which moves it to `article_author`) or decline (which deletes it). The author can also take it back.
+create table article_author_invitation
+(
+    invitation_id uuid primary key default uuid_generate_v1mc(),
+
+    article_id    uuid        not null references article (article_id) on delete cascade,
+    inviter_id    uuid        not null references "user" (user_id) on delete cascade,
+    invitee_id    uuid        not null references "user" (user_id) on delete cascade,
+
+    created_at    timestamptz not null default now(),
+
+    -- Inviting someone again while they still haven't answered is an error, rather than a second email.
+    unique (article_id, invitee_id)
+);
+
+-- For listing a user's pending invitations.
+create index on article_author_invitation (invitee_id, created_at);
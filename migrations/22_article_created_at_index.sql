@@ -0,0 +1,6 @@
+-- For `GET /api/articles` without any filters, which is most of them: this lets Postgres read the newest articles
+-- straight off the index, and seek to where a cursor left off rather than counting its way there with `offset`.
+-- See `db::articles::list()`.
+--
+-- Postgres can scan a B-tree index backwards just as well, so this doesn't need to be `desc`.
+create index on article (created_at, article_id);
@@ -0,0 +1,90 @@
+-- Tags used to be stored as an array on each article, `article.tag_list`, which made `GET /api/tags` a full table
+-- scan to find the distinct ones (see the comment on that column in `4_article.sql`). Now each tag has a row of its
+-- own in `tag`, and `article_tag` says which articles have it.
+--
+-- `muted_tag` and `tag_metadata` still refer to tags by name rather than by `tag_id`, since a user can mute a tag,
+-- and a moderator can describe one, before any article uses it.
+create table tag
+(
+    tag_id         uuid primary key     default uuid_generate_v1mc(),
+
+    -- Tags are matched exactly, so `Rust` and `rust` are different tags. Moderators can merge them, see
+    -- `http::articles::tags`.
+    name           text unique not null,
+
+    -- How many articles have the tag, including any that have been taken down, maintained by the trigger below like
+    -- `article.favorites_count` (see `6_favorites_count.sql`). This is what makes `GET /api/tags` cheap: it only has to
+    -- read this table, in the order of the index on `name`.
+    --
+    -- A tag isn't deleted when its last article is, so this can be 0. That's rare enough not to matter; they're just
+    -- left out of `GET /api/tags`.
+    articles_count int8        not null default 0,
+
+    created_at     timestamptz not null default now()
+);
+
+create table article_tag
+(
+    article_id uuid not null references article (article_id) on delete cascade,
+    tag_id     uuid not null references tag (tag_id),
+
+    -- An article has each of its tags once.
+    primary key (article_id, tag_id)
+);
+
+-- The primary key covers finding the tags of an article, and this covers finding the articles with a tag, which
+-- replaces the GIN index on `tag_list`.
+create index on article_tag (tag_id, article_id);
+
+-- Backfill from `tag_list`. As in `6_favorites_count.sql`, this is fine while the table is small.
+insert into tag (name)
+select distinct unnest(tag_list)
+from article;
+
+insert into article_tag (article_id, tag_id)
+select distinct article_id, tag_id
+from article, unnest(tag_list) tags(name)
+inner join tag using (name);
+
+update tag
+set articles_count = (select count(*) from article_tag where article_tag.tag_id = tag.tag_id);
+
+create or replace function update_tag_articles_count()
+    returns trigger as
+$$
+begin
+    if TG_OP = 'INSERT' then
+        update tag set articles_count = articles_count + 1 where tag_id = NEW.tag_id;
+    elsif TG_OP = 'DELETE' then
+        update tag set articles_count = articles_count - 1 where tag_id = OLD.tag_id;
+    end if;
+
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger update_tag_articles_count
+    after insert or delete
+    on article_tag
+    for each row
+execute function update_tag_articles_count();
+
+-- The tags of an article, as every query returning one needs them. They're sorted the way `create_article()` sorts
+-- them in Rust, i.e. by bytes, which is what the `C` collation does.
+--
+-- This is one index scan on the primary key of `article_tag` per article, so queries should call it on a page of
+-- articles they've already picked out, not on every row they're filtering.
+create function article_tags(article_id uuid)
+    returns text[]
+    language sql
+    stable
+as
+$$
+select coalesce(array_agg(name order by name collate "C"), '{}')
+from article_tag
+         inner join tag using (tag_id)
+where article_tag.article_id = $1
+$$;
+
+alter table article
+    drop column tag_list;
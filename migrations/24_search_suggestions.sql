@@ -0,0 +1,13 @@
+-- "Did you mean" suggestions for searches that found next to nothing, see `db::articles::suggest()`.
+--
+-- `pg_trgm` compares strings by the three-letter sequences they share, which is forgiving of exactly the kind of
+-- typo that makes a full-text search come up empty. Unlike the full-text search, it works on the text as written,
+-- without stemming, so it can suggest the tag or title the user was actually trying to type.
+--
+-- Like `uuid-ossp` in `1_setup.sql`, it ships with Postgres but has to be enabled in each database.
+create extension if not exists pg_trgm;
+
+-- Both `%` (similarity) and `<%` (word similarity) can use these, so the suggestions never scan either table.
+create index on tag using gin (name gin_trgm_ops);
+
+create index on article using gin (title gin_trgm_ops);
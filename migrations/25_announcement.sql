@@ -0,0 +1,46 @@
+-- Sitewide announcements for frontends to show as a banner, like upcoming maintenance or a change to the terms of
+-- service. Admins manage them, and users can dismiss them. See `http::announcements`.
+--
+-- Like `user_role` in `8_user_admin.sql`, the severity is an enum. Postgres orders enum values by the order they're
+-- declared in, so `order by severity desc` puts the most severe first.
+create type announcement_severity as enum ('info', 'warning', 'critical');
+
+create table announcement
+(
+    announcement_id    uuid primary key               default uuid_generate_v1mc(),
+
+    -- Plain text, since frontends show it in a banner of their own design.
+    message            text                  not null,
+
+    severity           announcement_severity not null default 'info',
+
+    -- The announcement is shown from `starts_at` until `ends_at`, or until it's deleted if that's null. Either can be in
+    -- the future, so an admin can announce maintenance a week ahead and have it come down by itself afterwards.
+    starts_at          timestamptz           not null default now(),
+    ends_at            timestamptz,
+
+    -- Who wrote it. Kept if they stop being an admin, but not if their account goes.
+    created_by_user_id uuid                  references "user" (user_id) on delete set null,
+
+    created_at         timestamptz           not null default now(),
+    updated_at         timestamptz,
+
+    check (ends_at is null or ends_at > starts_at)
+);
+
+select trigger_updated_at('announcement');
+
+-- There are only ever a handful of announcements, so `GET /api/announcements/active` reading all of them is fine
+-- without an index.
+
+-- Which users have dismissed which announcements, so they don't see them again on any device. Users who aren't
+-- logged in have to be kept track of by the frontend, e.g. in local storage.
+create table announcement_dismissal
+(
+    user_id         uuid        not null references "user" (user_id) on delete cascade,
+    announcement_id uuid        not null references announcement (announcement_id) on delete cascade,
+
+    created_at      timestamptz not null default now(),
+
+    primary key (user_id, announcement_id)
+);
@@ -0,0 +1,41 @@
+-- These triggers announce new articles and comments with `NOTIFY`, which every instance of the application
+-- `LISTEN`s for (see `src/events.rs`) and passes on to its own subscribers.
+--
+-- Doing this in a trigger rather than in the request handlers means we can't forget to do it when some other
+-- code path (an admin script, a background job, someone in `psql`) inserts a row, and since notifications are
+-- only delivered when the transaction commits, nobody hears about a row that was rolled back.
+--
+-- The payloads are kept small on purpose: `NOTIFY` payloads are limited to 8000 bytes, and listeners that want
+-- the whole row can look it up by ID anyway.
+
+create or replace function notify_article_published()
+    returns trigger as
+$$
+begin
+    perform pg_notify('article_published',
+                      json_build_object('article_id', NEW.article_id, 'slug', NEW.slug)::text);
+    return NEW;
+end;
+$$ language plpgsql;
+
+create trigger notify_article_published
+    after insert
+    on article
+    for each row
+execute function notify_article_published();
+
+create or replace function notify_comment_added()
+    returns trigger as
+$$
+begin
+    perform pg_notify('comment_added',
+                      json_build_object('comment_id', NEW.comment_id, 'article_id', NEW.article_id)::text);
+    return NEW;
+end;
+$$ language plpgsql;
+
+create trigger notify_comment_added
+    after insert
+    on article_comment
+    for each row
+execute function notify_comment_added();
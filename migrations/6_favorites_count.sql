@@ -0,0 +1,49 @@
+-- Every query returning an article used to count its favorites with a correlated subquery. That's fine for a handful
+-- of articles, but listing 20 popular articles means counting every favorite each of them has ever had, on every
+-- request.
+--
+-- So instead we keep a running count on the article itself, maintained by triggers on `article_favorite`. Doing it in
+-- triggers means it can't get out of sync no matter how a favorite is added or removed, including by the cascading
+-- deletes when a user or article is deleted.
+--
+-- The tradeoff is that favoriting an article now also locks and updates the article's row, so two users favoriting
+-- the same article at the same moment have to take turns. That's a short wait, and it's rare for one article to get
+-- so many favorites at once that it matters.
+alter table article
+    add column favorites_count int8 not null default 0;
+
+-- Backfill the counts for existing articles. This locks the `article` table for the duration, which is fine while
+-- it's small, but for a large table you'd want to do this in batches instead.
+update article
+set favorites_count = (select count(*) from article_favorite where article_favorite.article_id = article.article_id);
+
+create or replace function update_favorites_count()
+    returns trigger as
+$$
+begin
+    if TG_OP = 'INSERT' then
+        update article set favorites_count = favorites_count + 1 where article_id = NEW.article_id;
+    elsif TG_OP = 'DELETE' then
+        update article set favorites_count = favorites_count - 1 where article_id = OLD.article_id;
+    end if;
+
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger update_favorites_count
+    after insert or delete
+    on article_favorite
+    for each row
+execute function update_favorites_count();
+
+-- Favoriting an article shouldn't count as updating it, so we replace the trigger that `trigger_updated_at()`
+-- created for `article` with one that ignores changes to `favorites_count`.
+drop trigger set_updated_at on article;
+
+create trigger set_updated_at
+    before update
+    on article
+    for each row
+    when ((to_jsonb(OLD) - 'favorites_count') is distinct from (to_jsonb(NEW) - 'favorites_count'))
+execute function set_updated_at();
@@ -0,0 +1,109 @@
+-- The feed query used to join `follow` against `article` on every request, which means reading every article by every
+-- author the user follows just to find the newest 20. For a user following a few thousand prolific authors, that
+-- adds up fast.
+--
+-- Instead, we "fan out on write": when an article is published, we insert a row here for each of the author's
+-- followers, so reading a feed is a single index range scan no matter how many authors the user follows. The cost
+-- moves to publishing, which happens far less often than reading feeds, although an author with a million followers
+-- now makes for a rather slow insert.
+--
+-- If that becomes a problem, the fan-out should move out of the publishing transaction and into a background job.
+-- We don't have a job queue (yet), so for now the triggers below do the fan-out inline, which at least means it's
+-- always consistent with `follow` and `article`.
+create table feed_entry
+(
+    -- The user whose feed this is.
+    user_id    uuid        not null references "user" (user_id) on delete cascade,
+
+    article_id uuid        not null references article (article_id) on delete cascade,
+
+    -- Copied from `article.created_at`, so we can sort and paginate without touching the `article` table at all.
+    -- Articles can't be backdated, so this never needs to change.
+    created_at timestamptz not null,
+
+    primary key (user_id, article_id)
+);
+
+-- This is the index that actually serves the feed, newest first. The feed is paginated on
+-- `(created_at, article_id)` rather than with `offset`, so each page is a seek to where the previous one left off.
+create index on feed_entry (user_id, created_at desc, article_id desc);
+
+-- Fan a newly published article out to everyone following its author.
+create or replace function feed_entry_article_published()
+    returns trigger as
+$$
+begin
+    insert into feed_entry (user_id, article_id, created_at)
+    select following_user_id, NEW.article_id, NEW.created_at
+    from follow
+    where followed_user_id = NEW.user_id;
+
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger feed_entry_article_published
+    after insert
+    on article
+    for each row
+execute function feed_entry_article_published();
+
+-- When a user follows someone, their existing articles show up in the feed straight away, as they did before.
+--
+-- We only backfill the most recent ones, since `src/db/feed.rs` prunes each feed down to that many entries anyway.
+-- Keep the limit here in sync with `MAX_ENTRIES_PER_USER` there.
+create or replace function feed_entry_followed()
+    returns trigger as
+$$
+begin
+    insert into feed_entry (user_id, article_id, created_at)
+    select NEW.following_user_id, article_id, created_at
+    from article
+    where user_id = NEW.followed_user_id
+    order by created_at desc
+    limit 1000
+    on conflict do nothing;
+
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger feed_entry_followed
+    after insert
+    on follow
+    for each row
+execute function feed_entry_followed();
+
+-- And when they unfollow someone, their articles disappear from the feed.
+create or replace function feed_entry_unfollowed()
+    returns trigger as
+$$
+begin
+    delete
+    from feed_entry
+    using article
+    where feed_entry.user_id = OLD.following_user_id
+      and feed_entry.article_id = article.article_id
+      and article.user_id = OLD.followed_user_id;
+
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger feed_entry_unfollowed
+    after delete
+    on follow
+    for each row
+execute function feed_entry_unfollowed();
+
+-- Backfill the feeds for the follows that already exist, with the same limit per followed author as above.
+insert into feed_entry (user_id, article_id, created_at)
+select follow.following_user_id, recent.article_id, recent.created_at
+from follow
+cross join lateral (
+    select article_id, created_at
+    from article
+    where article.user_id = follow.followed_user_id
+    order by created_at desc
+    limit 1000
+    ) recent;
@@ -0,0 +1,15 @@
+-- What a user is allowed to do besides the usual. Nothing in the Realworld spec needs this; it's for operators and
+-- moderators, who for now are managed with the `admin` subcommand.
+--
+-- An enum rather than a `text` column with a check constraint, since SQLx can map it straight to a Rust enum and
+-- Postgres stores it in 4 bytes. Adding a value later is a one-liner (`alter type user_role add value ...`),
+-- removing one is not, so think twice before adding one.
+create type user_role as enum ('user', 'moderator', 'admin');
+
+alter table "user"
+    add column role      user_role not null default 'user',
+
+    -- When the user was banned, if they are. A banned user can't log in, and their existing tokens stop working for
+    -- anything that needs authentication. Their articles and comments stay up; taking those down is a separate
+    -- decision.
+    add column banned_at timestamptz;
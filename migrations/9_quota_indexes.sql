@@ -0,0 +1,6 @@
+-- Per-user quotas (see `http::quota`) count how many articles or comments a user has posted recently, on every post.
+-- Without these, that's a scan of everything they've ever posted, which is fine for most users and not at all fine
+-- for exactly the ones the quotas are there for.
+create index on article (user_id, created_at);
+
+create index on article_comment (user_id, created_at);
@@ -0,0 +1,102 @@
+use anyhow::bail;
+
+use crate::db::users::Role;
+use crate::db::{self, Db};
+
+// These are for operators who need to do something *now*, from a shell on a box that can reach
+// the database, without a frontend or an admin account to do it with. They use the same queries
+// as the API, so they play by the same rules (and the same triggers) as everything else.
+//
+// Anything that changes data prints what it did and logs it, so there's a record in the logs
+// of who ran what alongside everything else that happened at the time.
+//
+// Note that other running instances won't notice an article deleted here until their caches
+// expire, see `http::articles::article_cache()`.
+
+/// The `admin` subcommands.
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum AdminCommand {
+    /// Manage users.
+    #[clap(subcommand)]
+    User(UserCommand),
+    /// Manage articles.
+    #[clap(subcommand)]
+    Article(ArticleCommand),
+}
+
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum UserCommand {
+    /// Stop a user from logging in, and from using the tokens they already have.
+    Ban { username: String },
+    /// Let a banned user back in.
+    Unban { username: String },
+    /// Delete a user's account but keep their articles and comments, credited to `[deleted]`.
+    Anonymize { username: String },
+    /// Change what a user is allowed to do.
+    SetRole {
+        username: String,
+        #[clap(arg_enum)]
+        role: Role,
+    },
+}
+
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum ArticleCommand {
+    /// Delete an article, whoever wrote it.
+    Delete { slug: String },
+}
+
+/// Run an `admin` subcommand, failing if whatever it acts on doesn't exist.
+pub async fn run(db: &Db, command: AdminCommand) -> anyhow::Result<()> {
+    let e = db.primary();
+
+    match command {
+        AdminCommand::User(UserCommand::Ban { username }) => {
+            if !db::users::set_banned(e, &username, true).await? {
+                bail!("there is no user named {:?}", username);
+            }
+
+            done(format!("banned user {:?}", username));
+        }
+        AdminCommand::User(UserCommand::Unban { username }) => {
+            if !db::users::set_banned(e, &username, false).await? {
+                bail!("there is no user named {:?}", username);
+            }
+
+            done(format!("unbanned user {:?}", username));
+        }
+        AdminCommand::User(UserCommand::Anonymize { username }) => {
+            let user = match db::users::find_by_username(e, &username).await? {
+                Some(user) => user,
+                None => bail!("there is no user named {:?}", username),
+            };
+
+            if !db::users::anonymize(e, user.user_id).await? {
+                bail!("user {:?} can't be anonymized", username);
+            }
+
+            done(format!("anonymized user {:?}", username));
+        }
+        AdminCommand::User(UserCommand::SetRole { username, role }) => {
+            if !db::users::set_role(e, &username, role).await? {
+                bail!("there is no user named {:?}", username);
+            }
+
+            done(format!("set the role of user {:?} to {:?}", username, role));
+        }
+        AdminCommand::Article(ArticleCommand::Delete { slug }) => {
+            if !db::articles::delete_any(e, &slug).await? {
+                bail!("there is no article with slug {:?}", slug);
+            }
+
+            done(format!("deleted article {:?}", slug));
+        }
+    }
+
+    Ok(())
+}
+
+fn done(message: String) {
+    log::info!("admin: {}", message);
+    println!("{}", message);
+}
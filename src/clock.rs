@@ -0,0 +1,73 @@
+use std::sync::Mutex;
+
+use time::{Duration, OffsetDateTime};
+
+// Anything that depends on what time it is in Rust, like whether a token has expired, should ask
+// a `Clock` from `ApiContext` instead of calling `OffsetDateTime::now_utc()` itself. Otherwise the
+// only way to test it is to actually wait, which for a two-week session isn't really an option.
+//
+// Timestamps that Postgres fills in with `now()` aren't affected by this. That's intentional:
+// they record when something *happened*, which a test has no reason to lie about.
+
+/// Tells the time. See `SystemClock` and `MockClock`.
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> OffsetDateTime;
+}
+
+/// The real time, which is what the application uses outside of tests.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemClock;
+
+/// A clock that only moves when it's told to, for tests.
+///
+/// Share it with the application in an `Arc` and keep a handle to call `advance()` on.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<OffsetDateTime>,
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+impl MockClock {
+    /// A clock stopped at `now`.
+    pub fn new(now: OffsetDateTime) -> Self {
+        MockClock {
+            now: Mutex::new(now),
+        }
+    }
+
+    /// Move the clock forward by `duration`, or backward if it's negative.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    /// Set the clock to `now`.
+    pub fn set(&self, now: OffsetDateTime) {
+        *self.now.lock().unwrap() = now;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> OffsetDateTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[test]
+fn test_mock_clock() {
+    let start = OffsetDateTime::now_utc();
+    let clock = MockClock::new(start);
+
+    assert_eq!(clock.now(), start);
+    assert_eq!(clock.now(), start);
+
+    clock.advance(Duration::hours(1));
+    assert_eq!(clock.now(), start + Duration::hours(1));
+
+    clock.set(start);
+    assert_eq!(clock.now(), start);
+}
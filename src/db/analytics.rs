@@ -0,0 +1,191 @@
+use sqlx::PgExecutor;
+use time::Date;
+
+use crate::db::instrument::instrument;
+use crate::db::types::UserId;
+
+/// How many followers a user had at the end of a day, in UTC.
+pub struct FollowersOnDay {
+    pub day: Date,
+    pub followers: i64,
+}
+
+/// How many favorites one of a user's articles has.
+pub struct ArticleFavorites {
+    pub slug: String,
+    pub favorites: i64,
+}
+
+/// Count `user_id`'s followers at the end of each day someone started following them, oldest
+/// first.
+///
+/// Unfollowing deletes the row in `follow`, so this is really "how many of their current
+/// followers had followed them by then": someone who followed and then unfollowed doesn't count
+/// on any day, and the total on the last day is how many followers they have now.
+pub async fn followers_by_day(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+) -> sqlx::Result<Vec<FollowersOnDay>> {
+    sqlx::query_as!(
+        FollowersOnDay,
+        r#"
+            select
+                (created_at at time zone 'UTC')::date "day!",
+                (sum(count(*)) over (order by (created_at at time zone 'UTC')::date))::int8 "followers!"
+            from follow
+            where followed_user_id = $1
+            group by 1
+            order by 1
+        "#,
+        user_id as UserId
+    )
+    .fetch_all(instrument("analytics::followers_by_day", e))
+    .await
+}
+
+/// Count the favorites on each of `user_id`'s articles, oldest article first.
+pub async fn favorites_by_article(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+) -> sqlx::Result<Vec<ArticleFavorites>> {
+    sqlx::query_as!(
+        ArticleFavorites,
+        r#"
+            select slug, favorites_count "favorites"
+            from article
+            where user_id = $1
+            order by created_at, article_id
+        "#,
+        user_id as UserId
+    )
+    .fetch_all(instrument("analytics::favorites_by_article", e))
+    .await
+}
+
+/// The activity around a user on one day, in UTC.
+pub struct DayActivity {
+    pub day: Date,
+    /// How many of their current followers started following them this day.
+    pub new_followers: i64,
+    /// How many of their current followers had followed them by the end of this day.
+    pub followers: i64,
+    /// How many times their articles were favorited this day, counting only favorites that
+    /// haven't since been taken back.
+    pub favorites: i64,
+    /// How many comments were posted on their articles this day, including their own.
+    pub comments: i64,
+}
+
+/// The activity on one of a user's articles over a window of days.
+pub struct ArticleActivity {
+    pub slug: String,
+    pub title: String,
+    pub favorites: i64,
+    pub comments: i64,
+    /// All the favorites the article has, whenever they were added.
+    pub total_favorites: i64,
+}
+
+/// The activity around `user_id` on each of the last `days` days including today, oldest first.
+///
+/// There's a row for every day, even if nothing happened, so a frontend can chart it as-is.
+pub async fn activity_by_day(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+    days: i32,
+) -> sqlx::Result<Vec<DayActivity>> {
+    // Every timestamp is truncated to a day in UTC, rather than whatever time zone the session
+    // happens to be in, so that the days line up with the ones in the CSV export.
+    sqlx::query_as!(
+        DayActivity,
+        r#"
+            with day as (
+                select day::date
+                from generate_series(
+                    date_trunc('day', now() at time zone 'UTC') - ($2::int4 - 1) * interval '1 day',
+                    date_trunc('day', now() at time zone 'UTC'),
+                    interval '1 day'
+                ) day
+            ),
+            new_follower as (
+                select date_trunc('day', created_at at time zone 'UTC')::date "day", count(*)
+                from follow
+                where followed_user_id = $1
+                group by 1
+            ),
+            favorite as (
+                select date_trunc('day', article_favorite.created_at at time zone 'UTC')::date "day", count(*)
+                from article_favorite
+                inner join article using (article_id)
+                where article.user_id = $1
+                group by 1
+            ),
+            comment as (
+                select date_trunc('day', article_comment.created_at at time zone 'UTC')::date "day", count(*)
+                from article_comment
+                inner join article using (article_id)
+                where article.user_id = $1
+                group by 1
+            )
+            select
+                day.day "day!",
+                coalesce(new_follower.count, 0) "new_followers!",
+                (
+                    select coalesce(sum(count), 0)::int8
+                    from new_follower before
+                    where before.day <= day.day
+                ) "followers!",
+                coalesce(favorite.count, 0) "favorites!",
+                coalesce(comment.count, 0) "comments!"
+            from day
+            left join new_follower using (day)
+            left join favorite using (day)
+            left join comment using (day)
+            order by day.day
+        "#,
+        user_id as UserId,
+        days
+    )
+    .fetch_all(instrument("analytics::activity_by_day", e))
+    .await
+}
+
+/// The activity on each of `user_id`'s articles over the last `days` days including today,
+/// newest article first.
+pub async fn activity_by_article(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+    days: i32,
+) -> sqlx::Result<Vec<ArticleActivity>> {
+    sqlx::query_as!(
+        ArticleActivity,
+        r#"
+            with since as (
+                select (
+                    date_trunc('day', now() at time zone 'UTC') - ($2::int4 - 1) * interval '1 day'
+                ) at time zone 'UTC' "since"
+            )
+            select
+                slug,
+                title,
+                (
+                    select count(*)
+                    from article_favorite
+                    where article_id = article.article_id and created_at >= since
+                ) "favorites!",
+                (
+                    select count(*)
+                    from article_comment
+                    where article_id = article.article_id and created_at >= since
+                ) "comments!",
+                favorites_count "total_favorites"
+            from article, since
+            where user_id = $1
+            order by created_at desc, article_id
+        "#,
+        user_id as UserId,
+        days
+    )
+    .fetch_all(instrument("analytics::activity_by_article", e))
+    .await
+}
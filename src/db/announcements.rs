@@ -0,0 +1,223 @@
+use sqlx::PgExecutor;
+use time::OffsetDateTime;
+
+use crate::db::instrument::instrument;
+use crate::db::types::{AnnouncementId, UserId};
+
+/// How urgent an announcement is, see `migrations/25_announcement.sql`.
+#[derive(
+    sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default,
+)]
+#[sqlx(type_name = "announcement_severity", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+/// A sitewide announcement, see `migrations/25_announcement.sql`.
+pub struct Announcement {
+    pub announcement_id: AnnouncementId,
+    pub message: String,
+    pub severity: Severity,
+    pub starts_at: OffsetDateTime,
+    pub ends_at: Option<OffsetDateTime>,
+    pub created_at: OffsetDateTime,
+    pub updated_at: Option<OffsetDateTime>,
+}
+
+/// The fields of an announcement that an admin sets, for `create()` and `update()`.
+pub struct AnnouncementFields<'a> {
+    pub message: &'a str,
+    pub severity: Severity,
+    pub starts_at: OffsetDateTime,
+    pub ends_at: Option<OffsetDateTime>,
+}
+
+/// The announcements showing at `now`, most severe first and then newest first, leaving out
+/// any that `user_id` has dismissed.
+pub async fn list_active(
+    e: impl PgExecutor<'_>,
+    now: OffsetDateTime,
+    user_id: Option<UserId>,
+) -> sqlx::Result<Vec<Announcement>> {
+    sqlx::query_as!(
+        Announcement,
+        r#"
+            select
+                announcement_id "announcement_id: AnnouncementId",
+                message,
+                severity "severity: Severity",
+                starts_at,
+                ends_at,
+                created_at,
+                updated_at
+            from announcement
+            where starts_at <= $1
+                and (ends_at is null or ends_at > $1)
+                and not exists(
+                    select 1
+                    from announcement_dismissal
+                    where announcement_dismissal.announcement_id = announcement.announcement_id
+                        and user_id = $2
+                )
+            order by severity desc, starts_at desc
+        "#,
+        now,
+        user_id as Option<UserId>
+    )
+    .fetch_all(instrument("announcements::list_active", e))
+    .await
+}
+
+/// Every announcement, past, present and future, latest starting first, for admins.
+///
+/// If `after` is the `starts_at` and ID of the last one on the previous page, this picks up
+/// where that left off.
+pub async fn list(
+    e: impl PgExecutor<'_>,
+    after: Option<(OffsetDateTime, AnnouncementId)>,
+    limit: i64,
+) -> sqlx::Result<Vec<Announcement>> {
+    let (after_starts_at, after_id) = after.unzip();
+
+    sqlx::query_as!(
+        Announcement,
+        r#"
+            select
+                announcement_id "announcement_id: AnnouncementId",
+                message,
+                severity "severity: Severity",
+                starts_at,
+                ends_at,
+                created_at,
+                updated_at
+            from announcement
+            where $1::timestamptz is null or (starts_at, announcement_id) < ($1, $2)
+            order by starts_at desc, announcement_id desc
+            limit $3
+        "#,
+        after_starts_at,
+        after_id as Option<AnnouncementId>,
+        limit
+    )
+    .fetch_all(instrument("announcements::list", e))
+    .await
+}
+
+/// How many announcements there are, for the total of `list()`.
+pub async fn count(e: impl PgExecutor<'_>) -> sqlx::Result<i64> {
+    sqlx::query_scalar!(r#"select count(*) "count!" from announcement"#)
+        .fetch_one(instrument("announcements::count", e))
+        .await
+}
+
+/// Add an announcement, written by the admin `created_by`.
+pub async fn create(
+    e: impl PgExecutor<'_>,
+    created_by: UserId,
+    fields: &AnnouncementFields<'_>,
+) -> sqlx::Result<Announcement> {
+    sqlx::query_as!(
+        Announcement,
+        r#"
+            insert into announcement (message, severity, starts_at, ends_at, created_by_user_id)
+            values ($1, $2, $3, $4, $5)
+            returning
+                announcement_id "announcement_id: AnnouncementId",
+                message,
+                severity "severity: Severity",
+                starts_at,
+                ends_at,
+                created_at,
+                updated_at
+        "#,
+        fields.message,
+        fields.severity as Severity,
+        fields.starts_at,
+        fields.ends_at,
+        created_by as UserId
+    )
+    .fetch_one(instrument("announcements::create", e))
+    .await
+}
+
+/// Replace all the fields of an announcement. Dismissals are kept, so fix a typo with this
+/// but post anything new as a new announcement.
+///
+/// Returns `None` if there's no such announcement.
+pub async fn update(
+    e: impl PgExecutor<'_>,
+    announcement_id: AnnouncementId,
+    fields: &AnnouncementFields<'_>,
+) -> sqlx::Result<Option<Announcement>> {
+    sqlx::query_as!(
+        Announcement,
+        r#"
+            update announcement
+            set message = $2, severity = $3, starts_at = $4, ends_at = $5
+            where announcement_id = $1
+            returning
+                announcement_id "announcement_id: AnnouncementId",
+                message,
+                severity "severity: Severity",
+                starts_at,
+                ends_at,
+                created_at,
+                updated_at
+        "#,
+        announcement_id as AnnouncementId,
+        fields.message,
+        fields.severity as Severity,
+        fields.starts_at,
+        fields.ends_at
+    )
+    .fetch_optional(instrument("announcements::update", e))
+    .await
+}
+
+/// Delete an announcement, along with its dismissals.
+///
+/// Returns `false` if there's no such announcement.
+pub async fn delete(e: impl PgExecutor<'_>, announcement_id: AnnouncementId) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        "delete from announcement where announcement_id = $1",
+        announcement_id as AnnouncementId
+    )
+    .execute(instrument("announcements::delete", e))
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Remember that `user_id` has dismissed an announcement. Dismissing it again does nothing.
+///
+/// Returns `false` if there's no such announcement.
+pub async fn dismiss(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+    announcement_id: AnnouncementId,
+) -> sqlx::Result<bool> {
+    // Selecting from `announcement` rather than inserting the ID as-is means a missing one
+    // inserts nothing, instead of failing the foreign key.
+    let exists = sqlx::query_scalar!(
+        r#"
+            with inserted as (
+                insert into announcement_dismissal (user_id, announcement_id)
+                select $1, announcement_id
+                from announcement
+                where announcement_id = $2
+                on conflict do nothing
+            )
+            select exists(select 1 from announcement where announcement_id = $2) "exists!"
+        "#,
+        user_id as UserId,
+        announcement_id as AnnouncementId
+    )
+    .fetch_one(instrument("announcements::dismiss", e))
+    .await?;
+
+    Ok(exists)
+}
@@ -0,0 +1,1120 @@
+use sqlx::{Connection, PgConnection, PgExecutor};
+use time::OffsetDateTime;
+
+use crate::db::instrument::instrument;
+use crate::db::types::{ArticleId, UserId};
+use crate::db::{Deleted, Recent};
+
+// One place that SQLx could still improve upon is when a query wants to return a nested
+// object, such as an article does with its author.
+// For 1:1 relations like that, what we usually do is deserialize the nested object as columns
+// flattened into the main query, then fixup the structure afterwards.
+//
+// It's a good chunk of boilerplate but thankfully you usually only have to write it a few
+// times across a whole project.
+
+/// An article with its author's profile flattened in, as seen by a particular user.
+#[derive(Clone)]
+pub struct Article {
+    pub article_id: ArticleId,
+    pub slug: String,
+    pub title: String,
+    pub description: String,
+    pub body: String,
+    pub tag_list: Vec<String>,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+    pub favorited: bool,
+    pub favorites_count: i64,
+    pub author_username: String,
+    pub author_bio: String,
+    pub author_image: Option<String>,
+    // This was originally `author_following` to match other fields but that's kind of confusing.
+    // That made it sound like a flag showing if the author is following the current user
+    // but the intent is the other way round.
+    pub following_author: bool,
+}
+
+/// Just the IDs of an article, for checking permissions.
+pub struct ArticleMeta {
+    pub article_id: ArticleId,
+    pub user_id: UserId,
+}
+
+/// The fields of an article that can be changed, where `None` leaves the field as-is.
+pub struct ArticleUpdate<'a> {
+    pub slug: Option<&'a str>,
+    pub title: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub body: Option<&'a str>,
+}
+
+/// The filters for `list()`, all of which are optional.
+pub struct ListFilter<'a> {
+    pub tag: Option<&'a str>,
+    pub author: Option<&'a str>,
+    pub favorited: Option<&'a str>,
+    /// Leave out articles with any of these tags, see `db::muted_tags`.
+    pub muted: &'a [String],
+    /// Start after this article rather than from the newest. `offset` is applied after that.
+    pub cursor: Option<Cursor>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Where a page of articles left off, so the next page can pick up from there.
+///
+/// This is the sort key of the last article on the page. Paginating like this rather than with
+/// `offset` means Postgres can seek straight to the right place in the index, and articles
+/// published in the meantime don't shift everything over by one.
+///
+/// Both `list()` and `feed::list()` sort by this, newest first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    pub created_at: OffsetDateTime,
+    pub article_id: ArticleId,
+}
+
+impl Cursor {
+    /// The cursor pointing just past `article`.
+    pub fn after(article: &Article) -> Self {
+        Cursor {
+            created_at: article.created_at,
+            article_id: article.article_id,
+        }
+    }
+}
+
+/// Insert a new article, returning it as seen by its author.
+///
+/// `tag_list` must already be sorted and free of duplicates, see `create_article()`. Any of the
+/// tags that don't exist yet are created.
+///
+/// Fails with a violation of `article_slug_key` if the slug is taken.
+pub async fn create(
+    e: impl PgExecutor<'_>,
+    author: UserId,
+    slug: &str,
+    title: &str,
+    description: &str,
+    body: &str,
+    tag_list: &[String],
+) -> sqlx::Result<Article> {
+    // For fun, this is how we combine several operations into a single query for brevity.
+    sqlx::query_as!(
+        Article,
+        // language=PostgreSQL
+        r#"
+            with inserted_article as (
+                insert into article (user_id, slug, title, description, body)
+                values ($1, $2, $3, $4, $5)
+                returning
+                    article_id,
+                    slug,
+                    title,
+                    description,
+                    body,
+                    favorites_count,
+                    created_at,
+                    updated_at
+            ),
+            -- `do nothing` wouldn't return the tags that already exist, so we "update" those
+            -- to get their IDs. The row is about to be updated by the trigger keeping
+            -- `articles_count` anyway.
+            article_tags as (
+                insert into tag (name)
+                select unnest($6::text[])
+                on conflict (name) do update set name = excluded.name
+                returning tag_id
+            ),
+            inserted_article_tags as (
+                insert into article_tag (article_id, tag_id)
+                select article_id, tag_id from inserted_article, article_tags
+            )
+            select
+                -- This is how you can override the inferred type of a column.
+                article_id "article_id: ArticleId",
+                slug,
+                title,
+                description,
+                body,
+                favorites_count,
+                inserted_article.created_at,
+                inserted_article.updated_at,
+                -- The main query can't see the rows inserted above (see `delete()`), but the
+                -- tags are just the ones we were given.
+                $6 "tag_list!",
+                false "favorited!",
+                username author_username,
+                bio author_bio,
+                image author_image,
+                -- user is forbidden to follow themselves
+                false "following_author!"
+            from inserted_article
+            inner join "user" on user_id = $1
+        "#,
+        author as UserId,
+        slug,
+        title,
+        description,
+        body,
+        // The typechecking code that SQLx emits for parameters sometimes chokes on vectors.
+        // This slicing operation shouldn't be required, but it took a mess of type-system
+        // hacks just to get the codegen this far.
+        tag_list
+    )
+    .fetch_one(instrument("articles::create", e))
+    .await
+}
+
+/// Look up the IDs of an article by slug.
+pub async fn find_meta(e: impl PgExecutor<'_>, slug: &str) -> sqlx::Result<Option<ArticleMeta>> {
+    sqlx::query_as!(
+        ArticleMeta,
+        r#"select article_id "article_id: ArticleId", user_id "user_id: UserId" from article where slug = $1"#,
+        slug
+    )
+    .fetch_optional(instrument("articles::find_meta", e))
+    .await
+}
+
+/// Look up the IDs of an article by slug, locking the row until the end of the transaction.
+pub async fn find_meta_for_update(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+) -> sqlx::Result<Option<ArticleMeta>> {
+    sqlx::query_as!(
+        ArticleMeta,
+        // This locks the `article` row for the duration of the transaction so we're
+        // not interleaving this with other possible updates.
+        r#"select article_id "article_id: ArticleId", user_id "user_id: UserId" from article where slug = $1 for update"#,
+        slug
+    )
+    .fetch_optional(instrument("articles::find_meta_for_update", e))
+    .await
+}
+
+pub async fn find_id_by_slug(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+) -> sqlx::Result<Option<ArticleId>> {
+    sqlx::query_scalar!(
+        r#"select article_id "article_id: ArticleId" from article where slug = $1"#,
+        slug
+    )
+    .fetch_optional(instrument("articles::find_id_by_slug", e))
+    .await
+}
+
+/// Look up how many users have favorited the article with the given slug.
+///
+/// Returns `None` if there's no such article.
+pub async fn favorites_count(e: impl PgExecutor<'_>, slug: &str) -> sqlx::Result<Option<i64>> {
+    sqlx::query_scalar!(
+        r#"
+            select favorites_count
+            from article
+            where slug = $1
+                and not exists(
+                    select 1 from article_takedown where article_id = article.article_id
+                )
+        "#,
+        slug
+    )
+    .fetch_optional(instrument("articles::favorites_count", e))
+    .await
+}
+
+/// Someone who favorited an article, from `list_favoriters()`.
+pub struct Favoriter {
+    pub user_id: UserId,
+    /// When they favorited it, which is what the list is sorted by.
+    pub favorited_at: OffsetDateTime,
+    pub username: String,
+    pub bio: String,
+    pub image: Option<String>,
+    pub following: bool,
+}
+
+/// List the users who favorited the article with the given slug, as seen by `viewer`, most
+/// recent first.
+///
+/// If `after` is the `favorited_at` and user ID of the last one on the previous page, this picks
+/// up where that left off.
+pub async fn list_favoriters(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+    viewer: Option<UserId>,
+    after: Option<(OffsetDateTime, UserId)>,
+    limit: i64,
+) -> sqlx::Result<Vec<Favoriter>> {
+    let (after_favorited_at, after_user_id) = after.unzip();
+
+    sqlx::query_as!(
+        Favoriter,
+        r#"
+            select
+                user_id "user_id: UserId",
+                article_favorite.created_at "favorited_at",
+                username,
+                bio,
+                image,
+                exists(
+                    select 1 from follow
+                    where followed_user_id = "user".user_id and following_user_id = $2
+                ) "following!"
+            from article_favorite
+            inner join "user" using (user_id)
+            where article_id = (select article_id from article where slug = $1)
+                and (
+                    $3::timestamptz is null
+                    or (article_favorite.created_at, user_id) < ($3, $4)
+                )
+            order by article_favorite.created_at desc, user_id desc
+            limit $5
+        "#,
+        slug,
+        viewer as Option<UserId>,
+        after_favorited_at,
+        after_user_id as Option<UserId>,
+        limit
+    )
+    .fetch_all(instrument("articles::list_favoriters", e))
+    .await
+}
+
+/// Look up the current slug of an article, for `GET /a/:article_id`.
+pub async fn find_slug_by_id(
+    e: impl PgExecutor<'_>,
+    article_id: ArticleId,
+) -> sqlx::Result<Option<String>> {
+    sqlx::query_scalar!(
+        "select slug from article where article_id = $1",
+        article_id as ArticleId
+    )
+    .fetch_optional(instrument("articles::find_slug_by_id", e))
+    .await
+}
+
+/// Update the fields of an article that are set in `update`, returning it as seen by `editor`.
+///
+/// The caller must have already checked that `editor` is allowed to, i.e. that they wrote the
+/// article or are one of its co-authors.
+///
+/// Fails with a violation of `article_slug_key` if the new slug is taken.
+pub async fn update(
+    e: impl PgExecutor<'_>,
+    article_id: ArticleId,
+    editor: UserId,
+    update: ArticleUpdate<'_>,
+) -> sqlx::Result<Article> {
+    // Update the article and return the new values in the same query.
+    //
+    // This is perhaps toeing the line of "too clever" as I talked about in `profiles::follow_user()`,
+    // however I think here it saves us some code duplication as well as an extra round-trip
+    // to the database, and isn't too hard to understand.
+    //
+    // I could also have folded the permission check into the update, and have in the past,
+    // but I think that's where it starts to get too confusing as it relies on the fact that CTEs
+    // with `INSERT/UPDATE/DELETE` statements are executed even if they're not read from.
+    sqlx::query_as!(
+        Article,
+        // language=PostgreSQL
+        r#"
+            with updated_article as (
+                update article
+                set
+                    slug = coalesce($1, slug),
+                    title = coalesce($2, title),
+                    description = coalesce($3, description),
+                    body = coalesce($4, body)
+                where article_id = $5
+                returning
+                    article_id,
+                    slug,
+                    title,
+                    description,
+                    body,
+                    favorites_count,
+                    user_id,
+                    article.created_at,
+                    article.updated_at
+            )
+            -- SQLx can't see through the CTE to tell that none of these can be null,
+            -- so we tell it with `!`, like in `list()`.
+            select
+                article_id "article_id!: ArticleId",
+                slug "slug!",
+                title "title!",
+                description "description!",
+                body "body!",
+                article_tags(article_id) "tag_list!",
+                favorites_count "favorites_count!",
+                updated_article.created_at "created_at!",
+                updated_article.updated_at "updated_at!",
+                exists(
+                    select 1 from article_favorite
+                    where article_id = $5 and user_id = $6
+                ) "favorited!",
+                author.username "author_username!",
+                author.bio "author_bio!",
+                author.image author_image,
+                -- This used to be `false`, as users can't follow themselves, but a co-author
+                -- editing the article may well follow its author.
+                exists(
+                    select 1 from follow
+                    where followed_user_id = author.user_id and following_user_id = $6
+                ) "following_author!"
+            from updated_article
+            inner join "user" author using (user_id)
+        "#,
+        // In a query with a lot of bind parameters, it can be difficult to keep them all straight.
+        // There's an open proposal to improve this: https://github.com/launchbadge/sqlx/issues/875
+        update.slug,
+        update.title,
+        update.description,
+        update.body,
+        article_id as ArticleId,
+        editor as UserId
+    )
+    .fetch_one(instrument("articles::update", e))
+    .await
+}
+
+/// Delete an article, but only if `user_id` wrote it.
+pub async fn delete(e: impl PgExecutor<'_>, slug: &str, user_id: UserId) -> sqlx::Result<Deleted> {
+    sqlx::query_as!(
+        Deleted,
+        // I like to use raw strings for most queries mainly because CLion doesn't try
+        // to escape newlines.
+        // language=PostgreSQL
+        r#"
+            -- The main query cannot observe side-effects of data-modifying CTEs and
+            -- by design, always sees the "before" picture of the database,
+            -- so this lets us fold our permissions check together with the actual delete.
+            --
+            -- This was the "being too clever" I was talking about before. However, I think it's
+            -- permissible here as we're not pairing this together with a huge select, so it
+            -- should be relatively easy to understand the intended effect here.
+            with deleted_article as (
+                delete from article
+                -- Important: we only delete the article if the user actually authored it.
+                where slug = $1 and user_id = $2
+                -- We just need to return *something* for `exists()` below.
+                returning 1
+            )
+            select
+                -- This will be `true` if the article existed before we deleted it.
+                exists(select 1 from article where slug = $1) "existed!",
+                -- This will only be `true` if we actually deleted the article.
+                exists(select 1 from deleted_article) "deleted!"
+        "#,
+        slug,
+        user_id as UserId
+    )
+    .fetch_one(instrument("articles::delete", e))
+    .await
+}
+
+/// Delete an article, whoever wrote it. This is for moderation; see `delete()` for authors.
+///
+/// Returns `false` if there's no such article.
+pub async fn delete_any(e: impl PgExecutor<'_>, slug: &str) -> sqlx::Result<bool> {
+    let result = sqlx::query!("delete from article where slug = $1", slug)
+        .execute(instrument("articles::delete_any", e))
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Count the articles `author` has published since `since`, for their quota.
+pub async fn count_recent(
+    e: impl PgExecutor<'_>,
+    author: UserId,
+    since: OffsetDateTime,
+) -> sqlx::Result<Recent> {
+    sqlx::query_as!(
+        Recent,
+        r#"
+            select count(*) "count!", min(created_at) oldest
+            from article
+            where user_id = $1 and created_at > $2
+        "#,
+        author as UserId,
+        since
+    )
+    .fetch_one(instrument("articles::count_recent", e))
+    .await
+}
+
+/// Look up an article by slug, as seen by `viewer`.
+pub async fn find_by_slug(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+    viewer: Option<UserId>,
+) -> sqlx::Result<Option<Article>> {
+    sqlx::query_as!(
+        Article,
+        // language=PostgreSQL
+        r#"
+            select
+                article_id "article_id: ArticleId",
+                slug,
+                title,
+                description,
+                body,
+                article_tags(article.article_id) "tag_list!",
+                article.created_at,
+                article.updated_at,
+                exists(
+                    select 1 from article_favorite
+                    where article_id = article.article_id and user_id = $1
+                ) "favorited!",
+                favorites_count,
+                author.username author_username,
+                author.bio author_bio,
+                author.image author_image,
+                exists(select 1 from follow where followed_user_id = author.user_id and following_user_id = $1) "following_author!"
+            from article
+            inner join "user" author using (user_id)
+            where slug = $2
+                -- Only the author can see an article that's been taken down.
+                and (
+                    author.user_id = $1
+                    or not exists(
+                        select 1 from article_takedown where article_id = article.article_id
+                    )
+                )
+        "#,
+        viewer as Option<UserId>,
+        slug
+    )
+    .fetch_optional(instrument("articles::find_by_slug", e))
+    .await
+}
+
+/// Make `user_id` favorite the article with the given slug, if they haven't already.
+///
+/// Returns the article as seen by `user_id`, or `None` if there's no such article.
+pub async fn favorite(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+    user_id: UserId,
+) -> sqlx::Result<Option<Article>> {
+    // This used to return just the article ID, and the handler looked the article up
+    // afterwards, which is a second round-trip for what `create()` does in one query.
+    //
+    // The catch is that the main query always sees the "before" picture of the database
+    // (see `delete()`), and `favorites_count` is kept up to date by a trigger (see
+    // `migrations/6_favorites_count.sql`), so we have to account for our own insert ourselves.
+    // It's the trigger that actually writes the new count, we just predict what it'll be.
+    sqlx::query_as!(
+        Article,
+        // language=PostgreSQL
+        r#"
+            with inserted_favorite as (
+                insert into article_favorite(article_id, user_id)
+                select article_id, $2
+                from article
+                where slug = $1
+                    and not exists(select 1 from article_takedown where article_id = article.article_id)
+                -- if the article is already favorited
+                on conflict do nothing
+                returning 1
+            )
+            select
+                article_id "article_id: ArticleId",
+                slug,
+                title,
+                description,
+                body,
+                article_tags(article.article_id) "tag_list!",
+                article.created_at,
+                article.updated_at,
+                true "favorited!",
+                -- If we didn't insert anything, the article was already favorited
+                -- and the count already includes it.
+                favorites_count + (select count(*) from inserted_favorite) "favorites_count!",
+                author.username author_username,
+                author.bio author_bio,
+                author.image author_image,
+                exists(select 1 from follow where followed_user_id = author.user_id and following_user_id = $2) "following_author!"
+            from article
+            inner join "user" author using (user_id)
+            where slug = $1
+                -- Nobody can see an article that's been taken down to favorite it.
+                and not exists(select 1 from article_takedown where article_id = article.article_id)
+        "#,
+        slug,
+        user_id as UserId
+    )
+    .fetch_optional(instrument("articles::favorite", e))
+    .await
+}
+
+/// Make `user_id` stop favoriting the article with the given slug, if they had.
+///
+/// Returns the article as seen by `user_id`, or `None` if there's no such article.
+pub async fn unfavorite(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+    user_id: UserId,
+) -> sqlx::Result<Option<Article>> {
+    // The mirror image of `favorite()`.
+    sqlx::query_as!(
+        Article,
+        // language=PostgreSQL
+        r#"
+            with deleted_favorite as (
+                delete from article_favorite
+                where article_id = (select article_id from article where slug = $1)
+                and user_id = $2
+                returning 1
+            )
+            select
+                article_id "article_id: ArticleId",
+                slug,
+                title,
+                description,
+                body,
+                article_tags(article.article_id) "tag_list!",
+                article.created_at,
+                article.updated_at,
+                false "favorited!",
+                favorites_count - (select count(*) from deleted_favorite) "favorites_count!",
+                author.username author_username,
+                author.bio author_bio,
+                author.image author_image,
+                exists(select 1 from follow where followed_user_id = author.user_id and following_user_id = $2) "following_author!"
+            from article
+            inner join "user" author using (user_id)
+            where slug = $1
+                and not exists(select 1 from article_takedown where article_id = article.article_id)
+        "#,
+        slug,
+        user_id as UserId
+    )
+    .fetch_optional(instrument("articles::unfavorite", e))
+    .await
+}
+
+/// List articles matching `filter`, most recent first, as seen by `viewer`.
+pub async fn list(
+    e: impl PgExecutor<'_>,
+    viewer: Option<UserId>,
+    filter: &ListFilter<'_>,
+) -> sqlx::Result<Vec<Article>> {
+    let (cursor_created_at, cursor_article_id) = match filter.cursor {
+        Some(cursor) => (Some(cursor.created_at), Some(cursor.article_id)),
+        None => (None, None),
+    };
+
+    sqlx::query_as!(
+        Article,
+        // We used to work out `favorited` with a subquery in the select list, once for every row.
+        // Instead, we pick out the page first, and then look up which of the articles on it the
+        // viewer has favorited all at once, with `= any()` on the primary key of
+        // `article_favorite`. That's one index scan per page rather than one per row.
+        //
+        // `page` is referenced twice, so Postgres materializes it rather than inlining it and
+        // running the filters twice.
+        //
+        // language=PostgreSQL
+        r#"
+            with page as (
+                select
+                    article_id,
+                    slug,
+                    title,
+                    description,
+                    body,
+                    article.created_at,
+                    article.updated_at,
+                    favorites_count,
+                    author.user_id author_id,
+                    author.username author_username,
+                    author.bio author_bio,
+                    author.image author_image
+                from article
+                inner join "user" author using (user_id)
+                -- the current way to do conditional filtering in SQLx
+                where (
+                    -- check if `query.tag` is null or the article has the given tag
+                    $2::text is null or article.article_id in (
+                        select article_id from article_tag inner join tag using (tag_id) where name = $2
+                    )
+                )
+                  and
+                (
+                    $3::text is null or author.username = $3
+                )
+                  and
+                (
+                    $4::text is null or exists(
+                        select 1
+                        from "user"
+                        inner join article_favorite af using (user_id)
+                        where username = $4 and af.article_id = article.article_id
+                    )
+                )
+                  and
+                -- has none of the muted tags
+                not exists(
+                    select 1 from article_tag inner join tag using (tag_id)
+                    where article_tag.article_id = article.article_id and name = any($7)
+                )
+                  and
+                -- Articles that have been taken down aren't listed, even for their authors.
+                not exists(select 1 from article_takedown where article_id = article.article_id)
+                  and
+                -- See `feed::list()` for how this works.
+                (
+                    $8::timestamptz is null
+                    or (article.created_at, article.article_id) < ($8, $9)
+                )
+                -- `article_id` breaks ties, so the cursor never skips or repeats an article.
+                order by article.created_at desc, article.article_id desc
+                limit $5
+                offset $6
+            ),
+            favorited as (
+                select article_id
+                from article_favorite
+                where user_id = $1 and article_id = any(array(select article_id from page))
+            )
+            -- SQLx can't see through the CTE to tell that none of these can be null,
+            -- so we tell it with `!`.
+            select
+                page.article_id "article_id!: ArticleId",
+                slug "slug!",
+                title "title!",
+                description "description!",
+                body "body!",
+                article_tags(page.article_id) "tag_list!",
+                created_at "created_at!",
+                updated_at "updated_at!",
+                favorited.article_id is not null "favorited!",
+                favorites_count "favorites_count!",
+                author_username "author_username!",
+                author_bio "author_bio!",
+                author_image,
+                exists(select 1 from follow where followed_user_id = author_id and following_user_id = $1) "following_author!"
+            from page
+            left join favorited using (article_id)
+            order by created_at desc, article_id desc
+        "#,
+        viewer as Option<UserId>,
+        filter.tag,
+        filter.author,
+        filter.favorited,
+        filter.limit,
+        filter.offset,
+        filter.muted,
+        cursor_created_at,
+        cursor_article_id as Option<ArticleId>
+    )
+    .fetch_all(instrument("articles::list", e))
+    .await
+}
+
+/// Count the articles matching `filter`, ignoring its `cursor`, `limit` and `offset`.
+///
+/// This has to visit every matching row, so see `estimate_count()` and `estimate_total()` for
+/// when that's too many.
+pub async fn count(e: impl PgExecutor<'_>, filter: &ListFilter<'_>) -> sqlx::Result<i64> {
+    // The filters here need to stay the same as in `list()`, or the count won't match.
+    sqlx::query_scalar!(
+        // language=PostgreSQL
+        r#"
+            select count(*) "count!"
+            from article
+            inner join "user" author using (user_id)
+            where (
+                $1::text is null or article.article_id in (
+                    select article_id from article_tag inner join tag using (tag_id) where name = $1
+                )
+            )
+              and
+            (
+                $2::text is null or author.username = $2
+            )
+              and
+            (
+                $3::text is null or exists(
+                    select 1
+                    from "user"
+                    inner join article_favorite af using (user_id)
+                    where username = $3 and af.article_id = article.article_id
+                )
+            )
+              and
+            not exists(
+                select 1 from article_tag inner join tag using (tag_id)
+                where article_tag.article_id = article.article_id and name = any($4)
+            )
+              and
+            not exists(select 1 from article_takedown where article_id = article.article_id)
+        "#,
+        filter.tag,
+        filter.author,
+        filter.favorited,
+        filter.muted,
+    )
+    .fetch_one(instrument("articles::count", e))
+    .await
+}
+
+/// Ask the query planner how many articles it thinks match `filter`, without running the query.
+///
+/// This is only as good as the table statistics, which `autovacuum` refreshes as the table
+/// changes, and for a combination of filters it can easily be off by an order of magnitude.
+/// It's only worth it when the exact count would be large enough that nobody can tell.
+pub async fn estimate_count(e: impl PgExecutor<'_>, filter: &ListFilter<'_>) -> sqlx::Result<i64> {
+    // `EXPLAIN` returns the plan as lines of text, the first of which is the top node of the
+    // plan, e.g. `Hash Join  (cost=1.09..2.20 rows=4 width=0)`. `rows` is the estimate we want.
+    //
+    // We could get it as JSON instead with `EXPLAIN (FORMAT JSON)`, but that'd mean enabling
+    // SQLx's `json` feature just for this, and the text format hasn't changed in forever.
+    //
+    // This is one of the few queries we can't check at compile time, as the macros work out the
+    // nullability of each output column by, funnily enough, running `EXPLAIN` on the query,
+    // which doesn't work when the query is an `EXPLAIN` itself.
+    let plan: String = sqlx::query_scalar(
+        // language=PostgreSQL
+        r#"
+            explain
+            select 1
+            from article
+            inner join "user" author using (user_id)
+            where (
+                $1::text is null or article.article_id in (
+                    select article_id from article_tag inner join tag using (tag_id) where name = $1
+                )
+            )
+              and
+            (
+                $2::text is null or author.username = $2
+            )
+              and
+            (
+                $3::text is null or exists(
+                    select 1
+                    from "user"
+                    inner join article_favorite af using (user_id)
+                    where username = $3 and af.article_id = article.article_id
+                )
+            )
+              and
+            not exists(
+                select 1 from article_tag inner join tag using (tag_id)
+                where article_tag.article_id = article.article_id and name = any($4)
+            )
+              and
+            not exists(select 1 from article_takedown where article_id = article.article_id)
+        "#,
+    )
+    .bind(filter.tag)
+    .bind(filter.author)
+    .bind(filter.favorited)
+    .bind(filter.muted)
+    .fetch_one(instrument("articles::estimate_count", e))
+    .await?;
+
+    parse_plan_rows(&plan)
+        .ok_or_else(|| sqlx::Error::Protocol(format!("unexpected EXPLAIN output: {:?}", plan)))
+}
+
+/// Get the planner's estimate of the total number of articles, which is nearly free.
+///
+/// Returns `None` if the table hasn't been analyzed yet, so there is no estimate.
+pub async fn estimate_total(e: impl PgExecutor<'_>) -> sqlx::Result<Option<i64>> {
+    // `reltuples` is what the planner starts from for every query on the table. It's updated by
+    // `VACUUM` and `ANALYZE` (including when `autovacuum` does them), so it lags behind a bit.
+    //
+    // Since Postgres 14 it's -1 for a table that has never been analyzed, before that it's 0,
+    // which is indistinguishable from an empty table. Either way, we fall back to counting.
+    let estimate = sqlx::query_scalar!(
+        // language=PostgreSQL
+        r#"
+            select reltuples::int8 "reltuples!"
+            from pg_class
+            where oid = 'article'::regclass
+        "#
+    )
+    .fetch_one(instrument("articles::estimate_total", e))
+    .await?;
+
+    Ok((estimate > 0).then_some(estimate))
+}
+
+fn parse_plan_rows(line: &str) -> Option<i64> {
+    let (_, rest) = line.split_once(" rows=")?;
+    let rows = rest.split(' ').next()?;
+    rows.parse().ok()
+}
+
+/// Search articles for `query`, best match first, as seen by `viewer`.
+///
+/// `query` is in the syntax of `websearch_to_tsquery()`, i.e. what people type into search
+/// boxes anyway: words, `"quoted phrases"`, `or`, and `-word` to leave a word out. It never
+/// fails to parse; at worst it matches nothing.
+pub async fn search(
+    e: impl PgExecutor<'_>,
+    viewer: Option<UserId>,
+    query: &str,
+    muted: &[String],
+    limit: i64,
+    offset: i64,
+) -> sqlx::Result<Vec<Article>> {
+    // The same shape as `list()`, see the comment there; only the filter and order differ.
+    sqlx::query_as!(
+        Article,
+        // `@@` is "matches", and can use the GIN index on `search`. `ts_rank()` can't, but it
+        // only has to score the articles that matched.
+        //
+        // language=PostgreSQL
+        r#"
+            with page as (
+                select
+                    article_id,
+                    slug,
+                    title,
+                    description,
+                    body,
+                    article.created_at,
+                    article.updated_at,
+                    favorites_count,
+                    author.user_id author_id,
+                    author.username author_username,
+                    author.bio author_bio,
+                    author.image author_image,
+                    ts_rank(search, query) rank
+                from article
+                inner join "user" author using (user_id)
+                cross join websearch_to_tsquery('english', $2) query
+                where search @@ query
+                  and
+                not exists(
+                    select 1 from article_tag inner join tag using (tag_id)
+                    where article_tag.article_id = article.article_id and name = any($5)
+                )
+                  and
+                not exists(select 1 from article_takedown where article_id = article.article_id)
+                -- Ties are likely for short queries, and without a tiebreaker the pages
+                -- could overlap.
+                order by rank desc, article.created_at desc, article_id
+                limit $3
+                offset $4
+            ),
+            favorited as (
+                select article_id
+                from article_favorite
+                where user_id = $1 and article_id = any(array(select article_id from page))
+            )
+            select
+                page.article_id "article_id!: ArticleId",
+                slug "slug!",
+                title "title!",
+                description "description!",
+                body "body!",
+                article_tags(page.article_id) "tag_list!",
+                created_at "created_at!",
+                updated_at "updated_at!",
+                favorited.article_id is not null "favorited!",
+                favorites_count "favorites_count!",
+                author_username "author_username!",
+                author_bio "author_bio!",
+                author_image,
+                exists(select 1 from follow where followed_user_id = author_id and following_user_id = $1) "following_author!"
+            from page
+            left join favorited using (article_id)
+            order by rank desc, created_at desc, article_id
+        "#,
+        viewer as Option<UserId>,
+        query,
+        limit,
+        offset,
+        muted
+    )
+    .fetch_all(instrument("articles::search", e))
+    .await
+}
+
+/// Count the articles matching `query`, as `search()` would find them.
+pub async fn count_search(
+    e: impl PgExecutor<'_>,
+    query: &str,
+    muted: &[String],
+) -> sqlx::Result<i64> {
+    sqlx::query_scalar!(
+        // language=PostgreSQL
+        r#"
+            select count(*) "count!"
+            from article
+            where search @@ websearch_to_tsquery('english', $1)
+              and
+            not exists(
+                select 1 from article_tag inner join tag using (tag_id)
+                where article_tag.article_id = article.article_id and name = any($2)
+            )
+              and
+            not exists(select 1 from article_takedown where article_id = article.article_id)
+        "#,
+        query,
+        muted
+    )
+    .fetch_one(instrument("articles::count_search", e))
+    .await
+}
+
+/// How similar part of an article's title has to be to a search for `suggest()` to suggest it,
+/// from 0 to 1. Passed to Postgres as a setting, hence the string.
+const WORD_SIMILARITY_THRESHOLD: &str = "0.4";
+
+/// Suggest up to `limit` tags and article titles that look like what `query` was meant to be,
+/// most similar first, for when `search()` finds little or nothing.
+///
+/// Tags are compared with the whole query, titles with the part of the title that's most like
+/// it, as a short query is never going to look much like a whole title. Anything that matches
+/// `query` exactly, ignoring case, is left out, as is anything the user would have found anyway
+/// if it weren't for `muted`.
+///
+/// This changes a setting for the duration of the query, so it runs in a transaction of its own.
+pub async fn suggest(
+    conn: &mut PgConnection,
+    query: &str,
+    muted: &[String],
+    limit: i64,
+) -> sqlx::Result<Vec<String>> {
+    // `%` and `<%` are `similarity()` and `word_similarity()` above their thresholds, the
+    // `pg_trgm.*similarity_threshold` settings. They're what can use the trigram indexes, see
+    // `migrations/24_search_suggestions.sql`; the functions can't.
+    //
+    // The default threshold for `similarity()`, 0.3, is fine for tags. The one for
+    // `word_similarity()` is 0.6, which a single wrong letter in a seven-letter word already
+    // falls short of, so we lower it. The `true` makes it only last until the end of the
+    // transaction, so it doesn't stick to the connection when it goes back into the pool.
+    let mut tx = conn.begin().await?;
+
+    sqlx::query_scalar!(
+        r#"select set_config('pg_trgm.word_similarity_threshold', $1, true) "threshold!""#,
+        WORD_SIMILARITY_THRESHOLD
+    )
+    .fetch_one(instrument("articles::suggest", &mut *tx))
+    .await?;
+
+    // The query goes in as typed, search operators and all. A `-` or a pair of quotes makes little
+    // difference to which trigrams it has.
+    let suggestions = sqlx::query_scalar!(
+        // language=PostgreSQL
+        r#"
+            select suggestion "suggestion!"
+            from (
+                select name suggestion, similarity(name, $1) score
+                from tag
+                where name % $1
+                    and articles_count > 0
+                    and name <> all($2)
+                union all
+                select title, word_similarity($1, title)
+                from article
+                where $1 <% title
+                    and not exists(select 1 from article_takedown where article_id = article.article_id)
+                    and not exists(
+                        select 1 from article_tag inner join tag using (tag_id)
+                        where article_tag.article_id = article.article_id and name = any($2)
+                    )
+            ) candidate
+            where lower(suggestion) <> lower($1)
+            -- Two articles can have the same title, or a tag be the same as a title.
+            group by suggestion
+            order by max(score) desc, suggestion
+            limit $3
+        "#,
+        query,
+        muted,
+        limit
+    )
+    .fetch_all(instrument("articles::suggest", &mut *tx))
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(suggestions)
+}
+
+/// How many articles have any of the tags in `from`, for a dry run of `merge_tags()`.
+pub async fn count_tagged(e: impl PgExecutor<'_>, from: &[String]) -> sqlx::Result<i64> {
+    sqlx::query_scalar!(
+        r#"
+            select count(distinct article_id) "count!"
+            from article_tag
+            inner join tag using (tag_id)
+            where name = any($1)
+        "#,
+        from
+    )
+    .fetch_one(instrument("articles::count_tagged", e))
+    .await
+}
+
+/// Replace the tags in `from` with `to` on every article that has any of them, returning the
+/// slugs of the articles that changed.
+///
+/// `from` must not include `to`. An article that had more than one of them only gets `to` once.
+/// This bumps the articles' `updated_at`, which is a little misleading as their authors didn't
+/// touch them, but it's also what tells clients their copies are out of date.
+///
+/// The tags in `from` are left with no articles, so they drop out of `tags::list()`.
+pub async fn merge_tags(
+    e: impl PgExecutor<'_>,
+    from: &[String],
+    to: &str,
+) -> sqlx::Result<Vec<String>> {
+    // As in `create()`, `to` may be new, and we need its ID either way. All the parts see the
+    // tables as they were before the query, so an article that already has `to` just hits the
+    // `on conflict`.
+    sqlx::query_scalar!(
+        // language=PostgreSQL
+        r#"
+            with
+                to_tag as (
+                    insert into tag (name)
+                    values ($2)
+                    on conflict (name) do update set name = excluded.name
+                    returning tag_id
+                ),
+                deleted as (
+                    delete from article_tag
+                    where tag_id in (select tag_id from tag where name = any($1))
+                    returning article_id
+                ),
+                inserted as (
+                    insert into article_tag (article_id, tag_id)
+                    select distinct article_id, tag_id from deleted, to_tag
+                    on conflict do nothing
+                )
+            update article
+            set updated_at = now()
+            where article_id in (select article_id from deleted)
+            returning slug
+        "#,
+        from,
+        to
+    )
+    .fetch_all(instrument("articles::merge_tags", e))
+    .await
+}
+
+#[test]
+fn test_parse_plan_rows() {
+    assert_eq!(
+        parse_plan_rows("Hash Join  (cost=1.09..2.20 rows=4 width=0)"),
+        Some(4)
+    );
+    assert_eq!(
+        parse_plan_rows("Seq Scan on article  (cost=0.00..186418.00 rows=4500000 width=4)"),
+        Some(4500000)
+    );
+    assert_eq!(parse_plan_rows("Result  (cost=0.00..0.01 width=4)"), None);
+}
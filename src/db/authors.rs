@@ -0,0 +1,184 @@
+use sqlx::PgExecutor;
+use time::OffsetDateTime;
+
+use crate::db::instrument::instrument;
+use crate::db::types::{ArticleId, InvitationId, UserId};
+
+/// An invitation to co-author an article that hasn't been answered yet.
+pub struct Invitation {
+    pub invitation_id: InvitationId,
+    pub article_slug: String,
+    pub article_title: String,
+    pub inviter_username: String,
+    pub invitee_username: String,
+    pub created_at: OffsetDateTime,
+}
+
+/// Check whether `user_id` is a co-author of `article_id`.
+///
+/// This doesn't include the article's author, who the caller can check against
+/// `ArticleMeta::user_id` without a query.
+pub async fn is_coauthor(
+    e: impl PgExecutor<'_>,
+    article_id: ArticleId,
+    user_id: UserId,
+) -> sqlx::Result<bool> {
+    sqlx::query_scalar!(
+        r#"
+            select exists(
+                select 1 from article_author where article_id = $1 and user_id = $2
+            ) "exists!"
+        "#,
+        article_id as ArticleId,
+        user_id as UserId
+    )
+    .fetch_one(instrument("authors::is_coauthor", e))
+    .await
+}
+
+/// How many invitations to co-author `article_id` are waiting for an answer.
+pub async fn count_pending(e: impl PgExecutor<'_>, article_id: ArticleId) -> sqlx::Result<i64> {
+    sqlx::query_scalar!(
+        r#"select count(*) "count!" from article_author_invitation where article_id = $1"#,
+        article_id as ArticleId
+    )
+    .fetch_one(instrument("authors::count_pending", e))
+    .await
+}
+
+/// Invite `invitee` to co-author `article_id`.
+///
+/// Fails with a violation of `article_author_invitation_article_id_invitee_id_key` if they've
+/// already been invited and haven't answered yet.
+pub async fn invite(
+    e: impl PgExecutor<'_>,
+    article_id: ArticleId,
+    inviter: UserId,
+    invitee: UserId,
+) -> sqlx::Result<Invitation> {
+    sqlx::query_as!(
+        Invitation,
+        // language=PostgreSQL
+        r#"
+            with inserted as (
+                insert into article_author_invitation (article_id, inviter_id, invitee_id)
+                values ($1, $2, $3)
+                returning invitation_id, article_id, inviter_id, invitee_id, created_at
+            )
+            -- As in `articles::list()`, SQLx can't see through the CTE, so we tell it none of these
+            -- can be null.
+            select
+                invitation_id "invitation_id!: InvitationId",
+                article.slug "article_slug!",
+                article.title "article_title!",
+                inviter.username "inviter_username!",
+                invitee.username "invitee_username!",
+                inserted.created_at "created_at!"
+            from inserted
+            inner join article using (article_id)
+            inner join "user" inviter on inviter.user_id = inviter_id
+            inner join "user" invitee on invitee.user_id = invitee_id
+        "#,
+        article_id as ArticleId,
+        inviter as UserId,
+        invitee as UserId
+    )
+    .fetch_one(instrument("authors::invite", e))
+    .await
+}
+
+/// List the invitations `invitee` hasn't answered yet, oldest first.
+///
+/// Invitations to articles that have since been taken down are left out, since accepting them
+/// would only fail.
+pub async fn list_invitations(
+    e: impl PgExecutor<'_>,
+    invitee: UserId,
+) -> sqlx::Result<Vec<Invitation>> {
+    sqlx::query_as!(
+        Invitation,
+        // language=PostgreSQL
+        r#"
+            select
+                invitation_id "invitation_id: InvitationId",
+                article.slug article_slug,
+                article.title article_title,
+                inviter.username inviter_username,
+                invitee.username invitee_username,
+                invitation.created_at
+            from article_author_invitation invitation
+            inner join article using (article_id)
+            inner join "user" inviter on inviter.user_id = inviter_id
+            inner join "user" invitee on invitee.user_id = invitee_id
+            where invitee_id = $1
+                and not exists(select 1 from article_takedown where article_id = article.article_id)
+            order by invitation.created_at
+        "#,
+        invitee as UserId
+    )
+    .fetch_all(instrument("authors::list_invitations", e))
+    .await
+}
+
+/// Make `invitee` a co-author of the article they were invited to, returning its slug.
+///
+/// Returns `None` if there's no such invitation, or it's not theirs, or the article has been
+/// taken down.
+pub async fn accept(
+    e: impl PgExecutor<'_>,
+    invitation_id: InvitationId,
+    invitee: UserId,
+) -> sqlx::Result<Option<String>> {
+    // The `on conflict` is just in case; `invite()` shouldn't have let them be invited if they
+    // were a co-author already.
+    sqlx::query_scalar!(
+        // language=PostgreSQL
+        r#"
+            with accepted as (
+                delete from article_author_invitation
+                where invitation_id = $1
+                    and invitee_id = $2
+                    and not exists(
+                        select 1 from article_takedown
+                        where article_takedown.article_id = article_author_invitation.article_id
+                    )
+                returning article_id, invitee_id
+            ),
+            inserted as (
+                insert into article_author (article_id, user_id)
+                select article_id, invitee_id from accepted
+                on conflict do nothing
+            )
+            select slug "slug!"
+            from accepted
+            inner join article using (article_id)
+        "#,
+        invitation_id as InvitationId,
+        invitee as UserId
+    )
+    .fetch_optional(instrument("authors::accept", e))
+    .await
+}
+
+/// Delete an invitation, but only if `user_id` sent or received it.
+///
+/// That's declining it for the invitee, and taking it back for the inviter. Returns `false` if
+/// there's no such invitation or it's neither of theirs.
+pub async fn delete_invitation(
+    e: impl PgExecutor<'_>,
+    invitation_id: InvitationId,
+    user_id: UserId,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+            delete from article_author_invitation
+            where invitation_id = $1 and (invitee_id = $2 or inviter_id = $2)
+        "#,
+        invitation_id as InvitationId,
+        user_id as UserId
+    )
+    .execute(instrument("authors::delete_invitation", e))
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
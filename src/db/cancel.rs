@@ -0,0 +1,122 @@
+use futures::future::BoxFuture;
+use metrics::{describe_counter, increment_counter};
+use sqlx::pool::PoolConnection;
+use sqlx::{Connection, PgConnection, PgPool, Postgres};
+
+use crate::db::instrument::instrument;
+
+// When a client gives up on a request (closes the tab, hits a timeout, navigates away), Hyper
+// drops the future handling it, and with it any query future it was waiting on. That stops us
+// waiting for the results, but not Postgres working them out: as far as the server knows, the
+// connection is still there and someone wants the answer. SQLx just reads (and throws away)
+// whatever's left the next time the connection is used. For the cheap queries that's nothing
+// to worry about, but a search or a listing with an unusual combination of filters can keep
+// a backend busy for a while after anyone cares about the result.
+//
+// Postgres does have a way to cancel a query, but SQLx (as of 0.5) doesn't expose it, so we do
+// it the long way round: `Cancellable` notes the backend PID of its connection up front, and if
+// it's dropped while a query is running, asks another connection from the same pool to
+// `pg_cancel_backend()` it.
+//
+// The connection itself is never put back in the pool after that. The query may have finished
+// in the meantime, in which case the cancel might otherwise land on whichever query the next
+// user of the connection runs.
+//
+// This costs a round-trip for the PID every time, so it's only for the queries that could be
+// slow, not everything.
+
+/// A connection whose query is cancelled if this is dropped while it's running.
+///
+/// Get one with `Db::cancellable()`, and run queries with `run()`.
+pub struct Cancellable {
+    /// Only `None` once it's been dropped.
+    conn: Option<PoolConnection<Postgres>>,
+    /// The pool `conn` came from, which can reach the same server to cancel its query.
+    pool: PgPool,
+    /// The backend PID of `conn`, or `None` if we can't cancel its queries, see `Db::connect()`.
+    pid: Option<i32>,
+    /// Whether `run()` is waiting on a query right now.
+    in_flight: bool,
+}
+
+/// Register descriptions for the metrics in this module, see `instrument::describe_metrics()`.
+pub fn describe_metrics() {
+    describe_counter!(
+        "db_queries_cancelled_total",
+        "How many queries were cancelled because the request waiting on them went away."
+    );
+}
+
+impl Cancellable {
+    pub(super) async fn acquire(pool: &PgPool, can_cancel: bool) -> sqlx::Result<Self> {
+        let mut conn = pool.acquire().await?;
+
+        let pid = if can_cancel {
+            Some(
+                sqlx::query_scalar!(r#"select pg_backend_pid() "pid!""#)
+                    .fetch_one(instrument("cancel::backend_pid", &mut conn))
+                    .await?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Cancellable {
+            conn: Some(conn),
+            pool: pool.clone(),
+            pid,
+            in_flight: false,
+        })
+    }
+
+    /// Run `query` on the connection, e.g.
+    /// `conn.run(|conn| Box::pin(articles::list(conn, viewer, &filter)))`.
+    ///
+    /// If this is dropped before it finishes, the query is cancelled.
+    pub async fn run<'c, T>(
+        &'c mut self,
+        query: impl FnOnce(&'c mut PgConnection) -> BoxFuture<'c, sqlx::Result<T>>,
+    ) -> sqlx::Result<T> {
+        // Borrowing the fields separately lets us get at `in_flight` while `query` still has
+        // the connection.
+        let Cancellable {
+            conn, in_flight, ..
+        } = self;
+
+        let conn = conn.as_mut().expect("connection taken before drop");
+
+        *in_flight = true;
+        let result = query(conn).await;
+        *in_flight = false;
+
+        result
+    }
+}
+
+impl Drop for Cancellable {
+    fn drop(&mut self) {
+        let (pid, conn) = match (self.in_flight, self.pid, self.conn.take()) {
+            (true, Some(pid), Some(conn)) => (pid, conn),
+            _ => return,
+        };
+
+        let conn = conn.detach();
+        let pool = self.pool.clone();
+
+        increment_counter!("db_queries_cancelled_total");
+
+        tokio::spawn(async move {
+            // `pg_cancel_backend()` returns `false` if there was nothing to cancel, which is fine.
+            if let Err(e) = sqlx::query_scalar!(r#"select pg_cancel_backend($1) "cancelled!""#, pid)
+                .fetch_one(instrument("cancel::cancel_backend", &pool))
+                .await
+            {
+                log::warn!("failed to cancel the query on backend {}: {}", pid, e);
+            }
+
+            if let Err(e) = conn.close().await {
+                log::debug!("failed to close connection to backend {}: {}", pid, e);
+            }
+        });
+    }
+}
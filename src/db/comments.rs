@@ -0,0 +1,174 @@
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use sqlx::{PgExecutor, PgPool};
+use time::OffsetDateTime;
+
+use crate::db::instrument::instrument;
+use crate::db::types::{CommentId, UserId};
+use crate::db::{Deleted, Recent};
+
+/// A comment with its author's profile flattened in, see `articles::Article` for why.
+pub struct Comment {
+    pub comment_id: CommentId,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+    pub body: String,
+    pub author_username: String,
+    pub author_bio: String,
+    pub author_image: Option<String>,
+    pub following_author: bool,
+}
+
+/// How many comments `stream_for_article()` reads ahead of the caller.
+const STREAM_BUFFER: usize = 64;
+
+/// All comments on the article with the given slug, oldest first, as seen by `viewer`.
+///
+/// This returns an empty stream if there's no such article, so check for that separately.
+/// It can be done at the same time, as this starts running the query straight away.
+///
+/// A popular article can rack up thousands of comments and the Realworld spec doesn't paginate
+/// them, so rather than collecting them all into a `Vec` first, this passes them on as Postgres
+/// sends them back. Only up to `STREAM_BUFFER` of them are in memory at a time.
+///
+/// The stream returned by `fetch()` borrows the connection it's running on, which makes it
+/// impossible to hand back to the caller along with the pool. So instead the query runs in a task
+/// of its own, holding onto a connection from `db` until the caller drops the receiver or
+/// reads the last comment.
+pub fn stream_for_article(
+    db: PgPool,
+    slug: String,
+    viewer: Option<UserId>,
+) -> mpsc::Receiver<sqlx::Result<Comment>> {
+    let (mut tx, rx) = mpsc::channel(STREAM_BUFFER);
+
+    tokio::spawn(async move {
+        let mut comments = sqlx::query_as!(
+            Comment,
+            r#"
+                select
+                    comment_id "comment_id: CommentId",
+                    comment.created_at,
+                    comment.updated_at,
+                    comment.body,
+                    author.username author_username,
+                    author.bio author_bio,
+                    author.image author_image,
+                    exists(select 1 from follow where followed_user_id = author.user_id and following_user_id = $1) "following_author!"
+                from article_comment comment
+                inner join "user" author using (user_id)
+                where article_id = (select article_id from article where slug = $2)
+                order by created_at
+            "#,
+            viewer as Option<UserId>,
+            slug
+        )
+        .fetch(instrument("comments::stream_for_article", &db));
+
+        while let Some(comment) = comments.next().await {
+            let failed = comment.is_err();
+
+            // If this fails, the caller went away (e.g. the client disconnected), so there's
+            // no point fetching the rest.
+            if tx.send(comment).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// Add a comment to the article with the given slug.
+///
+/// Returns `None` if there's no such article.
+pub async fn create(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+    author: UserId,
+    body: &str,
+) -> sqlx::Result<Option<Comment>> {
+    sqlx::query_as!(
+        Comment,
+        r#"
+            with inserted_comment as (
+                insert into article_comment(article_id, user_id, body)
+                select article_id, $1, $2
+                from article
+                where slug = $3
+                returning comment_id, created_at, updated_at, body
+            )
+            select
+                comment_id "comment_id: CommentId",
+                comment.created_at,
+                comment.updated_at,
+                body,
+                author.username author_username,
+                author.bio author_bio,
+                author.image author_image,
+                false "following_author!"
+            from inserted_comment comment
+            inner join "user" author on user_id = $1
+        "#,
+        author as UserId,
+        body,
+        slug
+    )
+    .fetch_optional(instrument("comments::create", e))
+    .await
+}
+
+/// Count the comments `author` has posted since `since`, on any article, for their quota.
+pub async fn count_recent(
+    e: impl PgExecutor<'_>,
+    author: UserId,
+    since: OffsetDateTime,
+) -> sqlx::Result<Recent> {
+    sqlx::query_as!(
+        Recent,
+        r#"
+            select count(*) "count!", min(created_at) oldest
+            from article_comment
+            where user_id = $1 and created_at > $2
+        "#,
+        author as UserId,
+        since
+    )
+    .fetch_one(instrument("comments::count_recent", e))
+    .await
+}
+
+/// Delete a comment on the article with the given slug, but only if `user_id` wrote it.
+pub async fn delete(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+    comment_id: CommentId,
+    user_id: UserId,
+) -> sqlx::Result<Deleted> {
+    // Identical technique to `articles::delete()`
+    sqlx::query_as!(
+        Deleted,
+        r#"
+            with deleted_comment as (
+                delete from article_comment
+                where
+                    comment_id = $1
+                    and article_id in (select article_id from article where slug = $2)
+                    and user_id = $3
+                returning 1
+            )
+            select
+                exists(
+                    select 1 from article_comment
+                    inner join article using (article_id)
+                    where comment_id = $1 and slug = $2
+                ) "existed!",
+                exists(select 1 from deleted_comment) "deleted!"
+        "#,
+        comment_id as CommentId,
+        slug,
+        user_id as UserId
+    )
+    .fetch_one(instrument("comments::delete", e))
+    .await
+}
@@ -0,0 +1,86 @@
+use sqlx::PgExecutor;
+use time::OffsetDateTime;
+
+use crate::db::instrument::instrument;
+
+/// A distinct Content Security Policy violation, see `migrations/17_csp_report.sql`.
+pub struct CspReport {
+    pub document_uri: String,
+    pub violated_directive: String,
+    pub blocked_uri: String,
+    pub count: i64,
+    /// The latest report of this violation as the browser sent it, as JSON.
+    pub sample: String,
+    pub first_seen_at: OffsetDateTime,
+    pub last_seen_at: OffsetDateTime,
+}
+
+/// A violation to count with `record()`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct NewCspReport {
+    pub document_uri: String,
+    pub violated_directive: String,
+    pub blocked_uri: String,
+    /// The report as the browser sent it, as JSON.
+    pub sample: String,
+}
+
+/// How long after it was last reported we forget about a violation.
+const RETENTION_DAYS: i32 = 30;
+
+/// Count a violation, and forget any that haven't been reported in `RETENTION_DAYS`.
+///
+/// Forgetting them here rather than in a background task means there's nothing to keep
+/// running; it's an index scan that almost always finds nothing.
+pub async fn record(e: impl PgExecutor<'_>, report: &NewCspReport) -> sqlx::Result<()> {
+    // SQLx can't encode `serde_json::Value` without its `json` feature, which we don't otherwise
+    // need, so the sample goes over as text and Postgres parses it.
+    sqlx::query!(
+        r#"
+            with forgotten as (
+                delete from csp_report
+                where last_seen_at < now() - $5::int4 * interval '1 day'
+            )
+            insert into csp_report (document_uri, violated_directive, blocked_uri, sample)
+            values ($1, $2, $3, $4::text::jsonb)
+            on conflict (document_uri, violated_directive, blocked_uri) do update set
+                count = csp_report.count + 1,
+                sample = excluded.sample,
+                last_seen_at = now()
+        "#,
+        report.document_uri,
+        report.violated_directive,
+        report.blocked_uri,
+        report.sample,
+        RETENTION_DAYS,
+    )
+    .execute(instrument("csp_reports::record", e))
+    .await?;
+
+    Ok(())
+}
+
+/// List the violations reported recently, most recently reported first.
+pub async fn list(e: impl PgExecutor<'_>, limit: i64, offset: i64) -> sqlx::Result<Vec<CspReport>> {
+    sqlx::query_as!(
+        CspReport,
+        r#"
+            select
+                document_uri,
+                violated_directive,
+                blocked_uri,
+                count,
+                sample::text "sample!",
+                first_seen_at,
+                last_seen_at
+            from csp_report
+            order by last_seen_at desc, document_uri, violated_directive, blocked_uri
+            limit $1
+            offset $2
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(instrument("csp_reports::list", e))
+    .await
+}
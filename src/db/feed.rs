@@ -0,0 +1,244 @@
+use std::time::Duration;
+
+use metrics::counter;
+use sqlx::PgExecutor;
+use time::OffsetDateTime;
+
+use crate::db::articles::{Article, Cursor};
+use crate::db::instrument::instrument;
+use crate::db::types::{ArticleId, UserId};
+use crate::db::{password_resets, Db};
+
+// The feed is materialized in the `feed_entry` table, which triggers keep up to date as articles
+// are published and users follow and unfollow each other. See `migrations/7_feed_entry.sql`.
+
+/// The most entries we keep in any one user's feed.
+///
+/// Nobody scrolls back through a thousand articles in their feed, and without a cap a user
+/// following a lot of prolific authors would keep accumulating rows forever.
+///
+/// The `feed_entry_followed()` trigger backfills this many articles at most when following
+/// someone, so keep the two in sync.
+pub const MAX_ENTRIES_PER_USER: i64 = 1000;
+
+/// How often `prune_periodically()` trims the feeds down to `MAX_ENTRIES_PER_USER`.
+///
+/// Feeds can go over the limit in between, which doesn't hurt anything.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// List articles by the authors that `user_id` follows, newest first, leaving out any with one
+/// of the `muted` tags.
+///
+/// If `cursor` is given, this starts after it, otherwise from the newest article.
+/// `offset` is applied after that, and is only here because the Realworld spec calls for it.
+///
+/// Feeds are capped at `MAX_ENTRIES_PER_USER`, so an `offset` past that is always an empty page,
+/// which this returns without asking the database.
+pub async fn list(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+    muted: &[String],
+    cursor: Option<Cursor>,
+    limit: i64,
+    offset: i64,
+) -> sqlx::Result<Vec<Article>> {
+    // A feed can go over the cap in between prunes, but those extra entries are the oldest ones,
+    // which are about to be deleted anyway.
+    if offset >= MAX_ENTRIES_PER_USER {
+        return Ok(vec![]);
+    }
+
+    let (cursor_created_at, cursor_article_id) = match cursor {
+        Some(cursor) => (Some(cursor.created_at), Some(cursor.article_id)),
+        None => (None, None),
+    };
+
+    sqlx::query_as!(
+        Article,
+        // As a rule of thumb, you always want the most specific dataset to be your outermost
+        // `SELECT` so the query planner does as little extraneous work as possible, and then
+        // your joins are just fetching data related to rows you already know you're returning.
+        //
+        // Here we take that one step further: the page is picked from `feed_entry` alone,
+        // in a CTE, and only then joined with `article` and `user`. Every column it needs
+        // is in the index, so skipping over `offset` rows only reads index entries, rather than
+        // doing both joins for each row just to throw the result away.
+        //
+        // Likewise, `favorited` is looked up for the whole page at once, see `articles::list()`.
+        //
+        // The row comparison `(a, b) < (c, d)` is what makes the cursor work: it compares
+        // `created_at` first and only looks at `article_id` to break ties, which matches
+        // the index on `feed_entry` exactly.
+        //
+        // language=PostgreSQL
+        r#"
+            with page as (
+                select article_id, created_at
+                from feed_entry
+                where user_id = $1
+                    and (
+                        $2::timestamptz is null
+                        or (created_at, article_id) < ($2, $3)
+                    )
+                    and not exists(
+                        select 1 from article_takedown
+                        where article_takedown.article_id = feed_entry.article_id
+                    )
+                    -- Checking for muted tags means looking up the article's tags, so we only
+                    -- do that if there are any.
+                    and (
+                        cardinality($6::text[]) = 0
+                        or not exists(
+                            select 1 from article_tag
+                            inner join tag using (tag_id)
+                            where article_tag.article_id = feed_entry.article_id
+                                and name = any($6)
+                        )
+                    )
+                order by created_at desc, article_id desc
+                limit $4
+                offset $5
+            ),
+            favorited as (
+                select article_id
+                from article_favorite
+                where user_id = $1 and article_id = any(array(select article_id from page))
+            )
+            -- SQLx can't see through the CTE to tell that none of these can be null,
+            -- so we tell it with `!`.
+            select
+                article.article_id "article_id!: ArticleId",
+                slug "slug!",
+                title "title!",
+                description "description!",
+                body "body!",
+                article_tags(article.article_id) "tag_list!",
+                article.created_at "created_at!",
+                article.updated_at "updated_at!",
+                favorited.article_id is not null "favorited!",
+                favorites_count "favorites_count!",
+                author.username "author_username!",
+                author.bio "author_bio!",
+                author.image author_image,
+                -- we wouldn't be returning this otherwise
+                true "following_author!"
+            from page
+            inner join article using (article_id)
+            inner join "user" author on author.user_id = article.user_id
+            left join favorited on favorited.article_id = page.article_id
+            -- Joining doesn't preserve the order of `page`, so we have to sort again,
+            -- but only `limit` rows this time.
+            order by page.created_at desc, page.article_id desc
+        "#,
+        user_id as UserId,
+        cursor_created_at,
+        cursor_article_id as Option<ArticleId>,
+        limit,
+        offset,
+        muted
+    )
+    .fetch_all(instrument("feed::list", e))
+    .await
+}
+
+/// Count the articles in `user_id`'s feed, as `list()` would return them, leaving out any with
+/// one of the `muted` tags.
+///
+/// This is at most `MAX_ENTRIES_PER_USER`, like the feed itself, so it only ever has to count
+/// that many index entries.
+pub async fn count(e: impl PgExecutor<'_>, user_id: UserId, muted: &[String]) -> sqlx::Result<i64> {
+    // The filters here need to stay the same as in `list()`, or the count won't match.
+    sqlx::query_scalar!(
+        // language=PostgreSQL
+        r#"
+            select least(count(*), $3) "count!"
+            from feed_entry
+            where user_id = $1
+                and not exists(
+                    select 1 from article_takedown
+                    where article_takedown.article_id = feed_entry.article_id
+                )
+                and (
+                    cardinality($2::text[]) = 0
+                    or not exists(
+                        select 1 from article_tag
+                        inner join tag using (tag_id)
+                        where article_tag.article_id = feed_entry.article_id
+                            and name = any($2)
+                    )
+                )
+        "#,
+        user_id as UserId,
+        muted,
+        MAX_ENTRIES_PER_USER
+    )
+    .fetch_one(instrument("feed::count", e))
+    .await
+}
+
+/// Trim every user's feed down to its newest `max_entries` entries.
+///
+/// Returns the number of entries deleted.
+pub async fn prune(e: impl PgExecutor<'_>, max_entries: i64) -> sqlx::Result<u64> {
+    // This reads the whole table, but only once an hour, and the index on
+    // `(user_id, created_at desc, article_id desc)` means it doesn't have to sort anything.
+    let res = sqlx::query!(
+        r#"
+            delete from feed_entry
+            using (
+                select user_id, article_id
+                from (
+                    select
+                        user_id,
+                        article_id,
+                        row_number() over (
+                            partition by user_id
+                            order by created_at desc, article_id desc
+                        ) position
+                    from feed_entry
+                ) ranked
+                where position > $1
+            ) old
+            where feed_entry.user_id = old.user_id and feed_entry.article_id = old.article_id
+        "#,
+        max_entries
+    )
+    .execute(instrument("feed::prune", e))
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Spawn a task that calls `prune()` every `PRUNE_INTERVAL` for as long as the application is
+/// running, along with `password_resets::prune()`, which is another hourly cleanup that there's
+/// no need for a task of its own for.
+///
+/// Every instance of the application does this, which is harmless; whichever gets there first
+/// does the work and the rest find nothing to delete.
+pub fn prune_periodically(db: Db) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match prune(db.primary(), MAX_ENTRIES_PER_USER).await {
+                Ok(0) => (),
+                Ok(deleted) => {
+                    log::info!("pruned {} old feed entries", deleted);
+                    counter!("db_rows_pruned_total", deleted, "table" => "feed_entry");
+                }
+                Err(e) => log::error!("failed to prune feeds: {}", e),
+            }
+
+            match password_resets::prune(db.primary(), OffsetDateTime::now_utc()).await {
+                Ok(0) => (),
+                Ok(deleted) => {
+                    log::info!("pruned {} old password reset tokens", deleted);
+                    counter!("db_rows_pruned_total", deleted, "table" => "password_reset_token");
+                }
+                Err(e) => log::error!("failed to prune password reset tokens: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,522 @@
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash};
+use itertools::Itertools;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+// Factories for fake users, articles and comments, for setting up tests and for the `seed`
+// subcommand.
+//
+// Each factory starts out with plausible random values for everything, so a test only has to
+// spell out the parts it actually cares about:
+//
+// ```rust,ignore
+// let mut fixtures = Fixtures::new(0);
+// let alice = fixtures.user().username("alice").insert(&db).await?;
+// fixtures.article(&alice).with_tags(&["rust"]).insert(&db).await?;
+// ```
+//
+// The randomness all comes from one seeded RNG, so the same seed and the same sequence of calls
+// always produces the same data, which keeps tests and load tests repeatable.
+//
+// Rows are inserted directly, without going through the API, which is a lot faster when a test
+// needs a lot of them. `seed` goes one step further and only `build()`s them here, then inserts
+// them in bulk; see `db::seed`.
+
+/// The password of every generated user, so you can log in as any of them.
+///
+/// Obviously, never run `seed` against a production database.
+pub const DEMO_PASSWORD: &str = "password";
+
+/// The source of all the factories, see the module docs.
+pub struct Fixtures {
+    rng: ChaCha8Rng,
+    seed: u64,
+    users: usize,
+    articles: usize,
+    /// The hash of `DEMO_PASSWORD`, computed the first time it's needed since it's slow.
+    password_hash: Option<String>,
+}
+
+/// A user as generated by `UserFactory`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub bio: String,
+}
+
+/// An article as generated by `ArticleFactory`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewArticle {
+    pub article_id: Uuid,
+    pub user_id: Uuid,
+    pub slug: String,
+    pub title: String,
+    pub description: String,
+    pub body: String,
+    /// Sorted, the same as the API stores them.
+    pub tags: Vec<String>,
+    /// How long ago the article was published.
+    pub age_secs: f64,
+}
+
+/// A comment as generated by `CommentFactory`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewComment {
+    pub article_id: Uuid,
+    pub user_id: Uuid,
+    pub body: String,
+    /// How long ago the comment was posted. Never more than the article's age.
+    pub age_secs: f64,
+}
+
+/// Builds a `NewUser`, see `Fixtures::user()`.
+pub struct UserFactory<'a> {
+    fixtures: &'a mut Fixtures,
+    user: NewUser,
+    password: Option<String>,
+}
+
+/// Builds a `NewArticle`, see `Fixtures::article()`.
+pub struct ArticleFactory {
+    article: NewArticle,
+    /// The number that keeps the slug unique if the title is changed.
+    index: usize,
+    seed: u64,
+    slug_overridden: bool,
+}
+
+/// Builds a `NewComment`, see `Fixtures::comment()`.
+pub struct CommentFactory {
+    comment: NewComment,
+}
+
+// These lists don't need to be long, just long enough that the generated data
+// doesn't look *too* repetitive.
+
+const ADJECTIVES: &[&str] = &[
+    "quick", "lazy", "sleepy", "brave", "clever", "fuzzy", "gentle", "happy", "jolly", "mighty",
+    "nimble", "proud", "quiet", "rusty", "shiny", "witty",
+];
+
+const ANIMALS: &[&str] = &[
+    "otter", "badger", "crab", "falcon", "ferret", "gecko", "heron", "koala", "lemur", "marmot",
+    "narwhal", "ocelot", "panda", "quokka", "raven", "walrus",
+];
+
+const TOPICS: &[&str] = &[
+    "async Rust",
+    "database indexes",
+    "error handling",
+    "code review",
+    "technical debt",
+    "connection pooling",
+    "type systems",
+    "pair programming",
+    "on-call rotations",
+    "API design",
+    "property testing",
+    "migrations",
+];
+
+const TITLE_TEMPLATES: &[&str] = &[
+    "How I learned to stop worrying and love {}",
+    "Everything you know about {} is wrong",
+    "A gentle introduction to {}",
+    "{} in production: lessons learned",
+    "Why we rewrote our {} from scratch",
+    "The case against {}",
+    "Ten things I wish I knew about {}",
+];
+
+const TAGS: &[&str] = &[
+    "rust",
+    "postgres",
+    "sql",
+    "axum",
+    "webdev",
+    "backend",
+    "programming",
+    "tutorial",
+    "career",
+    "devops",
+    "testing",
+    "performance",
+    "security",
+    "opinion",
+];
+
+const SENTENCES: &[&str] = &[
+    "It all started with a bug report that nobody could reproduce.",
+    "In hindsight, the signs were there all along.",
+    "The first attempt was, predictably, a disaster.",
+    "It turns out the answer was in the documentation the whole time.",
+    "Benchmarks don't lie, but they don't tell the whole truth either.",
+    "We shipped it on a Friday, which was our first mistake.",
+    "The compiler was right, as usual.",
+    "Nobody remembers who wrote that code, and `git blame` isn't talking.",
+    "After a week of profiling, the culprit was a single missing index.",
+    "Your mileage may vary, but this worked for us.",
+];
+
+const COMMENTS: &[&str] = &[
+    "Great article, thanks for sharing!",
+    "I ran into the exact same problem last month.",
+    "Have you considered just using Postgres for this?",
+    "This is the way.",
+    "Strongly disagree, but well argued.",
+    "Bookmarked for later.",
+    "Could you share the benchmarks?",
+    "This should be required reading.",
+];
+
+const MAX_AGE_SECS: f64 = 90.0 * 24.0 * 60.0 * 60.0;
+
+impl Fixtures {
+    pub fn new(seed: u64) -> Self {
+        Fixtures {
+            // `StdRng` isn't guaranteed to produce the same sequence across versions of `rand`,
+            // which would defeat the point of having a seed.
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            seed,
+            users: 0,
+            articles: 0,
+            password_hash: None,
+        }
+    }
+
+    /// The RNG the factories draw from, for anything else that should follow from the seed.
+    pub fn rng(&mut self) -> &mut ChaCha8Rng {
+        &mut self.rng
+    }
+
+    /// Start building a user with a random name, and the password `DEMO_PASSWORD`.
+    pub fn user(&mut self) -> UserFactory<'_> {
+        // The index keeps usernames unique, and the seed keeps them unique across different seeds.
+        let username = format!(
+            "{}_{}_{}_{}",
+            ADJECTIVES.choose(&mut self.rng).unwrap(),
+            ANIMALS.choose(&mut self.rng).unwrap(),
+            self.seed,
+            self.users
+        );
+        self.users += 1;
+
+        let user = NewUser {
+            user_id: uuid(&mut self.rng),
+            email: format!("{}@example.com", username),
+            bio: format!(
+                "Just a {} writing about software.",
+                username.replace('_', " ")
+            ),
+            username,
+        };
+
+        UserFactory {
+            fixtures: self,
+            user,
+            password: None,
+        }
+    }
+
+    /// Start building an article by `author` with a random title, body and tags.
+    pub fn article(&mut self, author: &NewUser) -> ArticleFactory {
+        let topic = TOPICS.choose(&mut self.rng).unwrap();
+        let title = TITLE_TEMPLATES
+            .choose(&mut self.rng)
+            .unwrap()
+            .replacen("{}", topic, 1);
+
+        let paragraphs = self.rng.gen_range(1..=4);
+        let body = (0..paragraphs)
+            .map(|_| {
+                SENTENCES
+                    .choose_multiple(&mut self.rng, 3)
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .join("\n\n");
+
+        let tag_count = self.rng.gen_range(0..=4);
+        let tags: Vec<&str> = TAGS
+            .choose_multiple(&mut self.rng, tag_count)
+            .copied()
+            .collect();
+
+        let index = self.articles;
+        self.articles += 1;
+
+        ArticleFactory {
+            article: NewArticle {
+                article_id: uuid(&mut self.rng),
+                user_id: author.user_id,
+                slug: String::new(),
+                description: format!("Some thoughts on {}.", topic),
+                title: String::new(),
+                body,
+                tags: vec![],
+                age_secs: self.rng.gen_range(0.0..MAX_AGE_SECS),
+            },
+            index,
+            seed: self.seed,
+            slug_overridden: false,
+        }
+        .title(&title)
+        .with_tags(&tags)
+    }
+
+    /// Start building a comment by `author` on `article`, with a random body.
+    pub fn comment(&mut self, article: &NewArticle, author: &NewUser) -> CommentFactory {
+        CommentFactory {
+            comment: NewComment {
+                article_id: article.article_id,
+                user_id: author.user_id,
+                body: COMMENTS.choose(&mut self.rng).unwrap().to_string(),
+                // Comments shouldn't be older than the article they're on.
+                age_secs: self.rng.gen_range(0.0..=article.age_secs),
+            },
+        }
+    }
+
+    /// The hash of `DEMO_PASSWORD`.
+    ///
+    /// Argon2 is slow on purpose, so we only hash it once per `Fixtures`; with thousands of
+    /// users, hashing each one's password separately would take minutes.
+    pub fn demo_password_hash(&mut self) -> anyhow::Result<String> {
+        if let Some(hash) = &self.password_hash {
+            return Ok(hash.clone());
+        }
+
+        let hash = hash_password(DEMO_PASSWORD)?;
+        self.password_hash = Some(hash.clone());
+        Ok(hash)
+    }
+}
+
+impl UserFactory<'_> {
+    pub fn username(mut self, username: &str) -> Self {
+        self.user.username = username.to_string();
+        self.user.email = format!("{}@example.com", username);
+        self
+    }
+
+    pub fn email(mut self, email: &str) -> Self {
+        self.user.email = email.to_string();
+        self
+    }
+
+    pub fn bio(mut self, bio: &str) -> Self {
+        self.user.bio = bio.to_string();
+        self
+    }
+
+    /// Use a password other than `DEMO_PASSWORD`. Only affects `insert()`.
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    pub fn build(self) -> NewUser {
+        self.user
+    }
+
+    pub async fn insert(self, e: impl PgExecutor<'_>) -> anyhow::Result<NewUser> {
+        // We're not serving requests here, so there's no need to move this to a blocking thread.
+        let password_hash = match &self.password {
+            Some(password) => hash_password(password)?,
+            None => self.fixtures.demo_password_hash()?,
+        };
+
+        let user = self.user;
+
+        sqlx::query!(
+            r#"
+                insert into "user" (user_id, username, email, bio, password_hash)
+                values ($1, $2, $3, $4, $5)
+            "#,
+            user.user_id,
+            user.username,
+            user.email,
+            user.bio,
+            password_hash
+        )
+        .execute(e)
+        .await?;
+
+        Ok(user)
+    }
+}
+
+impl ArticleFactory {
+    /// Set the title, and the slug to go with it unless it's been set explicitly.
+    pub fn title(mut self, title: &str) -> Self {
+        self.article.title = title.to_string();
+
+        if !self.slug_overridden {
+            self.article.slug = format!(
+                "{}-{}-{}",
+                title
+                    .to_lowercase()
+                    .split(|c: char| !c.is_ascii_alphanumeric())
+                    .filter(|s| !s.is_empty())
+                    .join("-"),
+                self.seed,
+                self.index
+            );
+        }
+
+        self
+    }
+
+    pub fn slug(mut self, slug: &str) -> Self {
+        self.article.slug = slug.to_string();
+        self.slug_overridden = true;
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.article.description = description.to_string();
+        self
+    }
+
+    pub fn body(mut self, body: &str) -> Self {
+        self.article.body = body.to_string();
+        self
+    }
+
+    pub fn with_tags(mut self, tags: &[&str]) -> Self {
+        self.article.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        // Tags are stored sorted, see `create_article()`.
+        self.article.tags.sort_unstable();
+        self.article.tags.dedup();
+        self
+    }
+
+    /// Set how long ago the article was published.
+    pub fn age_secs(mut self, age_secs: f64) -> Self {
+        self.article.age_secs = age_secs;
+        self
+    }
+
+    pub fn build(self) -> NewArticle {
+        self.article
+    }
+
+    pub async fn insert(self, e: impl PgExecutor<'_>) -> sqlx::Result<NewArticle> {
+        let article = self.article;
+
+        // Tagged the same way as `articles::create()`, see there.
+        sqlx::query!(
+            r#"
+                with
+                    inserted_article as (
+                        insert into article (article_id, user_id, slug, title, description, body, created_at)
+                        values ($1, $2, $3, $4, $5, $6, now() - make_interval(secs => $8))
+                        returning article_id
+                    ),
+                    article_tags as (
+                        insert into tag (name)
+                        select unnest($7::text[])
+                        on conflict (name) do update set name = excluded.name
+                        returning tag_id
+                    )
+                insert into article_tag (article_id, tag_id)
+                select article_id, tag_id from inserted_article, article_tags
+            "#,
+            article.article_id,
+            article.user_id,
+            article.slug,
+            article.title,
+            article.description,
+            article.body,
+            &article.tags[..],
+            article.age_secs
+        )
+        .execute(e)
+        .await?;
+
+        Ok(article)
+    }
+}
+
+impl CommentFactory {
+    pub fn body(mut self, body: &str) -> Self {
+        self.comment.body = body.to_string();
+        self
+    }
+
+    pub fn build(self) -> NewComment {
+        self.comment
+    }
+
+    pub async fn insert(self, e: impl PgExecutor<'_>) -> sqlx::Result<NewComment> {
+        let comment = self.comment;
+
+        sqlx::query!(
+            r#"
+                insert into article_comment (article_id, user_id, body, created_at)
+                values ($1, $2, $3, now() - make_interval(secs => $4))
+            "#,
+            comment.article_id,
+            comment.user_id,
+            comment.body,
+            comment.age_secs
+        )
+        .execute(e)
+        .await?;
+
+        Ok(comment)
+    }
+}
+
+/// Hash `password` the same way `http::users` does.
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(rand::thread_rng());
+
+    Ok(
+        PasswordHash::generate(Argon2::default(), password, salt.as_str())
+            .map_err(|e| anyhow::anyhow!("failed to generate password hash: {}", e))?
+            .to_string(),
+    )
+}
+
+/// `Uuid::new_v4()` uses the OS RNG (and isn't enabled anyway), so we make our own
+/// from the bytes of our seeded RNG.
+fn uuid(rng: &mut ChaCha8Rng) -> Uuid {
+    uuid::Builder::from_bytes(rng.gen())
+        .set_variant(uuid::Variant::RFC4122)
+        .set_version(uuid::Version::Random)
+        .build()
+}
+
+#[test]
+fn test_factories_are_deterministic() {
+    let generate = |seed| {
+        let mut fixtures = Fixtures::new(seed);
+        let user = fixtures.user().build();
+        let article = fixtures.article(&user).build();
+        let comment = fixtures.comment(&article, &user).build();
+        (user, article, comment)
+    };
+
+    assert_eq!(generate(0), generate(0));
+    assert_ne!(generate(0), generate(1));
+
+    let mut fixtures = Fixtures::new(0);
+    let user = fixtures.user().username("alice").build();
+    assert_eq!(user.email, "alice@example.com");
+
+    let article = fixtures
+        .article(&user)
+        .title("Hello, World!")
+        .with_tags(&["b", "a", "b"])
+        .build();
+    assert_eq!(article.slug, "hello-world-0-0");
+    assert_eq!(article.tags, ["a", "b"]);
+}
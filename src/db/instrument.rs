@@ -0,0 +1,234 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use either::Either;
+use futures::future::BoxFuture;
+use futures::stream::{BoxStream, StreamExt};
+use metrics::{
+    describe_counter, describe_gauge, describe_histogram, gauge, histogram, increment_counter, Unit,
+};
+use sqlx::postgres::{PgQueryResult, PgRow, PgStatement, PgTypeInfo};
+use sqlx::{Describe, Execute, Executor, PgPool, Postgres};
+
+use crate::db::{Db, MAX_CONNECTIONS};
+
+// When the database starts struggling, the first thing you see is usually requests getting
+// slower across the board, which doesn't tell you much on its own. The metrics here are meant
+// to answer the follow-up questions: is it one query in particular, or are we just running out
+// of connections?
+
+/// How often `monitor_pools()` samples the connection pools.
+const MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wraps an executor to record how long each query run through it takes,
+/// under the given name. See [`instrument()`].
+pub struct Instrumented<E> {
+    name: &'static str,
+    inner: E,
+}
+
+/// Record metrics for the queries run through `executor` under the name `name`.
+///
+/// Every function in the repository modules does this with its own name, e.g.
+/// `.fetch_one(instrument("users::create", e))`, so that we get latencies per query
+/// rather than one number for the whole database.
+///
+/// Note that if `executor` is a pool, the time includes waiting for a connection,
+/// which is exactly what you want to know about when the pool is exhausted.
+pub fn instrument<E>(name: &'static str, executor: E) -> Instrumented<E> {
+    Instrumented {
+        name,
+        inner: executor,
+    }
+}
+
+/// Register descriptions for the metrics in this module, which show up as `# HELP` lines
+/// in the Prometheus output.
+///
+/// This only works once a recorder has been installed.
+pub fn describe_metrics() {
+    describe_histogram!(
+        "db_query_duration_seconds",
+        Unit::Seconds,
+        "How long each named query took, including waiting for a connection."
+    );
+    describe_counter!(
+        "db_query_errors_total",
+        "How many times each named query has failed."
+    );
+    describe_gauge!(
+        "db_pool_connections",
+        "The number of open connections in each pool, by state."
+    );
+    describe_gauge!(
+        "db_pool_max_connections",
+        "The maximum number of connections each pool will open."
+    );
+    describe_histogram!(
+        "db_pool_acquire_seconds",
+        Unit::Seconds,
+        "How long it took to get a connection from each pool, sampled periodically."
+    );
+    // This one is recorded by the cleanup jobs, like `feed::prune_periodically()`, so that a
+    // job that suddenly deletes far more (or nothing at all) stands out.
+    describe_counter!(
+        "db_rows_pruned_total",
+        "How many old rows the periodic cleanup jobs have deleted, by table."
+    );
+}
+
+/// Spawn a task that periodically records the state of the connection pools.
+pub fn monitor_pools(db: Db) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MONITOR_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            record_pool("primary", db.primary()).await;
+
+            if let Some(replica) = &db.replica {
+                record_pool("replica", replica).await;
+            }
+        }
+    });
+}
+
+async fn record_pool(name: &'static str, pool: &PgPool) {
+    let size = pool.size();
+    // `num_idle()` may briefly disagree with `size()` while connections are being opened
+    // or closed.
+    let idle = (pool.num_idle() as u32).min(size);
+
+    gauge!("db_pool_connections", f64::from(idle), "pool" => name, "state" => "idle");
+    gauge!("db_pool_connections", f64::from(size - idle), "pool" => name, "state" => "active");
+    gauge!("db_pool_max_connections", f64::from(MAX_CONNECTIONS), "pool" => name);
+
+    // SQLx doesn't tell us how long each `acquire()` waited, so we find out by doing one
+    // ourselves and handing the connection straight back. If the pool is exhausted, this waits
+    // in line like everyone else, so it's a fair sample of what requests are seeing right now.
+    let start = Instant::now();
+
+    match pool.acquire().await {
+        Ok(conn) => {
+            histogram!("db_pool_acquire_seconds", start.elapsed(), "pool" => name);
+            drop(conn);
+        }
+        Err(e) => log::warn!(
+            "failed to acquire a connection from the {} pool: {}",
+            name,
+            e
+        ),
+    }
+}
+
+/// Records the duration of a query when dropped, so we get a measurement however the query
+/// ends, including the caller losing interest halfway through a stream.
+struct QueryTimer {
+    name: &'static str,
+    start: Instant,
+    failed: bool,
+}
+
+impl QueryTimer {
+    fn start(name: &'static str) -> Self {
+        QueryTimer {
+            name,
+            start: Instant::now(),
+            failed: false,
+        }
+    }
+}
+
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        histogram!("db_query_duration_seconds", self.start.elapsed(), "query" => self.name);
+
+        if self.failed {
+            increment_counter!("db_query_errors_total", "query" => self.name);
+        }
+    }
+}
+
+// `Executor` requires `Debug` for some reason.
+impl<E> fmt::Debug for Instrumented<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Instrumented")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+// `fetch_many()` and `fetch_optional()` are the only two methods that actually run queries;
+// all the others are implemented on top of them. The signatures are a bit of a mouthful,
+// but they're just copied from the trait.
+impl<'c, E> Executor<'c> for Instrumented<E>
+where
+    E: Executor<'c, Database = Postgres>,
+{
+    type Database = Postgres;
+
+    fn fetch_many<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxStream<'e, Result<Either<PgQueryResult, PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        Q: Execute<'q, Postgres> + 'q,
+    {
+        let mut timer = QueryTimer::start(self.name);
+
+        Box::pin(self.inner.fetch_many(query).inspect(move |res| {
+            // Closures only capture the fields they use, so without this line the closure
+            // would only take `timer.failed` and the rest of the timer would be dropped
+            // (and record a duration) before the query even started.
+            let timer = &mut timer;
+
+            if res.is_err() {
+                timer.failed = true;
+            }
+        }))
+    }
+
+    fn fetch_optional<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxFuture<'e, Result<Option<PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        Q: Execute<'q, Postgres> + 'q,
+    {
+        let timer = QueryTimer::start(self.name);
+        let fut = self.inner.fetch_optional(query);
+
+        Box::pin(async move {
+            // Same as above, this makes sure we take the whole timer.
+            let mut timer = timer;
+
+            let res = fut.await;
+            timer.failed = res.is_err();
+            res
+        })
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [PgTypeInfo],
+    ) -> BoxFuture<'e, Result<PgStatement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.inner.prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<Describe<Postgres>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.inner.describe(sql)
+    }
+}
@@ -0,0 +1,108 @@
+use sqlx::PgExecutor;
+use time::OffsetDateTime;
+
+use crate::db::instrument::instrument;
+use crate::db::types::ArticleId;
+
+/// Whether a link is something to click on or an image that's shown inline.
+#[derive(sqlx::Type, serde::Serialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[sqlx(type_name = "link_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    Link,
+    Image,
+}
+
+/// A link found in an article, see `migrations/15_article_link.sql`.
+pub struct Link {
+    pub slug: String,
+    pub kind: LinkKind,
+    pub url: String,
+    pub host: String,
+    pub created_at: OffsetDateTime,
+}
+
+/// A link to store with `replace()`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NewLink {
+    pub kind: LinkKind,
+    pub url: String,
+    pub host: String,
+}
+
+/// Replace the stored links of an article with `links`.
+///
+/// Links it already had keep their `created_at`.
+pub async fn replace(
+    e: impl PgExecutor<'_>,
+    article_id: ArticleId,
+    links: &[NewLink],
+) -> sqlx::Result<()> {
+    let mut kinds = Vec::with_capacity(links.len());
+    let mut urls = Vec::with_capacity(links.len());
+    let mut hosts = Vec::with_capacity(links.len());
+
+    for link in links {
+        // SQLx 0.5 can't encode arrays of custom enums, so they go over as text.
+        kinds.push(match link.kind {
+            LinkKind::Link => "link",
+            LinkKind::Image => "image",
+        });
+        urls.push(link.url.as_str());
+        hosts.push(link.host.as_str());
+    }
+
+    // Both parts see the table as it was before the query, so the delete only has to leave
+    // alone the links that are still there, and the insert skips those same links.
+    sqlx::query!(
+        r#"
+            with new_link as (
+                select kind::link_kind, url, host
+                from unnest($2::text[], $3::text[], $4::text[]) as t(kind, url, host)
+            ),
+            deleted as (
+                delete from article_link
+                where article_id = $1
+                    and (kind, url) not in (select kind, url from new_link)
+            )
+            insert into article_link (article_id, kind, url, host)
+            select $1, kind, url, host
+            from new_link
+            on conflict do nothing
+        "#,
+        article_id as ArticleId,
+        &kinds as &[&str],
+        &urls as &[&str],
+        &hosts as &[&str],
+    )
+    .execute(instrument("links::replace", e))
+    .await?;
+
+    Ok(())
+}
+
+/// List the links in all articles, newest first, optionally only those to `host`.
+pub async fn list(
+    e: impl PgExecutor<'_>,
+    host: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> sqlx::Result<Vec<Link>> {
+    sqlx::query_as!(
+        Link,
+        r#"
+            select slug, kind "kind: LinkKind", url, host, article_link.created_at
+            from article_link
+            inner join article using (article_id)
+            where $1::text is null or host = $1
+            order by article_link.created_at desc, article_id, kind, url
+            limit $2
+            offset $3
+        "#,
+        host,
+        limit,
+        offset
+    )
+    .fetch_all(instrument("links::list", e))
+    .await
+}
@@ -0,0 +1,99 @@
+use sqlx::PgExecutor;
+use time::OffsetDateTime;
+
+use crate::db::instrument::instrument;
+use crate::db::types::{ArticleId, UserId};
+
+/// Someone's lock on editing an article, and who they are.
+pub struct Lock {
+    pub user_id: UserId,
+    pub username: String,
+    pub expires_at: OffsetDateTime,
+}
+
+/// Take the lock on editing `article_id` for `user_id` until `expires_at`, or extend it if they
+/// already hold it.
+///
+/// If someone else holds a lock that hasn't expired as of `now`, that's left alone and returned
+/// instead, so check `user_id` on the result.
+pub async fn acquire(
+    e: impl PgExecutor<'_>,
+    article_id: ArticleId,
+    user_id: UserId,
+    now: OffsetDateTime,
+    expires_at: OffsetDateTime,
+) -> sqlx::Result<Lock> {
+    // The outer `select` sees the table as it was before the upsert, so if the upsert went
+    // through we have to take the lock from what it returned, not from the table.
+    sqlx::query_as!(
+        Lock,
+        r#"
+            with acquired as (
+                insert into article_lock (article_id, user_id, expires_at)
+                values ($1, $2, $4)
+                on conflict (article_id) do update
+                set user_id = excluded.user_id, expires_at = excluded.expires_at
+                where article_lock.user_id = excluded.user_id or article_lock.expires_at <= $3
+                returning user_id, expires_at
+            ),
+            current_lock as (
+                select user_id, expires_at from acquired
+                union all
+                select user_id, expires_at from article_lock
+                where article_id = $1 and not exists(select 1 from acquired)
+            )
+            select
+                user_id "user_id!: UserId",
+                username "username!",
+                expires_at "expires_at!"
+            from current_lock
+            inner join "user" using (user_id)
+        "#,
+        article_id as ArticleId,
+        user_id as UserId,
+        now,
+        expires_at
+    )
+    .fetch_one(instrument("locks::acquire", e))
+    .await
+}
+
+/// Release `user_id`'s lock on editing `article_id`, if they hold one.
+///
+/// Someone else's lock is left alone.
+pub async fn release(
+    e: impl PgExecutor<'_>,
+    article_id: ArticleId,
+    user_id: UserId,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "delete from article_lock where article_id = $1 and user_id = $2",
+        article_id as ArticleId,
+        user_id as UserId
+    )
+    .execute(instrument("locks::release", e))
+    .await?;
+
+    Ok(())
+}
+
+/// Look up the lock on editing `article_id`, unless there isn't one or it expired before `now`.
+pub async fn find_active(
+    e: impl PgExecutor<'_>,
+    article_id: ArticleId,
+    now: OffsetDateTime,
+) -> sqlx::Result<Option<Lock>> {
+    sqlx::query_as!(
+        Lock,
+        r#"
+            select user_id "user_id: UserId", username, expires_at
+            from article_lock
+            inner join "user" using (user_id)
+            where article_id = $1 and expires_at > $2
+        "#,
+        article_id as ArticleId,
+        now
+    )
+    .fetch_optional(instrument("locks::find_active", e))
+    .await
+}
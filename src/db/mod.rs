@@ -0,0 +1,419 @@
+use anyhow::Context;
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Connection, Executor, PgPool};
+use time::OffsetDateTime;
+
+use crate::config::Config;
+
+// These modules hold all the queries the application makes, as plain functions taking
+// any executor (a pool, a connection or a transaction).
+//
+// I personally prefer writing queries inline in request handlers, as it's easier to understand
+// a query's semantics in the wider context of where it's invoked. However, once the same queries
+// are needed elsewhere (background jobs, admin tooling, another API surface) that means
+// copy-pasting them, and then they inevitably drift apart. So here they live.
+//
+// The functions return plain `sqlx::Result`s and deliberately know nothing about HTTP;
+// turning constraint violations and missing rows into API errors is the handler's job.
+//
+// Because they take any executor, when a handler needs the results of two queries that don't
+// depend on each other, it can run them at the same time by passing the pool to both:
+//
+// ```rust,ignore
+// let (count, articles) = tokio::try_join!(
+//     articles::count(db.read(), &filter),
+//     articles::list(db.read(), viewer, &filter),
+// )?;
+// ```
+//
+// Each one checks out a connection of its own, so this saves a round-trip at the cost of holding
+// two connections instead of one. That's a good trade for the hot read paths, but it doesn't
+// work inside a transaction: a transaction is one connection, and Postgres runs one query at
+// a time per connection, so the borrow checker won't let you try.
+
+/// Queries summarizing authors' followers and articles over time, for their analytics.
+pub mod analytics;
+/// Queries on the `announcement` and `announcement_dismissal` tables, for sitewide banners.
+pub mod announcements;
+/// Queries on the `article`, `article_favorite` tables, and tags.
+pub mod articles;
+/// Queries on the `article_author` and `article_author_invitation` tables, for co-authors.
+pub mod authors;
+/// Cancelling a query when the request waiting on it goes away.
+pub mod cancel;
+/// Queries on the `article_comment` table.
+pub mod comments;
+/// Queries on the `csp_report` table, for Content Security Policy violations.
+pub mod csp_reports;
+/// Queries on the `feed_entry` table, which holds each user's feed.
+pub mod feed;
+/// Factories for fake users, articles and comments, for tests and the `seed` subcommand.
+pub mod fixtures;
+/// Metrics for the connection pools and the queries in the modules above.
+pub mod instrument;
+/// Queries on the `article_link` table, the links found in articles.
+pub mod links;
+/// Queries on the `article_lock` table, for editing locks.
+pub mod locks;
+/// Queries on the `muted_tag` table.
+pub mod muted_tags;
+/// Queries on the `password_reset_token` table, for resetting forgotten passwords.
+pub mod password_resets;
+/// Inserts a small set of demo data, for the `seed` subcommand.
+pub mod seed;
+/// Queries on the `tag_metadata` table, and about individual tags.
+pub mod tags;
+/// Queries on the `article_takedown` table, for moderation.
+pub mod takedowns;
+/// Strongly typed IDs for our tables.
+pub mod types;
+/// Queries on the `user_settings` table.
+pub mod user_settings;
+/// Queries on the `user` and `follow` tables.
+pub mod users;
+
+/// The most connections each pool will open.
+///
+/// The default connection limit for a Postgres server is 100 connections, minus 3 for superusers.
+/// Since we're using the default superuser we don't have to worry about this too much,
+/// although we should leave some connections available for manual access.
+///
+/// If you're deploying your application with multiple replicas, then the total
+/// across all replicas should not exceed the Postgres connection limit.
+pub const MAX_CONNECTIONS: u32 = 50;
+
+/// This embeds database migrations in the application binary so we can ensure the database
+/// is migrated correctly on startup, or with the `migrate` subcommand.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// A thin wrapper around our database connection pools.
+///
+/// Most of the time this is just the one pool, connected to the primary database.
+/// If `database_read_url` is set, we also open a second pool to a read replica and send
+/// queries from routes that only read data to it, taking some load off the primary.
+///
+/// The catch is that replicas lag behind the primary, usually by milliseconds but sometimes
+/// by a lot more. Anything that writes, or reads something it (or the same user) just wrote,
+/// should use [`Db::primary()`]. Only use [`Db::read()`] where it's acceptable to return data
+/// that's slightly stale.
+///
+/// This intentionally doesn't implement `Deref<Target = PgPool>` or `sqlx::Executor`,
+/// so every query has to pick a side explicitly.
+#[derive(Clone)]
+pub struct Db {
+    primary: PgPool,
+    replica: Option<PgPool>,
+    /// Whether `cancellable()` can actually cancel anything, see `cancel::Cancellable`.
+    can_cancel: bool,
+}
+
+impl Db {
+    /// Connect to the primary database and, if configured, the read replica.
+    pub async fn connect(config: &Config) -> anyhow::Result<Self> {
+        // We create a single connection pool for SQLx that's shared across the whole application.
+        // This saves us from opening a new connection for every API call, which is wasteful.
+        let mut settings = vec![];
+
+        // See `Config::statement_timeout_ms`. This goes for the replica too.
+        if let Some(timeout) = config.statement_timeout_ms {
+            settings.push(format!("SET statement_timeout = {}", timeout));
+        }
+
+        let primary = pool_options(settings.clone())
+            .connect_with(connect_options(config, &config.database_url)?)
+            .await
+            .context("could not connect to database_url")?;
+
+        // If there's a read replica, it gets its own pool.
+        let replica = match &config.database_read_url {
+            Some(url) => {
+                // Replicas reject writes anyway, but this gives a clearer error if one of our
+                // queries tries to write, and also covers pointing this at the primary by mistake.
+                settings.push("SET default_transaction_read_only = on".into());
+
+                Some(
+                    pool_options(settings)
+                        .connect_with(connect_options(config, url)?)
+                        .await
+                        .context("could not connect to database_read_url")?,
+                )
+            }
+            None => None,
+        };
+
+        if !config.pgbouncer_mode {
+            warn_if_pooled(&primary, &config.database_url, "database_url").await;
+
+            if let (Some(replica), Some(url)) = (&replica, &config.database_read_url) {
+                warn_if_pooled(replica, url, "database_read_url").await;
+            }
+        }
+
+        Ok(Db {
+            primary,
+            replica,
+            // In transaction pooling mode, the server connection that ran a query may be serving
+            // someone else's by the time we'd cancel it.
+            can_cancel: !config.pgbouncer_mode,
+        })
+    }
+
+    /// The pool for the primary database, for anything that writes or needs to see the
+    /// most recent data.
+    pub fn primary(&self) -> &PgPool {
+        &self.primary
+    }
+
+    /// The pool for the read replica if there is one, or the primary otherwise.
+    ///
+    /// The connections in the replica pool are read-only, so accidentally writing through it
+    /// fails loudly instead of, well, however your replication setup feels like failing.
+    pub fn read(&self) -> &PgPool {
+        self.replica.as_ref().unwrap_or(&self.primary)
+    }
+
+    /// Take a connection from `pool` (i.e. `primary()` or `read()`) for queries that should be
+    /// cancelled if the request waiting on them goes away. See `cancel::Cancellable`.
+    pub async fn cancellable(&self, pool: &PgPool) -> sqlx::Result<cancel::Cancellable> {
+        cancel::Cancellable::acquire(pool, self.can_cancel).await
+    }
+
+    /// The pool for the read replica, if there is one.
+    ///
+    /// Queries should generally use `read()` instead; this is for when it matters which
+    /// database you're talking to, like health checks.
+    pub fn replica(&self) -> Option<&PgPool> {
+        self.replica.as_ref()
+    }
+
+    /// Check that the primary database would accept a write, without actually writing anything.
+    ///
+    /// After a failover, `database_url` can end up pointing at a server that's still (or now)
+    /// a standby, which answers reads just fine but rejects every write. The same goes for
+    /// a server that's been set to `default_transaction_read_only`, e.g. because its disk
+    /// is about to fill up.
+    pub async fn check_writable(&self) -> anyhow::Result<()> {
+        let (in_recovery, read_only): (bool, String) =
+            sqlx::query_as("select pg_is_in_recovery(), current_setting('transaction_read_only')")
+                .fetch_one(&self.primary)
+                .await
+                .context("failed to query the database")?;
+
+        if in_recovery {
+            anyhow::bail!("the database is a standby, which is read-only");
+        }
+
+        if read_only == "on" {
+            anyhow::bail!("the database only allows read-only transactions");
+        }
+
+        Ok(())
+    }
+
+    /// Apply any pending migrations to the primary database.
+    ///
+    /// This is safe to run from several instances of the application at once, which is what
+    /// happens when a deployment with multiple replicas rolls out: SQLx takes a Postgres advisory
+    /// lock for the duration, so one instance runs the migrations while the others wait for it,
+    /// and then find there's nothing left to do.
+    pub async fn migrate(&self) -> anyhow::Result<()> {
+        // Migrations can take a lot longer than any query should (see
+        // `Config::statement_timeout_ms`), so they get a connection without the timeout. It isn't
+        // put back in the pool afterwards, so nothing else ends up running without it.
+        let mut conn = self
+            .primary
+            .acquire()
+            .await
+            .context("failed to connect to run migrations")?
+            .detach();
+
+        conn.execute("SET statement_timeout = 0")
+            .await
+            .context("failed to turn off statement_timeout for migrations")?;
+
+        let migrated = MIGRATOR.run(&mut conn).await;
+
+        if let Err(e) = conn.close().await {
+            log::debug!("failed to close the connection used for migrations: {}", e);
+        }
+
+        migrated.map_err(|e| match e {
+            // These are the ones that usually mean someone deployed the wrong thing,
+            // and the default messages don't make that terribly obvious.
+            MigrateError::VersionMissing(version) => anyhow::anyhow!(
+                "the database has migration {} applied, which this build doesn't know about; \
+                 is an older version of the application being run against a newer database?",
+                version
+            ),
+            MigrateError::VersionMismatch(version) => anyhow::anyhow!(
+                "migration {} was modified after it was applied to the database; \
+                 migrations must not be changed once they've been deployed, add a new one instead",
+                version
+            ),
+            MigrateError::Dirty(version) => anyhow::anyhow!(
+                "migration {} previously failed partway through and must be fixed manually",
+                version
+            ),
+            e => anyhow::Error::new(e).context("failed to run database migrations"),
+        })
+    }
+
+    /// Check that the primary database has all of our migrations applied.
+    pub async fn check_migrations(&self) -> anyhow::Result<()> {
+        check_applied_migrations(&self.primary, "the database").await
+    }
+
+    /// Check that the read replica, if any, has caught up with our migrations.
+    ///
+    /// This is meant to be called at startup right after migrating the primary. Replication is
+    /// usually fast enough that it's caught up by then, and if it isn't, it's better that we fail
+    /// to start (and get restarted) than serve errors about missing tables or columns.
+    pub async fn check_replica_migrations(&self) -> anyhow::Result<()> {
+        match &self.replica {
+            Some(replica) => check_applied_migrations(replica, "the read replica")
+                .await
+                .context("is database_read_url pointing at a replica of database_url?"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Check that `pool` can get a connection and run a trivial query on it.
+pub async fn ping(pool: &PgPool) -> anyhow::Result<()> {
+    pool.execute("select 1")
+        .await
+        .context("failed to query the database")?;
+    Ok(())
+}
+
+/// Returns `true` if `e` means a transaction was aborted because it conflicted with
+/// another one running at the same time, and would likely succeed if run again from the start.
+///
+/// These are `serialization_failure` and `deadlock_detected`. The former only happens with
+/// the `repeatable read` and `serializable` isolation levels, but deadlocks can happen
+/// at any isolation level if two transactions lock the same rows in a different order.
+pub fn is_retryable(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Database(dbe) => matches!(dbe.code().as_deref(), Some("40001" | "40P01")),
+        _ => false,
+    }
+}
+
+/// The result of a delete that's restricted to the owner of the row, so the caller can tell
+/// "doesn't exist" apart from "not yours".
+pub struct Deleted {
+    /// `true` if the row existed before we tried to delete it.
+    pub existed: bool,
+    /// `true` only if we actually deleted the row.
+    pub deleted: bool,
+}
+
+/// How many rows a user has created since some point in time, for enforcing quotas.
+///
+/// See `http::quota`.
+pub struct Recent {
+    pub count: i64,
+    /// When the oldest of those rows was created, if there are any.
+    pub oldest: Option<OffsetDateTime>,
+}
+
+/// The options for a pool whose connections run each of `settings` (`SET` statements) as soon as
+/// they're opened.
+fn pool_options(settings: Vec<String>) -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(MAX_CONNECTIONS)
+        .after_connect(move |conn| {
+            let settings = settings.clone();
+
+            Box::pin(async move {
+                for setting in &settings {
+                    conn.execute(&**setting).await?;
+                }
+                Ok(())
+            })
+        })
+}
+
+fn connect_options(config: &Config, url: &str) -> anyhow::Result<PgConnectOptions> {
+    let options: PgConnectOptions = url.parse()?;
+
+    // See `Config::pgbouncer_mode` for the tradeoff here.
+    if config.pgbouncer_mode {
+        Ok(options.statement_cache_capacity(0))
+    } else {
+        Ok(options)
+    }
+}
+
+/// Log a warning if it looks like `url` connects through a connection pooler, as that likely
+/// needs `pgbouncer_mode` to work properly.
+async fn warn_if_pooled(pool: &PgPool, url: &str, which: &str) {
+    // PgBouncer and friends listen on a port of their own (6432 by default) and then connect to
+    // Postgres on its port, so if Postgres says it's listening on a different port than the one
+    // we connected to, there's probably something in between.
+    //
+    // This isn't foolproof: a port mapping (like Docker's `-p 5433:5432`) trips it too,
+    // and it misses a pooler listening on the same port as Postgres. It's only a warning, though.
+    //
+    // This is `NULL` if we're connected over a Unix socket, in which case we can't tell.
+    let server_port: Option<i32> = match sqlx::query_scalar("select inet_server_port()")
+        .fetch_one(pool)
+        .await
+    {
+        Ok(port) => port,
+        Err(e) => {
+            log::debug!("failed to check for a connection pooler: {}", e);
+            return;
+        }
+    };
+
+    let url_port = url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.port())
+        .unwrap_or(5432);
+
+    match server_port {
+        Some(server_port) if server_port != i32::from(url_port) => log::warn!(
+            "{} connects on port {} but the database server is listening on port {}, \
+             so there may be a connection pooler like PgBouncer in between; \
+             if it uses transaction pooling, set pgbouncer_mode",
+            which,
+            url_port,
+            server_port
+        ),
+        _ => (),
+    }
+}
+
+async fn check_applied_migrations(pool: &PgPool, which: &str) -> anyhow::Result<()> {
+    let expected = MIGRATOR.iter().map(|m| m.version).max();
+
+    // This is the table SQLx records applied migrations in. We can't use `Migrator::run()`
+    // to check since that takes a lock and may create the table, neither of which works
+    // on a read-only connection.
+    let applied: Option<i64> =
+        match sqlx::query_scalar("select max(version) from _sqlx_migrations where success")
+            .fetch_one(pool)
+            .await
+        {
+            Ok(applied) => applied,
+            // `undefined_table`: no migrations have ever been run.
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("42P01") => None,
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("failed to check the migrations on {}", which)))
+            }
+        };
+
+    if applied < expected {
+        anyhow::bail!(
+            "{} is at migration {} but this build expects {}",
+            which,
+            applied.map_or("(none)".to_string(), |v| v.to_string()),
+            expected.map_or("(none)".to_string(), |v| v.to_string()),
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use sqlx::PgExecutor;
+
+use crate::db::instrument::instrument;
+use crate::db::types::UserId;
+
+/// Mute `tag` for `user_id`, if they haven't already.
+pub async fn mute(e: impl PgExecutor<'_>, user_id: UserId, tag: &str) -> sqlx::Result<()> {
+    sqlx::query!(
+        "insert into muted_tag (user_id, tag) values ($1, $2) on conflict do nothing",
+        user_id as UserId,
+        tag
+    )
+    .execute(instrument("muted_tags::mute", e))
+    .await?;
+
+    Ok(())
+}
+
+/// Unmute `tag` for `user_id`, if they'd muted it.
+pub async fn unmute(e: impl PgExecutor<'_>, user_id: UserId, tag: &str) -> sqlx::Result<()> {
+    sqlx::query!(
+        "delete from muted_tag where user_id = $1 and tag = $2",
+        user_id as UserId,
+        tag
+    )
+    .execute(instrument("muted_tags::unmute", e))
+    .await?;
+
+    Ok(())
+}
+
+/// List the tags `user_id` has muted, sorted.
+pub async fn list(e: impl PgExecutor<'_>, user_id: UserId) -> sqlx::Result<Vec<String>> {
+    sqlx::query_scalar!(
+        "select tag from muted_tag where user_id = $1 order by tag",
+        user_id as UserId
+    )
+    .fetch_all(instrument("muted_tags::list", e))
+    .await
+}
+
+/// Mute `to` instead for everyone who'd muted any of the tags in `from`, for
+/// `articles::merge_tags()`.
+pub async fn merge(e: impl PgExecutor<'_>, from: &[String], to: &str) -> sqlx::Result<()> {
+    // Both parts see the table as it was before the query, so the insert doesn't have to
+    // dodge the rows being deleted, and anyone who'd muted `to` already keeps it.
+    sqlx::query!(
+        r#"
+            with deleted as (
+                delete from muted_tag
+                where tag = any($1) and tag <> $2
+                returning user_id
+            )
+            insert into muted_tag (user_id, tag)
+            select distinct user_id, $2
+            from deleted
+            on conflict do nothing
+        "#,
+        from,
+        to
+    )
+    .execute(instrument("muted_tags::merge", e))
+    .await?;
+
+    Ok(())
+}
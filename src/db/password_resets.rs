@@ -0,0 +1,92 @@
+use sqlx::PgExecutor;
+use time::OffsetDateTime;
+
+use crate::db::instrument::instrument;
+use crate::db::types::UserId;
+
+/// Store a new password reset token for `user_id`, created at `now` and usable until
+/// `expires_at`, unless they've already been sent `max_per_hour` of them in the hour before.
+///
+/// Returns whether the token was stored, i.e. whether to send it.
+pub async fn create(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+    token_hash: &[u8],
+    now: OffsetDateTime,
+    expires_at: OffsetDateTime,
+    max_per_hour: i64,
+) -> sqlx::Result<bool> {
+    // Two requests at the same time could both squeeze in under the limit, but that's one email
+    // too many, not an inbox full of them.
+    let res = sqlx::query!(
+        r#"
+            insert into password_reset_token (token_hash, user_id, expires_at, created_at)
+            select $1, $2, $3, $4
+            where (
+                select count(*)
+                from password_reset_token
+                where user_id = $2 and created_at > $4::timestamptz - interval '1 hour'
+            ) < $5
+        "#,
+        token_hash,
+        user_id as UserId,
+        expires_at,
+        now,
+        max_per_hour
+    )
+    .execute(instrument("password_resets::create", e))
+    .await?;
+
+    Ok(res.rows_affected() == 1)
+}
+
+/// Delete the tokens that expired more than an hour before `now`, used or not.
+///
+/// By then they're no use to anyone, and have long since stopped counting towards the hourly
+/// limit in `create()`. Returns the number of tokens deleted.
+pub async fn prune(e: impl PgExecutor<'_>, now: OffsetDateTime) -> sqlx::Result<u64> {
+    let res = sqlx::query!(
+        "delete from password_reset_token where expires_at < $1::timestamptz - interval '1 hour'",
+        now
+    )
+    .execute(instrument("password_resets::prune", e))
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Use up the token with `token_hash` if it hasn't been used and hasn't expired as of `now`,
+/// returning whose it was.
+///
+/// Any other tokens the user still had are used up along with it, since whichever email
+/// they're in is now out of date.
+pub async fn consume(
+    e: impl PgExecutor<'_>,
+    token_hash: &[u8],
+    now: OffsetDateTime,
+) -> sqlx::Result<Option<UserId>> {
+    // Both updates see the table as it was before the query, so the second one has to leave
+    // alone the row the first one is updating.
+    sqlx::query_scalar!(
+        r#"
+            with used as (
+                update password_reset_token
+                set used_at = $2
+                where token_hash = $1 and used_at is null and expires_at > $2
+                returning user_id
+            ),
+            others as (
+                update password_reset_token
+                set used_at = $2
+                where user_id in (select user_id from used)
+                    and token_hash <> $1
+                    and used_at is null
+            )
+            select user_id "user_id: UserId" from used
+        "#,
+        token_hash,
+        now
+    )
+    .fetch_optional(instrument("password_resets::consume", e))
+    .await
+}
@@ -0,0 +1,415 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::fixtures::{self, Fixtures, NewArticle, NewComment, NewUser, DEMO_PASSWORD};
+
+/// How much demo data to generate, for the `seed` subcommand.
+///
+/// The defaults are enough to click around a frontend and see every part of it. For load tests,
+/// crank them up; inserting is done in bulk, so even a few hundred thousand rows doesn't take long.
+#[derive(clap::Args, Copy, Clone, Debug)]
+pub struct SeedOptions {
+    /// The number of users to generate.
+    #[clap(long, default_value = "10")]
+    pub users: usize,
+
+    /// The number of articles to generate, spread randomly over the users.
+    #[clap(long, default_value = "50")]
+    pub articles: usize,
+
+    /// The number of comments to generate, spread randomly over the articles.
+    #[clap(long, default_value = "200")]
+    pub comments: usize,
+
+    /// The number of follows to generate between random pairs of users.
+    #[clap(long, default_value = "30")]
+    pub follows: usize,
+
+    /// The number of favorites to generate between random users and articles.
+    #[clap(long, default_value = "100")]
+    pub favorites: usize,
+
+    /// The seed for the random number generator.
+    ///
+    /// The same seed and options always generate the same data, which makes load tests
+    /// repeatable and bug reports reproducible. Use a different seed to seed the same
+    /// database more than once.
+    #[clap(long, default_value = "0")]
+    pub seed: u64,
+}
+
+impl Default for SeedOptions {
+    fn default() -> Self {
+        SeedOptions {
+            users: 10,
+            articles: 50,
+            comments: 200,
+            follows: 30,
+            favorites: 100,
+            seed: 0,
+        }
+    }
+}
+
+/// Generate demo data according to `options` and insert it, all in one transaction.
+///
+/// This is what the `seed` subcommand runs, but it's also meant for tests that need a database
+/// with some realistic data in it.
+///
+/// This does nothing if the first user that would be generated already exists, so running it
+/// twice with the same options is harmless.
+pub async fn seed(db: &PgPool, options: &SeedOptions) -> anyhow::Result<()> {
+    let plan = Plan::generate(options);
+
+    let mut tx = db.begin().await?;
+
+    if let Some(first) = plan.users.first() {
+        let already_seeded = sqlx::query_scalar!(
+            r#"select exists(select 1 from "user" where username = $1) "exists!""#,
+            first.username
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        if already_seeded {
+            log::info!(
+                "demo data for seed {} is already present, nothing to do",
+                options.seed
+            );
+            return Ok(());
+        }
+    }
+
+    // Every user gets the same password, so we only need to hash it once, which is a good thing
+    // when generating thousands of users since Argon2 is slow on purpose.
+    let password_hash = fixtures::hash_password(DEMO_PASSWORD)?;
+
+    // The factories can insert rows themselves, but one at a time. Here everything is inserted
+    // with `unnest()` instead, which turns arrays into rows, so each table is a single query
+    // no matter how many rows there are.
+    //
+    // We generate the primary keys ourselves so we can refer to them in later inserts without
+    // having to read them back and match them up.
+    sqlx::query!(
+        r#"
+            insert into "user" (user_id, username, email, bio, password_hash)
+            select user_id, username, email, bio, $5
+            from unnest($1::uuid[], $2::text[], $3::text[], $4::text[])
+                as new_user(user_id, username, email, bio)
+        "#,
+        &plan.users.iter().map(|u| u.user_id).collect::<Vec<_>>(),
+        &plan
+            .users
+            .iter()
+            .map(|u| u.username.clone())
+            .collect::<Vec<_>>(),
+        &plan
+            .users
+            .iter()
+            .map(|u| u.email.clone())
+            .collect::<Vec<_>>(),
+        &plan.users.iter().map(|u| u.bio.clone()).collect::<Vec<_>>(),
+        password_hash
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to insert users; if they already exist, try a different --seed")?;
+
+    sqlx::query!(
+        r#"
+            insert into article (article_id, user_id, slug, title, description, body, created_at)
+            select
+                article_id,
+                user_id,
+                slug,
+                title,
+                description,
+                body,
+                now() - make_interval(secs => age_secs)
+            from unnest(
+                $1::uuid[], $2::uuid[], $3::text[], $4::text[], $5::text[], $6::text[], $7::float8[]
+            ) as new_article(article_id, user_id, slug, title, description, body, age_secs)
+        "#,
+        &plan
+            .articles
+            .iter()
+            .map(|a| a.article_id)
+            .collect::<Vec<_>>(),
+        &plan.articles.iter().map(|a| a.user_id).collect::<Vec<_>>(),
+        &plan
+            .articles
+            .iter()
+            .map(|a| a.slug.clone())
+            .collect::<Vec<_>>(),
+        &plan
+            .articles
+            .iter()
+            .map(|a| a.title.clone())
+            .collect::<Vec<_>>(),
+        &plan
+            .articles
+            .iter()
+            .map(|a| a.description.clone())
+            .collect::<Vec<_>>(),
+        &plan
+            .articles
+            .iter()
+            .map(|a| a.body.clone())
+            .collect::<Vec<_>>(),
+        &plan.articles.iter().map(|a| a.age_secs).collect::<Vec<_>>(),
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to insert articles")?;
+
+    // One row per article and tag, which is what `article_tag` wants anyway. The tags are
+    // created first, since they need IDs before anything can refer to them.
+    let (tag_article_ids, tags): (Vec<_>, Vec<_>) = plan
+        .articles
+        .iter()
+        .flat_map(|a| a.tags.iter().map(move |tag| (a.article_id, tag.clone())))
+        .unzip();
+
+    sqlx::query!(
+        r#"
+            insert into tag (name)
+            select distinct unnest($1::text[])
+            on conflict do nothing
+        "#,
+        &tags
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to insert tags")?;
+
+    sqlx::query!(
+        r#"
+            insert into article_tag (article_id, tag_id)
+            select article_id, tag_id
+            from unnest($1::uuid[], $2::text[]) as new_article_tag(article_id, name)
+            inner join tag using (name)
+        "#,
+        &tag_article_ids,
+        &tags
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to tag articles")?;
+
+    sqlx::query!(
+        r#"
+            insert into article_comment (article_id, user_id, body, created_at)
+            select article_id, user_id, body, now() - make_interval(secs => age_secs)
+            from unnest($1::uuid[], $2::uuid[], $3::text[], $4::float8[])
+                as new_comment(article_id, user_id, body, age_secs)
+        "#,
+        &plan
+            .comments
+            .iter()
+            .map(|c| c.article_id)
+            .collect::<Vec<_>>(),
+        &plan.comments.iter().map(|c| c.user_id).collect::<Vec<_>>(),
+        &plan
+            .comments
+            .iter()
+            .map(|c| c.body.clone())
+            .collect::<Vec<_>>(),
+        &plan.comments.iter().map(|c| c.age_secs).collect::<Vec<_>>(),
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to insert comments")?;
+
+    sqlx::query!(
+        r#"
+            insert into follow (following_user_id, followed_user_id)
+            select * from unnest($1::uuid[], $2::uuid[])
+        "#,
+        &plan.follows.iter().map(|(a, _)| *a).collect::<Vec<_>>(),
+        &plan.follows.iter().map(|(_, b)| *b).collect::<Vec<_>>(),
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to insert follows")?;
+
+    sqlx::query!(
+        r#"
+            insert into article_favorite (user_id, article_id)
+            select * from unnest($1::uuid[], $2::uuid[])
+        "#,
+        &plan.favorites.iter().map(|(u, _)| *u).collect::<Vec<_>>(),
+        &plan.favorites.iter().map(|(_, a)| *a).collect::<Vec<_>>(),
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to insert favorites")?;
+
+    tx.commit().await.context("failed to commit demo data")?;
+
+    log::info!(
+        "inserted {} users, {} articles, {} comments, {} follows and {} favorites",
+        plan.users.len(),
+        plan.articles.len(),
+        plan.comments.len(),
+        plan.follows.len(),
+        plan.favorites.len(),
+    );
+
+    if let Some(first) = plan.users.first() {
+        log::info!(
+            "log in as any user with password {:?}, e.g. {}",
+            DEMO_PASSWORD,
+            first.email
+        );
+    }
+
+    Ok(())
+}
+
+/// All the data to insert, generated up-front so that it only depends on the `SeedOptions`.
+#[derive(Debug, PartialEq)]
+struct Plan {
+    users: Vec<NewUser>,
+    articles: Vec<NewArticle>,
+    comments: Vec<NewComment>,
+    follows: Vec<(Uuid, Uuid)>,
+    favorites: Vec<(Uuid, Uuid)>,
+}
+
+impl Plan {
+    /// Generate the data with the same factories the tests use, seeded with `options.seed`.
+    fn generate(options: &SeedOptions) -> Self {
+        let fixtures = &mut Fixtures::new(options.seed);
+
+        let users: Vec<NewUser> = (0..options.users)
+            .map(|_| fixtures.user().build())
+            .collect();
+
+        // Without any users there's no one to write articles, comments, etc.
+        if users.is_empty() {
+            return Plan {
+                users,
+                articles: vec![],
+                comments: vec![],
+                follows: vec![],
+                favorites: vec![],
+            };
+        }
+
+        let articles: Vec<NewArticle> = (0..options.articles)
+            .map(|_| {
+                let author = users.choose(fixtures.rng()).unwrap();
+                fixtures.article(author).build()
+            })
+            .collect();
+
+        let comments = if articles.is_empty() {
+            vec![]
+        } else {
+            (0..options.comments)
+                .map(|_| {
+                    let article = articles.choose(fixtures.rng()).unwrap();
+                    let author = users.choose(fixtures.rng()).unwrap();
+                    fixtures.comment(article, author).build()
+                })
+                .collect()
+        };
+
+        let rng = fixtures.rng();
+
+        // There are only so many distinct pairs, so we cap these to avoid looping forever
+        // looking for a pair we haven't used yet.
+        let max_follows = users.len() * (users.len() - 1);
+        let follows = random_pairs(rng, options.follows.min(max_follows), |rng| {
+            let pair = users.choose_multiple(rng, 2).collect::<Vec<_>>();
+            // A user can't follow themselves, which `choose_multiple()` already guarantees,
+            // but if there's only one user then we only get one back.
+            (pair.len() == 2).then(|| (pair[0].user_id, pair[1].user_id))
+        });
+
+        let max_favorites = users.len() * articles.len();
+        let favorites = random_pairs(rng, options.favorites.min(max_favorites), |rng| {
+            Some((
+                users.choose(rng).unwrap().user_id,
+                articles.choose(rng)?.article_id,
+            ))
+        });
+
+        Plan {
+            users,
+            articles,
+            comments,
+            follows,
+            favorites,
+        }
+    }
+}
+
+/// Generate up to `count` distinct pairs with `generate`, stopping early if it returns `None`.
+fn random_pairs(
+    rng: &mut ChaCha8Rng,
+    count: usize,
+    mut generate: impl FnMut(&mut ChaCha8Rng) -> Option<(Uuid, Uuid)>,
+) -> Vec<(Uuid, Uuid)> {
+    let mut seen = HashSet::new();
+    let mut pairs = Vec::with_capacity(count);
+
+    while pairs.len() < count {
+        let pair = match generate(rng) {
+            Some(pair) => pair,
+            None => break,
+        };
+
+        // Keep the order stable (unlike iterating the `HashSet`) so the output is deterministic.
+        if seen.insert(pair) {
+            pairs.push(pair);
+        }
+    }
+
+    pairs
+}
+
+#[test]
+fn test_plan_is_deterministic() {
+    let options = SeedOptions::default();
+
+    let plan = Plan::generate(&options);
+
+    assert_eq!(plan, Plan::generate(&options));
+    assert_ne!(plan, Plan::generate(&SeedOptions { seed: 1, ..options }));
+
+    assert_eq!(plan.users.len(), options.users);
+    assert_eq!(plan.articles.len(), options.articles);
+    assert_eq!(plan.comments.len(), options.comments);
+    assert_eq!(plan.follows.len(), options.follows);
+    assert_eq!(plan.favorites.len(), options.favorites);
+}
+
+#[test]
+fn test_plan_edge_cases() {
+    // One user can't follow anyone.
+    let plan = Plan::generate(&SeedOptions {
+        users: 1,
+        ..SeedOptions::default()
+    });
+    assert!(plan.follows.is_empty());
+
+    // No articles means nothing to comment on or favorite.
+    let plan = Plan::generate(&SeedOptions {
+        articles: 0,
+        ..SeedOptions::default()
+    });
+    assert!(plan.comments.is_empty());
+    assert!(plan.favorites.is_empty());
+
+    let plan = Plan::generate(&SeedOptions {
+        users: 0,
+        ..SeedOptions::default()
+    });
+    assert_eq!(plan.articles.len(), 0);
+}
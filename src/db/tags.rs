@@ -0,0 +1,129 @@
+use sqlx::PgExecutor;
+
+use crate::db::instrument::instrument;
+
+// Which articles have which tags is in `article_tag`, and is written by `articles::create()` and
+// `articles::merge_tags()`. This is for the tags themselves.
+
+/// A tag with its metadata and counts, for its landing page.
+pub struct Tag {
+    pub tag: String,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    /// How many articles have the tag, not counting any that have been taken down.
+    pub articles_count: i64,
+    /// How many different authors those articles are by.
+    pub authors_count: i64,
+}
+
+/// A tag with how many articles have it, for `GET /api/tags`.
+#[derive(Clone)]
+pub struct TagCount {
+    pub tag: String,
+    /// This includes articles that have been taken down, unlike `Tag::articles_count`.
+    pub articles_count: i64,
+}
+
+/// All the tags that any article has, sorted.
+pub async fn list(e: impl PgExecutor<'_>) -> sqlx::Result<Vec<TagCount>> {
+    // This used to be a `select distinct` over every article's tags, which was a full table scan
+    // on every request. Now it only reads `tag`, which is as big as the number of distinct tags,
+    // in the order of the index on `name`. `articles_count` is kept up to date by a trigger,
+    // see `migrations/23_tag.sql`.
+    //
+    // Taken down articles still count, since leaving them out would mean checking every article
+    // again. It was the same before, when their tags were listed anyway.
+    sqlx::query_as!(
+        TagCount,
+        r#"
+            select name tag, articles_count
+            from tag
+            where articles_count > 0
+            order by name
+        "#
+    )
+    .fetch_all(instrument("tags::list", e))
+    .await
+}
+
+/// Look up `tag`, or `None` if no article has it and it has no metadata either.
+pub async fn find(e: impl PgExecutor<'_>, tag: &str) -> sqlx::Result<Option<Tag>> {
+    sqlx::query_as!(
+        Tag,
+        r#"
+            with counts as (
+                select count(*) articles_count, count(distinct user_id) authors_count
+                from article_tag
+                inner join tag using (tag_id)
+                inner join article using (article_id)
+                where name = $1
+                    and not exists(select 1 from article_takedown where article_id = article.article_id)
+            )
+            select
+                $1 "tag!",
+                description,
+                image,
+                articles_count "articles_count!",
+                authors_count "authors_count!"
+            from counts
+            left join tag_metadata on tag = $1
+            where articles_count > 0 or tag is not null
+        "#,
+        tag
+    )
+    .fetch_optional(instrument("tags::find", e))
+    .await
+}
+
+/// Set the description and image of `tag`, replacing whatever it had.
+pub async fn set_metadata(
+    e: impl PgExecutor<'_>,
+    tag: &str,
+    description: Option<&str>,
+    image: Option<&str>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+            insert into tag_metadata (tag, description, image)
+            values ($1, $2, $3)
+            on conflict (tag) do update
+            set description = excluded.description, image = excluded.image
+        "#,
+        tag,
+        description,
+        image
+    )
+    .execute(instrument("tags::set_metadata", e))
+    .await?;
+
+    Ok(())
+}
+
+/// Move the metadata of the tags in `from` to `to`, for `articles::merge_tags()`.
+///
+/// If `to` already has metadata it's kept, otherwise it gets whichever of the others was
+/// updated most recently. The rest is deleted along with the tags.
+pub async fn merge_metadata(e: impl PgExecutor<'_>, from: &[String], to: &str) -> sqlx::Result<()> {
+    // As in `muted_tags::merge()`, the insert sees the table as it was before the delete.
+    sqlx::query!(
+        r#"
+            with deleted as (
+                delete from tag_metadata
+                where tag = any($1) and tag <> $2
+                returning description, image, coalesce(updated_at, created_at) updated_at
+            )
+            insert into tag_metadata (tag, description, image)
+            select $2, description, image
+            from deleted
+            order by updated_at desc
+            limit 1
+            on conflict do nothing
+        "#,
+        from,
+        to
+    )
+    .execute(instrument("tags::merge_metadata", e))
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use sqlx::PgExecutor;
+use time::OffsetDateTime;
+
+use crate::db::instrument::instrument;
+use crate::db::types::UserId;
+
+/// A moderator's decision to take an article down, see `migrations/13_article_takedown.sql`.
+pub struct Takedown {
+    pub slug: String,
+    pub reason: String,
+    pub appeal: Option<String>,
+    pub appealed_at: Option<OffsetDateTime>,
+    pub created_at: OffsetDateTime,
+}
+
+/// Take down the article with the given slug for `reason`.
+///
+/// If it was already taken down, this replaces the reason and dismisses any appeal, which is
+/// how a moderator upholds the decision.
+///
+/// Returns `None` if there's no such article.
+pub async fn take_down(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+    moderator: UserId,
+    reason: &str,
+) -> sqlx::Result<Option<Takedown>> {
+    sqlx::query_as!(
+        Takedown,
+        r#"
+            with upserted as (
+                insert into article_takedown (article_id, moderator_user_id, reason)
+                select article_id, $2, $3
+                from article
+                where slug = $1
+                on conflict (article_id) do update set
+                    moderator_user_id = excluded.moderator_user_id,
+                    reason = excluded.reason,
+                    appeal = null,
+                    appealed_at = null
+                returning *
+            )
+            select
+                $1 "slug!",
+                reason "reason!",
+                appeal,
+                appealed_at,
+                created_at "created_at!"
+            from upserted
+        "#,
+        slug,
+        moderator as UserId,
+        reason
+    )
+    .fetch_optional(instrument("takedowns::take_down", e))
+    .await
+}
+
+/// Put the article with the given slug back up.
+///
+/// Returns `false` if there's no such article, or it wasn't taken down.
+pub async fn restore(e: impl PgExecutor<'_>, slug: &str) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+            delete from article_takedown
+            where article_id = (select article_id from article where slug = $1)
+        "#,
+        slug
+    )
+    .execute(instrument("takedowns::restore", e))
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Look up the takedown of the article with the given slug, but only if `author` wrote it.
+///
+/// This is how the author gets to see why, when nobody else can even see the article.
+pub async fn find_for_author(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+    author: UserId,
+) -> sqlx::Result<Option<Takedown>> {
+    sqlx::query_as!(
+        Takedown,
+        r#"
+            select slug, reason, appeal, appealed_at, article_takedown.created_at
+            from article_takedown
+            inner join article using (article_id)
+            where slug = $1 and article.user_id = $2
+        "#,
+        slug,
+        author as UserId
+    )
+    .fetch_optional(instrument("takedowns::find_for_author", e))
+    .await
+}
+
+/// Appeal the takedown of the article with the given slug on behalf of its `author`, replacing
+/// any earlier appeal.
+///
+/// Returns `None` if there's no such article, `author` didn't write it, or it isn't taken down.
+pub async fn appeal(
+    e: impl PgExecutor<'_>,
+    slug: &str,
+    author: UserId,
+    appeal: &str,
+) -> sqlx::Result<Option<Takedown>> {
+    sqlx::query_as!(
+        Takedown,
+        r#"
+            update article_takedown
+            set appeal = $3, appealed_at = now()
+            from article
+            where article.article_id = article_takedown.article_id
+                and slug = $1
+                and article.user_id = $2
+            returning slug, reason, appeal, appealed_at, article_takedown.created_at
+        "#,
+        slug,
+        author as UserId,
+        appeal
+    )
+    .fetch_optional(instrument("takedowns::appeal", e))
+    .await
+}
+
+/// List the takedowns with an appeal waiting on a moderator, oldest appeal first.
+pub async fn list_appealed(e: impl PgExecutor<'_>) -> sqlx::Result<Vec<Takedown>> {
+    sqlx::query_as!(
+        Takedown,
+        r#"
+            select slug, reason, appeal, appealed_at, article_takedown.created_at
+            from article_takedown
+            inner join article using (article_id)
+            where appealed_at is not null
+            order by appealed_at
+        "#
+    )
+    .fetch_all(instrument("takedowns::list_appealed", e))
+    .await
+}
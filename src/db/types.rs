@@ -0,0 +1,57 @@
+use uuid::Uuid;
+
+// Typed IDs for each of our tables.
+//
+// Bare `Uuid`s are all the same type as far as the compiler is concerned, so there's nothing
+// stopping you from passing a user ID where an article ID is expected, and the query will
+// happily run and just return nothing. Wrapping them in newtypes makes that a compile error.
+//
+// `#[sqlx(transparent)]` and `#[serde(transparent)]` make these encode and decode exactly like
+// the inner type, so this doesn't change the database schema or the API at all.
+//
+// When passing these to `query!()` and friends, you need to use a type override like
+// `auth_user.user_id as UserId`, as the macros otherwise expect the exact type of the column.
+// It looks like a no-op, but it's checked by the compiler, so `article_id as UserId`
+// fails to compile.
+
+/// The primary key of the `user` table.
+#[derive(
+    sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash,
+)]
+#[sqlx(transparent)]
+#[serde(transparent)]
+pub struct UserId(pub Uuid);
+
+/// The primary key of the `article` table.
+#[derive(
+    sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash,
+)]
+#[sqlx(transparent)]
+#[serde(transparent)]
+pub struct ArticleId(pub Uuid);
+
+/// The primary key of the `article_comment` table.
+///
+/// See the comments on `article_comment.comment_id` for why this is an integer.
+#[derive(
+    sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash,
+)]
+#[sqlx(transparent)]
+#[serde(transparent)]
+pub struct CommentId(pub i64);
+
+/// The primary key of the `article_author_invitation` table.
+#[derive(
+    sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash,
+)]
+#[sqlx(transparent)]
+#[serde(transparent)]
+pub struct InvitationId(pub Uuid);
+
+/// The primary key of the `announcement` table.
+#[derive(
+    sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash,
+)]
+#[sqlx(transparent)]
+#[serde(transparent)]
+pub struct AnnouncementId(pub Uuid);
@@ -0,0 +1,101 @@
+use sqlx::PgExecutor;
+
+use crate::db::instrument::instrument;
+use crate::db::types::UserId;
+
+/// One of the steps of onboarding a new user.
+#[derive(serde::Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OnboardingStep {
+    PickedTags,
+    FollowedAuthors,
+    WroteFirstArticle,
+}
+
+/// Which steps of onboarding a user has done.
+pub struct Onboarding {
+    pub picked_tags: bool,
+    pub followed_authors: bool,
+    pub wrote_first_article: bool,
+    /// Whether they've finished or skipped onboarding, whichever steps they did.
+    pub completed: bool,
+}
+
+/// Find out how far `user_id` has got with onboarding, or `None` if there's no such user.
+///
+/// Following anyone or having written any article counts as having done that step, whether or
+/// not the frontend reported it with `complete_onboarding()`.
+pub async fn onboarding(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+) -> sqlx::Result<Option<Onboarding>> {
+    sqlx::query_as!(
+        Onboarding,
+        r#"
+            select
+                picked_tags_at is not null "picked_tags!",
+                followed_authors_at is not null or exists(
+                    select 1 from follow where following_user_id = "user".user_id
+                ) "followed_authors!",
+                wrote_first_article_at is not null or exists(
+                    select 1 from article where user_id = "user".user_id
+                ) "wrote_first_article!",
+                onboarding_completed_at is not null "completed!"
+            from "user"
+            left join user_settings using (user_id)
+            where user_id = $1
+        "#,
+        user_id as UserId
+    )
+    .fetch_optional(instrument("user_settings::onboarding", e))
+    .await
+}
+
+/// Record that `user_id` has done `step` of onboarding, or finished onboarding altogether if
+/// `step` is `None`.
+///
+/// Doing either again keeps the time it was first done.
+pub async fn complete_onboarding(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+    step: Option<OnboardingStep>,
+) -> sqlx::Result<()> {
+    let column = match step {
+        Some(OnboardingStep::PickedTags) => "picked_tags",
+        Some(OnboardingStep::FollowedAuthors) => "followed_authors",
+        Some(OnboardingStep::WroteFirstArticle) => "wrote_first_article",
+        None => "completed",
+    };
+
+    // Picking the column in SQL rather than formatting it into the query means SQLx can still
+    // check it at compile time.
+    sqlx::query!(
+        r#"
+            insert into user_settings (
+                user_id, picked_tags_at, followed_authors_at, wrote_first_article_at, onboarding_completed_at
+            )
+            values (
+                $1,
+                case when $2 = 'picked_tags' then now() end,
+                case when $2 = 'followed_authors' then now() end,
+                case when $2 = 'wrote_first_article' then now() end,
+                case when $2 = 'completed' then now() end
+            )
+            on conflict (user_id) do update set
+                picked_tags_at = coalesce(user_settings.picked_tags_at, excluded.picked_tags_at),
+                followed_authors_at = coalesce(user_settings.followed_authors_at, excluded.followed_authors_at),
+                wrote_first_article_at = coalesce(
+                    user_settings.wrote_first_article_at, excluded.wrote_first_article_at
+                ),
+                onboarding_completed_at = coalesce(
+                    user_settings.onboarding_completed_at, excluded.onboarding_completed_at
+                )
+        "#,
+        user_id as UserId,
+        column
+    )
+    .execute(instrument("user_settings::complete_onboarding", e))
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,633 @@
+use sqlx::{PgExecutor, PgPool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::db::instrument::instrument;
+use crate::db::types::UserId;
+use crate::db::Recent;
+
+/// The synthetic user that anonymized users' articles and comments are reattributed to,
+/// see `migrations/11_ghost_user.sql`.
+pub const GHOST_USER_ID: UserId = UserId(Uuid::nil());
+
+/// A user's own account details.
+pub struct User {
+    pub user_id: UserId,
+    pub email: String,
+    pub username: String,
+    pub bio: String,
+    pub image: Option<String>,
+}
+
+/// A user's public profile, as seen by another user (or nobody, if not logged in).
+pub struct Profile {
+    pub username: String,
+    pub bio: String,
+    pub image: Option<String>,
+    pub following: bool,
+}
+
+/// A `Profile` along with when the user's row last changed, for `find_profile()`.
+#[derive(Clone)]
+pub struct VersionedProfile {
+    pub username: String,
+    pub bio: String,
+    pub image: Option<String>,
+    pub following: bool,
+    /// When the user last changed anything about their account, or signed up if they never
+    /// have. This includes their email and password, which aren't in the profile, but a change
+    /// to either is rare enough that it doesn't matter.
+    pub updated_at: OffsetDateTime,
+}
+
+/// What a user is allowed to do besides the usual, see `migrations/8_user_admin.sql`.
+#[derive(sqlx::Type, clap::ArgEnum, Debug, Copy, Clone, PartialEq, Eq)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+/// The fields of a user that can be changed, where `None` leaves the field as-is.
+pub struct UserUpdate<'a> {
+    pub email: Option<&'a str>,
+    pub username: Option<&'a str>,
+    pub password_hash: Option<&'a str>,
+    pub bio: Option<&'a str>,
+    pub image: Option<&'a str>,
+}
+
+/// Where a new user signed up from, as far as we can tell. See
+/// `migrations/14_user_signup_origin.sql`.
+#[derive(Default)]
+pub struct SignupOrigin<'a> {
+    pub ip: Option<&'a str>,
+    pub device: Option<&'a str>,
+}
+
+/// Insert a new user, returning their ID.
+///
+/// Fails with a violation of the constraint `user_username_key` or `user_email_key`
+/// if either is already taken.
+pub async fn create(
+    e: impl PgExecutor<'_>,
+    username: &str,
+    email: &str,
+    password_hash: &str,
+    origin: &SignupOrigin<'_>,
+) -> sqlx::Result<UserId> {
+    sqlx::query_scalar!(
+        // language=PostgreSQL
+        r#"
+            insert into "user" (username, email, password_hash, signup_ip, signup_device)
+            values ($1, $2, $3, $4, $5)
+            returning user_id "user_id: UserId"
+        "#,
+        username,
+        email,
+        password_hash,
+        origin.ip,
+        origin.device
+    )
+    .fetch_one(instrument("users::create", e))
+    .await
+}
+
+/// Count the users who signed up from `ip` since `since`.
+pub async fn count_recent_signups_by_ip(
+    e: impl PgExecutor<'_>,
+    ip: &str,
+    since: OffsetDateTime,
+) -> sqlx::Result<Recent> {
+    sqlx::query_as!(
+        Recent,
+        r#"
+            select count(*) "count!", min(created_at) oldest
+            from "user"
+            where signup_ip = $1 and created_at > $2
+        "#,
+        ip,
+        since
+    )
+    .fetch_one(instrument("users::count_recent_signups_by_ip", e))
+    .await
+}
+
+/// Count the users who signed up from `device` since `since`.
+pub async fn count_recent_signups_by_device(
+    e: impl PgExecutor<'_>,
+    device: &str,
+    since: OffsetDateTime,
+) -> sqlx::Result<Recent> {
+    sqlx::query_as!(
+        Recent,
+        r#"
+            select count(*) "count!", min(created_at) oldest
+            from "user"
+            where signup_device = $1 and created_at > $2
+        "#,
+        device,
+        since
+    )
+    .fetch_one(instrument("users::count_recent_signups_by_device", e))
+    .await
+}
+
+/// A user's login details: their password hash, and whether they're allowed to log in at all.
+pub struct Credentials {
+    pub password_hash: String,
+    pub banned: bool,
+}
+
+/// Look up a user by email for logging in, along with their credentials.
+pub async fn find_by_email_with_password(
+    e: impl PgExecutor<'_>,
+    email: &str,
+) -> sqlx::Result<Option<(User, Credentials)>> {
+    let row = sqlx::query!(
+        r#"
+            select
+                user_id "user_id: UserId",
+                email,
+                username,
+                bio,
+                image,
+                password_hash,
+                banned_at is not null "banned!"
+            from "user" where email = $1
+        "#,
+        email,
+    )
+    .fetch_optional(instrument("users::find_by_email_with_password", e))
+    .await?;
+
+    Ok(row.map(|row| {
+        (
+            User {
+                user_id: row.user_id,
+                email: row.email,
+                username: row.username,
+                bio: row.bio,
+                image: row.image,
+            },
+            Credentials {
+                password_hash: row.password_hash,
+                banned: row.banned,
+            },
+        )
+    }))
+}
+
+/// Look up a user's credentials, for when they have to confirm their password.
+pub async fn find_credentials(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+) -> sqlx::Result<Option<Credentials>> {
+    sqlx::query_as!(
+        Credentials,
+        r#"select password_hash, banned_at is not null "banned!" from "user" where user_id = $1"#,
+        user_id as UserId
+    )
+    .fetch_optional(instrument("users::find_credentials", e))
+    .await
+}
+
+/// When `user_id` signed up, for `http::articles::restrictions`.
+pub async fn find_created_at(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+) -> sqlx::Result<Option<OffsetDateTime>> {
+    sqlx::query_scalar!(
+        r#"select created_at from "user" where user_id = $1"#,
+        user_id as UserId
+    )
+    .fetch_optional(instrument("users::find_created_at", e))
+    .await
+}
+
+pub async fn find_by_id(e: impl PgExecutor<'_>, user_id: UserId) -> sqlx::Result<Option<User>> {
+    sqlx::query_as!(
+        User,
+        r#"select user_id "user_id: UserId", email, username, bio, image from "user" where user_id = $1"#,
+        user_id as UserId
+    )
+    .fetch_optional(instrument("users::find_by_id", e))
+    .await
+}
+
+pub async fn find_by_username(
+    e: impl PgExecutor<'_>,
+    username: &str,
+) -> sqlx::Result<Option<User>> {
+    sqlx::query_as!(
+        User,
+        r#"select user_id "user_id: UserId", email, username, bio, image from "user" where username = $1"#,
+        username
+    )
+    .fetch_optional(instrument("users::find_by_username", e))
+    .await
+}
+
+/// Update the fields of a user that are set in `update`, returning the updated user.
+///
+/// Fails with the same constraint violations as `create()` if the new username
+/// or email is already taken.
+pub async fn update(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+    update: UserUpdate<'_>,
+) -> sqlx::Result<User> {
+    sqlx::query_as!(
+        User,
+        // This is how we do optional updates of fields without needing a separate query for each.
+        // language=PostgreSQL
+        r#"
+            update "user"
+            set email = coalesce($1, "user".email),
+                username = coalesce($2, "user".username),
+                password_hash = coalesce($3, "user".password_hash),
+                bio = coalesce($4, "user".bio),
+                image = coalesce($5, "user".image)
+            where user_id = $6
+            returning user_id "user_id: UserId", email, username, bio, image
+        "#,
+        update.email,
+        update.username,
+        update.password_hash,
+        update.bio,
+        update.image,
+        user_id as UserId
+    )
+    .fetch_one(instrument("users::update", e))
+    .await
+}
+
+/// Look up a user's profile by username, as seen by `viewer`.
+pub async fn find_profile(
+    e: impl PgExecutor<'_>,
+    username: &str,
+    viewer: Option<UserId>,
+) -> sqlx::Result<Option<VersionedProfile>> {
+    // Since our query columns directly match an existing struct definition,
+    // we can use `query_as!()` and save a bit of manual mapping.
+    sqlx::query_as!(
+        VersionedProfile,
+        r#"
+            select
+                username,
+                bio,
+                image,
+                exists(
+                    select 1 from follow
+                    where followed_user_id = "user".user_id and following_user_id = $2
+                ) "following!", -- This tells SQLx that this column will never be null
+                coalesce(updated_at, created_at) "updated_at!"
+            from "user"
+            where username = $1
+        "#,
+        username,
+        viewer as Option<UserId>
+    )
+    .fetch_optional(instrument("users::find_profile", e))
+    .await
+}
+
+/// Make `follower` follow `followed`, if they aren't already.
+///
+/// Fails with a violation of `user_cannot_follow_self` if they're the same user.
+pub async fn follow(
+    e: impl PgExecutor<'_>,
+    follower: UserId,
+    followed: UserId,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "insert into follow(following_user_id, followed_user_id) values ($1, $2) \
+         on conflict do nothing", // If the row already exists, we don't need to do anything.
+        follower as UserId,
+        followed as UserId
+    )
+    .execute(instrument("users::follow", e))
+    .await?;
+
+    Ok(())
+}
+
+/// Make `follower` stop following `followed`, if they were.
+pub async fn unfollow(
+    e: impl PgExecutor<'_>,
+    follower: UserId,
+    followed: UserId,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "delete from follow where following_user_id = $1 and followed_user_id = $2",
+        follower as UserId,
+        followed as UserId
+    )
+    .execute(instrument("users::unfollow", e))
+    .await?;
+
+    Ok(())
+}
+
+/// What happened to one of the users passed to `follow_many()` or `unfollow_many()`.
+pub struct BatchFollowResult {
+    /// As it was passed in, not necessarily as the user spells it.
+    pub username: String,
+    pub found: bool,
+    /// Whether it's the follower themselves, who can't follow themselves.
+    pub is_self: bool,
+    /// Whether the user was actually followed or unfollowed, rather than already being so.
+    pub changed: bool,
+}
+
+/// Make `follower` follow each of the users named in `usernames`, in one statement.
+///
+/// Returns a result for each username, in the same order. Names that don't exist, and the
+/// follower's own name, are skipped rather than failing the whole batch.
+pub async fn follow_many(
+    e: impl PgExecutor<'_>,
+    follower: UserId,
+    usernames: &[String],
+) -> sqlx::Result<Vec<BatchFollowResult>> {
+    sqlx::query_as!(
+        BatchFollowResult,
+        // `unnest()` gives us a row per username, and `with ordinality` numbers them so we can
+        // return the results in the same order. The comparison has to name the collation since
+        // our parameter doesn't have one, see `migrations/2_user.sql`.
+        r#"
+            with target as (
+                select t.username, t.n, "user".user_id
+                from unnest($2::text[]) with ordinality as t(username, n)
+                left join "user" on "user".username = t.username collate "case_insensitive"
+            ),
+            inserted as (
+                insert into follow (following_user_id, followed_user_id)
+                select $1, user_id
+                from target
+                where user_id is not null and user_id != $1
+                on conflict do nothing
+                returning followed_user_id
+            )
+            select
+                target.username "username!",
+                target.user_id is not null "found!",
+                target.user_id is not distinct from $1 "is_self!",
+                exists(
+                    select 1 from inserted where followed_user_id = target.user_id
+                ) "changed!"
+            from target
+            order by n
+        "#,
+        follower as UserId,
+        usernames
+    )
+    .fetch_all(instrument("users::follow_many", e))
+    .await
+}
+
+/// Make `follower` stop following each of the users named in `usernames`, in one statement.
+///
+/// The counterpart to `follow_many()`, with results in the same form.
+pub async fn unfollow_many(
+    e: impl PgExecutor<'_>,
+    follower: UserId,
+    usernames: &[String],
+) -> sqlx::Result<Vec<BatchFollowResult>> {
+    sqlx::query_as!(
+        BatchFollowResult,
+        r#"
+            with target as (
+                select t.username, t.n, "user".user_id
+                from unnest($2::text[]) with ordinality as t(username, n)
+                left join "user" on "user".username = t.username collate "case_insensitive"
+            ),
+            deleted as (
+                delete from follow
+                where following_user_id = $1
+                    and followed_user_id in (select user_id from target)
+                returning followed_user_id
+            )
+            select
+                target.username "username!",
+                target.user_id is not null "found!",
+                target.user_id is not distinct from $1 "is_self!",
+                exists(
+                    select 1 from deleted where followed_user_id = target.user_id
+                ) "changed!"
+            from target
+            order by n
+        "#,
+        follower as UserId,
+        usernames
+    )
+    .fetch_all(instrument("users::unfollow_many", e))
+    .await
+}
+
+/// Suggest up to `limit` authors for `user_id` to follow, best first.
+///
+/// See `http::profiles::suggestions()` for how they're picked. Their profiles all have
+/// `following: false`, since anyone the user already follows is left out.
+pub async fn suggestions(
+    e: impl PgExecutor<'_>,
+    user_id: UserId,
+    limit: i64,
+) -> sqlx::Result<Vec<Profile>> {
+    // Every part of this is bounded by something other than the size of the whole database:
+    // the user's own follows, the people they follow's follows, their 100 most recent favorites
+    // and the 1000 most recent articles sharing a tag with those. Counting everyone's followers
+    // to find the most followed is the exception, but that only happens for a user who has
+    // neither followed nor favorited anything, for whom there's nothing else to go on.
+    sqlx::query_as!(
+        Profile,
+        r#"
+            with
+                followed as (
+                    select followed_user_id user_id from follow where following_user_id = $1
+                ),
+                favorite_tags as (
+                    select distinct tag_id
+                    from (
+                        select article_id
+                        from article_favorite
+                        where user_id = $1
+                        order by created_at desc
+                        limit 100
+                    ) recent_favorites
+                    inner join article_tag using (article_id)
+                ),
+                candidate as (
+                    -- Someone followed by people the user follows, once for each of them.
+                    select followed_user_id user_id, 2 score
+                    from follow
+                    where following_user_id in (select user_id from followed)
+                    union all
+                    -- The author of a recent article tagged with something the user has
+                    -- favorited before, once for each article.
+                    select user_id, 1
+                    from (
+                        select user_id
+                        from article
+                        where article_id in (
+                            select article_id
+                            from article_tag
+                            where tag_id in (select tag_id from favorite_tags)
+                        )
+                        order by created_at desc
+                        limit 1000
+                    ) tagged
+                    union all
+                    select user_id, 0
+                    from (
+                        select followed_user_id user_id
+                        from follow
+                        where not exists(select 1 from followed)
+                            and not exists(select 1 from favorite_tags)
+                            and followed_user_id != $1
+                        group by followed_user_id
+                        order by count(*) desc
+                        limit $2
+                    ) most_followed
+                )
+            select username, bio, image, false "following!"
+            from (
+                select user_id, sum(score) score from candidate group by user_id
+            ) scored
+            inner join "user" using (user_id)
+            where user_id != $1
+                and user_id not in (select user_id from followed)
+                -- This also leaves out the ghost user.
+                and banned_at is null
+                and exists(select 1 from article where article.user_id = scored.user_id)
+            order by
+                score desc,
+                (select count(*) from follow where followed_user_id = scored.user_id) desc,
+                username
+            limit $2
+        "#,
+        user_id as UserId,
+        limit
+    )
+    .fetch_all(instrument("users::suggestions", e))
+    .await
+}
+
+/// Check whether a user has been banned (or deleted, which amounts to the same thing).
+pub async fn is_banned(e: impl PgExecutor<'_>, user_id: UserId) -> sqlx::Result<bool> {
+    sqlx::query_scalar!(
+        r#"
+            select not exists(
+                select 1 from "user" where user_id = $1 and banned_at is null
+            ) "banned!"
+        "#,
+        user_id as UserId
+    )
+    .fetch_one(instrument("users::is_banned", e))
+    .await
+}
+
+/// Ban or unban the user with the given username.
+///
+/// Banning a user who's already banned keeps the original `banned_at`.
+///
+/// Returns `false` if there's no such user.
+pub async fn set_banned(
+    e: impl PgExecutor<'_>,
+    username: &str,
+    banned: bool,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+            update "user"
+            set banned_at = case when $2 then coalesce(banned_at, now()) end
+            where username = $1
+        "#,
+        username,
+        banned
+    )
+    .execute(instrument("users::set_banned", e))
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Look up a user's role, or `None` if there's no such user.
+pub async fn find_role(e: impl PgExecutor<'_>, user_id: UserId) -> sqlx::Result<Option<Role>> {
+    sqlx::query_scalar!(
+        r#"select role "role: Role" from "user" where user_id = $1"#,
+        user_id as UserId
+    )
+    .fetch_optional(instrument("users::find_role", e))
+    .await
+}
+
+/// Change the role of the user with the given username.
+///
+/// Returns `false` if there's no such user.
+pub async fn set_role(e: impl PgExecutor<'_>, username: &str, role: Role) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"update "user" set role = $2 where username = $1"#,
+        username,
+        role as Role
+    )
+    .execute(instrument("users::set_role", e))
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Remove everything that identifies a user while keeping what they wrote, which is reattributed
+/// to the ghost user (`GHOST_USER_ID`).
+///
+/// This deletes their account, follows and favorites, so their email, username, bio, image and
+/// password hash go with it, and their tokens stop working. Their articles and comments stay up,
+/// with `[deleted]` as the author, so conversations don't lose their context.
+///
+/// It's all or nothing, so this takes a pool and runs in a transaction of its own.
+///
+/// Returns `false` if there's no such user.
+pub async fn anonymize(db: &PgPool, user_id: UserId) -> sqlx::Result<bool> {
+    let mut tx = db.begin().await?;
+
+    // Unfollowing first takes the user's articles out of their followers' feeds (see
+    // `migrations/7_feed_entry.sql`), which wouldn't happen if the follows were only deleted
+    // by cascading from the user, after the articles were no longer theirs.
+    sqlx::query!(
+        "delete from follow where followed_user_id = $1 or following_user_id = $1",
+        user_id as UserId
+    )
+    .execute(instrument("users::anonymize", &mut *tx))
+    .await?;
+
+    sqlx::query!(
+        "update article set user_id = $2 where user_id = $1",
+        user_id as UserId,
+        GHOST_USER_ID as UserId
+    )
+    .execute(instrument("users::anonymize", &mut *tx))
+    .await?;
+
+    sqlx::query!(
+        "update article_comment set user_id = $2 where user_id = $1",
+        user_id as UserId,
+        GHOST_USER_ID as UserId
+    )
+    .execute(instrument("users::anonymize", &mut *tx))
+    .await?;
+
+    // Everything else of theirs, like favorites and editing locks, goes with the account.
+    let deleted = sqlx::query!(
+        r#"delete from "user" where user_id = $1 and user_id != $2"#,
+        user_id as UserId,
+        GHOST_USER_ID as UserId
+    )
+    .execute(instrument("users::anonymize", &mut *tx))
+    .await?
+    .rows_affected()
+        > 0;
+
+    tx.commit().await?;
+
+    Ok(deleted)
+}
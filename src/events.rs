@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::postgres::{PgListener, PgNotification};
+use tokio::sync::broadcast;
+
+use crate::db::types::{ArticleId, CommentId};
+use crate::db::Db;
+
+// If we're running more than one instance of the application, a comment posted through one
+// of them has to reach the WebSocket/SSE clients connected to all the others. We already have
+// something every instance is connected to that can do that for us: Postgres.
+//
+// Triggers in `migrations/5_notify.sql` `NOTIFY` a channel whenever an article or comment is
+// inserted, and each instance holds one connection that `LISTEN`s on those channels and passes
+// whatever it hears to an in-process `tokio::sync::broadcast` channel.
+//
+// Importantly, handlers should *not* publish to the hub directly after they write something.
+// The instance that made the change gets the notification from Postgres like everyone else,
+// so every subscriber sees every event exactly once no matter which instance it's connected to.
+
+/// The channels we `LISTEN` on. Each has a trigger in `migrations/5_notify.sql`.
+const CHANNELS: &[&str] = &["article_published", "comment_added"];
+
+/// How many events a slow subscriber can fall behind before it starts missing them.
+///
+/// Subscribers that lag get a `RecvError::Lagged` and should just carry on from there,
+/// the same as if they'd been disconnected for a moment.
+const CAPACITY: usize = 1024;
+
+/// Something that happened in the database that subscribers may want to know about.
+///
+/// These only carry IDs; look up whatever else you need, as the row may well have been updated
+/// (or deleted) again by the time you get to it.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    ArticlePublished {
+        article_id: ArticleId,
+        slug: String,
+    },
+    CommentAdded {
+        comment_id: CommentId,
+        article_id: ArticleId,
+    },
+}
+
+impl Event {
+    /// Parse the payload of a notification on one of `CHANNELS`.
+    fn parse(channel: &str, payload: &str) -> anyhow::Result<Self> {
+        // The payloads are JSON objects without the tag, since the channel already says
+        // what kind of event it is, so we just splice the channel name in.
+        let mut value: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(payload).context("notification payload is not a JSON object")?;
+        value.insert("type".into(), channel.into());
+
+        serde_json::from_value(value.into()).context("notification payload is invalid")
+    }
+}
+
+/// The in-process broadcast hub for `Event`s, fed by `LISTEN`ing to Postgres.
+///
+/// This is cheap to clone, and all the clones share the same subscribers.
+#[derive(Clone)]
+pub struct EventHub {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventHub {
+    /// Connect a listener to the primary database and spawn a task that passes its
+    /// notifications on to the hub's subscribers for as long as the application is running.
+    ///
+    /// This holds one connection of its own, outside the pool, which needs to be a direct
+    /// connection to Postgres (or to a PgBouncer in session mode), as `LISTEN` is tied to the
+    /// session that ran it.
+    pub async fn listen(db: &Db) -> anyhow::Result<Self> {
+        let mut listener = PgListener::connect_with(db.primary())
+            .await
+            .context("failed to connect to the database to listen for events")?;
+
+        listener
+            .listen_all(CHANNELS.iter().copied())
+            .await
+            .context("failed to listen for events")?;
+
+        let (sender, _) = broadcast::channel(CAPACITY);
+        let hub = EventHub { sender };
+
+        tokio::spawn(hub.clone().forward(listener));
+
+        Ok(hub)
+    }
+
+    /// Get a receiver for all the events from here on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    async fn forward(self, mut listener: PgListener) {
+        loop {
+            // We use `try_recv()` instead of `recv()` so we find out when the connection drops.
+            // `PgListener` reconnects and re-subscribes on the next call by itself, but anything
+            // sent in the meantime is lost, which subscribers may want to know about eventually.
+            let notification = match listener.try_recv().await {
+                Ok(Some(notification)) => notification,
+                Ok(None) => {
+                    log::warn!("lost the connection listening for events, reconnecting");
+                    continue;
+                }
+                Err(e) => {
+                    // This usually means reconnecting failed, e.g. because the database
+                    // is restarting. Back off a bit so we don't spin.
+                    log::error!("error listening for events: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+
+            self.publish(&notification);
+        }
+    }
+
+    fn publish(&self, notification: &PgNotification) {
+        let event = match Event::parse(notification.channel(), notification.payload()) {
+            Ok(event) => event,
+            Err(e) => {
+                log::error!(
+                    "ignoring notification on {:?}: {:#}",
+                    notification.channel(),
+                    e
+                );
+                return;
+            }
+        };
+
+        log::debug!("received event: {:?}", event);
+
+        // This only fails if there are no subscribers right now, which is fine.
+        let _ = self.sender.send(event);
+    }
+}
+
+#[test]
+fn test_parse_event() {
+    let article_id = "0bd6b8b4-4b8a-11ee-9a38-5b0a6a1d0e3c".parse().unwrap();
+
+    assert_eq!(
+        Event::parse(
+            "comment_added",
+            r#"{"comment_id" : 42, "article_id" : "0bd6b8b4-4b8a-11ee-9a38-5b0a6a1d0e3c"}"#
+        )
+        .unwrap(),
+        Event::CommentAdded {
+            comment_id: CommentId(42),
+            article_id: ArticleId(article_id),
+        }
+    );
+
+    assert!(Event::parse("comment_added", r#"{"slug": "foo"}"#).is_err());
+    assert!(Event::parse("article_deleted", r#"{"slug": "foo"}"#).is_err());
+    assert!(Event::parse("comment_added", "not json").is_err());
+}
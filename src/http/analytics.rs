@@ -0,0 +1,183 @@
+use std::fmt::Write;
+
+use axum::extract::{Extension, Query};
+use axum::http::header::{CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue};
+use axum::routing::get;
+use axum::{Json, Router};
+
+use crate::db;
+use crate::http::extractor::AuthUser;
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// Numbers for authors about how their articles and their following are doing, for them and
+// nobody else.
+//
+// We don't record article views anywhere, so there are no view counts here, only what we can
+// work out from the follows, favorites and comments we already keep.
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/user/analytics", get(get_analytics))
+        .route("/api/user/analytics/export", get(export_analytics))
+}
+
+/// The windows `GET /api/user/analytics` can cover, in days.
+///
+/// Only a few, so that frontends all chart the same thing and nobody asks for ten years of
+/// daily rows.
+const WINDOWS: &[i32] = &[7, 30, 90];
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct AnalyticsQuery {
+    /// How many days to cover, up to and including today. One of `WINDOWS`, 30 by default.
+    window: Option<i32>,
+}
+
+#[derive(serde::Serialize)]
+struct AnalyticsBody {
+    analytics: Analytics,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Analytics {
+    window: i32,
+    days: Vec<Day>,
+    articles: Vec<Article>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Day {
+    /// The day in UTC, as `YYYY-MM-DD`.
+    date: String,
+    new_followers: i64,
+    followers: i64,
+    favorites: i64,
+    comments: i64,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Article {
+    slug: String,
+    title: String,
+    /// Favorites and comments added during the window.
+    favorites: i64,
+    comments: i64,
+    /// The same as `favoritesCount` on the article.
+    favorites_count: i64,
+}
+
+/// Summarize how the current user's articles and following have done, for a frontend to chart.
+///
+/// `days` has a row for every day in the window, oldest first, and `articles` has a row for every
+/// one of their articles, newest first; see `db::analytics` for exactly what's counted.
+async fn get_analytics(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    query: Query<AnalyticsQuery>,
+) -> Result<Json<AnalyticsBody>> {
+    let window = query.window.unwrap_or(30);
+
+    if !WINDOWS.contains(&window) {
+        return Err(Error::unprocessable_entity([(
+            "window",
+            "must be 7, 30 or 90 days",
+        )]));
+    }
+
+    let (days, articles) = tokio::try_join!(
+        db::analytics::activity_by_day(ctx.db.read(), auth_user.user_id, window),
+        db::analytics::activity_by_article(ctx.db.read(), auth_user.user_id, window),
+    )?;
+
+    Ok(Json(AnalyticsBody {
+        analytics: Analytics {
+            window,
+            days: days
+                .into_iter()
+                .map(|day| Day {
+                    date: day.day.format("%F"),
+                    new_followers: day.new_followers,
+                    followers: day.followers,
+                    favorites: day.favorites,
+                    comments: day.comments,
+                })
+                .collect(),
+            articles: articles
+                .into_iter()
+                .map(|article| Article {
+                    slug: article.slug,
+                    title: article.title,
+                    favorites: article.favorites,
+                    comments: article.comments,
+                    favorites_count: article.total_favorites,
+                })
+                .collect(),
+        },
+    }))
+}
+
+/// Download the current user's analytics as CSV, for a spreadsheet.
+///
+/// The rows are in "long" format, one number per row, so that different kinds of numbers can
+/// share a file:
+///
+/// ```csv
+/// metric,date,slug,value
+/// followers,2021-11-02,,1
+/// followers,2021-11-05,,3
+/// favorites,,how-to-train-your-dragon,12
+/// ```
+///
+/// * `followers` has the number of followers at the end of each day (UTC) that it changed,
+///   see `db::analytics::followers_by_day()` for exactly what counts.
+/// * `favorites` has the number of favorites each article has now.
+///
+/// Even a prolific author has a few thousand rows at most, so this is put together right
+/// away rather than in the background; it's two index scans.
+async fn export_analytics(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+) -> Result<(HeaderMap, String)> {
+    let (followers, favorites) = tokio::try_join!(
+        db::analytics::followers_by_day(ctx.db.read(), auth_user.user_id),
+        db::analytics::favorites_by_article(ctx.db.read(), auth_user.user_id),
+    )?;
+
+    // Slugs, dates and numbers never need quoting, so there's no need for a CSV library.
+    let mut csv = String::from("metric,date,slug,value\r\n");
+
+    for day in followers {
+        write!(
+            csv,
+            "followers,{},,{}\r\n",
+            day.day.format("%F"),
+            day.followers
+        )
+        .expect("writing to a String can't fail");
+    }
+
+    for article in favorites {
+        write!(csv, "favorites,,{},{}\r\n", article.slug, article.favorites)
+            .expect("writing to a String can't fail");
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/csv; charset=utf-8; header=present"),
+    );
+    headers.insert(
+        CONTENT_DISPOSITION,
+        HeaderValue::from_static("attachment; filename=\"analytics.csv\""),
+    );
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
+
+    Ok((headers, csv))
+}
@@ -0,0 +1,269 @@
+use axum::extract::{Extension, Path, Query};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+
+use crate::db;
+use crate::db::announcements::Severity;
+use crate::db::types::AnnouncementId;
+use crate::http::articles::require_admin;
+use crate::http::extractor::{AuthUser, MaybeAuthUser};
+use crate::http::types::{Cursor, Listing, Page, Timestamptz};
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// Every so often there's something everyone using the site should know about: maintenance
+// next Tuesday, new terms of service, an incident that's being worked on. Admins post those
+// with `POST /api/admin/announcements`, and frontends check `GET /api/announcements/active`
+// on load to show whatever's current as a banner, styled by its `severity`.
+//
+// An announcement can be scheduled in advance with `startsAt`, and comes down by itself at
+// `endsAt` if it has one, so nobody has to be awake at 3am to post the maintenance notice
+// or remember to take it down afterwards.
+//
+// Once a user has read one, the frontend can dismiss it with
+// `POST /api/announcements/:id/dismiss`, and it's left out for them from then on, on every
+// device. Anonymous users can't be told apart, so frontends have to remember their dismissals
+// themselves, e.g. in local storage by `id`.
+
+/// The longest an announcement may be, in characters. It has to fit in a banner.
+const MAX_MESSAGE_LEN: usize = 500;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/announcements/active", get(list_active_announcements))
+        .route("/api/announcements/:id/dismiss", post(dismiss_announcement))
+        .route(
+            "/api/admin/announcements",
+            get(list_announcements).post(create_announcement),
+        )
+        .route(
+            "/api/admin/announcements/:id",
+            put(update_announcement).delete(delete_announcement),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AnnouncementBody<T = Announcement> {
+    announcement: T,
+}
+
+#[derive(serde::Serialize)]
+struct AnnouncementsBody {
+    announcements: Vec<Announcement>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AnnouncementFields {
+    message: String,
+    #[serde(default)]
+    severity: Severity,
+    /// Leave this out to show the announcement straight away.
+    #[serde(default)]
+    starts_at: Option<Timestamptz>,
+    /// Leave this out to show the announcement until it's deleted.
+    #[serde(default)]
+    ends_at: Option<Timestamptz>,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct ListAnnouncementsQuery {
+    limit: Option<i64>,
+    /// Where the previous page left off, from its `nextCursor`.
+    cursor: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Announcement {
+    id: AnnouncementId,
+    message: String,
+    severity: Severity,
+    starts_at: Timestamptz,
+    ends_at: Option<Timestamptz>,
+    created_at: Timestamptz,
+    updated_at: Option<Timestamptz>,
+}
+
+impl From<db::announcements::Announcement> for Announcement {
+    fn from(announcement: db::announcements::Announcement) -> Self {
+        Announcement {
+            id: announcement.announcement_id,
+            message: announcement.message,
+            severity: announcement.severity,
+            starts_at: Timestamptz(announcement.starts_at),
+            ends_at: announcement.ends_at.map(Timestamptz),
+            created_at: Timestamptz(announcement.created_at),
+            updated_at: announcement.updated_at.map(Timestamptz),
+        }
+    }
+}
+
+/// List the announcements showing right now, most severe first, without any the user has
+/// dismissed.
+async fn list_active_announcements(
+    maybe_user: MaybeAuthUser,
+    ctx: Extension<ApiContext>,
+) -> Result<Json<AnnouncementsBody>> {
+    let user_id = maybe_user.user_id();
+
+    // The primary for logged-in users, so one they've just dismissed doesn't come back.
+    let announcements = match user_id {
+        Some(_) => {
+            db::announcements::list_active(ctx.db.primary(), ctx.clock.now(), user_id).await?
+        }
+        None => db::announcements::list_active(ctx.db.read(), ctx.clock.now(), None).await?,
+    };
+
+    Ok(Json(AnnouncementsBody {
+        announcements: announcements.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Stop showing an announcement to the user. Dismissing one twice is fine.
+async fn dismiss_announcement(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(announcement_id): Path<AnnouncementId>,
+) -> Result<()> {
+    if !db::announcements::dismiss(ctx.db.primary(), auth_user.user_id, announcement_id).await? {
+        return Err(Error::NotFound);
+    }
+
+    Ok(())
+}
+
+/// List every announcement, including past and scheduled ones, latest starting first, for
+/// admins.
+///
+/// This isn't a route from the spec, so it's a `Page`, with `?limit=` and `?cursor=`.
+async fn list_announcements(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    query: Query<ListAnnouncementsQuery>,
+) -> Result<Json<Page<Announcement>>> {
+    require_admin(&ctx, &auth_user).await?;
+
+    let after = query
+        .cursor
+        .as_deref()
+        .map(|cursor| Cursor::parse(&ctx, Listing::Announcements, cursor))
+        .transpose()?
+        .map(|cursor| (cursor.sort_key, AnnouncementId(cursor.id)));
+
+    let limit = query.limit.unwrap_or(20);
+
+    let (total, announcements) = tokio::try_join!(
+        db::announcements::count(ctx.db.read()),
+        db::announcements::list(ctx.db.read(), after, limit),
+    )?;
+
+    let announcements = announcements.into_iter().map(Announcement::from).collect();
+
+    Ok(Json(Page::new(announcements, total, limit, |last| {
+        Cursor {
+            sort_key: last.starts_at.0,
+            id: last.id.0,
+        }
+        .format(&ctx, Listing::Announcements)
+    })))
+}
+
+async fn create_announcement(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Json(req): Json<AnnouncementBody<AnnouncementFields>>,
+) -> Result<Json<AnnouncementBody>> {
+    require_admin(&ctx, &auth_user).await?;
+
+    let fields = check_fields(&ctx, &req.announcement)?;
+
+    let announcement =
+        db::announcements::create(ctx.db.primary(), auth_user.user_id, &fields).await?;
+
+    Ok(Json(AnnouncementBody {
+        announcement: announcement.into(),
+    }))
+}
+
+/// Replace an announcement's fields with the ones given, as for `POST`.
+async fn update_announcement(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(announcement_id): Path<AnnouncementId>,
+    Json(req): Json<AnnouncementBody<AnnouncementFields>>,
+) -> Result<Json<AnnouncementBody>> {
+    require_admin(&ctx, &auth_user).await?;
+
+    let fields = check_fields(&ctx, &req.announcement)?;
+
+    let announcement = db::announcements::update(ctx.db.primary(), announcement_id, &fields)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(Json(AnnouncementBody {
+        announcement: announcement.into(),
+    }))
+}
+
+async fn delete_announcement(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(announcement_id): Path<AnnouncementId>,
+) -> Result<()> {
+    require_admin(&ctx, &auth_user).await?;
+
+    if !db::announcements::delete(ctx.db.primary(), announcement_id).await? {
+        return Err(Error::NotFound);
+    }
+
+    Ok(())
+}
+
+fn check_fields<'a>(
+    ctx: &ApiContext,
+    fields: &'a AnnouncementFields,
+) -> Result<db::announcements::AnnouncementFields<'a>> {
+    let mut errors = Vec::new();
+
+    let message = fields.message.trim();
+    let len = message.chars().count();
+
+    if len == 0 || len > MAX_MESSAGE_LEN {
+        errors.push((
+            "message",
+            format!("must be between 1 and {} characters", MAX_MESSAGE_LEN),
+        ));
+    }
+
+    let starts_at = fields
+        .starts_at
+        .as_ref()
+        .map_or_else(|| ctx.clock.now(), |starts_at| starts_at.0);
+
+    let ends_at = fields.ends_at.as_ref().map(|ends_at| ends_at.0);
+
+    // The table has a check constraint for this too, but that would be a `500`.
+    if ends_at.is_some_and(|ends_at| ends_at <= starts_at) {
+        errors.push(("endsAt", "must be after startsAt".into()));
+    }
+
+    if !errors.is_empty() {
+        return Err(Error::unprocessable_entity(errors));
+    }
+
+    Ok(db::announcements::AnnouncementFields {
+        message,
+        severity: fields.severity,
+        starts_at,
+        ends_at,
+    })
+}
+
+/// See `articles::fuzz_request_bodies()`.
+#[cfg(feature = "fuzzing")]
+pub(super) fn fuzz_request_bodies(data: &[u8]) {
+    serde_json::from_slice::<AnnouncementBody<AnnouncementFields>>(data).ok();
+}
@@ -0,0 +1,247 @@
+use axum::extract::{Extension, Path};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use sqlx::PgExecutor;
+
+use crate::db;
+use crate::db::articles::ArticleMeta;
+use crate::db::types::{InvitationId, UserId};
+use crate::http::articles::{Article, ArticleBody};
+use crate::http::extractor::AuthUser;
+use crate::http::types::{Slug, Timestamptz};
+use crate::http::{ApiContext, Error, Result, ResultExt};
+use crate::mail::Message;
+
+// Not part of the Realworld spec.
+//
+// An article's author can ask other users to help write it, by inviting them with
+// `POST /api/articles/:slug/authors/invite`. Nobody gets added to an article without agreeing
+// to it, so that just leaves an invitation waiting for them, which we email them about. It shows
+// up in their `GET /api/invitations`, where they can accept it with
+// `POST /api/invitations/:id/accept` or decline it with `DELETE /api/invitations/:id`. The author
+// can take it back the same way, if they haven't accepted it yet.
+//
+// Once they've accepted, they're a co-author, which means they can edit the article and take the
+// editing lock on it (see `locks`), just like its author. Everything else about the article
+// stays with its author: it's still shown as theirs, only their followers get it in their feed,
+// and only they can delete it, invite anyone else, or appeal a takedown.
+
+/// The most invitations an article can have waiting for an answer at once.
+///
+/// Every one of them is an email to someone who didn't ask for it, so this stops an article
+/// being used to send those to everybody.
+const MAX_PENDING_INVITATIONS: i64 = 10;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/articles/:slug/authors/invite", post(invite_author))
+        .route("/api/invitations", get(list_invitations))
+        .route("/api/invitations/:invitation_id", delete(delete_invitation))
+        .route(
+            "/api/invitations/:invitation_id/accept",
+            post(accept_invitation),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct InvitationBody<T = Invitation> {
+    invitation: T,
+}
+
+#[derive(serde::Serialize)]
+struct InvitationsBody {
+    invitations: Vec<Invitation>,
+}
+
+#[derive(serde::Deserialize)]
+struct Invite {
+    /// Who to invite.
+    username: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Invitation {
+    id: InvitationId,
+    slug: String,
+    title: String,
+    /// The username of the article's author, who sent the invitation.
+    inviter: String,
+    invitee: String,
+    created_at: Timestamptz,
+}
+
+impl From<db::authors::Invitation> for Invitation {
+    fn from(invitation: db::authors::Invitation) -> Self {
+        Invitation {
+            id: invitation.invitation_id,
+            slug: invitation.article_slug,
+            title: invitation.article_title,
+            inviter: invitation.inviter_username,
+            invitee: invitation.invitee_username,
+            created_at: Timestamptz(invitation.created_at),
+        }
+    }
+}
+
+/// Check that `user_id` may edit `article`, i.e. that they wrote it or are one of its co-authors.
+pub(super) async fn check_can_edit(
+    e: impl PgExecutor<'_>,
+    article: &ArticleMeta,
+    user_id: UserId,
+) -> Result<()> {
+    if article.user_id == user_id
+        || db::authors::is_coauthor(e, article.article_id, user_id).await?
+    {
+        Ok(())
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
+/// Invite a user to co-author an article, and email them about it.
+async fn invite_author(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(slug): Path<Slug>,
+    Json(req): Json<InvitationBody<Invite>>,
+) -> Result<Json<InvitationBody>> {
+    let article = db::articles::find_meta(ctx.db.primary(), slug.as_str())
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    // Only the author, not co-authors; it's their article.
+    if article.user_id != auth_user.user_id {
+        return Err(Error::Forbidden);
+    }
+
+    // A banned user couldn't log in to accept, so there's no point telling them about it.
+    let invitee = match db::users::find_by_username(ctx.db.primary(), &req.invitation.username)
+        .await?
+    {
+        Some(invitee) if !db::users::is_banned(ctx.db.primary(), invitee.user_id).await? => invitee,
+        _ => return Err(Error::unprocessable_entity([("username", "no such user")])),
+    };
+
+    if invitee.user_id == article.user_id
+        || db::authors::is_coauthor(ctx.db.primary(), article.article_id, invitee.user_id).await?
+    {
+        return Err(Error::unprocessable_entity([(
+            "username",
+            "is already an author of this article",
+        )]));
+    }
+
+    // Two invitations at once could both get in under the limit, which isn't worth a lock.
+    if db::authors::count_pending(ctx.db.primary(), article.article_id).await?
+        >= MAX_PENDING_INVITATIONS
+    {
+        return Err(Error::unprocessable_entity([(
+            "username",
+            format!(
+                "an article can only have {} invitations waiting for an answer",
+                MAX_PENDING_INVITATIONS
+            ),
+        )]));
+    }
+
+    let invitation = db::authors::invite(
+        ctx.db.primary(),
+        article.article_id,
+        auth_user.user_id,
+        invitee.user_id,
+    )
+    .await
+    .on_constraint(
+        "article_author_invitation_article_id_invitee_id_key",
+        |_| Error::unprocessable_entity([("username", "has already been invited")]),
+    )?;
+
+    // The invitation is there whether or not this gets through, so it's not worth failing
+    // the request over.
+    let sent = ctx
+        .mailer
+        .send(Message {
+            to: invitee.email,
+            subject: format!(
+                "{} invited you to co-author an article",
+                invitation.inviter_username
+            ),
+            body: format!(
+                "Hi {},\n\n\
+                 {} has invited you to help write their article \"{}\". You'll find the \
+                 invitation with any others you have, where you can accept or decline it.\n\n\
+                 If you accept, you'll be able to edit the article. If you don't want to, you \
+                 can ignore this email.\n",
+                invitation.invitee_username, invitation.inviter_username, invitation.article_title
+            ),
+        })
+        .await;
+
+    if let Err(e) = sent {
+        log::warn!(
+            "failed to email {} about an invitation to co-author {}: {:#}",
+            invitation.invitee_username,
+            invitation.article_slug,
+            e
+        );
+    }
+
+    Ok(Json(InvitationBody {
+        invitation: invitation.into(),
+    }))
+}
+
+/// List the invitations to co-author articles that the user hasn't answered yet, oldest first.
+async fn list_invitations(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+) -> Result<Json<InvitationsBody>> {
+    // From the primary, so an invitation that was just accepted or declined doesn't come back.
+    let invitations = db::authors::list_invitations(ctx.db.primary(), auth_user.user_id).await?;
+
+    Ok(Json(InvitationsBody {
+        invitations: invitations.into_iter().map(Invitation::from).collect(),
+    }))
+}
+
+/// Accept an invitation to co-author an article, returning the article.
+///
+/// This is a `404 Not Found` if the invitation isn't the user's, so nobody can find out which
+/// invitations exist by guessing.
+async fn accept_invitation(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(invitation_id): Path<InvitationId>,
+) -> Result<Json<ArticleBody>> {
+    let slug = db::authors::accept(ctx.db.primary(), invitation_id, auth_user.user_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let article = db::articles::find_by_slug(ctx.db.primary(), &slug, Some(auth_user.user_id))
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(Json(ArticleBody {
+        article: Article::new(article, &ctx.config),
+    }))
+}
+
+/// Decline an invitation to co-author an article, or take it back if the user sent it.
+async fn delete_invitation(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(invitation_id): Path<InvitationId>,
+) -> Result<()> {
+    if db::authors::delete_invitation(ctx.db.primary(), invitation_id, auth_user.user_id).await? {
+        Ok(())
+    } else {
+        Err(Error::NotFound)
+    }
+}
+
+/// See `articles::fuzz_request_bodies()`.
+#[cfg(feature = "fuzzing")]
+pub(super) fn fuzz_request_bodies(data: &[u8]) {
+    serde_json::from_slice::<InvitationBody<Invite>>(data).ok();
+}
@@ -1,13 +1,18 @@
+use crate::db;
+use crate::http::articles::restrictions;
 use crate::http::extractor::{AuthUser, MaybeAuthUser};
 use crate::http::profiles::Profile;
-use crate::http::types::Timestamptz;
+use crate::http::quota;
+use crate::http::types::{json_array_stream, CommentId, Slug, Timestamptz};
 use crate::http::ApiContext;
 use crate::http::{Error, Result};
 use axum::extract::{Extension, Path};
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
 use axum::routing::{delete, get};
 use axum::{Json, Router};
 use futures::TryStreamExt;
-use time::OffsetDateTime;
+use metrics::increment_counter;
 
 pub fn router() -> Router {
     // Unlike those in `listing`, these routes are fortunately all self-contained
@@ -27,11 +32,6 @@ struct CommentBody<T = Comment> {
     comment: T,
 }
 
-#[derive(serde::Serialize)]
-struct MultipleCommentsBody {
-    comments: Vec<Comment>,
-}
-
 #[derive(serde::Deserialize)]
 struct AddComment {
     body: String,
@@ -40,38 +40,26 @@ struct AddComment {
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct Comment {
-    id: i64,
+    id: CommentId,
     created_at: Timestamptz,
     updated_at: Timestamptz,
     body: String,
     author: Profile,
 }
 
-// Same thing as `ArticleFromQuery`
-struct CommentFromQuery {
-    comment_id: i64,
-    created_at: OffsetDateTime,
-    updated_at: OffsetDateTime,
-    body: String,
-    author_username: String,
-    author_bio: String,
-    author_image: Option<String>,
-    following_author: bool,
-}
-
-impl CommentFromQuery {
-    fn into_comment(self) -> Comment {
+impl From<db::comments::Comment> for Comment {
+    fn from(comment: db::comments::Comment) -> Self {
         Comment {
-            id: self.comment_id,
+            id: comment.comment_id,
             // doing this conversion in-code does save having to use the type overrides in query
-            created_at: Timestamptz(self.created_at),
-            updated_at: Timestamptz(self.updated_at),
-            body: self.body,
+            created_at: Timestamptz(comment.created_at),
+            updated_at: Timestamptz(comment.updated_at),
+            body: comment.body,
             author: Profile {
-                username: self.author_username,
-                bio: self.author_bio,
-                image: self.author_image,
-                following: self.following_author,
+                username: comment.author_username,
+                bio: comment.author_bio,
+                image: comment.author_image,
+                following: comment.following_author,
             },
         }
     }
@@ -81,115 +69,69 @@ impl CommentFromQuery {
 async fn get_article_comments(
     maybe_auth_user: MaybeAuthUser,
     ctx: Extension<ApiContext>,
-    Path(slug): Path<String>,
-) -> Result<Json<MultipleCommentsBody>> {
-    // With this, we can return 404 if the article slug was not found.
-    let article_id = sqlx::query_scalar!("select article_id from article where slug = $1", slug)
-        .fetch_optional(&ctx.db)
-        .await?
-        .ok_or(Error::NotFound)?;
-
-    let comments = sqlx::query_as!(
-        CommentFromQuery,
-        r#"
-            select
-                comment_id,
-                comment.created_at,
-                comment.updated_at,
-                comment.body,
-                author.username author_username,
-                author.bio author_bio,
-                author.image author_image,
-                exists(select 1 from follow where followed_user_id = author.user_id and following_user_id = $1) "following_author!"
-            from article_comment comment
-            inner join "user" author using (user_id)
-            where article_id = $2
-            order by created_at
-        "#,
+    Path(slug): Path<Slug>,
+) -> Result<impl IntoResponse> {
+    // This is the one list in the Realworld spec without any pagination, so we stream it
+    // instead. The response is the same `{"comments": [...]}` as if we'd used `Json`.
+    //
+    // The query starts running in the background as soon as we call this, so it doesn't have
+    // to wait for us to check that the article exists.
+    let comments = db::comments::stream_for_article(
+        ctx.db.primary().clone(),
+        slug.as_str().to_string(),
         maybe_auth_user.user_id(),
-        article_id
     )
-        .fetch(&ctx.db)
-        .map_ok(CommentFromQuery::into_comment)
-        .try_collect()
-        .await?;
+    .map_ok(Comment::from);
 
-    Ok(Json(MultipleCommentsBody { comments }))
+    // With this, we can return 404 if the article slug was not found, rather than an empty list.
+    // Dropping `comments` stops the query.
+    db::articles::find_id_by_slug(ctx.db.primary(), slug.as_str())
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(json_array_stream("comments", comments))
 }
 
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#add-comments-to-an-article
 async fn add_comment(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
-    Path(slug): Path<String>,
+    Path(slug): Path<Slug>,
     req: Json<CommentBody<AddComment>>,
-) -> Result<Json<CommentBody>> {
-    let comment = sqlx::query_as!(
-        CommentFromQuery,
-        r#"
-            with inserted_comment as (
-                insert into article_comment(article_id, user_id, body)
-                select article_id, $1, $2
-                from article
-                where slug = $3
-                returning comment_id, created_at, updated_at, body
-            )
-            select
-                comment_id,
-                comment.created_at,
-                comment.updated_at,
-                body,
-                author.username author_username,
-                author.bio author_bio,
-                author.image author_image,
-                false "following_author!"
-            from inserted_comment comment
-            inner join "user" author on user_id = $1
-        "#,
+) -> Result<(HeaderMap, Json<CommentBody>)> {
+    restrictions::check_comment(&ctx, auth_user.user_id, &req.comment.body).await?;
+
+    let quota = quota::check_comments(&ctx, auth_user.user_id).await?;
+
+    let comment = db::comments::create(
+        ctx.db.primary(),
+        slug.as_str(),
         auth_user.user_id,
-        req.comment.body,
-        slug
+        &req.comment.body,
     )
-    .fetch_optional(&ctx.db)
     .await?
     // In this case, we know a comment should have been inserted unless the article slug
     // was not found.
     .ok_or(Error::NotFound)?
-    .into_comment();
+    .into();
 
-    Ok(Json(CommentBody { comment }))
+    increment_counter!("comments_added_total");
+
+    Ok((quota, Json(CommentBody { comment })))
 }
 
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#delete-comment
 async fn delete_comment(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
-    Path((slug, comment_id)): Path<(String, i64)>,
+    Path((slug, comment_id)): Path<(Slug, CommentId)>,
 ) -> Result<()> {
-    // Identical technique to `articles::delete_article()`
-    let result = sqlx::query!(
-        r#"
-            with deleted_comment as (
-                delete from article_comment
-                where 
-                    comment_id = $1
-                    and article_id in (select article_id from article where slug = $2)
-                    and user_id = $3
-                returning 1 
-            )
-            select 
-                exists(
-                    select 1 from article_comment
-                    inner join article using (article_id)
-                    where comment_id = $1 and slug = $2
-                ) "existed!",
-                exists(select 1 from deleted_comment) "deleted!"
-        "#,
+    let result = db::comments::delete(
+        ctx.db.primary(),
+        slug.as_str(),
         comment_id,
-        slug,
-        auth_user.user_id
+        auth_user.user_id,
     )
-    .fetch_one(&ctx.db)
     .await?;
 
     if result.deleted {
@@ -200,3 +142,9 @@ async fn delete_comment(
         Err(Error::NotFound)
     }
 }
+
+/// See `articles::fuzz_request_bodies()`.
+#[cfg(feature = "fuzzing")]
+pub(super) fn fuzz_request_bodies(data: &[u8]) {
+    serde_json::from_slice::<CommentBody<AddComment>>(data).ok();
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Extension, Query};
+use axum::routing::get;
+use axum::{Json, Router};
+use url::Url;
+
+use crate::db;
+use crate::db::links::{LinkKind, NewLink};
+use crate::http::articles::takedowns::require_moderator;
+use crate::http::extractor::AuthUser;
+use crate::http::types::Timestamptz;
+use crate::http::{ApiContext, Result};
+
+// Not part of the Realworld spec.
+//
+// Every time an article is created or its body is edited, we pull the external links and images
+// out of it and keep them in `article_link`. Spam is mostly links, so this lets moderators look
+// through what's being linked to, or everything linking to a domain they've just found out is
+// hosting malware, without grepping every article body. Taking down what they find is then
+// up to `takedowns`.
+//
+// The stored links are also what a checker for dead links would work from, flagging them to
+// their authors. That needs an HTTP client, something to run it in the background and a way to
+// tell authors about it, none of which we have yet.
+
+pub fn router() -> Router {
+    Router::new().route("/api/admin/links", get(list_links))
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct ListLinksQuery {
+    /// Only list links to this host, e.g. `example.com`. Subdomains are hosts of their own.
+    host: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct LinksBody {
+    links: Vec<Link>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Link {
+    /// The slug of the article the link is in.
+    slug: String,
+    kind: LinkKind,
+    url: String,
+    host: String,
+    /// When the link was added to the article.
+    created_at: Timestamptz,
+}
+
+/// List the links in all articles, most recently added first, for moderators.
+///
+/// Paginated with `limit` and `offset` like `GET /api/articles`. This includes the links in
+/// articles that have been taken down, so moderators can tell what's already been dealt with.
+async fn list_links(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    query: Query<ListLinksQuery>,
+) -> Result<Json<LinksBody>> {
+    require_moderator(&ctx, &auth_user).await?;
+
+    let host = query.host.as_deref().map(str::to_lowercase);
+
+    let links = db::links::list(
+        ctx.db.read(),
+        host.as_deref(),
+        query.limit.unwrap_or(20),
+        query.offset.unwrap_or(0),
+    )
+    .await?;
+
+    Ok(Json(LinksBody {
+        links: links
+            .into_iter()
+            .map(|link| Link {
+                slug: link.slug,
+                kind: link.kind,
+                url: link.url,
+                host: link.host,
+                created_at: Timestamptz(link.created_at),
+            })
+            .collect(),
+    }))
+}
+
+/// Find the external links and images in an article body, in no particular order.
+///
+/// "External" means an absolute `http:` or `https:` URL to somewhere other than `base_url`.
+/// Relative links can only lead back here, and anything else (`mailto:`, `javascript:`, ...)
+/// isn't something we could check or a frontend should render anyway.
+pub(super) fn extract(body: &str, base_url: &str) -> Vec<NewLink> {
+    let mut html = String::with_capacity(body.len());
+    pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(body));
+
+    // Markdown allows raw HTML, which `pulldown-cmark` passes through, so rather than pick links
+    // out of its events we look at the HTML as `ammonia` sees it when sanitizing (the same way as
+    // `MarkdownBody`), which catches `<a href>` and `<img src>` too. The callback has to be
+    // `'static`, hence the `Arc`.
+    let found = Arc::new(Mutex::new(Vec::new()));
+
+    ammonia::Builder::default()
+        .attribute_filter({
+            let found = found.clone();
+
+            move |element, attribute, value| {
+                let kind = match (element, attribute) {
+                    ("a", "href") => Some(LinkKind::Link),
+                    ("img", "src") => Some(LinkKind::Image),
+                    _ => None,
+                };
+
+                if let Some(kind) = kind {
+                    found.lock().unwrap().push((kind, value.to_string()));
+                }
+
+                Some(value.into())
+            }
+        })
+        .clean(&html);
+
+    let our_host = Url::parse(base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string));
+
+    let found = std::mem::take(&mut *found.lock().unwrap());
+
+    found
+        .into_iter()
+        .filter_map(|(kind, url)| {
+            let url = Url::parse(&url).ok()?;
+
+            if !matches!(url.scheme(), "http" | "https") {
+                return None;
+            }
+
+            // `Url` has already lowercased it.
+            let host = url.host_str()?.to_string();
+
+            if Some(&host) == our_host.as_ref() {
+                return None;
+            }
+
+            Some(NewLink {
+                kind,
+                url: url.into(),
+                host,
+            })
+        })
+        // The same link is often in an article more than once.
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+#[test]
+fn test_extract() {
+    let body = r#"
+Check out [my site](https://Example.com/about) and <https://example.com/about>,
+not [this one](https://realworld.example/a/123) or [this](/a/123) or [this](mailto:me@example.com).
+
+![a cat](http://cats.example/cat.png "A cat")
+
+<a href="https://raw.example/page">raw HTML</a> <img src="https://raw.example/img.gif">
+<a href="javascript:alert(1)">nope</a>
+"#;
+
+    let mut links = extract(body, "https://realworld.example");
+    links.sort_by(|a, b| a.url.cmp(&b.url));
+
+    let link = |kind, url: &str, host: &str| NewLink {
+        kind,
+        url: url.to_string(),
+        host: host.to_string(),
+    };
+
+    assert_eq!(
+        links,
+        [
+            link(
+                LinkKind::Image,
+                "http://cats.example/cat.png",
+                "cats.example"
+            ),
+            link(LinkKind::Link, "https://example.com/about", "example.com"),
+            link(
+                LinkKind::Image,
+                "https://raw.example/img.gif",
+                "raw.example"
+            ),
+            link(LinkKind::Link, "https://raw.example/page", "raw.example"),
+        ]
+    );
+}
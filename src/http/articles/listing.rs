@@ -1,12 +1,13 @@
 use axum::extract::{Extension, Query};
 use axum::Json;
-use futures::TryStreamExt;
 
-use crate::http;
-use crate::http::articles::{Article, ArticleFromQuery};
+use crate::db;
+use crate::db::types::UserId;
+use crate::http::articles::Article;
 use crate::http::extractor::{AuthUser, MaybeAuthUser};
-use crate::http::types::Timestamptz;
+use crate::http::types::{ArticleId, Cursor, Listing};
 use crate::http::ApiContext;
+use crate::http::{self, Error};
 
 #[derive(serde::Deserialize, Default)]
 #[serde(default)]
@@ -31,6 +32,12 @@ pub struct ListArticlesQuery {
     // However, this is what the Realworld spec calls for.
     limit: Option<i64>,
     offset: Option<i64>,
+
+    // So we do both: `cursor` below works like it does for the feed, and clients that only know
+    // the spec can keep using `offset`.
+    /// Where the previous page left off, from its `nextCursor`. Also accepted as `after`.
+    #[serde(alias = "after")]
+    cursor: Option<String>,
 }
 
 // This is technically a subset of `ListArticlesQuery` so we could do some composition
@@ -42,6 +49,23 @@ pub struct FeedArticlesQuery {
     // See comment on these fields in `ListArticlesQuery` above.
     limit: Option<i64>,
     offset: Option<i64>,
+
+    // The feed does what that comment suggests: each response includes a `nextCursor` that
+    // the frontend can pass back here to get the next page. This isn't in the Realworld spec,
+    // but clients that don't know about it can just keep using `offset`.
+    #[serde(alias = "after")]
+    cursor: Option<String>,
+}
+
+// Not part of the Realworld spec, see `search_articles()`.
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub struct SearchArticlesQuery {
+    q: Option<String>,
+    // See comment on these fields in `ListArticlesQuery` above. Results are ordered by relevance,
+    // which isn't a column, so there's no cursor here either.
+    limit: Option<i64>,
+    offset: Option<i64>,
 }
 
 #[derive(serde::Serialize)]
@@ -70,9 +94,24 @@ pub struct MultipleArticlesBody {
     // don't usually care where they are in the total ordering of things, or if they do
     // then the scrollbar is already an intuitive indication of where they're at.
     //
-    // The Postman collection doesn't test pagination, so as a cop-out I've decided to just
-    // return the count of articles currently being returned, which satisfies the happy-path tests.
-    articles_count: usize,
+    // The Postman collection doesn't test pagination, so as a cop-out I originally decided to
+    // just return the count of articles currently being returned, which satisfies the happy-path
+    // tests. That broke any client that actually tried to show page numbers, though.
+    //
+    // Now every listing returns the total. For `GET /api/articles`, see `count_articles()` for
+    // the catch. The feed is capped at `db::feed::MAX_ENTRIES_PER_USER`, so counting it exactly
+    // is always cheap enough.
+    articles_count: i64,
+
+    /// Pass this as `?cursor=` to get the next page. Only the feed and `GET /api/articles`
+    /// return this, and only if there might be a next page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+
+    /// What the user might have meant to search for instead, best first. Only the search
+    /// returns this, and only when it found fewer than `SUGGESTIONS_BELOW` articles.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestions: Option<Vec<String>>,
 }
 
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#list-articles
@@ -82,138 +121,271 @@ pub(in crate::http) async fn list_articles(
     ctx: Extension<ApiContext>,
     query: Query<ListArticlesQuery>,
 ) -> http::Result<Json<MultipleArticlesBody>> {
-    let articles: Vec<_> = sqlx::query_as!(
-        ArticleFromQuery,
-        // language=PostgreSQL
-        r#"
-            select
-                slug,
-                title,
-                description,
-                body,
-                tag_list,
-                article.created_at "created_at: Timestamptz",
-                article.updated_at "updated_at: Timestamptz",
-                exists(select 1 from article_favorite where user_id = $1) "favorited!",
-                coalesce(
-                    -- `count(*)` returns `NULL` if the query returned zero columns
-                    -- not exactly a fan of that design choice but whatever
-                    (select count(*) from article_favorite fav where fav.article_id = article.article_id),
-                    0
-                ) "favorites_count!",
-                author.username author_username,
-                author.bio author_bio,
-                author.image author_image,
-                exists(select 1 from follow where followed_user_id = author.user_id and following_user_id = $1) "following_author!"
-            from article
-            inner join "user" author using (user_id)
-            -- the current way to do conditional filtering in SQLx
-            where (
-                -- check if `query.tag` is null or contains the given tag
-                -- PostgresSQL doesn't have an "array contains element" operator
-                -- so instead we check if the tag_list contains an array of just the given tag
-                $2::text is null or tag_list @> array[$2]
-            )
-              and
-            (
-                $3::text is null or author.username = $3
-            )
-              and
-            (
-                $4::text is null or exists(
-                    select 1
-                    from "user"
-                    inner join article_favorite af using (user_id)
-                    where username = $4
-                )
-            )
-            order by article.created_at desc
-            limit $5
-            offset $6
-        "#,
-        maybe_auth_user.user_id(),
-        query.tag,
-        query.author,
-        query.favorited,
-        query.limit.unwrap_or(20),
-        query.offset.unwrap_or(0)
-    )
-        // We fetch a `Stream` this time so that we can map it on-the-fly
-        // without collecting to an intermediate `Vec` first.
-        .fetch(&ctx.db)
-        .map_ok(ArticleFromQuery::into_article)
-        .try_collect()
-        .await?;
+    let mut muted = muted_tags(&ctx, maybe_auth_user.user_id()).await?;
+
+    // Asking for a tag by name overrides muting it.
+    muted.retain(|tag| Some(tag) != query.tag.as_ref());
+
+    let filter = db::articles::ListFilter {
+        tag: query.tag.as_deref(),
+        author: query.author.as_deref(),
+        favorited: query.favorited.as_deref(),
+        muted: &muted,
+        cursor: query
+            .cursor
+            .as_deref()
+            .map(|cursor| parse_cursor_param(&ctx, Listing::Articles, cursor))
+            .transpose()?,
+        limit: query.limit.unwrap_or(20),
+        offset: query.offset.unwrap_or(0),
+    };
+
+    // Neither of these needs the result of the other, so there's no reason to wait for one
+    // before starting the other. See the comment at the top of `db` for how this works.
+    //
+    // Some combinations of filters can make for a slow query, so if the client gives up on the
+    // request, we cancel it rather than leave Postgres working on it. See `db::cancel`.
+    let (articles_count, articles) = tokio::try_join!(count_articles(&ctx, &filter), async {
+        let mut conn = ctx.db.cancellable(ctx.db.read()).await?;
+        let viewer = maybe_auth_user.user_id();
+
+        Ok(conn
+            .run(|conn| Box::pin(db::articles::list(conn, viewer, &filter)))
+            .await?)
+    },)?;
+
+    let next_cursor = next_cursor(&ctx, Listing::Articles, &articles, filter.limit);
+
+    let articles: Vec<Article> = articles
+        .into_iter()
+        .map(|article| Article::new(article, &ctx.config))
+        .collect();
+
+    // `articles_count` is the total for the filters, wherever the cursor is, so a client can
+    // show "page 3 of 12" either way.
+    Ok(Json(MultipleArticlesBody {
+        articles_count,
+        articles,
+        next_cursor,
+        suggestions: None,
+    }))
+}
+
+/// A search that finds fewer articles than this also suggests what the user might have meant.
+///
+/// One or two results for a misspelled word are usually articles that happen to share the typo.
+const SUGGESTIONS_BELOW: i64 = 3;
+
+/// The most suggestions a search returns.
+const MAX_SUGGESTIONS: i64 = 5;
+
+/// The longest search query we'll run, in characters. Every word is another lookup in the index,
+/// and nobody types more than a sentence into a search box.
+const MAX_SEARCH_QUERY_LEN: usize = 200;
+
+// Not part of the Realworld spec.
+//
+// `GET /api/articles/search?q=` finds articles by the words in their title, description and
+// body, most relevant first, as `db::articles::search()` explains. Otherwise it's like
+// `GET /api/articles`: it takes `limit` and `offset`, leaves out taken-down articles and muted
+// tags, and shows whether the user has favorited each article and follows its author.
+//
+// When it finds next to nothing, it also returns `suggestions`: tags and article titles that look
+// like what the user typed, in case it was a typo, as `db::articles::suggest()` explains. Frontends
+// can offer those as "did you mean" links that search again.
+pub(in crate::http) async fn search_articles(
+    maybe_auth_user: MaybeAuthUser,
+    ctx: Extension<ApiContext>,
+    query: Query<SearchArticlesQuery>,
+) -> http::Result<Json<MultipleArticlesBody>> {
+    let q = query.q.as_deref().map(str::trim).unwrap_or_default();
+
+    if q.is_empty() || q.chars().count() > MAX_SEARCH_QUERY_LEN {
+        return Err(Error::unprocessable_entity([(
+            "q",
+            format!(
+                "search query must be between 1 and {} characters",
+                MAX_SEARCH_QUERY_LEN
+            ),
+        )]));
+    }
+
+    let muted = muted_tags(&ctx, maybe_auth_user.user_id()).await?;
+
+    let viewer = maybe_auth_user.user_id();
+    let limit = query.limit.unwrap_or(20);
+    let offset = query.offset.unwrap_or(0);
+
+    // Unlike `count_articles()`, this always counts exactly: a search only has to count the
+    // articles that matched, which the index finds for us. A query with a lot of common words
+    // can still take a while, though, so both are cancelled if the client goes away.
+    let (articles_count, articles) = tokio::try_join!(
+        async {
+            let mut conn = ctx.db.cancellable(ctx.db.read()).await?;
+            conn.run(|conn| Box::pin(db::articles::count_search(conn, q, &muted)))
+                .await
+        },
+        async {
+            let mut conn = ctx.db.cancellable(ctx.db.read()).await?;
+            conn.run(|conn| Box::pin(db::articles::search(conn, viewer, q, &muted, limit, offset)))
+                .await
+        },
+    )?;
+
+    // Only worth the extra query when the search came up (nearly) empty, which is also when the
+    // user needs it.
+    let suggestions = if articles_count < SUGGESTIONS_BELOW {
+        let mut conn = ctx.db.cancellable(ctx.db.read()).await?;
+        let suggestions = conn
+            .run(|conn| Box::pin(db::articles::suggest(conn, q, &muted, MAX_SUGGESTIONS)))
+            .await?;
+
+        Some(suggestions)
+    } else {
+        None
+    };
+
+    let articles: Vec<Article> = articles
+        .into_iter()
+        .map(|article| Article::new(article, &ctx.config))
+        .collect();
 
     Ok(Json(MultipleArticlesBody {
-        // This is probably incorrect but is deliberate and the Postman collection allows it.
-        //
-        // See the comment on the field definition for details.
-        articles_count: articles.len(),
+        articles_count,
         articles,
+        next_cursor: None,
+        suggestions,
     }))
 }
 
+/// Count the articles matching `filter`, exactly if there aren't too many of them.
+///
+/// Once there are millions of articles, counting them for every page of the listing would be
+/// the slowest part of the request by far. So we ask Postgres for an estimate first, which is
+/// cheap, and only count exactly if that comes in below `Config::exact_count_threshold`.
+///
+/// That does mean the count can jump around a bit from page to page on a busy site, but a
+/// frontend showing "page 3 of 92,417" isn't going to be any the wiser.
+async fn count_articles(
+    ctx: &ApiContext,
+    filter: &db::articles::ListFilter<'_>,
+) -> http::Result<i64> {
+    let unfiltered = filter.tag.is_none()
+        && filter.author.is_none()
+        && filter.favorited.is_none()
+        && filter.muted.is_empty();
+
+    // For the whole table, the planner's own statistics are as good an estimate as any and cost
+    // nothing to look up. With filters, we have to ask it to plan the query.
+    let estimate = if unfiltered {
+        db::articles::estimate_total(ctx.db.read()).await?
+    } else {
+        Some(db::articles::estimate_count(ctx.db.read(), filter).await?)
+    };
+
+    match estimate {
+        Some(estimate) if estimate >= ctx.config.exact_count_threshold => Ok(estimate),
+        _ => {
+            // This is the one that visits every matching row, see `list_articles()`.
+            let mut conn = ctx.db.cancellable(ctx.db.read()).await?;
+            Ok(conn
+                .run(|conn| Box::pin(db::articles::count(conn, filter)))
+                .await?)
+        }
+    }
+}
+
+/// Look up the tags the user has muted, if they're logged in.
+///
+/// This goes to the primary even though the listing itself doesn't, so that muting a tag takes
+/// effect straight away rather than whenever the replica catches up.
+async fn muted_tags(ctx: &ApiContext, user_id: Option<UserId>) -> http::Result<Vec<String>> {
+    match user_id {
+        Some(user_id) => Ok(db::muted_tags::list(ctx.db.primary(), user_id).await?),
+        None => Ok(vec![]),
+    }
+}
+
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#feed-articles
 pub(in crate::http) async fn feed_articles(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
     query: Query<FeedArticlesQuery>,
 ) -> http::Result<Json<MultipleArticlesBody>> {
-    let articles: Vec<_> = sqlx::query_as!(
-        ArticleFromQuery,
-        // As a rule of thumb, you always want the most specific dataset to be your outermost
-        // `SELECT` so the query planner does as little extraneous work as possible, and then
-        // your joins are just fetching data related to rows you already know you're returning.
-        // 
-        // In this case, our primary table is the `follow` table so we select from that first
-        // and join the `article` and `user` tables from there.
-        //
-        // The structure is otherwise very similar to other queries returning `Article`s, so you'd
-        // think that SQLx should provide some way to deduplicate them. However, I think that
-        // would ultimately just make each query harder to understand on its own.
-        //
-        // language=PostgreSQL
-        r#"
-            select
-                slug,
-                title,
-                description,
-                body,
-                tag_list,
-                article.created_at "created_at: Timestamptz",
-                article.updated_at "updated_at: Timestamptz",
-                exists(select 1 from article_favorite where user_id = $1) "favorited!",
-                coalesce(
-                    (select count(*) from article_favorite fav where fav.article_id = article.article_id),
-                    0
-                ) "favorites_count!",
-                author.username author_username,
-                author.bio author_bio,
-                author.image author_image,
-                -- we wouldn't be returning this otherwise
-                true "following_author!"
-            from follow
-            inner join article on followed_user_id = article.user_id
-            inner join "user" author using (user_id)
-            where following_user_id = $1
-            limit $2
-            offset $3
-        "#,
-        auth_user.user_id,
-        query.limit.unwrap_or(20),
-        query.offset.unwrap_or(0)
-    )
-        .fetch(&ctx.db)
-        .map_ok(ArticleFromQuery::into_article)
-        .try_collect()
-        .await?;
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|cursor| parse_cursor_param(&ctx, Listing::Feed, cursor))
+        .transpose()?;
+
+    let limit = query.limit.unwrap_or(20);
+
+    let muted = muted_tags(&ctx, Some(auth_user.user_id)).await?;
+
+    // As in `list_articles()`, there's no reason to wait for one of these before the other.
+    let (articles_count, articles) = tokio::try_join!(
+        db::feed::count(ctx.db.primary(), auth_user.user_id, &muted),
+        db::feed::list(
+            ctx.db.primary(),
+            auth_user.user_id,
+            &muted,
+            cursor,
+            limit,
+            query.offset.unwrap_or(0),
+        ),
+    )?;
+
+    let next_cursor = next_cursor(&ctx, Listing::Feed, &articles, limit);
+
+    let articles: Vec<Article> = articles
+        .into_iter()
+        .map(|article| Article::new(article, &ctx.config))
+        .collect();
 
     Ok(Json(MultipleArticlesBody {
-        // This is probably incorrect but is deliberate and the Postman collection allows it.
-        //
-        // See the comment on the field definition for details.
-        articles_count: articles.len(),
+        articles_count,
         articles,
+        next_cursor,
+        suggestions: None,
     }))
 }
+
+/// The cursor for the page after `articles`, if there might be one.
+fn next_cursor(
+    ctx: &ApiContext,
+    listing: Listing,
+    articles: &[db::articles::Article],
+    limit: i64,
+) -> Option<String> {
+    // If we got fewer articles than we asked for, we've reached the end.
+    match articles.last() {
+        Some(last) if articles.len() as i64 == limit => {
+            let cursor = db::articles::Cursor::after(last);
+
+            Some(
+                Cursor {
+                    sort_key: cursor.created_at,
+                    id: cursor.article_id.0,
+                }
+                .format(ctx, listing),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Parse `?cursor=`, or fail with `422 Unprocessable Entity`.
+///
+/// It's signed, see `types::Cursor`, so what comes back is always a cursor we handed out.
+fn parse_cursor_param(
+    ctx: &ApiContext,
+    listing: Listing,
+    cursor: &str,
+) -> http::Result<db::articles::Cursor> {
+    let cursor = Cursor::parse(ctx, listing, cursor)?;
+
+    Ok(db::articles::Cursor {
+        created_at: cursor.sort_key,
+        article_id: ArticleId(cursor.id),
+    })
+}
@@ -0,0 +1,102 @@
+use axum::extract::{Extension, Path};
+use axum::routing::post;
+use axum::{Json, Router};
+use time::Duration;
+
+use crate::db;
+use crate::http::articles::authors;
+use crate::http::extractor::AuthUser;
+use crate::http::types::{Slug, Timestamptz};
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// An editing lock tells everyone else that an article is being edited, so two people don't make
+// changes at the same time and have the second save silently throw away the first. A frontend
+// takes the lock when the editor opens, takes it again every minute or so while it stays open,
+// and releases it when the editor closes. While someone holds the lock, anyone else's
+// `PUT /api/articles/:slug` fails with `423 Locked`, saying who holds it.
+//
+// Locks expire after `LOCK_TTL` rather than lasting until they're released, because a browser
+// tab that's closed or crashes never gets around to releasing its lock.
+//
+// Anyone who can edit the article can take the lock, which is its author and any co-authors
+// (see `authors`).
+
+/// How long a lock lasts unless it's taken again.
+const LOCK_TTL: Duration = Duration::minutes(5);
+
+pub fn router() -> Router {
+    Router::new().route(
+        "/api/articles/:slug/lock",
+        post(lock_article).delete(unlock_article),
+    )
+}
+
+#[derive(serde::Serialize)]
+struct LockBody {
+    lock: Lock,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Lock {
+    /// Who holds the lock.
+    username: String,
+    expires_at: Timestamptz,
+}
+
+/// Take the lock on editing an article, or extend it if the user already holds it.
+async fn lock_article(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(slug): Path<Slug>,
+) -> Result<Json<LockBody>> {
+    let article = db::articles::find_meta(ctx.db.primary(), slug.as_str())
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    authors::check_can_edit(ctx.db.primary(), &article, auth_user.user_id).await?;
+
+    let now = ctx.clock.now();
+
+    let lock = db::locks::acquire(
+        ctx.db.primary(),
+        article.article_id,
+        auth_user.user_id,
+        now,
+        now + LOCK_TTL,
+    )
+    .await?;
+
+    if lock.user_id != auth_user.user_id {
+        return Err(Error::Locked {
+            username: lock.username,
+            expires_at: lock.expires_at,
+        });
+    }
+
+    Ok(Json(LockBody {
+        lock: Lock {
+            username: lock.username,
+            expires_at: Timestamptz(lock.expires_at),
+        },
+    }))
+}
+
+/// Release the user's lock on editing an article.
+///
+/// This succeeds whether or not they held it, since either way they don't now.
+async fn unlock_article(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(slug): Path<Slug>,
+) -> Result<()> {
+    let article_id = db::articles::find_id_by_slug(ctx.db.primary(), slug.as_str())
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    db::locks::release(ctx.db.primary(), article_id, auth_user.user_id).await?;
+
+    Ok(())
+}
@@ -1,17 +1,42 @@
-use axum::extract::{Extension, Path};
+use axum::extract::{Extension, Path, Query};
+use axum::http::header::{CACHE_CONTROL, LOCATION};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::routing::{get, post};
 use axum::{Json, Router};
+use headers::HeaderMapExt;
 use itertools::Itertools;
-use sqlx::{Executor, Postgres};
-use uuid::Uuid;
-
-use crate::http::extractor::{AuthUser, MaybeAuthUser};
+use metrics::increment_counter;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::db;
+use crate::db::types::{ArticleId, UserId};
+use crate::events::{Event, EventHub};
+
+use crate::http::cache::{Cached, CachedMap};
+use crate::http::extractor::{AuthUser, MaybeAuthUser, Preconditions};
 use crate::http::profiles::Profile;
-use crate::http::types::Timestamptz;
+use crate::http::quota;
+use crate::http::tx::Tx;
+use crate::http::types::{json_etag, Cursor, Listing, Page, Slug, Timestamptz};
 use crate::http::{ApiContext, Error, Result, ResultExt};
 
+mod authors;
 mod comments;
+mod links;
 mod listing;
+mod locks;
+mod muted_tags;
+mod oembed;
+mod og;
+pub(in crate::http) mod restrictions;
+mod tags;
+mod takedowns;
+
+pub(super) use takedowns::{require_admin, require_moderator};
 
 pub fn router() -> Router {
     // I would prefer `listing` to have its own `router()` method and keep the handler
@@ -24,6 +49,7 @@ pub fn router() -> Router {
         )
         // `feed_articles` could be private technically, but meh
         .route("/api/articles/feed", get(listing::feed_articles))
+        .route("/api/articles/search", get(listing::search_articles))
         .route(
             "/api/articles/:slug",
             get(get_article).put(update_article).delete(delete_article),
@@ -32,10 +58,20 @@ pub fn router() -> Router {
             "/api/articles/:slug/favorite",
             post(favorite_article).delete(unfavorite_article),
         )
+        .route("/api/articles/:slug/favoriters", get(list_favoriters))
         // This route isn't technically grouped with articles but it makes sense to include it
         // here since it touches the `article` table.
         .route("/api/tags", get(get_tags))
+        .route("/a/:article_id", get(article_permalink))
+        .merge(authors::router())
         .merge(comments::router())
+        .merge(links::router())
+        .merge(locks::router())
+        .merge(muted_tags::router())
+        .merge(oembed::router())
+        .merge(og::router())
+        .merge(tags::router())
+        .merge(takedowns::router())
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -45,8 +81,12 @@ struct ArticleBody<T = Article> {
 }
 
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 struct TagsBody {
     tags: Vec<String>,
+    /// Not part of the Realworld spec: how many articles have each of `tags`, by tag, for
+    /// frontends that want to size a tag cloud or show the busiest tags first.
+    articles_count: BTreeMap<String, i64>,
 }
 
 #[derive(serde::Deserialize)]
@@ -83,52 +123,36 @@ struct Article {
     favorited: bool,
     favorites_count: i64,
     author: Profile,
+    /// A link to the article that keeps working when the title (and so the slug) changes,
+    /// see `article_permalink()`. Not in the Realworld spec.
+    short_url: String,
+    /// Why a moderator took the article down, if they did. Only its author can see the article
+    /// at all then, and only in `GET /api/articles/:slug`, see `takedowns`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    takedown_reason: Option<String>,
 }
 
-// One place that SQLx could still improve upon is when a query wants to return a nested
-// object, such as `Article` wants to with the `author` field.
-// For 1:1 relations like that, what we usually do is deserialize the nested object as columns
-// flattened into the main query, then fixup the structure afterwards.
-//
-// It's a good chunk of boilerplate but thankfully you usually only have to write it a few
-// times across a whole project.
-struct ArticleFromQuery {
-    slug: String,
-    title: String,
-    description: String,
-    body: String,
-    tag_list: Vec<String>,
-    created_at: Timestamptz,
-    updated_at: Timestamptz,
-    favorited: bool,
-    favorites_count: i64,
-    author_username: String,
-    author_bio: String,
-    author_image: Option<String>,
-    // This was originally `author_following` to match other fields but that's kind of confusing.
-    // That made it sound like a flag showing if the author is following the current user
-    // but the intent is the other way round.
-    following_author: bool,
-}
-
-impl ArticleFromQuery {
-    fn into_article(self) -> Article {
+impl Article {
+    /// We'd implement `From<db::articles::Article>`, but the short URL needs the configuration.
+    fn new(article: db::articles::Article, config: &Config) -> Self {
         Article {
-            slug: self.slug,
-            title: self.title,
-            description: self.description,
-            body: self.body,
-            tag_list: self.tag_list,
-            created_at: self.created_at,
-            updated_at: self.updated_at,
-            favorited: self.favorited,
-            favorites_count: self.favorites_count,
+            short_url: format!("{}/a/{}", config.base_url(), article.article_id.0),
+            slug: article.slug,
+            title: article.title,
+            description: article.description,
+            body: article.body,
+            tag_list: article.tag_list,
+            created_at: Timestamptz(article.created_at),
+            updated_at: Timestamptz(article.updated_at),
+            favorited: article.favorited,
+            favorites_count: article.favorites_count,
             author: Profile {
-                username: self.author_username,
-                bio: self.author_bio,
-                image: self.author_image,
-                following: self.following_author,
+                username: article.author_username,
+                bio: article.author_bio,
+                image: article.author_image,
+                following: article.following_author,
             },
+            takedown_reason: None,
         }
     }
 }
@@ -137,8 +161,20 @@ impl ArticleFromQuery {
 async fn create_article(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
+    mut tx: Tx,
     Json(mut req): Json<ArticleBody<CreateArticle>>,
-) -> Result<Json<ArticleBody>> {
+) -> Result<(HeaderMap, Json<ArticleBody>)> {
+    check_limits(
+        &ctx.config,
+        Some(&req.article.title),
+        Some(&req.article.body),
+        Some(&req.article.tag_list),
+    )?;
+
+    restrictions::check_article(&ctx, auth_user.user_id, &req.article.body).await?;
+
+    let quota = quota::check_articles(&ctx, auth_user.user_id).await?;
+
     let slug = slugify(&req.article.title);
 
     // Never specified unless you count just showing them sorted in the examples:
@@ -148,193 +184,146 @@ async fn create_article(
     // have acknowledged this oversight and are willing to loosen the requirement:
     // https://github.com/gothinkster/realworld/issues/839#issuecomment-1002806224
     req.article.tag_list.sort();
+    // An article can only have each tag once, see `migrations/23_tag.sql`.
+    req.article.tag_list.dedup();
 
-    // For fun, this is how we combine several operations into a single query for brevity.
-    let article = sqlx::query_as!(
-        ArticleFromQuery,
-        // language=PostgreSQL
-        r#"
-            with inserted_article as (
-                insert into article (user_id, slug, title, description, body, tag_list)
-                values ($1, $2, $3, $4, $5, $6)
-                returning 
-                    slug, 
-                    title, 
-                    description, 
-                    body, 
-                    tag_list, 
-                    -- This is how you can override the inferred type of a column.
-                    created_at "created_at: Timestamptz", 
-                    updated_at "updated_at: Timestamptz"
-            )
-            select 
-                inserted_article.*,
-                false "favorited!",
-                0::int8 "favorites_count!",
-                username author_username,
-                bio author_bio,
-                image author_image,
-                -- user is forbidden to follow themselves
-                false "following_author!"
-            from inserted_article
-            inner join "user" on user_id = $1
-        "#,
+    let article = db::articles::create(
+        tx.conn().await?,
         auth_user.user_id,
-        slug,
-        req.article.title,
-        req.article.description,
-        req.article.body,
-        // The typechecking code that SQLx emits for parameters sometimes chokes on vectors.
-        // This slicing operation shouldn't be required, but it took a mess of type-system
-        // hacks just to get the codegen this far.
-        &req.article.tag_list[..]
+        &slug,
+        &req.article.title,
+        &req.article.description,
+        &req.article.body,
+        &req.article.tag_list,
     )
-    .fetch_one(&ctx.db)
     .await
     .on_constraint("article_slug_key", |_| {
         Error::unprocessable_entity([("slug", format!("duplicate article slug: {}", slug))])
     })?;
 
-    Ok(Json(ArticleBody {
-        article: article.into_article(),
-    }))
+    let links = links::extract(&article.body, &ctx.config.base_url());
+    db::links::replace(tx.conn().await?, article.article_id, &links).await?;
+
+    // Any of the tags may be new, and the counts of the rest have changed.
+    let tags = ctx.tags.clone();
+
+    tx.after_commit(move || {
+        tags.invalidate();
+        increment_counter!("articles_published_total");
+    });
+
+    Ok((
+        quota,
+        Json(ArticleBody {
+            article: Article::new(article, &ctx.config),
+        }),
+    ))
 }
 
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#update-article
 async fn update_article(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
-    Path(slug): Path<String>,
+    mut tx: Tx,
+    Path(slug): Path<Slug>,
     Json(req): Json<ArticleBody<UpdateArticle>>,
 ) -> Result<Json<ArticleBody>> {
-    let mut tx = ctx.db.begin().await?;
+    check_limits(
+        &ctx.config,
+        req.article.title.as_deref(),
+        req.article.body.as_deref(),
+        None,
+    )?;
 
     let new_slug = req.article.title.as_deref().map(slugify);
 
-    let article_meta = sqlx::query!(
-        // This locks the `article` row for the duration of the transaction so we're
-        // not interleaving this with other possible updates.
-        "select article_id, user_id from article where slug = $1 for update",
-        slug
-    )
-    .fetch_optional(&mut tx)
-    .await?
-    .ok_or(Error::NotFound)?;
-
-    if article_meta.user_id != auth_user.user_id {
-        return Err(Error::Forbidden);
-    }
+    // The closure below may run more than once, so it can only borrow these, see `Tx::retry()`.
+    let (ctx, slug, req, new_slug) = (&*ctx, slug.as_str(), &req.article, new_slug.as_deref());
+
+    let article = tx
+        .retry(|mut tx| {
+            Box::pin(async move {
+                let article_meta = db::articles::find_meta_for_update(&mut *tx, slug)
+                    .await?
+                    .ok_or(Error::NotFound)?;
+
+                authors::check_can_edit(&mut *tx, &article_meta, auth_user.user_id).await?;
+
+                if let Some(body) = &req.body {
+                    restrictions::check_article_edit(ctx, auth_user.user_id, body).await?;
+                }
+
+                // Someone else may be in the middle of editing it, see `locks`.
+                let lock =
+                    db::locks::find_active(&mut *tx, article_meta.article_id, ctx.clock.now())
+                        .await?;
+
+                if let Some(lock) = lock.filter(|lock| lock.user_id != auth_user.user_id) {
+                    return Err(Error::Locked {
+                        username: lock.username,
+                        expires_at: lock.expires_at,
+                    });
+                }
+
+                let article = db::articles::update(
+                    &mut *tx,
+                    article_meta.article_id,
+                    auth_user.user_id,
+                    db::articles::ArticleUpdate {
+                        slug: new_slug,
+                        title: req.title.as_deref(),
+                        description: req.description.as_deref(),
+                        body: req.body.as_deref(),
+                    },
+                )
+                .await
+                .on_constraint("article_slug_key", |_| {
+                    Error::unprocessable_entity([(
+                        "slug",
+                        format!("duplicate article slug: {}", new_slug.unwrap()),
+                    )])
+                })?;
+
+                if req.body.is_some() {
+                    let links = links::extract(&article.body, &ctx.config.base_url());
+                    db::links::replace(&mut *tx, article.article_id, &links).await?;
+                }
+
+                Ok(article)
+            })
+        })
+        .await?;
 
-    // Update the article and return the new values in the same query.
-    //
-    // This is perhaps toeing the line of "too clever" as I talked about in `profiles::follow_user()`,
-    // however I think here it saves us some code duplication as well as an extra round-trip
-    // to the database, and isn't too hard to understand.
-    //
-    // I could also have folded the above permission check into the update, and have in the past,
-    // but I think that's where it starts to get too confusing as it relies on the fact that CTEs
-    // with `INSERT/UPDATE/DELETE` statements are executed even if they're not read from.
-    let article = sqlx::query_as!(
-        ArticleFromQuery,
-        // language=PostgreSQL
-        r#"
-            with updated_article as (
-                update article
-                set
-                    slug = coalesce($1, slug),
-                    title = coalesce($2, title),
-                    description = coalesce($3, description),
-                    body = coalesce($4, body)
-                where article_id = $5
-                returning
-                    slug,
-                    title,
-                    description,
-                    body,
-                    tag_list,
-                    article.created_at "created_at: Timestamptz",
-                    article.updated_at "updated_at: Timestamptz"
-            )
-            select
-                updated_article.*,
-                exists(select 1 from article_favorite where user_id = $6) "favorited!",
-                coalesce(
-                    (select count(*) from article_favorite fav where fav.article_id = $5),
-                    0
-                ) "favorites_count!",
-                author.username author_username,
-                author.bio author_bio,
-                author.image author_image,
-                -- user not allowed to follow themselves
-                false "following_author!"
-            from updated_article
-            -- we've ensured the current user is the article's author so we can assume it here
-            inner join "user" author on author.user_id = $6
-        "#,
-        // In a query with a lot of bind parameters, it can be difficult to keep them all straight.
-        // There's an open proposal to improve this: https://github.com/launchbadge/sqlx/issues/875
-        new_slug,
-        req.article.title,
-        req.article.description,
-        req.article.body,
-        article_meta.article_id,
-        auth_user.user_id
-    )
-    .fetch_one(&mut tx)
-    .await
-    .on_constraint("article_slug_key", |_| {
-        Error::unprocessable_entity([(
-            "slug",
-            format!("duplicate article slug: {}", new_slug.unwrap()),
-        )])
-    })?
-    .into_article();
+    // Under both slugs if it changed, so nothing from before the update is served under either.
+    // And not until the update has been committed, or someone could put the old version back
+    // in the cache in the meantime.
+    let articles = ctx.articles.clone();
+    let slugs = [slug.to_string(), article.slug.clone()];
 
-    // Mustn't forget this!
-    tx.commit().await?;
+    tx.after_commit(move || {
+        for slug in &slugs {
+            articles.invalidate(slug);
+        }
+    });
 
-    Ok(Json(ArticleBody { article }))
+    Ok(Json(ArticleBody {
+        article: Article::new(article, &ctx.config),
+    }))
 }
 
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#delete-article
 async fn delete_article(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
-    Path(slug): Path<String>,
+    Path(slug): Path<Slug>,
 ) -> Result<()> {
-    let result = sqlx::query!(
-        // I like to use raw strings for most queries mainly because CLion doesn't try
-        // to escape newlines.
-        // language=PostgreSQL
-        r#"
-            -- The main query cannot observe side-effects of data-modifying CTEs and 
-            -- by design, always sees the "before" picture of the database,
-            -- so this lets us fold our permissions check together with the actual delete.
-            --
-            -- This was the "being too clever" I was talking about before. However, I think it's
-            -- permissible here as we're not pairing this together with a huge select, so it
-            -- should be relatively easy to understand the intended effect here.
-            with deleted_article as (
-                delete from article 
-                -- Important: we only delete the article if the user actually authored it.
-                where slug = $1 and user_id = $2
-                -- We just need to return *something* for `exists()` below.
-                returning 1
-            )
-            select
-                -- This will be `true` if the article existed before we deleted it.
-                exists(select 1 from article where slug = $1) "existed!",
-                -- This will only be `true` if we actually deleted the article.
-                exists(select 1 from deleted_article) "deleted!"
-        "#,
-        slug,
-        auth_user.user_id
-    )
-    .fetch_one(&ctx.db)
-    .await?;
+    let result = db::articles::delete(ctx.db.primary(), slug.as_str(), auth_user.user_id).await?;
 
     if result.deleted {
-        // Article successfully deleted!
+        // Article successfully deleted! That changes the counts of its tags, and may have been
+        // the last article with some tag.
+        ctx.tags.invalidate();
+        ctx.articles.invalidate(&slug.as_str().to_string());
         Ok(())
     } else if result.existed {
         // We found the article, but the user was not the author of that article.
@@ -351,83 +340,67 @@ async fn get_article(
     // "authentication optional" because we still need to check if the user is following the author.
     maybe_auth_user: MaybeAuthUser,
     ctx: Extension<ApiContext>,
-    Path(slug): Path<String>,
-) -> Result<Json<ArticleBody>> {
-    let article = sqlx::query_as!(
-        ArticleFromQuery,
-        // language=PostgreSQL
-        r#"
-            select
-                slug,
-                title,
-                description,
-                body,
-                tag_list,
-                article.created_at "created_at: Timestamptz",
-                article.updated_at "updated_at: Timestamptz",
-                exists(select 1 from article_favorite where user_id = $1) "favorited!",
-                coalesce(
-                    -- `count(*)` returns `NULL` if the query returned zero columns
-                    -- not exactly a fan of that design choice but whatever
-                    (select count(*) from article_favorite fav where fav.article_id = article.article_id),
-                    0
-                ) "favorites_count!",
-                author.username author_username,
-                author.bio author_bio,
-                author.image author_image,
-                exists(select 1 from follow where followed_user_id = author.user_id and following_user_id = $1) "following_author!"
-            from article
-            inner join "user" author using (user_id)
-            where slug = $2
-        "#,
-        maybe_auth_user.user_id(),
-        slug
-    )
-        .fetch_optional(&ctx.db)
-        .await?
-        .ok_or(Error::NotFound)?
-        .into_article();
-
-    Ok(Json(ArticleBody { article }))
+    preconditions: Preconditions,
+    Path(slug): Path<Slug>,
+) -> Result<(HeaderMap, Json<ArticleBody>)> {
+    // If the user just created or edited this article, they already got it back in the
+    // response to that, so it's okay if we're a little behind here.
+    let viewer = maybe_auth_user.user_id();
+    let find = || db::articles::find_by_slug(ctx.db.read(), slug.as_str(), viewer);
+
+    // Anonymous visitors all see the same thing, and they're most of the traffic to a popular
+    // article (e.g. one that's been linked from somewhere), so we can save the database some
+    // work there. Logged-in users each see their own `favorited` and `following`.
+    let (article, takedown) = match viewer {
+        // The takedown is only found if the viewer wrote the article, and otherwise they
+        // can't see it anyway.
+        Some(viewer) => tokio::try_join!(
+            find(),
+            db::takedowns::find_for_author(ctx.db.read(), slug.as_str(), viewer)
+        )?,
+        None => (
+            ctx.articles
+                .get_or_refresh(&slug.as_str().to_string(), find)
+                .await?,
+            None,
+        ),
+    };
+
+    let mut article = Article::new(article.ok_or(Error::NotFound)?, &ctx.config);
+    article.takedown_reason = takedown.map(|takedown| takedown.reason);
+
+    let body = ArticleBody { article };
+
+    // We can't use `article.updated_at` as a `Last-Modified` date because the response
+    // also changes when the article is favorited or the author is followed.
+    let etag = json_etag(&body)?;
+    preconditions.evaluate(Some(&etag), None)?;
+
+    let mut headers = HeaderMap::new();
+    headers.typed_insert(etag);
+
+    Ok((headers, Json(body)))
 }
 
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#favorite-article
 async fn favorite_article(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
-    Path(slug): Path<String>,
+    Path(slug): Path<Slug>,
 ) -> Result<Json<ArticleBody>> {
     // This is kind of where the pattern of "always return the updated object" gets a bit annoying,
-    // because it makes this handler and `unfavorite_article()` a lot more complicated than they
-    // need to be.
-    //
-    // Fortunately, we can deduplicate the article lookup with a function. We might prefer
-    // to do this to `update_article()` as well, but I wanted to demonstrate how you can use
-    // a CTE to implement that.
-
-    let article_id = sqlx::query_scalar!(
-        r#"
-            with selected_article as (
-                select article_id from article where slug = $1
-            ),
-            inserted_favorite as (
-                insert into article_favorite(article_id, user_id)
-                select article_id, $2
-                from selected_article
-                -- if the article is already favorited
-                on conflict do nothing
-            )
-            select article_id from selected_article
-        "#,
-        slug,
-        auth_user.user_id
-    )
-    .fetch_optional(&ctx.db)
-    .await?
-    .ok_or(Error::NotFound)?;
+    // but `db::articles::favorite()` folds the lookup into the same query so it's just one
+    // round-trip.
+    let article = db::articles::favorite(ctx.db.primary(), slug.as_str(), auth_user.user_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    // The favorites count changed.
+    ctx.articles.invalidate(&article.slug);
+    increment_counter!("articles_favorited_total");
 
     Ok(Json(ArticleBody {
-        article: article_by_id(&ctx.db, auth_user.user_id, article_id).await?,
+        article: Article::new(article, &ctx.config),
     }))
 }
 
@@ -435,124 +408,240 @@ async fn favorite_article(
 async fn unfavorite_article(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
-    Path(slug): Path<String>,
+    Path(slug): Path<Slug>,
 ) -> Result<Json<ArticleBody>> {
     // The Realworld spec doesn't say what to do if the user calls this on an article
     // that they haven't favorited. I've chosen to just do nothing as that's the easiest.
     //
     // The Postman collection doesn't test that case.
 
-    let article_id = sqlx::query_scalar!(
-        r#"
-            with selected_article as (
-                select article_id from article where slug = $1
-            ),
-            deleted_favorite as (
-                delete from article_favorite
-                where article_id = (select article_id from selected_article)
-                and user_id = $2
-            )
-            select article_id from selected_article
-        "#,
-        slug,
-        auth_user.user_id
-    )
-    .fetch_optional(&ctx.db)
-    .await?
-    .ok_or(Error::NotFound)?;
+    let article = db::articles::unfavorite(ctx.db.primary(), slug.as_str(), auth_user.user_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    ctx.articles.invalidate(&article.slug);
+    increment_counter!("articles_unfavorited_total");
 
     Ok(Json(ArticleBody {
-        article: article_by_id(&ctx.db, auth_user.user_id, article_id).await?,
+        article: Article::new(article, &ctx.config),
+    }))
+}
+
+/// Check the fields of an article against the limits in `Config`, reporting all of the ones that
+/// are over at once. `None` means the field isn't being set.
+fn check_limits(
+    config: &Config,
+    title: Option<&str>,
+    body: Option<&str>,
+    tag_list: Option<&[String]>,
+) -> Result<()> {
+    let mut errors = Vec::new();
+
+    if title.is_some_and(|title| title.chars().count() > config.max_title_len) {
+        errors.push((
+            "title",
+            format!("title must be at most {} characters", config.max_title_len),
+        ));
+    }
+
+    if body.is_some_and(|body| body.len() > config.max_body_bytes) {
+        errors.push((
+            "body",
+            format!("body must be at most {} bytes", config.max_body_bytes),
+        ));
+    }
+
+    let tag_list = tag_list.unwrap_or_default();
+
+    if tag_list.len() > config.max_tags_per_article {
+        errors.push((
+            "tagList",
+            format!(
+                "an article may have at most {} tags",
+                config.max_tags_per_article
+            ),
+        ));
+    }
+
+    if tag_list
+        .iter()
+        .any(|tag| tag.chars().count() > config.max_tag_len)
+    {
+        errors.push((
+            "tagList",
+            format!("tags must be at most {} characters", config.max_tag_len),
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::unprocessable_entity(errors))
+    }
+}
+
+/// Redirect to an article by its ID, wherever its slug has ended up. This is `shortUrl`.
+///
+/// Not in the Realworld spec, where articles are only ever addressed by slug. That's nice and
+/// readable, but it changes whenever the author edits the title, breaking every link to it
+/// that's been shared so far. Nothing else about an article ever changes, except the ID.
+async fn article_permalink(
+    ctx: Extension<ApiContext>,
+    Path(article_id): Path<ArticleId>,
+) -> Result<(StatusCode, HeaderMap)> {
+    let slug = db::articles::find_slug_by_id(ctx.db.read(), article_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let location = format!("{}/api/articles/{}", ctx.config.base_url(), slug);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        LOCATION,
+        HeaderValue::try_from(location).map_err(anyhow::Error::from)?,
+    );
+    // It's a 301 because this is the article's address for as long as it exists, but where it
+    // points can change. Browsers cache a 301 forever unless told otherwise, so we tell them to
+    // check back each time.
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+
+    Ok((StatusCode::MOVED_PERMANENTLY, headers))
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct ListFavoritersQuery {
+    limit: Option<i64>,
+    /// Where the previous page left off, from its `nextCursor`.
+    cursor: Option<String>,
+}
+
+/// List who favorited an article, for "liked by ..." in a frontend.
+///
+/// Not in the Realworld spec, so it's a `Page`, with `?limit=` and `?cursor=`. The total is
+/// free, since we keep a count of favorites on every article anyway.
+async fn list_favoriters(
+    maybe_auth_user: MaybeAuthUser,
+    ctx: Extension<ApiContext>,
+    Path(slug): Path<Slug>,
+    query: Query<ListFavoritersQuery>,
+) -> Result<Json<Page<Profile>>> {
+    let after = query
+        .cursor
+        .as_deref()
+        .map(|cursor| Cursor::parse(&ctx, Listing::Favoriters, cursor))
+        .transpose()?
+        .map(|cursor| (cursor.sort_key, UserId(cursor.id)));
+
+    let limit = query.limit.unwrap_or(20);
+
+    let (total, favoriters) = tokio::try_join!(
+        db::articles::favorites_count(ctx.db.read(), slug.as_str()),
+        db::articles::list_favoriters(
+            ctx.db.read(),
+            slug.as_str(),
+            maybe_auth_user.user_id(),
+            after,
+            limit,
+        ),
+    )?;
+
+    // An article nobody has favorited yet is an empty list, but one that doesn't exist is a 404.
+    let total = total.ok_or(Error::NotFound)?;
+
+    // The cursor needs the user ID, which isn't in the `Profile`, so the page is made first.
+    let page = Page::new(favoriters, total, limit, |last| {
+        Cursor {
+            sort_key: last.favorited_at,
+            id: last.user_id.0,
+        }
+        .format(&ctx, Listing::Favoriters)
+    });
+
+    Ok(Json(Page {
+        items: page.items.into_iter().map(Profile::from).collect(),
+        total: page.total,
+        next_cursor: page.next_cursor,
     }))
 }
 
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#get-tags
 async fn get_tags(ctx: Extension<ApiContext>) -> Result<Json<TagsBody>> {
-    // Note: this query requires a full table scan and is a likely point for a DoS attack.
-    //
-    // In practice, I might consider storing unique tags in their own table and then the
-    // `tag_list` of an article would be a list of indexes into that table, and then
-    // this query can just dump that table. I have not implemented that here for the sake of brevity
-    // in the other queries fetching from the `article` table.
-    //
-    // Alternatively you could store the unique list of tags as a materialized view that is
-    // periodically refreshed, or cache the result of this query in application code,
-    // or simply apply a global rate-limit to this route. Each has its tradeoffs.
-    let tags = sqlx::query_scalar!(
-        r#"
-            select distinct tag "tag!"
-            from article, unnest (article.tag_list) tags(tag)
-            order by tag
-        "#
-    )
-    .fetch_all(&ctx.db)
-    .await?;
-
-    Ok(Json(TagsBody { tags }))
+    // This used to be cached because the query was a full table scan. Since tags got a table of
+    // their own it's cheap (see `db::tags::list()`), but most frontends still ask for it on
+    // every page load, and it's the same for everyone, so the cache stayed.
+    let tags = ctx
+        .tags
+        .get_or_refresh(|| db::tags::list(ctx.db.read()))
+        .await?;
+
+    Ok(Json(TagsBody {
+        articles_count: tags
+            .iter()
+            .map(|tag| (tag.tag.clone(), tag.articles_count))
+            .collect(),
+        tags: tags.into_iter().map(|tag| tag.tag).collect(),
+    }))
 }
 
-// End handler functions.
-// Begin utility functions.
+/// How long `GET /api/tags` may be out of date.
+///
+/// Publishing or deleting an article through this instance refreshes the tags and their counts
+/// straight away, and publishing one through any other instance does so as soon as we hear
+/// about it from `EventHub`. The TTL is for the cases neither of those covers: articles deleted
+/// through another instance, and reading from a replica that hadn't caught up yet when we
+/// refreshed.
+const TAGS_TTL: Duration = Duration::from_secs(30);
+
+/// The cache for `GET /api/tags`, for `ApiContext`.
+pub(in crate::http) fn tags_cache(events: &EventHub) -> Cached<Vec<db::tags::TagCount>> {
+    Cached::new("tags", TAGS_TTL).invalidate_on(events, |event| {
+        matches!(event, Event::ArticlePublished { .. })
+    })
+}
 
-// This is used in a few different places so it makes sense to extract into its own function.
-//
-// I usually throw stuff like this at the bottom of the file but other engineers like
-// to put these kinds of functions in their own modules. Po-tay-to po-tah-to.
-async fn article_by_id(
-    e: impl Executor<'_, Database = Postgres>,
-    user_id: Uuid,
-    article_id: Uuid,
-) -> Result<Article> {
-    let article = sqlx::query_as!(
-        ArticleFromQuery,
-        // language=PostgreSQL
-        r#"
-            select
-                slug,
-                title,
-                description,
-                body,
-                tag_list,
-                article.created_at "created_at: Timestamptz",
-                article.updated_at "updated_at: Timestamptz",
-                exists(select 1 from article_favorite where user_id = $1) "favorited!",
-                coalesce(
-                    -- `count(*)` returns `NULL` if the query returned zero columns
-                    -- not exactly a fan of that design choice but whatever
-                    (select count(*) from article_favorite fav where fav.article_id = article.article_id),
-                    0
-                ) "favorites_count!",
-                author.username author_username,
-                author.bio author_bio,
-                author.image author_image,
-                exists(select 1 from follow where followed_user_id = author.user_id and following_user_id = $1) "following_author!"
-            from article
-            inner join "user" author using (user_id)
-            where article_id = $2
-        "#,
-        user_id,
-        article_id
-    )
-        .fetch_optional(e)
-        .await?
-        .ok_or(Error::NotFound)?
-        .into_article();
+/// How many articles to keep in `article_cache()`.
+///
+/// This is meant for the handful that are popular right now, not to hold the whole table.
+const ARTICLE_CACHE_CAPACITY: usize = 1000;
 
-    Ok(article)
+/// How long `GET /api/articles/:slug` may be out of date for anonymous users.
+///
+/// Editing, deleting or (un)favoriting an article through this instance refreshes it straight
+/// away. The TTL covers the rest: changes made through other instances, changes to the author's
+/// profile, and a refresh that read from a replica which hadn't caught up yet (or raced with
+/// an edit that hadn't been committed).
+const ARTICLE_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// The cache for `GET /api/articles/:slug` for anonymous users, for `ApiContext`.
+pub(in crate::http) fn article_cache() -> CachedMap<String, db::articles::Article> {
+    CachedMap::new("articles", ARTICLE_CACHE_CAPACITY, ARTICLE_CACHE_TTL)
 }
 
+// End handler functions.
+// Begin utility functions.
+
 /// Convert a title string to a slug for identifying an article.
 ///
 /// E.g. `slugify("Doctests are the Bee's Knees") == "doctests-are-the-bees-knees"`
 ///
-// (Sadly, doctests are not run on private functions it seems.)
-fn slugify(string: &str) -> String {
+/// Non-ASCII text is transliterated first, so `slugify("Überraschung 日本語")`
+/// gives `"uberraschung-ri-ben-yu"`. If there's nothing left after that (e.g. a title that's
+/// all emoji or punctuation), we fall back to a random string, so the result is always
+/// a non-empty, lowercase ASCII string.
+///
+// (Sadly, doctests are not run on private functions it seems. This is public now, but only for
+// the benchmarks in `benches/`.)
+pub fn slugify(string: &str) -> String {
     const QUOTE_CHARS: &[char] = &['\'', '"'];
 
-    string
+    // `deunicode` does a pretty decent job of romanizing most scripts, although it has no
+    // context so the results for e.g. Japanese kanji read more like Mandarin.
+    // It's still a lot more readable than a URL full of percent-encoded bytes.
+    let slug = deunicode::deunicode(string)
         // Split on anything that isn't a word character or quotation mark.
         // This has the effect of keeping contractions and possessives together.
-        .split(|c: char| !(QUOTE_CHARS.contains(&c) || c.is_alphanumeric()))
+        .split(|c: char| !(QUOTE_CHARS.contains(&c) || c.is_ascii_alphanumeric()))
         // If multiple non-word characters follow each other then we'll get empty substrings
         // so we'll filter those out.
         .filter(|s| !s.is_empty())
@@ -566,7 +655,33 @@ fn slugify(string: &str) -> String {
             s.make_ascii_lowercase();
             s
         })
-        .join("-")
+        // A word made up of only quotes would leave us with a doubled-up hyphen.
+        .filter(|s| !s.is_empty())
+        .join("-");
+
+    if !slug.is_empty() {
+        return slug;
+    }
+
+    // Eight random characters is plenty to make collisions unlikely, and if one does happen
+    // then the user just gets the usual "duplicate article slug" error and can try again.
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(|b| char::from(b).to_ascii_lowercase())
+        .collect()
+}
+
+/// Deserialize `data` as each of the request bodies in this module and its submodules, the same way `Json` would,
+/// for the fuzz targets in `fuzz/`. Only errors are expected; anything else is a bug.
+#[cfg(feature = "fuzzing")]
+pub(in crate::http) fn fuzz_request_bodies(data: &[u8]) {
+    serde_json::from_slice::<ArticleBody<CreateArticle>>(data).ok();
+    serde_json::from_slice::<ArticleBody<UpdateArticle>>(data).ok();
+    authors::fuzz_request_bodies(data);
+    comments::fuzz_request_bodies(data);
+    tags::fuzz_request_bodies(data);
+    takedowns::fuzz_request_bodies(data);
 }
 
 // This fulfills the "at least one unit test" requirement of the Realworld spec.
@@ -610,5 +725,24 @@ fn test_slugify() {
     assert_eq!(
         slugify("Converting to Rust from C: It's as Easy as 1, 2, 3!"),
         "converting-to-rust-from-c-its-as-easy-as-1-2-3"
-    )
+    );
+
+    assert_eq!(slugify("Überraschung 日本語"), "uberraschung-ri-ben-yu");
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    // Whatever the title, the slug had better be usable in a URL as-is.
+    #[test]
+    fn test_slugify_is_nonempty_lowercase_ascii(title in "\\PC*") {
+        let slug = slugify(&title);
+
+        proptest::prop_assert!(!slug.is_empty());
+        proptest::prop_assert!(
+            slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'),
+            "invalid slug: {:?}",
+            slug
+        );
+        proptest::prop_assert!(!slug.starts_with('-') && !slug.ends_with('-') && !slug.contains("--"));
+    }
 }
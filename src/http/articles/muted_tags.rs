@@ -0,0 +1,70 @@
+use axum::extract::{Extension, Path};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use crate::db;
+use crate::http::extractor::AuthUser;
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// Muting a tag hides articles with that tag from the user's article listings and their feed,
+// for someone who's had enough of reading about, say, JavaScript frameworks. Articles are left
+// out if they have any muted tag at all, whatever else they're tagged with, except when the user
+// asks for a muted tag by name with `GET /api/articles?tag=`, because then they clearly do want
+// to see it.
+//
+// Each of these routes responds with all the tags the user has muted, like `GET /api/tags`
+// without the counts.
+
+#[derive(serde::Serialize)]
+struct TagsBody {
+    tags: Vec<String>,
+}
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/tags/muted", get(list_muted_tags))
+        .route("/api/tags/:tag/mute", post(mute_tag).delete(unmute_tag))
+}
+
+async fn list_muted_tags(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+) -> Result<Json<TagsBody>> {
+    let tags = db::muted_tags::list(ctx.db.primary(), auth_user.user_id).await?;
+
+    Ok(Json(TagsBody { tags }))
+}
+
+async fn mute_tag(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(tag): Path<String>,
+) -> Result<Json<TagsBody>> {
+    // Nobody could have used a tag that's too long for an article, so there'd be no point.
+    if tag.is_empty() || tag.chars().count() > ctx.config.max_tag_len {
+        return Err(Error::unprocessable_entity([(
+            "tag",
+            format!(
+                "tags must be between 1 and {} characters",
+                ctx.config.max_tag_len
+            ),
+        )]));
+    }
+
+    db::muted_tags::mute(ctx.db.primary(), auth_user.user_id, &tag).await?;
+
+    list_muted_tags(auth_user, ctx).await
+}
+
+/// This succeeds whether or not the tag was muted, since either way it isn't now.
+async fn unmute_tag(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(tag): Path<String>,
+) -> Result<Json<TagsBody>> {
+    db::muted_tags::unmute(ctx.db.primary(), auth_user.user_id, &tag).await?;
+
+    list_muted_tags(auth_user, ctx).await
+}
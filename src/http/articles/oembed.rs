@@ -0,0 +1,184 @@
+use axum::extract::{Extension, Query};
+use axum::routing::get;
+use axum::{Json, Router};
+use url::Url;
+use uuid::Uuid;
+
+use crate::db;
+use crate::db::types::ArticleId;
+use crate::http::articles::og::escape;
+use crate::http::articles::Article;
+use crate::http::types::Slug;
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// oEmbed (https://oembed.com/) is how sites that embed links (blogging platforms, forums, chat
+// apps) ask the site a link points to for something to show in its place. Where the OpenGraph
+// tags in `og` are just for a preview, this gives them a snippet of HTML to drop into the page.
+//
+// A consumer finds this endpoint either from its own list of providers, or from a
+// `<link rel="alternate" type="application/json+oembed">` on the page being embedded, which is
+// up to the frontend to add.
+
+/// The width of the embed when the consumer doesn't ask for a maximum, in pixels.
+const DEFAULT_WIDTH: u32 = 600;
+
+/// The height of the embed, in pixels. It's a few lines of text, so it doesn't vary much.
+const HEIGHT: u32 = 200;
+
+pub fn router() -> Router {
+    Router::new().route("/api/oembed", get(oembed))
+}
+
+#[derive(serde::Deserialize)]
+struct OEmbedQuery {
+    url: String,
+    maxwidth: Option<u32>,
+    format: Option<String>,
+}
+
+/// The response, as the oEmbed spec calls it. Its fields are snake case, unlike ours.
+#[derive(serde::Serialize)]
+struct OEmbed {
+    /// Always `1.0`.
+    version: &'static str,
+    /// `rich`, since it's HTML that isn't a video.
+    r#type: &'static str,
+    title: String,
+    author_name: String,
+    author_url: String,
+    provider_url: String,
+    html: String,
+    width: u32,
+    height: u32,
+}
+
+/// Describe the article at `url` for embedding it.
+///
+/// `url` may be an article's `shortUrl`, its API URL, or its `/og/:slug` page. Per the spec,
+/// a URL that isn't one of ours or doesn't lead to an article is a `404 Not Found`, and asking
+/// for XML is a `501 Not Implemented`.
+async fn oembed(
+    ctx: Extension<ApiContext>,
+    Query(query): Query<OEmbedQuery>,
+) -> Result<Json<OEmbed>> {
+    if query.format.as_deref().unwrap_or("json") != "json" {
+        return Err(Error::NotImplemented("only the json format is supported"));
+    }
+
+    let base_url = ctx.config.base_url();
+
+    let slug = match parse_article_url(&base_url, &query.url).ok_or(Error::NotFound)? {
+        ArticleUrl::Slug(slug) => slug,
+        ArticleUrl::Id(article_id) => {
+            let slug = db::articles::find_slug_by_id(ctx.db.read(), article_id)
+                .await?
+                .ok_or(Error::NotFound)?;
+
+            Slug::try_from(slug).map_err(|e| anyhow::anyhow!("invalid slug in database: {}", e))?
+        }
+    };
+
+    // Consumers aren't logged in, so this can come from the same cache as anonymous visitors to
+    // `GET /api/articles/:slug`.
+    let article = ctx
+        .articles
+        .get_or_refresh(&slug.as_str().to_string(), || {
+            db::articles::find_by_slug(ctx.db.read(), slug.as_str(), None)
+        })
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let article = Article::new(article, &ctx.config);
+
+    Ok(Json(OEmbed {
+        version: "1.0",
+        r#type: "rich",
+        html: render(&article),
+        author_url: format!("{}/api/profiles/{}", base_url, article.author.username),
+        title: article.title,
+        author_name: article.author.username,
+        provider_url: base_url,
+        width: query.maxwidth.unwrap_or(DEFAULT_WIDTH).min(DEFAULT_WIDTH),
+        height: HEIGHT,
+    }))
+}
+
+/// How an article URL identifies the article.
+#[derive(Debug, PartialEq)]
+enum ArticleUrl {
+    Slug(Slug),
+    Id(ArticleId),
+}
+
+/// Work out which article `url` points to, if it's on this deployment at all.
+fn parse_article_url(base_url: &str, url: &str) -> Option<ArticleUrl> {
+    let base_url = Url::parse(base_url).ok()?;
+    let url = Url::parse(url).ok()?;
+
+    // `origin()` includes the scheme, host and port, so `http://` and `https://` versions of the
+    // same link are different. Consumers are supposed to ask with the link as it was shared,
+    // which will be whatever `base_url` was when it was made.
+    if url.origin() != base_url.origin() {
+        return None;
+    }
+
+    let segments: Vec<&str> = url.path_segments()?.collect();
+
+    match segments.as_slice() {
+        ["a", id] => Some(ArticleUrl::Id(ArticleId(Uuid::parse_str(id).ok()?))),
+        ["api", "articles", slug] | ["og", slug] => {
+            Some(ArticleUrl::Slug(Slug::try_from(slug.to_string()).ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// The HTML to embed: the title, linking to the article, with the description and author.
+fn render(article: &Article) -> String {
+    format!(
+        r#"<blockquote class="realworld-article"><p><a href="{}">{}</a></p><p>{}</p><p>by {}</p></blockquote>"#,
+        escape(&article.short_url),
+        escape(&article.title),
+        escape(&article.description),
+        escape(&article.author.username),
+    )
+}
+
+#[test]
+fn test_parse_article_url() {
+    let base_url = "https://example.com";
+    let id = Uuid::from_u128(0x936da01f_9abd_4d9d_80c7_02af85c822a8);
+    let slug = |slug: &str| Some(ArticleUrl::Slug(Slug::try_from(slug.to_string()).unwrap()));
+
+    assert_eq!(
+        parse_article_url(base_url, &format!("https://example.com/a/{}", id)),
+        Some(ArticleUrl::Id(ArticleId(id)))
+    );
+    assert_eq!(
+        parse_article_url(base_url, "https://example.com/api/articles/hello-world"),
+        slug("hello-world")
+    );
+    assert_eq!(
+        parse_article_url(base_url, "https://example.com/og/hello-world?utm_source=x"),
+        slug("hello-world")
+    );
+
+    // Not ours.
+    assert_eq!(
+        parse_article_url(base_url, "https://example.org/og/hello-world"),
+        None
+    );
+    assert_eq!(
+        parse_article_url(base_url, "http://example.com/og/hello-world"),
+        None
+    );
+    // Ours, but not an article.
+    assert_eq!(
+        parse_article_url(base_url, "https://example.com/api/profiles/alice"),
+        None
+    );
+    assert_eq!(parse_article_url(base_url, "https://example.com/a/1"), None);
+    assert_eq!(parse_article_url(base_url, "not a url"), None);
+}
@@ -0,0 +1,158 @@
+use std::fmt::Write;
+
+use axum::extract::{Extension, Path};
+use axum::http::header::CACHE_CONTROL;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+
+use crate::db;
+use crate::http::articles::Article;
+use crate::http::types::Slug;
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// When someone pastes a link into a chat app or a social network, it fetches the page to make
+// a preview ("unfurl" it) out of the OpenGraph and Twitter Card `<meta>` tags in the `<head>`.
+// None of them run JavaScript to do it, so a single-page frontend only ever shows them whatever
+// its `index.html` says, which is the same for every article.
+//
+// This serves a page with nothing but those tags, for a frontend or its CDN to send those
+// crawlers to instead (they're easy to pick out by `User-Agent`). Someone who does open the
+// page gets a link to the article.
+//
+// There's no templating engine here, just `write!()`, since it's one small page and everything
+// that goes in it is escaped by `escape()`.
+
+/// How long crawlers and caches may keep a preview, in seconds.
+///
+/// Previews are cached by the sites that show them for much longer than this anyway, so there's
+/// no point making every share of a popular article hit the database.
+const MAX_AGE_SECS: u32 = 300;
+
+pub fn router() -> Router {
+    Router::new().route("/og/:slug", get(article_preview))
+}
+
+/// A minimal HTML page with OpenGraph and Twitter Card tags describing an article.
+async fn article_preview(
+    ctx: Extension<ApiContext>,
+    Path(slug): Path<Slug>,
+) -> Result<(HeaderMap, Html<String>)> {
+    // Crawlers aren't logged in, so they can share the cache with anonymous visitors to
+    // `GET /api/articles/:slug`.
+    let article = ctx
+        .articles
+        .get_or_refresh(&slug.as_str().to_string(), || {
+            db::articles::find_by_slug(ctx.db.read(), slug.as_str(), None)
+        })
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let article = Article::new(article, &ctx.config);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::try_from(format!("public, max-age={}", MAX_AGE_SECS))
+            .map_err(anyhow::Error::from)?,
+    );
+
+    Ok((headers, Html(render(&article))))
+}
+
+fn render(article: &Article) -> String {
+    let title = escape(&article.title);
+    let description = escape(&article.description);
+    let author = escape(&article.author.username);
+    let url = escape(&article.short_url);
+
+    let mut meta = String::new();
+
+    // `write!()` to a `String` can't fail.
+    let mut tag = |attr: &str, name: &str, content: &str| {
+        let _ = writeln!(
+            meta,
+            r#"    <meta {}="{}" content="{}">"#,
+            attr, name, content
+        );
+    };
+
+    tag("property", "og:type", "article");
+    tag("property", "og:title", &title);
+    tag("property", "og:description", &description);
+    tag("property", "og:url", &url);
+    tag("property", "article:author", &author);
+    tag(
+        "property",
+        "article:published_time",
+        &escape(&article.created_at.to_string()),
+    );
+
+    for tag_name in &article.tag_list {
+        tag("property", "article:tag", &escape(tag_name));
+    }
+
+    // Articles don't have images of their own, so the best we can do is the author's avatar.
+    let image = article.author.image.as_deref().map(escape);
+
+    if let Some(image) = &image {
+        tag("property", "og:image", image);
+    }
+
+    // The small card, since an avatar is too small for the large one.
+    tag("name", "twitter:card", "summary");
+    tag("name", "twitter:title", &title);
+    tag("name", "twitter:description", &description);
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>{title}</title>
+    <meta name="description" content="{description}">
+    <meta name="author" content="{author}">
+{meta}</head>
+<body>
+    <p><a href="{url}">{title}</a> by {author}</p>
+</body>
+</html>
+"#,
+        title = title,
+        description = description,
+        author = author,
+        meta = meta,
+        url = url,
+    )
+}
+
+/// Escape `text` for use in HTML, including inside a double- or single-quoted attribute.
+pub(super) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(escape("Tom & Jerry"), "Tom &amp; Jerry");
+    assert_eq!(
+        escape(r#""><script>alert('hi')</script>"#),
+        "&quot;&gt;&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt;"
+    );
+    assert_eq!(escape("日本語"), "日本語");
+}
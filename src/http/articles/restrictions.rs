@@ -0,0 +1,225 @@
+use std::fmt;
+
+use metrics::{describe_counter, increment_counter};
+use time::{Duration, OffsetDateTime};
+
+use crate::db;
+use crate::db::types::UserId;
+use crate::http::articles::links;
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// Drive-by spam comes from accounts that were registered a minute ago: a script signs up, posts
+// as many articles and comments full of links as it can, and moves on. So on a public
+// deployment it's worth holding brand new accounts to a few extra rules for their first hours,
+// which anyone who's here to stay will hardly notice:
+//
+// * `new_account_max_articles`: how many articles they may publish, in total.
+// * `new_account_max_comments`: likewise for comments.
+// * `new_account_links`: whether they may post links or images to other sites at all.
+//
+// `new_account_hours` says how long an account counts as new, and nothing applies unless it's
+// set. All of these can be changed without a restart, for when a wave of spam is under way.
+//
+// This is on top of the quotas in `http::quota`, which apply to everyone. Unlike those, there's
+// nothing to wait out but the account's age, so breaking one of these is a `403 Forbidden`
+// rather than a `429 Too Many Requests`, with a body saying which rule it was and when it stops
+// applying:
+//
+// ```json
+// {
+//   "restriction": {
+//     "code": "new_account_links",
+//     "message": "accounts less than 24 hours old may not post links to other sites",
+//     "liftsAt": "2022-01-02T12:00:00Z"
+//   }
+// }
+// ```
+
+/// Which of the rules for new accounts a request broke, and when they stop applying.
+#[derive(Debug)]
+pub struct Restriction {
+    kind: Kind,
+    /// How many hours an account counts as new for.
+    hours: u32,
+    /// When the account stops counting as new.
+    lifts_at: OffsetDateTime,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Articles { limit: u32 },
+    Comments { limit: u32 },
+    Links,
+}
+
+/// Register descriptions for the metrics in this module, see `db::instrument::describe_metrics()`.
+pub fn describe_metrics() {
+    describe_counter!(
+        "new_account_restricted_total",
+        "How many requests were rejected because of a restriction on new accounts, by restriction."
+    );
+}
+
+/// Check that `user_id` may publish an article with `body`, see `Config::new_account_hours`.
+pub(super) async fn check_article(ctx: &ApiContext, user_id: UserId, body: &str) -> Result<()> {
+    let (hours, lifts_at) = match new_account(ctx, user_id).await? {
+        Some(new) => new,
+        None => return Ok(()),
+    };
+
+    let dynamic = ctx.dynamic.load();
+
+    if let Some(limit) = dynamic.new_account_max_articles {
+        // All of them, since the account is new.
+        let published =
+            db::articles::count_recent(ctx.db.primary(), user_id, OffsetDateTime::unix_epoch())
+                .await?;
+
+        if published.count >= i64::from(limit) {
+            return Err(Restriction::error(
+                Kind::Articles { limit },
+                hours,
+                lifts_at,
+            ));
+        }
+    }
+
+    check_links(ctx, body, hours, lifts_at)
+}
+
+/// Check that `user_id` may edit the body of one of their articles to `body`.
+///
+/// Only links matter here; editing doesn't count towards the number of articles.
+pub(super) async fn check_article_edit(
+    ctx: &ApiContext,
+    user_id: UserId,
+    body: &str,
+) -> Result<()> {
+    match new_account(ctx, user_id).await? {
+        Some((hours, lifts_at)) => check_links(ctx, body, hours, lifts_at),
+        None => Ok(()),
+    }
+}
+
+/// Check that `user_id` may post a comment with `body`.
+pub(super) async fn check_comment(ctx: &ApiContext, user_id: UserId, body: &str) -> Result<()> {
+    let (hours, lifts_at) = match new_account(ctx, user_id).await? {
+        Some(new) => new,
+        None => return Ok(()),
+    };
+
+    if let Some(limit) = ctx.dynamic.load().new_account_max_comments {
+        let posted =
+            db::comments::count_recent(ctx.db.primary(), user_id, OffsetDateTime::unix_epoch())
+                .await?;
+
+        if posted.count >= i64::from(limit) {
+            return Err(Restriction::error(
+                Kind::Comments { limit },
+                hours,
+                lifts_at,
+            ));
+        }
+    }
+
+    check_links(ctx, body, hours, lifts_at)
+}
+
+/// If `user_id` counts as a new account, how many hours that lasts and when it ends.
+async fn new_account(ctx: &ApiContext, user_id: UserId) -> Result<Option<(u32, OffsetDateTime)>> {
+    let hours = match ctx.dynamic.load().new_account_hours {
+        Some(hours) => hours,
+        None => return Ok(None),
+    };
+
+    // From the primary, since the accounts we care about most have only just been created.
+    let created_at = db::users::find_created_at(ctx.db.primary(), user_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let lifts_at = created_at + Duration::hours(hours.into());
+
+    Ok((ctx.clock.now() < lifts_at).then_some((hours, lifts_at)))
+}
+
+fn check_links(ctx: &ApiContext, body: &str, hours: u32, lifts_at: OffsetDateTime) -> Result<()> {
+    // Links back to us are fine; `extract()` leaves those out.
+    if !ctx.dynamic.load().new_account_links
+        && !links::extract(body, &ctx.config.base_url()).is_empty()
+    {
+        return Err(Restriction::error(Kind::Links, hours, lifts_at));
+    }
+
+    Ok(())
+}
+
+impl Restriction {
+    /// The error for breaking `kind`, counted in `new_account_restricted_total`.
+    fn error(kind: Kind, hours: u32, lifts_at: OffsetDateTime) -> Error {
+        let restriction = Restriction {
+            kind,
+            hours,
+            lifts_at,
+        };
+
+        increment_counter!("new_account_restricted_total", "restriction" => restriction.code());
+
+        Error::Restricted(restriction)
+    }
+
+    /// Which rule was broken, for clients to tell them apart.
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            Kind::Articles { .. } => "new_account_articles",
+            Kind::Comments { .. } => "new_account_comments",
+            Kind::Links => "new_account_links",
+        }
+    }
+
+    pub fn lifts_at(&self) -> OffsetDateTime {
+        self.lifts_at
+    }
+}
+
+impl fmt::Display for Restriction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: u32| if n == 1 { "" } else { "s" };
+
+        write!(
+            f,
+            "accounts less than {} hour{} old may ",
+            self.hours,
+            plural(self.hours)
+        )?;
+
+        match self.kind {
+            Kind::Articles { limit } => write!(f, "only post {} article{}", limit, plural(limit)),
+            Kind::Comments { limit } => write!(f, "only post {} comment{}", limit, plural(limit)),
+            Kind::Links => write!(f, "not post links to other sites"),
+        }
+    }
+}
+
+#[test]
+fn test_restriction_message() {
+    let restriction = |kind, hours| Restriction {
+        kind,
+        hours,
+        lifts_at: OffsetDateTime::unix_epoch(),
+    };
+
+    assert_eq!(
+        restriction(Kind::Articles { limit: 1 }, 24).to_string(),
+        "accounts less than 24 hours old may only post 1 article"
+    );
+    assert_eq!(
+        restriction(Kind::Comments { limit: 5 }, 1).to_string(),
+        "accounts less than 1 hour old may only post 5 comments"
+    );
+    assert_eq!(
+        restriction(Kind::Links, 72).to_string(),
+        "accounts less than 72 hours old may not post links to other sites"
+    );
+}
@@ -0,0 +1,250 @@
+use axum::extract::{Extension, Path};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use url::Url;
+
+use crate::db;
+use crate::http::articles::takedowns::require_moderator;
+use crate::http::extractor::AuthUser;
+use crate::http::tx::Tx;
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// Tags are whatever authors type, so the same topic tends to end up under a few of them:
+// `rust`, `Rust` and `rustlang`, say. Moderators can fold those into one with
+// `POST /api/admin/tags/merge`, which retags every article that has any of them, and carries
+// over anyone who'd muted them so those articles stay hidden, and their metadata (see below) if
+// the tag they're merged into has none. Renaming a tag is just merging it into a new one.
+//
+// It can touch a lot of articles, so with `dryRun` it only says how many it would change.
+//
+// Moderators can also give a tag a description and an image with `PUT /api/admin/tags/:tag`,
+// which `GET /api/tags/:tag` returns along with how many articles and authors use the tag,
+// for frontends to put at the top of the tag's page above the list of its articles.
+
+/// The longest description a tag may have, in characters. It's meant to be a paragraph.
+const MAX_DESCRIPTION_LEN: usize = 1000;
+
+/// The longest URL a tag's image may have.
+const MAX_IMAGE_URL_LEN: usize = 2048;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/tags/:tag", get(get_tag))
+        .route("/api/admin/tags/:tag", put(set_tag_metadata))
+        .route("/api/admin/tags/merge", post(merge_tags))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TagBody<T = Tag> {
+    tag: T,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct TagMetadata {
+    description: Option<String>,
+    image: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Tag {
+    name: String,
+    description: Option<String>,
+    image: Option<String>,
+    /// How many articles have the tag, as `GET /api/articles?tag=` would list.
+    articles_count: i64,
+    authors_count: i64,
+}
+
+impl From<db::tags::Tag> for Tag {
+    fn from(tag: db::tags::Tag) -> Self {
+        Tag {
+            name: tag.tag,
+            description: tag.description,
+            image: tag.image,
+            articles_count: tag.articles_count,
+            authors_count: tag.authors_count,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MergeBody<T> {
+    merge: T,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MergeTags {
+    /// The tags to replace. Tags are matched exactly, so list every spelling.
+    from: Vec<String>,
+    /// The tag to replace them with, which may be new or one of the articles already have.
+    to: String,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Merged {
+    from: Vec<String>,
+    to: String,
+    dry_run: bool,
+    /// How many articles were retagged, or would be with `dryRun`.
+    articles_count: i64,
+}
+
+/// Get a tag's metadata and counts, or `404 Not Found` if no article has the tag and it has no
+/// metadata either.
+async fn get_tag(ctx: Extension<ApiContext>, Path(tag): Path<String>) -> Result<Json<TagBody>> {
+    let tag = db::tags::find(ctx.db.read(), &tag)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(Json(TagBody { tag: tag.into() }))
+}
+
+/// Set a tag's description and image, replacing what it had before; leave either out to
+/// clear it.
+///
+/// The tag doesn't have to be in use yet, so moderators can get its page ready in advance.
+async fn set_tag_metadata(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(tag): Path<String>,
+    Json(req): Json<TagBody<TagMetadata>>,
+) -> Result<Json<TagBody>> {
+    require_moderator(&ctx, &auth_user).await?;
+
+    let mut errors = Vec::new();
+
+    // Nobody could use a tag that's too long for an article, so it'd never have a page.
+    if tag.is_empty() || tag.chars().count() > ctx.config.max_tag_len {
+        errors.push((
+            "tag",
+            format!(
+                "tags must be between 1 and {} characters",
+                ctx.config.max_tag_len
+            ),
+        ));
+    }
+
+    let description = req
+        .tag
+        .description
+        .as_deref()
+        .map(str::trim)
+        .filter(|description| !description.is_empty());
+
+    if description.is_some_and(|description| description.chars().count() > MAX_DESCRIPTION_LEN) {
+        errors.push((
+            "description",
+            format!(
+                "description must be at most {} characters",
+                MAX_DESCRIPTION_LEN
+            ),
+        ));
+    }
+
+    let image = req.tag.image.as_deref().filter(|image| !image.is_empty());
+
+    if let Some(image) = image {
+        let valid = image.len() <= MAX_IMAGE_URL_LEN
+            && Url::parse(image).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+
+        if !valid {
+            errors.push(("image", "image must be an http:// or https:// URL".into()));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(Error::unprocessable_entity(errors));
+    }
+
+    db::tags::set_metadata(ctx.db.primary(), &tag, description, image).await?;
+
+    // From the primary, so this includes what we just set.
+    let tag = db::tags::find(ctx.db.primary(), &tag)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(Json(TagBody { tag: tag.into() }))
+}
+
+async fn merge_tags(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    mut tx: Tx,
+    Json(req): Json<MergeBody<MergeTags>>,
+) -> Result<Json<MergeBody<Merged>>> {
+    require_moderator(&ctx, &auth_user).await?;
+
+    let MergeTags {
+        mut from,
+        to,
+        dry_run,
+    } = req.merge;
+
+    if to.is_empty() || to.chars().count() > ctx.config.max_tag_len {
+        return Err(Error::unprocessable_entity([(
+            "to",
+            format!(
+                "tags must be between 1 and {} characters",
+                ctx.config.max_tag_len
+            ),
+        )]));
+    }
+
+    // Merging a tag into itself wouldn't change anything, but without this every article
+    // that's already tagged `to` would be counted (and rewritten) anyway.
+    from.retain(|tag| *tag != to);
+    from.sort();
+    from.dedup();
+
+    if from.is_empty() {
+        return Err(Error::unprocessable_entity([(
+            "from",
+            "must list at least one tag other than `to`",
+        )]));
+    }
+
+    let articles_count = if dry_run {
+        db::articles::count_tagged(ctx.db.primary(), &from).await?
+    } else {
+        let slugs = db::articles::merge_tags(tx.conn().await?, &from, &to).await?;
+        db::muted_tags::merge(tx.conn().await?, &from, &to).await?;
+        db::tags::merge_metadata(tx.conn().await?, &from, &to).await?;
+
+        let articles_count = slugs.len() as i64;
+        let (tags, articles) = (ctx.tags.clone(), ctx.articles.clone());
+
+        tx.after_commit(move || {
+            tags.invalidate();
+
+            for slug in &slugs {
+                articles.invalidate(slug);
+            }
+        });
+
+        articles_count
+    };
+
+    Ok(Json(MergeBody {
+        merge: Merged {
+            from,
+            to,
+            dry_run,
+            articles_count,
+        },
+    }))
+}
+
+/// See `articles::fuzz_request_bodies()`.
+#[cfg(feature = "fuzzing")]
+pub(super) fn fuzz_request_bodies(data: &[u8]) {
+    serde_json::from_slice::<TagBody<TagMetadata>>(data).ok();
+    serde_json::from_slice::<MergeBody<MergeTags>>(data).ok();
+}
@@ -0,0 +1,224 @@
+use axum::extract::{Extension, Path};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use crate::db;
+use crate::db::users::Role;
+use crate::http::extractor::AuthUser;
+use crate::http::types::{Slug, Timestamptz};
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// Moderators (and admins) can take an article down, with a reason. That hides it from everyone
+// but its author: it drops out of the listings and feeds, `GET /api/articles/:slug` is a 404,
+// and it can't be favorited. Its author still sees it in `GET /api/articles/:slug`, with
+// `takedownReason` saying why, and can still edit it to fix whatever the problem was.
+//
+// If they disagree, or think they've fixed it, they can appeal, which puts the case back in
+// front of the moderators at `GET /api/admin/appeals`. A moderator then either puts the article
+// back up with `DELETE /api/admin/articles/:slug/takedown`, or takes it down again, with the
+// same reason or a new one, which dismisses the appeal.
+//
+// Comments aren't hidden along with the article, but without the article there's nowhere
+// they'd show up.
+//
+// There's nothing to notify the author when any of this happens, so for now they find out when
+// they next look at the article.
+
+/// The most characters in a takedown reason or an appeal. They're meant to be a sentence or
+/// two, not an essay.
+const MAX_LEN: usize = 2000;
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/admin/articles/:slug/takedown",
+            post(take_down_article).delete(restore_article),
+        )
+        .route("/api/admin/appeals", get(list_appeals))
+        .route("/api/articles/:slug/appeal", post(appeal_takedown))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TakedownBody<T = Takedown> {
+    takedown: T,
+}
+
+#[derive(serde::Deserialize)]
+struct TakeDown {
+    reason: String,
+}
+
+#[derive(serde::Deserialize)]
+struct AppealBody<T> {
+    appeal: T,
+}
+
+#[derive(serde::Deserialize)]
+struct Appeal {
+    body: String,
+}
+
+#[derive(serde::Serialize)]
+struct TakedownsBody {
+    takedowns: Vec<Takedown>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Takedown {
+    slug: String,
+    reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    appeal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    appealed_at: Option<Timestamptz>,
+    created_at: Timestamptz,
+}
+
+impl From<db::takedowns::Takedown> for Takedown {
+    fn from(takedown: db::takedowns::Takedown) -> Self {
+        Takedown {
+            slug: takedown.slug,
+            reason: takedown.reason,
+            appeal: takedown.appeal,
+            appealed_at: takedown.appealed_at.map(Timestamptz),
+            created_at: Timestamptz(takedown.created_at),
+        }
+    }
+}
+
+/// Take an article down, or take it down again to uphold the decision and dismiss an appeal.
+async fn take_down_article(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(slug): Path<Slug>,
+    Json(req): Json<TakedownBody<TakeDown>>,
+) -> Result<Json<TakedownBody>> {
+    require_moderator(&ctx, &auth_user).await?;
+    check_len("reason", &req.takedown.reason)?;
+
+    let takedown = db::takedowns::take_down(
+        ctx.db.primary(),
+        slug.as_str(),
+        auth_user.user_id,
+        req.takedown.reason.trim(),
+    )
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    // Other instances may show it to anonymous visitors until their caches expire.
+    ctx.articles.invalidate(&slug.as_str().to_string());
+
+    Ok(Json(TakedownBody {
+        takedown: takedown.into(),
+    }))
+}
+
+/// Put an article back up.
+async fn restore_article(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(slug): Path<Slug>,
+) -> Result<()> {
+    require_moderator(&ctx, &auth_user).await?;
+
+    if !db::takedowns::restore(ctx.db.primary(), slug.as_str()).await? {
+        return Err(Error::NotFound);
+    }
+
+    ctx.articles.invalidate(&slug.as_str().to_string());
+
+    Ok(())
+}
+
+/// List the takedowns that have been appealed, for moderators to review.
+async fn list_appeals(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+) -> Result<Json<TakedownsBody>> {
+    require_moderator(&ctx, &auth_user).await?;
+
+    let takedowns = db::takedowns::list_appealed(ctx.db.read()).await?;
+
+    Ok(Json(TakedownsBody {
+        takedowns: takedowns.into_iter().map(Takedown::from).collect(),
+    }))
+}
+
+/// Appeal the takedown of the user's own article, replacing any earlier appeal.
+async fn appeal_takedown(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(slug): Path<Slug>,
+    Json(req): Json<AppealBody<Appeal>>,
+) -> Result<Json<TakedownBody>> {
+    check_len("body", &req.appeal.body)?;
+
+    let article = db::articles::find_meta(ctx.db.primary(), slug.as_str())
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    if article.user_id != auth_user.user_id {
+        return Err(Error::Forbidden);
+    }
+
+    // If it hasn't been taken down, there's nothing to appeal.
+    let takedown = db::takedowns::appeal(
+        ctx.db.primary(),
+        slug.as_str(),
+        auth_user.user_id,
+        req.appeal.body.trim(),
+    )
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok(Json(TakedownBody {
+        takedown: takedown.into(),
+    }))
+}
+
+/// Fail with `403 Forbidden` unless the user is a moderator or an admin.
+///
+/// Roles are set with the `admin` subcommand, see `db::users::Role`.
+pub(in crate::http) async fn require_moderator(
+    ctx: &ApiContext,
+    auth_user: &AuthUser,
+) -> Result<()> {
+    match db::users::find_role(ctx.db.read(), auth_user.user_id).await? {
+        Some(Role::Moderator | Role::Admin) => Ok(()),
+        _ => Err(Error::Forbidden),
+    }
+}
+
+/// Fail with `403 Forbidden` unless the user is an admin.
+///
+/// For things that affect the whole site rather than a piece of content, like
+/// `announcements`.
+pub(in crate::http) async fn require_admin(ctx: &ApiContext, auth_user: &AuthUser) -> Result<()> {
+    match db::users::find_role(ctx.db.read(), auth_user.user_id).await? {
+        Some(Role::Admin) => Ok(()),
+        _ => Err(Error::Forbidden),
+    }
+}
+
+fn check_len(field: &'static str, text: &str) -> Result<()> {
+    let len = text.trim().chars().count();
+
+    if len == 0 || len > MAX_LEN {
+        return Err(Error::unprocessable_entity([(
+            field,
+            format!("must be between 1 and {} characters", MAX_LEN),
+        )]));
+    }
+
+    Ok(())
+}
+
+/// See `articles::fuzz_request_bodies()`.
+#[cfg(feature = "fuzzing")]
+pub(super) fn fuzz_request_bodies(data: &[u8]) {
+    serde_json::from_slice::<TakedownBody<TakeDown>>(data).ok();
+    serde_json::from_slice::<AppealBody<Appeal>>(data).ok();
+}
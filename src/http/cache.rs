@@ -0,0 +1,355 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use metrics::{describe_counter, increment_counter};
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::Instant;
+
+use crate::events::{Event, EventHub};
+
+// A cache for the results of expensive queries that every instance can afford to be a little
+// behind on, like the list of tags.
+//
+// This lives in each instance's memory rather than in something shared like Redis, which would
+// be one more thing to deploy and keep running. The price is that each instance only finds out
+// about changes made through the others by way of `EventHub`, or when the TTL runs out.
+
+/// Register descriptions for the metrics in this module, see `db::instrument::describe_metrics()`.
+pub fn describe_metrics() {
+    describe_counter!(
+        "cache_hits_total",
+        "How many lookups each in-memory cache answered without going to the database."
+    );
+    describe_counter!(
+        "cache_misses_total",
+        "How many lookups each in-memory cache had to go to the database for."
+    );
+}
+
+/// A single cached value, refreshed at most every `ttl`.
+pub struct Cached<T> {
+    /// For the `cache` label on the hit and miss counters.
+    name: &'static str,
+    ttl: Duration,
+    state: Mutex<State<T>>,
+    /// Held while refreshing, so a burst of requests on an empty cache runs the query once
+    /// rather than once each, which would defeat the point.
+    refreshing: tokio::sync::Mutex<()>,
+    invalidate_on: Option<InvalidateOn>,
+}
+
+/// See `Cached::invalidate_on()`.
+struct InvalidateOn {
+    events: Mutex<Receiver<Event>>,
+    matches: fn(&Event) -> bool,
+}
+
+struct State<T> {
+    value: Option<(Instant, T)>,
+    /// Bumped by `invalidate()`, so a refresh that was already running when the cache was
+    /// invalidated doesn't put its (possibly stale) result back.
+    generation: u64,
+}
+
+impl<T: Clone> Cached<T> {
+    pub fn new(name: &'static str, ttl: Duration) -> Self {
+        Cached {
+            name,
+            ttl,
+            state: Mutex::new(State {
+                value: None,
+                generation: 0,
+            }),
+            refreshing: tokio::sync::Mutex::new(()),
+            invalidate_on: None,
+        }
+    }
+
+    /// Also invalidate the cache whenever `events` broadcasts an event for which `matches`
+    /// returns `true`, wherever it came from.
+    pub fn invalidate_on(mut self, events: &EventHub, matches: fn(&Event) -> bool) -> Self {
+        self.invalidate_on = Some(InvalidateOn {
+            events: Mutex::new(events.subscribe()),
+            matches,
+        });
+        self
+    }
+
+    /// Return the cached value if it's fresh, otherwise call `refresh` for a new one and
+    /// cache that.
+    ///
+    /// Errors aren't cached, so the next call tries again.
+    pub async fn get_or_refresh<F, Fut, E>(&self, refresh: F) -> Result<T, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.check_events();
+
+        if let Some(value) = self.get() {
+            increment_counter!("cache_hits_total", "cache" => self.name);
+            return Ok(value);
+        }
+
+        let _refreshing = self.refreshing.lock().await;
+
+        // Someone else may have refreshed it while we were waiting.
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+
+        increment_counter!("cache_misses_total", "cache" => self.name);
+
+        let generation = self.state.lock().unwrap().generation;
+        let value = refresh().await?;
+
+        let mut state = self.state.lock().unwrap();
+
+        if state.generation == generation {
+            state.value = Some((Instant::now(), value.clone()));
+        }
+
+        Ok(value)
+    }
+
+    /// Throw away the cached value, so the next call to `get_or_refresh()` gets a new one.
+    ///
+    /// Call this after a write that changes the result, so the instance that made the change
+    /// reflects it straight away.
+    pub fn invalidate(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.value = None;
+        state.generation += 1;
+    }
+
+    fn get(&self) -> Option<T> {
+        let state = self.state.lock().unwrap();
+
+        match &state.value {
+            Some((refreshed_at, value)) if refreshed_at.elapsed() < self.ttl => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    /// Invalidate the cache if any matching events came in since the last call.
+    fn check_events(&self) {
+        let invalidate_on = match &self.invalidate_on {
+            Some(invalidate_on) => invalidate_on,
+            None => return,
+        };
+
+        let mut events = invalidate_on.events.lock().unwrap();
+
+        loop {
+            match events.try_recv() {
+                Ok(event) if (invalidate_on.matches)(&event) => self.invalidate(),
+                Ok(_) => (),
+                // We missed some events, any of which could have been one we care about.
+                Err(TryRecvError::Lagged(_)) => self.invalidate(),
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/// A cache of up to `capacity` values by key, each refreshed at most every `ttl`, which
+/// evicts the least recently used value to make room for a new one.
+///
+/// Unlike `Cached`, this doesn't stop a burst of requests for the same missing key from all
+/// going to the database. That would need a lock per key, and for what this is used for
+/// (popular articles) the first request fills the cache within a few milliseconds anyway.
+pub struct CachedMap<K, V> {
+    /// For the `cache` label on the hit and miss counters.
+    name: &'static str,
+    ttl: Duration,
+    capacity: usize,
+    state: Mutex<MapState<K, V>>,
+}
+
+struct MapState<K, V> {
+    entries: HashMap<K, MapEntry<V>>,
+    /// The keys in `entries` by when they were last used, so the first one is the one to evict.
+    ///
+    /// This is keyed by a counter rather than an `Instant`, which could repeat.
+    recency: BTreeMap<u64, K>,
+    next_use: u64,
+    /// See `State::generation`. This is shared by all keys, so invalidating one may throw
+    /// away the result of refreshing another, which is harmless.
+    generation: u64,
+}
+
+struct MapEntry<V> {
+    value: V,
+    refreshed_at: Instant,
+    /// Our key in `MapState::recency`.
+    last_used: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> CachedMap<K, V> {
+    pub fn new(name: &'static str, capacity: usize, ttl: Duration) -> Self {
+        CachedMap {
+            name,
+            ttl,
+            capacity,
+            state: Mutex::new(MapState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                next_use: 0,
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Return the cached value for `key` if it's fresh, otherwise call `refresh` for a new one
+    /// and cache that.
+    ///
+    /// Errors aren't cached, and neither is `None`, so a lookup of something that doesn't exist
+    /// yet doesn't keep returning `None` after it's been created.
+    pub async fn get_or_refresh<F, Fut, E>(&self, key: &K, refresh: F) -> Result<Option<V>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<V>, E>>,
+    {
+        let generation = {
+            let mut state = self.state.lock().unwrap();
+
+            if let Some(value) = state.get(key, self.ttl) {
+                increment_counter!("cache_hits_total", "cache" => self.name);
+                return Ok(Some(value));
+            }
+
+            state.generation
+        };
+
+        increment_counter!("cache_misses_total", "cache" => self.name);
+
+        let value = refresh().await?;
+
+        if let Some(value) = &value {
+            let mut state = self.state.lock().unwrap();
+
+            if state.generation == generation {
+                state.insert(key.clone(), value.clone(), self.capacity);
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Throw away the cached value for `key`, if any.
+    ///
+    /// Call this after a write that changes the value, so the instance that made the change
+    /// reflects it straight away.
+    pub fn invalidate(&self, key: &K) {
+        let mut state = self.state.lock().unwrap();
+        state.remove(key);
+        state.generation += 1;
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> MapState<K, V> {
+    fn get(&mut self, key: &K, ttl: Duration) -> Option<V> {
+        let entry = self.entries.get_mut(key)?;
+
+        if entry.refreshed_at.elapsed() >= ttl {
+            self.remove(key);
+            return None;
+        }
+
+        // Move it to the back of the line for eviction.
+        let key = self.recency.remove(&entry.last_used)?;
+        entry.last_used = self.next_use;
+        self.recency.insert(self.next_use, key);
+        self.next_use += 1;
+
+        Some(entry.value.clone())
+    }
+
+    fn insert(&mut self, key: K, value: V, capacity: usize) {
+        self.remove(&key);
+
+        while self.entries.len() >= capacity.max(1) {
+            match self.recency.pop_first() {
+                Some((_, evicted)) => self.entries.remove(&evicted),
+                None => break,
+            };
+        }
+
+        self.recency.insert(self.next_use, key.clone());
+        self.entries.insert(
+            key,
+            MapEntry {
+                value,
+                refreshed_at: Instant::now(),
+                last_used: self.next_use,
+            },
+        );
+        self.next_use += 1;
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_cached() {
+    tokio::time::pause();
+
+    let cached = Cached::new("test", Duration::from_secs(30));
+    let refresh = |value: u32| move || async move { Ok::<_, ()>(value) };
+
+    assert_eq!(cached.get_or_refresh(refresh(1)).await, Ok(1));
+    // Still fresh, so this doesn't get called.
+    assert_eq!(cached.get_or_refresh(refresh(2)).await, Ok(1));
+
+    tokio::time::advance(Duration::from_secs(31)).await;
+    assert_eq!(cached.get_or_refresh(refresh(3)).await, Ok(3));
+
+    cached.invalidate();
+    assert_eq!(cached.get_or_refresh(refresh(4)).await, Ok(4));
+
+    // Errors aren't cached.
+    cached.invalidate();
+    assert_eq!(cached.get_or_refresh(|| async { Err(()) }).await, Err(()));
+    assert_eq!(cached.get_or_refresh(refresh(5)).await, Ok(5));
+}
+
+#[tokio::test]
+async fn test_cached_map() {
+    tokio::time::pause();
+
+    let cached = CachedMap::new("test", 2, Duration::from_secs(30));
+    let refresh = |value: u32| move || async move { Ok::<_, ()>(Some(value)) };
+
+    assert_eq!(cached.get_or_refresh(&"a", refresh(1)).await, Ok(Some(1)));
+    assert_eq!(cached.get_or_refresh(&"b", refresh(2)).await, Ok(Some(2)));
+    // Still fresh, so this doesn't get called.
+    assert_eq!(cached.get_or_refresh(&"a", refresh(3)).await, Ok(Some(1)));
+
+    // That makes "b" the least recently used, so this evicts it.
+    assert_eq!(cached.get_or_refresh(&"c", refresh(4)).await, Ok(Some(4)));
+    assert_eq!(cached.get_or_refresh(&"a", refresh(5)).await, Ok(Some(1)));
+    assert_eq!(cached.get_or_refresh(&"b", refresh(6)).await, Ok(Some(6)));
+
+    cached.invalidate(&"b");
+    assert_eq!(cached.get_or_refresh(&"b", refresh(7)).await, Ok(Some(7)));
+
+    tokio::time::advance(Duration::from_secs(31)).await;
+    assert_eq!(cached.get_or_refresh(&"b", refresh(8)).await, Ok(Some(8)));
+
+    // `None` isn't cached.
+    assert_eq!(
+        cached
+            .get_or_refresh(&"d", || async { Ok::<_, ()>(None) })
+            .await,
+        Ok(None)
+    );
+    assert_eq!(cached.get_or_refresh(&"d", refresh(9)).await, Ok(Some(9)));
+}
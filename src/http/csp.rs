@@ -0,0 +1,285 @@
+use axum::body::Bytes;
+use axum::extract::{ContentLengthLimit, Extension, Query};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::Value;
+use url::Url;
+
+use crate::db;
+use crate::db::csp_reports::NewCspReport;
+use crate::http::articles::require_moderator;
+use crate::http::extractor::AuthUser;
+use crate::http::types::Timestamptz;
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// A Content Security Policy tells browsers which scripts, images and so on a page may load.
+// When a page breaks the policy, the browser can tell us about it, which is how you find out
+// that your policy blocks something it shouldn't, or that someone has found a way to get a
+// script into a page. A frontend served with a policy like
+//
+// ```text
+// Content-Security-Policy: default-src 'self'; report-uri https://api.example.com/api/csp-report
+// ```
+//
+// has the reports sent here, and moderators can look through them with
+// `GET /api/admin/csp-reports`.
+//
+// Browsers send reports in one of two formats:
+//
+// * The older `report-uri` directive sends one report per request, as
+//   `{"csp-report": {...}}` with `Content-Type: application/csp-report`.
+// * The newer Reporting API (the `report-to` directive) sends a batch of reports of all kinds
+//   per request, as `[{"type": "csp-violation", "body": {...}}, ...]` with
+//   `Content-Type: application/reports+json`.
+//
+// The shape of the JSON is enough to tell them apart, and not every browser gets the content
+// type right, so we don't look at it.
+//
+// Anyone can send us anything here, so reports are counted per distinct violation rather than
+// stored one by one (see `migrations/17_csp_report.sql`), and we only accept so much at a time.
+
+/// The largest request body we'll accept. A single report is well under a kilobyte.
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+/// The most reports we'll take from one request; the rest of a batch is ignored.
+const MAX_REPORTS_PER_REQUEST: usize = 20;
+
+/// The longest a URI or directive we store may be, in characters.
+const MAX_FIELD_LEN: usize = 2048;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/csp-report", post(report_violation))
+        .route("/api/admin/csp-reports", get(list_reports))
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct ListReportsQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportsBody {
+    csp_reports: Vec<CspReport>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CspReport {
+    document_uri: String,
+    violated_directive: String,
+    blocked_uri: String,
+    /// How many times this violation has been reported.
+    count: i64,
+    /// The latest report as the browser sent it.
+    sample: Value,
+    first_seen_at: Timestamptz,
+    last_seen_at: Timestamptz,
+}
+
+/// Count the violations in a report from a browser.
+///
+/// Browsers don't do anything with the response, so it's empty.
+async fn report_violation(
+    ctx: Extension<ApiContext>,
+    ContentLengthLimit(body): ContentLengthLimit<Bytes, MAX_BODY_BYTES>,
+) -> Result<StatusCode> {
+    let reports = parse_reports(&body)
+        .ok_or_else(|| Error::unprocessable_entity([("report", "not a CSP violation report")]))?;
+
+    for report in &reports {
+        db::csp_reports::record(ctx.db.primary(), report).await?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List the violations reported in the last 30 days, most recently reported first, for
+/// moderators.
+///
+/// Paginated with `limit` and `offset` like `GET /api/articles`.
+async fn list_reports(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    query: Query<ListReportsQuery>,
+) -> Result<Json<ReportsBody>> {
+    require_moderator(&ctx, &auth_user).await?;
+
+    let reports = db::csp_reports::list(
+        ctx.db.read(),
+        query.limit.unwrap_or(20),
+        query.offset.unwrap_or(0),
+    )
+    .await?;
+
+    Ok(Json(ReportsBody {
+        csp_reports: reports
+            .into_iter()
+            .map(|report| CspReport {
+                document_uri: report.document_uri,
+                violated_directive: report.violated_directive,
+                blocked_uri: report.blocked_uri,
+                count: report.count,
+                // It was valid JSON going in, and Postgres doesn't hand back anything else.
+                sample: serde_json::from_str(&report.sample).unwrap_or(Value::Null),
+                first_seen_at: Timestamptz(report.first_seen_at),
+                last_seen_at: Timestamptz(report.last_seen_at),
+            })
+            .collect(),
+    }))
+}
+
+/// Pick the CSP violations out of a request body in either format, or `None` if it isn't
+/// a report at all.
+///
+/// A Reporting API batch with no CSP violations in it (it could be all deprecation warnings,
+/// say) is fine, and comes out empty.
+fn parse_reports(body: &[u8]) -> Option<Vec<NewCspReport>> {
+    let json: Value = serde_json::from_slice(body).ok()?;
+
+    match &json {
+        Value::Object(object) => {
+            let report = object.get("csp-report")?;
+
+            // Older browsers put the whole directive in `violated-directive`, sources and all,
+            // so `effective-directive` is better when it's there.
+            let directive = report
+                .get("effective-directive")
+                .or_else(|| report.get("violated-directive"))?;
+
+            Some(vec![new_report(
+                report.get("document-uri")?,
+                directive,
+                report.get("blocked-uri").unwrap_or(&Value::Null),
+                &json,
+            )?])
+        }
+        Value::Array(reports) => reports
+            .iter()
+            .filter(|report| report["type"] == "csp-violation")
+            .take(MAX_REPORTS_PER_REQUEST)
+            .map(|report| {
+                let body = report.get("body")?;
+
+                new_report(
+                    body.get("documentURL").or_else(|| report.get("url"))?,
+                    body.get("effectiveDirective")?,
+                    body.get("blockedURL").unwrap_or(&Value::Null),
+                    report,
+                )
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+fn new_report(
+    document_uri: &Value,
+    directive: &Value,
+    blocked_uri: &Value,
+    sample: &Value,
+) -> Option<NewCspReport> {
+    // The first word of the directive, in case it's one with sources in.
+    let directive = directive.as_str()?.split_whitespace().next()?;
+
+    Some(NewCspReport {
+        document_uri: strip_uri(document_uri.as_str()?),
+        violated_directive: truncate(directive),
+        // Missing for some violations, like `inline`, in some browsers.
+        blocked_uri: strip_uri(blocked_uri.as_str().unwrap_or("")),
+        sample: sample.to_string(),
+    })
+}
+
+/// Drop the query string and fragment from a URL, so one violation on every article page is one
+/// row rather than thousands, and so we don't keep anything sensitive that was in them.
+///
+/// Anything that isn't a URL, like `inline` or `eval`, is left alone.
+fn strip_uri(uri: &str) -> String {
+    match Url::parse(uri) {
+        Ok(mut url) if url.has_host() => {
+            url.set_query(None);
+            url.set_fragment(None);
+            truncate(url.as_str())
+        }
+        _ => truncate(uri),
+    }
+}
+
+fn truncate(s: &str) -> String {
+    s.chars().take(MAX_FIELD_LEN).collect()
+}
+
+#[test]
+fn test_parse_reports() {
+    let legacy = br#"{
+        "csp-report": {
+            "document-uri": "https://example.com/article/hello?utm_source=x#comments",
+            "violated-directive": "img-src 'self'",
+            "effective-directive": "img-src",
+            "blocked-uri": "https://tracker.example/pixel.gif?id=123",
+            "original-policy": "default-src 'self'"
+        }
+    }"#;
+
+    let reports = parse_reports(legacy).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].document_uri, "https://example.com/article/hello");
+    assert_eq!(reports[0].violated_directive, "img-src");
+    assert_eq!(reports[0].blocked_uri, "https://tracker.example/pixel.gif");
+
+    // No `effective-directive`, and an inline script has no URL.
+    let legacy = br#"{
+        "csp-report": {
+            "document-uri": "https://example.com/",
+            "violated-directive": "script-src 'self'"
+        }
+    }"#;
+
+    let reports = parse_reports(legacy).unwrap();
+    assert_eq!(reports[0].violated_directive, "script-src");
+    assert_eq!(reports[0].blocked_uri, "");
+
+    let batch = br#"[
+        {
+            "type": "csp-violation",
+            "url": "https://example.com/settings",
+            "body": {
+                "documentURL": "https://example.com/settings",
+                "effectiveDirective": "script-src-elem",
+                "blockedURL": "inline",
+                "disposition": "enforce"
+            }
+        },
+        {
+            "type": "deprecation",
+            "url": "https://example.com/",
+            "body": { "id": "SomethingOld" }
+        }
+    ]"#;
+
+    let reports = parse_reports(batch).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].document_uri, "https://example.com/settings");
+    assert_eq!(reports[0].violated_directive, "script-src-elem");
+    assert_eq!(reports[0].blocked_uri, "inline");
+
+    assert_eq!(parse_reports(b"[]"), Some(vec![]));
+    assert_eq!(parse_reports(b"{}"), None);
+    assert_eq!(parse_reports(b"\"hello\""), None);
+    assert_eq!(parse_reports(b"not json"), None);
+    assert_eq!(parse_reports(br#"[{"type": "csp-violation"}]"#), None);
+}
+
+/// Parse `data` as a report, for the fuzz targets in `fuzz/`. Only `None` is expected for
+/// garbage; anything else is a bug.
+#[cfg(feature = "fuzzing")]
+pub(in crate::http) fn fuzz_request_bodies(data: &[u8]) {
+    parse_reports(data);
+}
@@ -0,0 +1,183 @@
+use axum::extract::Extension;
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{Map, Value};
+use time::OffsetDateTime;
+
+use crate::db;
+use crate::http::extractor::{AuthUser, DEFAULT_SESSION_LENGTH, SCHEME_PREFIX};
+use crate::http::types::{Timestamptz, UserId};
+use crate::http::{session, ApiContext, Error, Result};
+
+// Not part of the Realworld spec, and off unless `Config::debug_routes` is set.
+//
+// When you're working on a frontend, you spend a lot of time wondering what's in the token
+// you've got and when it runs out, and logging in as one user after another to see what they
+// see. These routes are for that:
+//
+// * `GET /api/debug/token` decodes whatever token is in the `Authorization` header, whether or
+//   not it's any good, and tells you what's wrong with it if it isn't.
+// * `POST /api/debug/token` with `{"userId": "..."}` hands you a token for that user, no
+//   password required.
+//
+// That second one is why this must never be turned on in production. When it's off, both
+// routes are a plain `404 Not Found`, so there's no telling they exist.
+//
+// With `Config::session_store` set, the tokens we hand out are sessions rather than JWTs, so
+// there's nothing in them to decode. `GET` says so with `{"session": true, "valid": ...}`, where
+// `valid` is whether the session is still going, and `POST` starts a session like logging in
+// would.
+
+pub fn router() -> Router {
+    Router::new().route("/api/debug/token", get(decode_token).post(mint_token))
+}
+
+/// What `GET /api/debug/token` says about a token, see above.
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum TokenInfo {
+    Jwt(DecodedToken),
+    Session(SessionToken),
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DecodedToken {
+    /// Always `false`, so a frontend can tell this from a `SessionToken`.
+    session: bool,
+    /// Everything in the token's payload, as-is.
+    claims: Map<String, Value>,
+    /// When the token expires, from its `exp` claim, if it has one.
+    expires_at: Option<Timestamptz>,
+    expired: bool,
+    /// Whether the API would accept this token: it's signed with our key and hasn't expired.
+    valid: bool,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionToken {
+    /// Always `true`.
+    session: bool,
+    /// Whether the API would accept this token: the session hasn't ended or expired.
+    valid: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MintToken {
+    user_id: UserId,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MintedToken {
+    token: String,
+    expires_at: Option<Timestamptz>,
+}
+
+async fn decode_token(ctx: Extension<ApiContext>, headers: HeaderMap) -> Result<Json<TokenInfo>> {
+    require_enabled(&ctx)?;
+
+    // Unlike `AuthUser`, we want to see what's in the token even if it doesn't check out, so we
+    // look at the header ourselves.
+    let header = headers.get(AUTHORIZATION).ok_or(Error::Unauthorized)?;
+
+    let token = header
+        .to_str()
+        .ok()
+        .and_then(|header| header.strip_prefix(SCHEME_PREFIX))
+        .ok_or(Error::Unauthorized)?;
+
+    // With sessions, every token we accept is one, so there's no point trying to decode it;
+    // a JWT from before they were turned on is just a session we don't know about.
+    if ctx.sessions.is_some() {
+        let valid = match AuthUser::from_authorization(&ctx, header).await {
+            Ok(_) => true,
+            Err(Error::Unauthorized) => false,
+            // e.g. Redis is down, which isn't the token's fault.
+            Err(e) => return Err(e),
+        };
+
+        return Ok(Json(TokenInfo::Session(SessionToken {
+            session: true,
+            valid,
+        })));
+    }
+
+    let claims = decode(token)?;
+    let now = ctx.clock.now();
+    let expires_at = expires_at(&claims);
+
+    // The same checks as `AuthUser` makes, minus the database; a token for a user who's since
+    // been banned or deleted is still `valid` here.
+    let valid = AuthUser::from_authorization(&ctx, header).await.is_ok();
+
+    Ok(Json(TokenInfo::Jwt(DecodedToken {
+        session: false,
+        expired: expires_at.is_some_and(|expires_at| expires_at < now),
+        expires_at: expires_at.map(Timestamptz),
+        claims,
+        valid,
+    })))
+}
+
+async fn mint_token(
+    ctx: Extension<ApiContext>,
+    Json(req): Json<MintToken>,
+) -> Result<Json<MintedToken>> {
+    require_enabled(&ctx)?;
+
+    db::users::find_by_id(ctx.db.primary(), req.user_id)
+        .await?
+        .ok_or_else(|| Error::unprocessable_entity([("userId", "no such user")]))?;
+
+    let token = session::issue_token(&ctx, req.user_id).await?;
+
+    // Sessions don't say when they expire, but they last as long as a JWT would.
+    let expires_at = match ctx.sessions {
+        Some(_) => Some(ctx.clock.now() + DEFAULT_SESSION_LENGTH),
+        None => expires_at(&decode(&token)?),
+    };
+
+    Ok(Json(MintedToken {
+        expires_at: expires_at.map(Timestamptz),
+        token,
+    }))
+}
+
+fn require_enabled(ctx: &ApiContext) -> Result<()> {
+    if ctx.config.debug_routes {
+        Ok(())
+    } else {
+        Err(Error::NotFound)
+    }
+}
+
+/// Decode the payload of a JWT without checking the signature.
+fn decode(token: &str) -> Result<Map<String, Value>> {
+    let token = jwt::Token::<jwt::Header, Map<String, Value>, _>::parse_unverified(token)
+        .map_err(|_| Error::unprocessable_entity([("token", "is not a JWT")]))?;
+
+    Ok(token.claims().clone())
+}
+
+/// When a token expires, from its `exp` claim.
+fn expires_at(claims: &Map<String, Value>) -> Option<OffsetDateTime> {
+    let exp = claims.get("exp")?.as_i64()?;
+
+    // `from_unix_timestamp()` panics outside of the years 1 to 9999, and a token from somewhere
+    // else could say anything.
+    (-62_135_596_800..=253_402_300_799)
+        .contains(&exp)
+        .then(|| OffsetDateTime::from_unix_timestamp(exp))
+}
+
+/// Deserialize `data` as each of the request bodies above, the same way `Json` would,
+/// for the fuzz targets in `fuzz/`. Only errors are expected; anything else is a bug.
+#[cfg(feature = "fuzzing")]
+pub(in crate::http) fn fuzz_request_bodies(data: &[u8]) {
+    serde_json::from_slice::<MintToken>(data).ok();
+}
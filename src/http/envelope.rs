@@ -0,0 +1,232 @@
+use std::task::{Context, Poll};
+
+use axum::body::{boxed, Body, BoxBody, Bytes, HttpBody};
+use axum::http::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use axum::http::{HeaderMap, HeaderValue, Request, Response, StatusCode};
+use axum::BoxError;
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use tower::{Layer, Service};
+
+use crate::config::EnvelopeMode;
+
+// Not part of the Realworld spec, quite the opposite.
+//
+// The Realworld spec gives every route its own response shape: `{"user": ...}`,
+// `{"articles": [...], "articlesCount": ...}`, `{"errors": {...}}` for a `422` and a plain
+// text message for other errors. That's fine for a hand-written frontend, but clients generated
+// from an API description would much rather every response looked the same, so they can be
+// unwrapped in one place:
+//
+// ```json
+// {"data": {"user": {...}}, "error": null, "meta": {"status": 200}}
+// {"data": null, "error": {"message": "authentication required", "details": null}, "meta": {"status": 401}}
+// ```
+//
+// `data` is exactly what the response would have been otherwise, so none of the handlers have
+// to know about this; it's all done here, after the fact. For an error, `error.message` is the
+// message (or the status's reason phrase if the error was JSON) and `error.details` is the
+// JSON body if there was one, e.g. the `{"errors": {...}}` of a `422`.
+//
+// Responses that aren't JSON or an error message, like `/metrics`, the CSV export or images from
+// `/imgproxy`, are left alone: there's nothing to put in `data` that a client could use.
+//
+// Whether a response is wrapped depends on `Config::response_envelope`. By default a client has
+// to ask for it with `Accept: application/vnd.realworld.envelope+json`, so anyone following
+// the spec gets what they expect.
+
+/// The media type of a wrapped response, which a client asks for in `Accept`.
+const ENVELOPE_TYPE: &str = "application/vnd.realworld.envelope+json";
+
+/// Wraps responses in an envelope according to `Config::response_envelope`.
+#[derive(Clone)]
+pub struct EnvelopeLayer {
+    pub mode: EnvelopeMode,
+}
+
+#[derive(Clone)]
+pub struct EnvelopeService<S> {
+    inner: S,
+    mode: EnvelopeMode,
+}
+
+impl<S> Layer<S> for EnvelopeLayer {
+    type Service = EnvelopeService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        EnvelopeService {
+            inner,
+            mode: self.mode,
+        }
+    }
+}
+
+impl<S, ResBody> Service<Request<Body>> for EnvelopeService<S>
+where
+    S: Service<Request<Body>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // See `TxService::call()` for why we swap in a clone.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let mode = self.mode;
+
+        let wrap = match mode {
+            EnvelopeMode::Off => false,
+            EnvelopeMode::Negotiate => accepts_envelope(req.headers()),
+            EnvelopeMode::Always => true,
+        };
+
+        Box::pin(async move {
+            let mut res = inner.call(req).await?;
+
+            // Otherwise a cache could hand a wrapped response to a client that didn't ask for
+            // one, or the other way around.
+            if mode == EnvelopeMode::Negotiate {
+                res.headers_mut()
+                    .append(VARY, HeaderValue::from_static("accept"));
+            }
+
+            if !wrap {
+                return Ok(res.map(boxed));
+            }
+
+            Ok(wrap_response(res).await)
+        })
+    }
+}
+
+/// Whether `Accept` asks for an envelope, not counting `q=0`, which means "anything but".
+fn accepts_envelope(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| {
+            let mut params = media_range.split(';').map(str::trim);
+
+            params
+                .next()
+                .is_some_and(|media_type| media_type.eq_ignore_ascii_case(ENVELOPE_TYPE))
+                && !params.any(|param| {
+                    param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+                })
+        })
+}
+
+async fn wrap_response<B>(res: Response<B>) -> Response<BoxBody>
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    let status = res.status();
+
+    // These have no body, and adding one would be a protocol error.
+    if status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+    {
+        return res.map(boxed);
+    }
+
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+
+    let is_json = content_type.starts_with("application/json");
+    let is_error = status.is_client_error() || status.is_server_error();
+
+    // An error message is plain text, or has no content type at all.
+    let is_message =
+        is_error && (content_type.is_empty() || content_type.starts_with("text/plain"));
+
+    if !(is_json || is_message) {
+        return res.map(boxed);
+    }
+
+    let (mut parts, body) = res.into_parts();
+
+    // Our JSON responses are all built in memory anyway, apart from the article listings,
+    // which are streamed to save memory on big pages; wrapping those costs us that saving.
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        // If we couldn't read it, the client couldn't have either.
+        Err(_) => return Response::from_parts(parts, boxed(Body::empty())),
+    };
+
+    let body = if is_json {
+        match serde_json::from_slice::<Value>(&bytes) {
+            Ok(body) => body,
+            // Not actually JSON, so leave it be.
+            Err(_) => return Response::from_parts(parts, boxed(Body::from(bytes))),
+        }
+    } else {
+        Value::String(String::from_utf8_lossy(&bytes).into_owned())
+    };
+
+    let envelope = if is_error {
+        let (message, details) = match body {
+            Value::String(message) => (message, Value::Null),
+            details => (
+                status.canonical_reason().unwrap_or("error").to_lowercase(),
+                details,
+            ),
+        };
+
+        json!({
+            "data": null,
+            "error": { "message": message, "details": details },
+            "meta": { "status": status.as_u16() },
+        })
+    } else {
+        json!({
+            "data": body,
+            "error": null,
+            "meta": { "status": status.as_u16() },
+        })
+    };
+
+    let bytes = serde_json::to_vec(&envelope).expect("serializing a Value can't fail");
+
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static(ENVELOPE_TYPE));
+    parts.headers.insert(CONTENT_LENGTH, bytes.len().into());
+
+    Response::from_parts(parts, boxed(Body::from(bytes)))
+}
+
+#[test]
+fn test_accepts_envelope() {
+    let accepts = |accept: &str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, accept.parse().unwrap());
+        accepts_envelope(&headers)
+    };
+
+    assert!(accepts("application/vnd.realworld.envelope+json"));
+    assert!(accepts(
+        "application/json;q=0.5, Application/Vnd.Realworld.Envelope+JSON; q=1"
+    ));
+    assert!(!accepts("application/json"));
+    assert!(!accepts("*/*"));
+    assert!(!accepts("application/vnd.realworld.envelope+json;q=0"));
+    assert!(!accepts("application/vnd.realworld.envelope+jsonx"));
+    assert!(!accepts_envelope(&HeaderMap::new()));
+}
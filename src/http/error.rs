@@ -1,11 +1,18 @@
 use axum::body::{Bytes, Full, HttpBody};
-use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::header::{RETRY_AFTER, WWW_AUTHENTICATE};
 use axum::http::{HeaderMap, HeaderValue, Response, StatusCode};
 use axum::response::IntoResponse;
 use axum::Json;
+use headers::{ETag, HeaderMapExt};
 use sqlx::error::DatabaseError;
 use std::borrow::Cow;
 use std::collections::HashMap;
+use time::OffsetDateTime;
+
+use crate::http::articles::restrictions::Restriction;
+use crate::http::quota::Quota;
+use crate::http::rate_limit::RateLimit;
+use crate::http::types::Timestamptz;
 
 /// A common error type that can be used throughout the API.
 ///
@@ -24,10 +31,28 @@ pub enum Error {
     #[error("user may not perform that action")]
     Forbidden,
 
+    /// Return `403 Forbidden`
+    ///
+    /// When the user's account is too new to do what they asked, see `articles::restrictions`.
+    /// The body says which restriction it was and when it lifts, so the frontend can explain.
+    #[error("{0}")]
+    Restricted(Restriction),
+
     /// Return `404 Not Found`
     #[error("request path not found")]
     NotFound,
 
+    /// Return `304 Not Modified`
+    ///
+    /// This isn't really an error, but returning it as one lets handlers bail out early with `?`
+    /// when the client's cached copy is still fresh. See `extractor::Preconditions`.
+    #[error("not modified")]
+    NotModified { etag: Option<ETag> },
+
+    /// Return `412 Precondition Failed`
+    #[error("precondition failed")]
+    PreconditionFailed,
+
     /// Return `422 Unprocessable Entity`
     ///
     /// This also serializes the `errors` map to JSON to satisfy the requirement for
@@ -43,6 +68,51 @@ pub enum Error {
         errors: HashMap<Cow<'static, str>, Vec<Cow<'static, str>>>,
     },
 
+    /// Return `423 Locked`
+    ///
+    /// When someone else holds the lock on editing an article, see `articles::locks`.
+    /// The body says who, and until when, so the frontend can tell the user who to talk to.
+    #[error("the article is being edited by {username}")]
+    Locked {
+        username: String,
+        expires_at: OffsetDateTime,
+    },
+
+    /// Return `429 Too Many Requests`
+    ///
+    /// When the user has used up one of their quotas, see `quota`. The response says how many
+    /// they get and when they can try again in its headers.
+    #[error("quota exceeded: {0}")]
+    QuotaExceeded(Quota),
+
+    /// Return `429 Too Many Requests`
+    ///
+    /// When the client is sending requests faster than `rate_limit` allows. Like
+    /// `QuotaExceeded`, the headers say how many they get and when they can try again.
+    #[error("too many requests, try again in {} seconds", .0.reset_secs())]
+    RateLimited(RateLimit),
+
+    /// Return `501 Not Implemented`
+    ///
+    /// For when the request is valid but asks for something we don't support, like oEmbed's
+    /// XML format. The message says what.
+    #[error("not implemented: {0}")]
+    NotImplemented(&'static str),
+
+    /// Return `502 Bad Gateway`
+    ///
+    /// For when we fetched something from another server on the client's behalf and didn't get
+    /// anything usable back, see `imgproxy`. The message says what went wrong, but not the
+    /// details, which are logged instead.
+    #[error("bad gateway: {0}")]
+    BadGateway(&'static str),
+
+    /// Return `503 Service Unavailable`
+    ///
+    /// Only used when `maintenance_mode` is on, see `extractor::MaintenanceGuard`.
+    #[error("the API is undergoing maintenance, please try again later")]
+    MaintenanceMode,
+
     /// Automatically return `500 Internal Server Error` on a `sqlx::Error`.
     ///
     /// Via the generated `From<sqlx::Error> for Error` impl,
@@ -103,9 +173,16 @@ impl Error {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
-            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::Forbidden | Self::Restricted(_) => StatusCode::FORBIDDEN,
             Self::NotFound => StatusCode::NOT_FOUND,
+            Self::NotModified { .. } => StatusCode::NOT_MODIFIED,
+            Self::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
             Self::UnprocessableEntity { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Locked { .. } => StatusCode::LOCKED,
+            Self::QuotaExceeded(_) | Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+            Self::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            Self::MaintenanceMode => StatusCode::SERVICE_UNAVAILABLE,
             Self::Sqlx(_) | Self::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -130,6 +207,56 @@ impl IntoResponse for Error {
 
                 return (StatusCode::UNPROCESSABLE_ENTITY, Json(Errors { errors })).into_response();
             }
+            Self::Locked {
+                username,
+                expires_at,
+            } => {
+                // The same shape as `POST /api/articles/:slug/lock` returns.
+                #[derive(serde::Serialize)]
+                #[serde(rename_all = "camelCase")]
+                struct Lock {
+                    username: String,
+                    expires_at: Timestamptz,
+                }
+
+                #[derive(serde::Serialize)]
+                struct LockBody {
+                    lock: Lock,
+                }
+
+                let body = LockBody {
+                    lock: Lock {
+                        username,
+                        expires_at: Timestamptz(expires_at),
+                    },
+                };
+
+                return (StatusCode::LOCKED, Json(body)).into_response();
+            }
+            Self::Restricted(ref restriction) => {
+                #[derive(serde::Serialize)]
+                #[serde(rename_all = "camelCase")]
+                struct RestrictionBody {
+                    code: &'static str,
+                    message: String,
+                    lifts_at: Timestamptz,
+                }
+
+                #[derive(serde::Serialize)]
+                struct Body {
+                    restriction: RestrictionBody,
+                }
+
+                let body = Body {
+                    restriction: RestrictionBody {
+                        code: restriction.code(),
+                        message: restriction.to_string(),
+                        lifts_at: Timestamptz(restriction.lifts_at()),
+                    },
+                };
+
+                return (self.status_code(), Json(body)).into_response();
+            }
             Self::Unauthorized => {
                 return (
                     self.status_code(),
@@ -149,6 +276,31 @@ impl IntoResponse for Error {
                 )
                     .into_response();
             }
+            Self::NotModified { etag } => {
+                let mut headers = HeaderMap::new();
+
+                // The `ETag` should be repeated in a `304 Not Modified` response so the client
+                // knows which version of the resource it's supposed to use.
+                if let Some(etag) = etag {
+                    headers.typed_insert(etag);
+                }
+
+                // A `304` response must not have a body.
+                return (StatusCode::NOT_MODIFIED, headers, Bytes::new()).into_response();
+            }
+            Self::QuotaExceeded(ref quota) => {
+                let mut headers = quota.headers();
+                headers.insert(RETRY_AFTER, HeaderValue::from(quota.reset_secs()));
+
+                return (self.status_code(), headers, self.to_string()).into_response();
+            }
+
+            Self::RateLimited(ref rate_limit) => {
+                let mut headers = rate_limit.headers();
+                headers.insert(RETRY_AFTER, HeaderValue::from(rate_limit.reset_secs()));
+
+                return (self.status_code(), headers, self.to_string()).into_response();
+            }
 
             Self::Sqlx(ref e) => {
                 // TODO: we probably want to use `tracing` instead
@@ -179,7 +331,7 @@ impl IntoResponse for Error {
 ///     email,
 ///     password_hash
 /// )
-///     .fetch_one(&ctxt.db)
+///     .fetch_one(ctxt.db.primary())
 ///     .await
 ///     .on_constraint("user_username_key", |_| Error::unprocessable_entity([("username", "already taken")]))?;
 /// ```
@@ -1,28 +1,36 @@
+use crate::config::Config;
+use crate::db;
 use crate::http::error::Error;
 use axum::body::Body;
 use axum::extract::{Extension, FromRequest, RequestParts};
 
-use crate::http::ApiContext;
+use crate::http::types::UserId;
+use crate::http::{session, trace, ApiContext};
 use async_trait::async_trait;
 use axum::http::header::AUTHORIZATION;
-use axum::http::HeaderValue;
+use axum::http::{HeaderMap, HeaderValue, Method};
+use headers::{ETag, HeaderMapExt, IfMatch, IfModifiedSince, IfNoneMatch, IfUnmodifiedSince};
 use hmac::{Hmac, NewMac};
 use jwt::{SignWithKey, VerifyWithKey};
 use sha2::Sha384;
+use std::net::IpAddr;
+use std::time::SystemTime;
 use time::OffsetDateTime;
-use uuid::Uuid;
 
-const DEFAULT_SESSION_LENGTH: time::Duration = time::Duration::weeks(2);
+pub(in crate::http) const DEFAULT_SESSION_LENGTH: time::Duration = time::Duration::weeks(2);
 
 // Ideally the Realworld spec would use the `Bearer` scheme as that's relatively standard
 // and has parsers available, but it's really not that hard to parse anyway.
-const SCHEME_PREFIX: &str = "Token ";
+pub(in crate::http) const SCHEME_PREFIX: &str = "Token ";
 
 /// Add this as a parameter to a handler function to require the user to be logged in.
 ///
-/// Parses a JWT from the `Authorization: Token <token>` header.
+/// Parses a JWT from the `Authorization: Token <token>` header, or looks up the session it's
+/// for if `Config::session_store` is set, see `http::session`.
 pub struct AuthUser {
-    pub user_id: Uuid,
+    pub user_id: UserId,
+    /// The token the user sent, if it's for a session rather than a JWT.
+    pub(in crate::http) session: Option<String>,
 }
 
 /// Add this as a parameter to a handler function to optionally check if the user is logged in.
@@ -36,7 +44,7 @@ pub struct MaybeAuthUser(pub Option<AuthUser>);
 
 #[derive(serde::Serialize, serde::Deserialize)]
 struct AuthUserClaims {
-    user_id: Uuid,
+    user_id: UserId,
     /// Standard JWT `exp` claim.
     exp: i64,
 }
@@ -48,43 +56,47 @@ impl AuthUser {
 
         AuthUserClaims {
             user_id: self.user_id,
-            exp: (OffsetDateTime::now_utc() + DEFAULT_SESSION_LENGTH).unix_timestamp(),
+            exp: (ctx.clock.now() + DEFAULT_SESSION_LENGTH).unix_timestamp(),
         }
         .sign_with_key(&hmac)
         .expect("HMAC signing should be infallible")
     }
 
     /// Attempt to parse `Self` from an `Authorization` header.
-    fn from_authorization(ctx: &ApiContext, auth_header: &HeaderValue) -> Result<Self, Error> {
-        let auth_header = auth_header.to_str().map_err(|_| {
-            log::debug!("Authorization header is not UTF-8");
-            Error::Unauthorized
-        })?;
-
-        if !auth_header.starts_with(SCHEME_PREFIX) {
-            log::debug!(
-                "Authorization header is using the wrong scheme: {:?}",
-                auth_header
-            );
-            return Err(Error::Unauthorized);
+    ///
+    /// This doesn't check the database, see `from_request()`. With sessions, it does look the
+    /// session up, but that's meant to be quick.
+    pub(in crate::http) async fn from_authorization(
+        ctx: &ApiContext,
+        auth_header: &HeaderValue,
+    ) -> Result<Self, Error> {
+        match &ctx.sessions {
+            Some(sessions) => {
+                session::authenticate(ctx, &**sessions, token_from_header(auth_header)?).await
+            }
+            None => Self::parse_authorization(&ctx.config.hmac_key, ctx.clock.now(), auth_header),
         }
+    }
 
-        let token = &auth_header[SCHEME_PREFIX.len()..];
+    /// The guts of `from_authorization()` for JWTs, which only needs the key and the time rather
+    /// than the whole `ApiContext`, so the fuzz targets can call it too.
+    pub(in crate::http) fn parse_authorization(
+        hmac_key: &str,
+        now: OffsetDateTime,
+        auth_header: &HeaderValue,
+    ) -> Result<Self, Error> {
+        let token = token_from_header(auth_header)?;
 
         let jwt =
             jwt::Token::<jwt::Header, AuthUserClaims, _>::parse_unverified(token).map_err(|e| {
-                log::debug!(
-                    "failed to parse Authorization header {:?}: {}",
-                    auth_header,
-                    e
-                );
+                log::debug!("failed to parse Authorization header {:?}: {}", token, e);
                 Error::Unauthorized
             })?;
 
         // Realworld doesn't specify the signing algorithm for use with the JWT tokens
         // so we picked SHA-384 (HS-384) as the HMAC, as it is more difficult to brute-force
         // than SHA-256 (recommended by the JWT spec) at the cost of a slightly larger token.
-        let hmac = Hmac::<Sha384>::new_from_slice(ctx.config.hmac_key.as_bytes())
+        let hmac = Hmac::<Sha384>::new_from_slice(hmac_key.as_bytes())
             .expect("HMAC-SHA-384 can accept any key length");
 
         // When choosing a JWT implementation, be sure to check that it validates that the signing
@@ -107,7 +119,8 @@ impl AuthUser {
         // In practice, Launchbadge has abandoned using JWTs for authenticating long-lived sessions,
         // instead storing session data in Redis, which can be accessed quickly and so adds less
         // overhead to every request compared to hitting Postgres, and allows tracking and
-        // invalidating individual sessions by simply deleting them from Redis.
+        // invalidating individual sessions by simply deleting them from Redis. That's what
+        // `Config::session_store` turns on, see `http::session`.
         //
         // Technically, the Realworld spec isn't all that adamant about using JWTs and there
         // may be some flexibility in using other kinds of tokens, depending on whether the frontend
@@ -121,20 +134,37 @@ impl AuthUser {
         // This also has the benefit of avoiding having to deal with securely storing the session
         // token on the frontend.
 
-        if claims.exp < OffsetDateTime::now_utc().unix_timestamp() {
+        if claims.exp < now.unix_timestamp() {
             log::debug!("token expired");
             return Err(Error::Unauthorized);
         }
 
         Ok(Self {
             user_id: claims.user_id,
+            session: None,
         })
     }
 }
 
+/// The token from an `Authorization: Token <token>` header.
+fn token_from_header(auth_header: &HeaderValue) -> Result<&str, Error> {
+    let auth_header = auth_header.to_str().map_err(|_| {
+        log::debug!("Authorization header is not UTF-8");
+        Error::Unauthorized
+    })?;
+
+    auth_header.strip_prefix(SCHEME_PREFIX).ok_or_else(|| {
+        log::debug!(
+            "Authorization header is using the wrong scheme: {:?}",
+            auth_header
+        );
+        Error::Unauthorized
+    })
+}
+
 impl MaybeAuthUser {
     /// If this is `Self(Some(AuthUser))`, return `AuthUser::user_id`
-    pub fn user_id(&self) -> Option<Uuid> {
+    pub fn user_id(&self) -> Option<UserId> {
         self.0.as_ref().map(|auth_user| auth_user.user_id)
     }
 }
@@ -162,7 +192,25 @@ impl FromRequest for AuthUser {
             .get(AUTHORIZATION)
             .ok_or(Error::Unauthorized)?;
 
-        Self::from_authorization(&ctx, auth_header)
+        let auth_user = Self::from_authorization(&ctx, auth_header).await?;
+        trace::record_user(auth_user.user_id);
+
+        // This is the one check we do make against the database (see `from_authorization()`),
+        // so that banning someone takes effect straight away rather than when their token
+        // expires. It's a primary key lookup, so it's cheap, but it is one more query for every
+        // request that needs authentication.
+        //
+        // It has to be the primary, not the read replica: the replica could be behind on a ban,
+        // and on an account that was only just registered, which it would take for banned.
+        //
+        // `MaybeAuthUser` doesn't bother: a banned user can still see what they could see
+        // logged out, so all they'd get out of it is whether they've favorited something.
+        if db::users::is_banned(ctx.db.primary(), auth_user.user_id).await? {
+            log::debug!("user {:?} is banned", auth_user.user_id);
+            return Err(Error::Unauthorized);
+        }
+
+        Ok(auth_user)
     }
 }
 
@@ -175,14 +223,217 @@ impl FromRequest for MaybeAuthUser {
             .await
             .expect("BUG: ApiContext was not added as an extension");
 
-        Ok(Self(
-            // Get the value of the `Authorization` header, if it was sent at all.
-            req.headers()
-                .and_then(|headers| {
-                    let auth_header = headers.get(AUTHORIZATION)?;
-                    Some(AuthUser::from_authorization(&ctx, auth_header))
-                })
-                .transpose()?,
-        ))
+        // Get the value of the `Authorization` header, if it was sent at all.
+        let auth_header = match req.headers().and_then(|headers| headers.get(AUTHORIZATION)) {
+            Some(auth_header) => auth_header,
+            None => return Ok(Self(None)),
+        };
+
+        let auth_user = AuthUser::from_authorization(&ctx, auth_header).await?;
+        trace::record_user(auth_user.user_id);
+
+        Ok(Self(Some(auth_user)))
+    }
+}
+
+/// Add this as a parameter to a handler function to support conditional requests.
+///
+/// This parses the `If-Match`, `If-None-Match`, `If-Modified-Since` and `If-Unmodified-Since`
+/// headers, which can then be checked against the current state of a resource with
+/// [`Preconditions::evaluate()`].
+///
+/// None of this is in the Realworld spec, but frontends (and browsers) will happily make use
+/// of it if we provide it, and it's a cheap way to save bandwidth on the more popular routes.
+///
+/// Headers that fail to parse are treated as if they were absent, as recommended by
+/// [RFC 7232, section 3](https://datatracker.ietf.org/doc/html/rfc7232#section-3).
+pub struct Preconditions {
+    // Only `GET` and `HEAD` requests get `304 Not Modified`, everything else gets
+    // `412 Precondition Failed` instead.
+    safe_method: bool,
+    if_match: Option<IfMatch>,
+    if_none_match: Option<IfNoneMatch>,
+    if_modified_since: Option<IfModifiedSince>,
+    if_unmodified_since: Option<IfUnmodifiedSince>,
+}
+
+impl Preconditions {
+    /// Check the preconditions of the request against the current `ETag` and/or
+    /// last-modified time of the resource.
+    ///
+    /// Returns `Error::NotModified` or `Error::PreconditionFailed` if the handler should stop
+    /// here, which is designed to be used with `?`.
+    ///
+    /// The headers are evaluated in the order given by
+    /// [RFC 7232, section 6](https://datatracker.ietf.org/doc/html/rfc7232#section-6).
+    pub fn evaluate(
+        &self,
+        etag: Option<&ETag>,
+        last_modified: Option<OffsetDateTime>,
+    ) -> Result<(), Error> {
+        // HTTP dates only have a resolution of seconds, so if we don't truncate the timestamp
+        // then the resource will look like it was modified after any date the client sends us.
+        let last_modified = last_modified
+            .map(|t| SystemTime::from(t - time::Duration::nanoseconds(t.nanosecond().into())));
+
+        if let Some(if_match) = &self.if_match {
+            // If there's no current representation then `If-Match` can never pass.
+            if !etag.is_some_and(|etag| if_match.precondition_passes(etag)) {
+                return Err(Error::PreconditionFailed);
+            }
+        } else if let (Some(if_unmodified_since), Some(last_modified)) =
+            (&self.if_unmodified_since, last_modified)
+        {
+            if !if_unmodified_since.precondition_passes(last_modified) {
+                return Err(Error::PreconditionFailed);
+            }
+        }
+
+        if let Some(if_none_match) = &self.if_none_match {
+            if etag.is_some_and(|etag| !if_none_match.precondition_passes(etag)) {
+                return Err(self.not_modified(etag));
+            }
+        } else if let (true, Some(if_modified_since), Some(last_modified)) =
+            (self.safe_method, &self.if_modified_since, last_modified)
+        {
+            if !if_modified_since.is_modified(last_modified) {
+                return Err(self.not_modified(etag));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn not_modified(&self, etag: Option<&ETag>) -> Error {
+        if self.safe_method {
+            Error::NotModified {
+                etag: etag.cloned(),
+            }
+        } else {
+            Error::PreconditionFailed
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequest for Preconditions {
+    type Rejection = Error;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        let safe_method = matches!(*req.method(), Method::GET | Method::HEAD);
+
+        // `typed_get()` returns `None` if the header is absent *or* fails to parse,
+        // which is exactly what we want.
+        let headers = req.headers();
+
+        Ok(Self {
+            safe_method,
+            if_match: headers.and_then(|h| h.typed_get()),
+            if_none_match: headers.and_then(|h| h.typed_get()),
+            if_modified_since: headers.and_then(|h| h.typed_get()),
+            if_unmodified_since: headers.and_then(|h| h.typed_get()),
+        })
+    }
+}
+
+/// Rejects any request that might write to the database with `503 Service Unavailable`
+/// while `maintenance_mode` is on.
+///
+/// This is applied to every route with `extractor_middleware()` in `http::serve()`,
+/// rather than added to the handlers that write, so that new routes can't forget about it.
+/// We go by the request method: `GET`, `HEAD` and `OPTIONS` are supposed to be safe
+/// so those are let through, and everything else is assumed to write.
+pub struct MaintenanceGuard;
+
+#[async_trait]
+impl FromRequest for MaintenanceGuard {
+    type Rejection = Error;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+            return Ok(Self);
+        }
+
+        let ctx: Extension<ApiContext> = Extension::from_request(req)
+            .await
+            .expect("BUG: ApiContext was not added as an extension");
+
+        if ctx.dynamic.load().maintenance_mode {
+            return Err(Error::MaintenanceMode);
+        }
+
+        Ok(Self)
+    }
+}
+
+/// The client's IP address, according to `Config::client_ip_header`.
+///
+/// This is `None` if that isn't set, or the header is missing or isn't an IP address. It's
+/// only as trustworthy as the proxy that sets the header, so it's fine for rate limiting and
+/// abuse reports but don't use it to decide what someone is allowed to do.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl FromRequest for ClientIp {
+    type Rejection = Error;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        let ctx: Extension<ApiContext> = Extension::from_request(req)
+            .await
+            .expect("BUG: ApiContext was not added as an extension");
+
+        Ok(match req.headers() {
+            Some(headers) => Self::from_headers(&ctx.config, headers),
+            None => Self(None),
+        })
+    }
+}
+
+impl ClientIp {
+    /// The guts of `from_request()`, for `rate_limit::RateLimitLayer`, which only has the headers.
+    pub(in crate::http) fn from_headers(config: &Config, headers: &HeaderMap) -> Self {
+        let header = match &config.client_ip_header {
+            Some(header) => header,
+            None => return Self(None),
+        };
+
+        // For `X-Forwarded-For`, the last address is the one our proxy added, see
+        // `Config::client_ip_header`. Any other header should only have the one.
+        let ip = headers
+            .get(header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+
+        Self(ip)
+    }
+}
+
+/// Whatever the frontend sent in `X-Device-Fingerprint`, which is supposed to identify the
+/// browser or app it's running in, for `Config::signups_per_device_per_hour`.
+///
+/// The client can send anything it likes here, so it's ignored if it's empty or longer than
+/// `DeviceFingerprint::MAX_LEN`, rather than being stored.
+pub struct DeviceFingerprint(pub Option<String>);
+
+impl DeviceFingerprint {
+    const HEADER: &'static str = "x-device-fingerprint";
+    const MAX_LEN: usize = 256;
+}
+
+#[async_trait]
+impl FromRequest for DeviceFingerprint {
+    type Rejection = Error;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        let fingerprint = req
+            .headers()
+            .and_then(|headers| headers.get(Self::HEADER))
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty() && value.len() <= Self::MAX_LEN)
+            .map(String::from);
+
+        Ok(Self(fingerprint))
     }
 }
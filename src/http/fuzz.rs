@@ -0,0 +1,120 @@
+use axum::http::HeaderValue;
+use time::OffsetDateTime;
+
+use crate::http::extractor::AuthUser;
+use crate::http::types::Timestamptz;
+use crate::http::{announcements, articles, csp, debug, onboarding, profiles, users};
+
+// Entry points for the `cargo fuzz` targets in `fuzz/`, which can't reach into the `http` module
+// otherwise. Each one takes the raw input from the fuzzer and feeds it to code that parses
+// untrusted input; a panic or a failed assertion here is a crash the fuzzer will report.
+//
+// Errors are fine, they're what most inputs *should* produce. The point is to find inputs that
+// take down the request (or the whole process, with `panic = "abort"`) instead.
+
+// The same as `test_util`'s. Tokens signed with any other key fail to verify, which is still
+// worth fuzzing, but the fuzzer can't find its way past the signature check anyway.
+const HMAC_KEY: &str = "0IoOXEW1SXjKsbhgZoQrUdcmb7XWlYHlhNx9ek3mDE1lGnKGPZbNAE/5D5BWW9Ar";
+
+/// Deserialize a `Timestamptz` from JSON, and check that it survives a round-trip.
+pub fn timestamptz(data: &[u8]) {
+    let timestamp = match serde_json::from_slice::<Timestamptz>(data) {
+        Ok(timestamp) => timestamp,
+        Err(_) => return,
+    };
+
+    let json = serde_json::to_string(&timestamp).expect("serializing a timestamp failed");
+
+    // We only serialize to the millisecond. Timestamps outside the range that RFC 3339 can
+    // represent (e.g. negative years, from milliseconds since the epoch) don't parse back,
+    // which is a limitation rather than a bug.
+    if let Ok(parsed) = serde_json::from_str::<Timestamptz>(&json) {
+        let millis = |t: OffsetDateTime| t.unix_timestamp_nanos().div_euclid(1_000_000);
+
+        assert_eq!(
+            millis(timestamp.0),
+            millis(parsed.0),
+            "{} didn't round-trip",
+            json
+        );
+    }
+}
+
+/// Slugify `title`, and check the result is usable in a URL as-is.
+pub fn slugify(title: &str) {
+    let slug = articles::slugify(title);
+
+    assert!(!slug.is_empty(), "empty slug for {:?}", title);
+    assert!(
+        slug.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'),
+        "invalid slug {:?} for {:?}",
+        slug,
+        title
+    );
+    assert!(
+        !slug.starts_with('-') && !slug.ends_with('-') && !slug.contains("--"),
+        "invalid slug {:?} for {:?}",
+        slug,
+        title
+    );
+}
+
+/// Parse `data` as the value of an `Authorization` header.
+pub fn authorization(data: &[u8]) {
+    if let Ok(header) = HeaderValue::from_bytes(data) {
+        AuthUser::parse_authorization(HMAC_KEY, OffsetDateTime::now_utc(), &header).ok();
+    }
+}
+
+/// Deserialize `data` as every JSON request body the API accepts.
+pub fn request_bodies(data: &[u8]) {
+    users::fuzz_request_bodies(data);
+    profiles::fuzz_request_bodies(data);
+    articles::fuzz_request_bodies(data);
+    onboarding::fuzz_request_bodies(data);
+    debug::fuzz_request_bodies(data);
+    csp::fuzz_request_bodies(data);
+    announcements::fuzz_request_bodies(data);
+}
+
+// The fuzzer needs nightly and `cargo-fuzz`, so these just make sure the checks themselves
+// don't go off on inputs that are fine.
+#[test]
+fn test_fuzz_entry_points() {
+    for input in [
+        &b"\"2016-02-18T03:22:56.637Z\""[..],
+        b"\"2016-02-18T03:22:56+05:00\"",
+        b"1455765776637",
+        b"-377705116800000",
+        b"253402300799999",
+        b"\"not a timestamp\"",
+    ] {
+        timestamptz(input);
+    }
+
+    for title in [
+        "",
+        "Hello, World!",
+        "'\"'",
+        "🦀🦀🦀",
+        "Überraschung 日本語",
+        "--a--",
+    ] {
+        slugify(title);
+    }
+
+    for header in [&b""[..], b"Token ", b"Token a.b.c", b"Bearer x", b"\xff"] {
+        authorization(header);
+    }
+
+    for body in [
+        &b"{}"[..],
+        br#"{"user":{"username":"","email":"x","password":""}}"#,
+        br#"{"article":{"title":"a","description":"b","body":"c","tagList":[]}}"#,
+        br#"{"comment":{"body":null}}"#,
+        b"[[[[[[[[[[",
+    ] {
+        request_bodies(body);
+    }
+}
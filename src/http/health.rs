@@ -0,0 +1,159 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+
+use crate::db;
+use crate::http::ApiContext;
+
+/// How long any one check in `deep_health()` gets before we call it failed.
+///
+/// This is well under the timeouts load balancers typically give a request, so that a hung
+/// dependency shows up as a failed check rather than the whole request timing out.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn router() -> Router {
+    // These aren't part of the Realworld spec, and they're not under `/api` because they're
+    // meant for the load balancer or orchestrator (e.g. Kubernetes probes) rather than clients.
+    //
+    // The `z` suffix is a Google convention that Kubernetes picked up, supposedly
+    // to make collisions with real routes less likely.
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        // This one *is* under `/api`, since it's for people rather than machines. Like
+        // `/metrics`, anyone who can reach the API can call it, so if the error messages
+        // are more than you want to share, block `/api/admin` at your load balancer.
+        .route("/api/admin/health/deep", get(deep_health))
+}
+
+/// Liveness: the process is up and serving requests, and that's all this checks.
+///
+/// Don't add database checks here; if the database goes down, restarting every instance
+/// of the application isn't going to bring it back.
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Readiness: whether this instance should be sent traffic.
+///
+/// That's the case when we can reach the database and its schema is up-to-date with the
+/// migrations embedded in this build, because otherwise a request might touch a table or column
+/// that doesn't exist yet. If `run_migrations` is off, this is what keeps a new version of the
+/// application from getting traffic until its migrations have been applied.
+async fn readyz(ctx: Extension<ApiContext>) -> Result<&'static str, (StatusCode, String)> {
+    let check = async {
+        ctx.db.check_migrations().await?;
+        ctx.db.check_replica_migrations().await
+    };
+
+    check.await.map(|()| "ok").map_err(|e| {
+        log::warn!("readiness check failed: {:?}", e);
+        (StatusCode::SERVICE_UNAVAILABLE, format!("{:#}", e))
+    })
+}
+
+#[derive(serde::Serialize)]
+struct DeepHealthBody {
+    /// `ok` if every check passed.
+    status: CheckStatus,
+    checks: Vec<Check>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Check {
+    name: &'static str,
+    status: CheckStatus,
+    /// Fractional, since a healthy database usually answers in well under a millisecond.
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum CheckStatus {
+    Ok,
+    Error,
+}
+
+/// Check every dependency of the application in turn, and report how each one is doing.
+///
+/// Unlike `/readyz`, this is for an operator trying to work out what's going on during a
+/// partial outage, so it runs all the checks even if the first one fails, and reports how long
+/// each one took, since "slow" is a failure mode too.
+///
+/// Don't point a load balancer at this; use `/readyz` for that. The checks here are more
+/// expensive, and a replica falling over shouldn't take every instance out of rotation.
+///
+/// Our only dependency right now is Postgres, which we check from a few different angles.
+/// If the application grows a cache, a mail server or the like, they should get a check here too.
+async fn deep_health(ctx: Extension<ApiContext>) -> (StatusCode, Json<DeepHealthBody>) {
+    // These run concurrently, so the whole thing takes as long as the slowest check
+    // rather than the sum of them.
+    let (primary_read, primary_write, replica_read, migrations) = futures::join!(
+        check("database_primary_read", db::ping(ctx.db.primary())),
+        check("database_primary_write", ctx.db.check_writable()),
+        async {
+            match ctx.db.replica() {
+                Some(replica) => Some(check("database_replica_read", db::ping(replica)).await),
+                None => None,
+            }
+        },
+        check("database_migrations", async {
+            ctx.db.check_migrations().await?;
+            ctx.db.check_replica_migrations().await
+        }),
+    );
+
+    let checks: Vec<Check> = [
+        Some(primary_read),
+        Some(primary_write),
+        replica_read,
+        Some(migrations),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let healthy = checks.iter().all(|check| check.status == CheckStatus::Ok);
+
+    let (status_code, status) = if healthy {
+        (StatusCode::OK, CheckStatus::Ok)
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, CheckStatus::Error)
+    };
+
+    (status_code, Json(DeepHealthBody { status, checks }))
+}
+
+async fn check(name: &'static str, fut: impl Future<Output = anyhow::Result<()>>) -> Check {
+    let start = Instant::now();
+    let res = tokio::time::timeout(CHECK_TIMEOUT, fut).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    let error = match res {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("{:#}", e)),
+        Err(_) => Some(format!("timed out after {:?}", CHECK_TIMEOUT)),
+    };
+
+    if let Some(error) = &error {
+        log::warn!("health check {} failed: {}", name, error);
+    }
+
+    Check {
+        name,
+        status: if error.is_none() {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Error
+        },
+        latency_ms,
+        error,
+    }
+}
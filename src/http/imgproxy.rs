@@ -0,0 +1,471 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::{Extension, Query};
+use axum::http::header::{
+    ACCEPT, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_SECURITY_POLICY, CONTENT_TYPE, HOST, LOCATION,
+    USER_AGENT, X_CONTENT_TYPE_OPTIONS,
+};
+use axum::http::{HeaderMap, HeaderValue, Request, Response};
+use axum::routing::get;
+use axum::Router;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use url::{Host, Position, Url};
+
+use crate::http::cache::CachedMap;
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// An article can embed images from anywhere on the web. When a reader's browser loads them,
+// whoever hosts them gets the reader's IP address, and can tell which article they're reading
+// from the `Referer`; and if the image is `http://` and we're on `https://`, the browser either
+// complains or refuses to load it. So this fetches images on readers' behalf and serves them
+// from our own origin instead, like GitHub's Camo does for READMEs.
+//
+// It isn't an open proxy: each URL has to be signed with `image_proxy_key`, which only we and
+// whatever renders articles know. The renderer rewrites
+//
+//     ![](https://images.example/cat.png)
+//
+// to `/imgproxy?url=https%3A%2F%2Fimages.example%2Fcat.png&sig=<sig>`, where `<sig>` is
+// `base64url(hmac_sha256(image_proxy_key, url))` without padding, see `sign_image_url()`.
+//
+// Even with a valid signature, we won't connect to anything on a private network (which would
+// include whatever else is running next to us, like a cloud provider's metadata service),
+// we only pass along `image/*` responses, and we give up on anything over
+// `image_proxy_max_bytes` or that takes longer than `FETCH_TIMEOUT`.
+
+/// How long we give the image's host to send the whole thing, across any redirects.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many redirects we follow. Image hosts often redirect `http://` to `https://`, or to a CDN,
+/// but there's no reason for a chain any longer than this.
+const MAX_REDIRECTS: usize = 3;
+
+/// How many images to keep in `image_cache()`. With the default `image_proxy_max_bytes`, that's
+/// at most 500 MiB, though most images in articles are a small fraction of the maximum.
+const IMAGE_CACHE_CAPACITY: usize = 100;
+
+/// How long to keep an image in `image_cache()`.
+///
+/// Images at a given URL rarely change, and if one does, a stale copy for an hour is harmless.
+const IMAGE_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How long browsers and CDNs may cache a proxied image, in seconds.
+///
+/// Longer than `IMAGE_CACHE_TTL`, since a CDN in front of us is a much better place to cache
+/// them than our own memory.
+const MAX_AGE_SECS: u32 = 24 * 60 * 60;
+
+/// We identify ourselves to image hosts, as is only polite.
+const USER_AGENT_VALUE: &str = concat!("realworld-axum-sqlx/", env!("CARGO_PKG_VERSION"));
+
+pub fn router() -> Router {
+    Router::new().route("/imgproxy", get(proxy_image))
+}
+
+#[derive(serde::Deserialize)]
+struct ImageQuery {
+    url: String,
+    sig: String,
+}
+
+/// An image we've fetched, for `image_cache()`.
+#[derive(Clone)]
+pub(in crate::http) struct Image {
+    content_type: HeaderValue,
+    // Cloning `Bytes` only bumps a reference count, so cache hits don't copy the image.
+    body: Bytes,
+}
+
+/// The cache of images we've fetched, by URL, for `ApiContext`.
+///
+/// This saves fetching the same image again for every reader of a popular article who isn't
+/// behind a CDN, and it's per-instance like the rest of our caches.
+pub(in crate::http) fn image_cache() -> CachedMap<String, Image> {
+    CachedMap::new("images", IMAGE_CACHE_CAPACITY, IMAGE_CACHE_TTL)
+}
+
+/// Sign `url` with `key` for `GET /imgproxy`, returning the `sig` parameter.
+///
+/// This is for a renderer written in Rust, and for our tests. Anything else can do the same
+/// with its own HMAC implementation, see the top of this module.
+pub fn sign_image_url(key: &str, url: &str) -> String {
+    base64::encode_config(
+        mac(key, url).finalize().into_bytes(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+fn mac(key: &str, url: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+        .expect("HMAC-SHA-256 can accept any key length");
+    mac.update(url.as_bytes());
+    mac
+}
+
+fn verify(key: &str, url: &str, sig: &str) -> bool {
+    match base64::decode_config(sig, base64::URL_SAFE_NO_PAD) {
+        // `verify()` does a constant-time comparison.
+        Ok(sig) => mac(key, url).verify(&sig).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Fetch the image at `url` and serve it, if `sig` is a valid signature for `url`.
+///
+/// This is a `404 Not Found` if `image_proxy_key` isn't set, `403 Forbidden` if the signature
+/// is wrong or the image is on a private network, and `502 Bad Gateway` if we couldn't fetch
+/// an image from there.
+async fn proxy_image(
+    ctx: Extension<ApiContext>,
+    Query(query): Query<ImageQuery>,
+) -> Result<(HeaderMap, Bytes)> {
+    let key = ctx
+        .config
+        .image_proxy_key
+        .as_deref()
+        .ok_or(Error::NotFound)?;
+
+    if !verify(key, &query.url, &query.sig) {
+        return Err(Error::Forbidden);
+    }
+
+    let url = Url::parse(&query.url).ok().filter(is_http).ok_or_else(|| {
+        Error::unprocessable_entity([("url", "must be an http:// or https:// URL")])
+    })?;
+
+    let max_bytes = ctx.config.image_proxy_max_bytes;
+
+    let image = ctx
+        .images
+        .get_or_refresh(&query.url, || async move {
+            match tokio::time::timeout(FETCH_TIMEOUT, fetch(url, max_bytes)).await {
+                Ok(image) => image.map(Some),
+                Err(_) => Err(Error::BadGateway("timed out fetching the image")),
+            }
+        })
+        .await?
+        // `fetch()` never returns `None`, it's just the shape `CachedMap` wants.
+        .ok_or(Error::NotFound)?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, image.content_type);
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::try_from(format!("public, max-age={}", MAX_AGE_SECS))
+            .map_err(anyhow::Error::from)?,
+    );
+    // It's someone else's file being served from our origin, so make sure the browser only
+    // ever treats it as an image.
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(
+        CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("default-src 'none'; sandbox"),
+    );
+
+    Ok((headers, image.body))
+}
+
+/// Fetch the image at `url`, following redirects.
+async fn fetch(mut url: Url, max_bytes: usize) -> Result<Image> {
+    for _ in 0..=MAX_REDIRECTS {
+        let res = fetch_once(&url).await?;
+
+        if res.status().is_redirection() {
+            // A redirect is as good as another signed URL, so it goes through all the same checks.
+            url = res
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| url.join(location).ok())
+                .filter(is_http)
+                .ok_or(Error::BadGateway(
+                    "the image's host sent an invalid redirect",
+                ))?;
+
+            continue;
+        }
+
+        if !res.status().is_success() {
+            log::debug!("imgproxy: {} returned {}", url, res.status());
+            return Err(Error::BadGateway("the image's host returned an error"));
+        }
+
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .and_then(image_type)
+            .ok_or(Error::BadGateway("that isn't an image"))?;
+
+        let content_length = res
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse::<usize>().ok());
+
+        // We check as we go as well, since the host doesn't have to tell us the length up front,
+        // or tell the truth about it.
+        if content_length.is_some_and(|len| len > max_bytes) {
+            return Err(Error::BadGateway("the image is too large"));
+        }
+
+        let mut body = res.into_body();
+        let mut bytes = Vec::with_capacity(content_length.unwrap_or(0));
+
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|e| upstream_error(&url, "failed to read the image", e))?;
+
+            if bytes.len() + chunk.len() > max_bytes {
+                return Err(Error::BadGateway("the image is too large"));
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        return Ok(Image {
+            content_type,
+            body: bytes.into(),
+        });
+    }
+
+    Err(Error::BadGateway("the image redirected too many times"))
+}
+
+/// Send a `GET` request for `url`, on a new connection.
+///
+/// Hyper's `Client` would pool connections for us, but it resolves and connects on its own,
+/// so we couldn't check where it connects to. Images are cached, so we don't make these
+/// often enough for the extra connections to matter.
+async fn fetch_once(url: &Url) -> Result<Response<Body>> {
+    let host = url
+        .host()
+        .ok_or(Error::BadGateway("the image's URL has no host"))?;
+
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addr = resolve(&host, port).await?;
+
+    // Connect to the address we checked, rather than letting something resolve the host again,
+    // which could give a different answer the second time.
+    let tcp = TcpStream::connect(addr)
+        .await
+        .map_err(|e| upstream_error(url, "failed to connect to the image's host", e))?;
+
+    let req = Request::get(&url[Position::BeforePath..Position::AfterQuery])
+        .header(HOST, &url[Position::BeforeHost..Position::AfterPort])
+        .header(USER_AGENT, USER_AGENT_VALUE)
+        .header(ACCEPT, "image/*")
+        .body(Body::empty())
+        .map_err(anyhow::Error::from)?;
+
+    if url.scheme() == "https" {
+        let tls = tokio_native_tls::TlsConnector::from(
+            tokio_native_tls::native_tls::TlsConnector::new().map_err(anyhow::Error::from)?,
+        );
+
+        let domain = url.host_str().unwrap_or_default();
+
+        let tls = tls
+            .connect(domain, tcp)
+            .await
+            .map_err(|e| upstream_error(url, "failed to connect to the image's host", e))?;
+
+        send(url, tls, req).await
+    } else {
+        send(url, tcp, req).await
+    }
+}
+
+async fn send<S>(url: &Url, io: S, req: Request<Body>) -> Result<Response<Body>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::handshake(io)
+        .await
+        .map_err(|e| upstream_error(url, "failed to connect to the image's host", e))?;
+
+    // This drives the connection, and finishes once the response has been read and `sender`
+    // has been dropped.
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            log::debug!("imgproxy: connection error: {}", e);
+        }
+    });
+
+    sender
+        .send_request(req)
+        .await
+        .map_err(|e| upstream_error(url, "failed to fetch the image", e))
+}
+
+/// Resolve `host` to an address we're willing to connect to.
+///
+/// If it resolves to any address on a private network, we refuse it altogether, rather than
+/// picking one of the others. A host that does that is either misconfigured or up to something.
+async fn resolve(host: &Host<&str>, port: u16) -> Result<SocketAddr> {
+    let addrs: Vec<SocketAddr> = match *host {
+        Host::Domain(domain) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| {
+                log::debug!("imgproxy: failed to resolve {:?}: {}", domain, e);
+                Error::BadGateway("failed to resolve the image's host")
+            })?
+            .collect(),
+        Host::Ipv4(ip) => vec![SocketAddr::new(ip.into(), port)],
+        Host::Ipv6(ip) => vec![SocketAddr::new(ip.into(), port)],
+    };
+
+    if addrs.iter().any(|addr| !is_public(addr.ip())) {
+        return Err(Error::Forbidden);
+    }
+
+    addrs
+        .into_iter()
+        .next()
+        .ok_or(Error::BadGateway("failed to resolve the image's host"))
+}
+
+/// Returns `true` if `ip` is on the public internet, as far as we can tell.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // "This network", 0.0.0.0/8.
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && b & 0xc0 == 64)
+                // Benchmarking, 198.18.0.0/15.
+                || (a == 198 && b & 0xfe == 18)
+                // Reserved, 240.0.0.0/4. Nothing should be there, but plenty of networks use it
+                // internally anyway.
+                || a & 0xf0 == 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(ip.into());
+            }
+
+            let segments = ip.segments();
+            let first = segments[0];
+
+            // These carry an IPv4 address inside them, and whatever translates them sends the
+            // request on to it, so it's that address that has to be public:
+            //
+            // * NAT64, 64:ff9b::/96, with the address in the last 32 bits.
+            // * 6to4, 2002::/16, with the address in the 32 bits after the prefix.
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                return is_public(embedded_ipv4(segments[6], segments[7]));
+            }
+
+            if first == 0x2002 {
+                return is_public(embedded_ipv4(segments[1], segments[2]));
+            }
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                // Unique local addresses, fc00::/7.
+                || first & 0xfe00 == 0xfc00
+                // Link-local addresses, fe80::/10.
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+/// The IPv4 address made of two segments of an IPv6 one, for `is_public()`.
+fn embedded_ipv4(high: u16, low: u16) -> IpAddr {
+    Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)).into()
+}
+
+fn is_http(url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+}
+
+/// Returns the MIME type to serve if `content_type` is an image we're willing to pass along.
+///
+/// SVG is an image, but it's also a document that can run scripts, so it's out.
+fn image_type(content_type: &str) -> Option<HeaderValue> {
+    let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+
+    if !mime.starts_with("image/") || mime == "image/svg+xml" {
+        return None;
+    }
+
+    HeaderValue::try_from(mime).ok()
+}
+
+fn upstream_error(url: &Url, message: &'static str, e: impl std::fmt::Display) -> Error {
+    // The details are for us, not the client. It's likely the host's problem anyway.
+    log::debug!("imgproxy: {}: {}: {}", url, message, e);
+    Error::BadGateway(message)
+}
+
+#[test]
+fn test_verify() {
+    let key = "a very secret key";
+    let url = "https://images.example/cat.png";
+    let sig = sign_image_url(key, url);
+
+    assert!(verify(key, url, &sig));
+    assert!(!verify(key, "https://images.example/dog.png", &sig));
+    assert!(!verify("another key", url, &sig));
+    assert!(!verify(key, url, ""));
+    assert!(!verify(key, url, "not base64!"));
+}
+
+#[test]
+fn test_is_public() {
+    let public = |ip: &str| is_public(ip.parse().unwrap());
+
+    assert!(public("93.184.216.34"));
+    assert!(public("2606:2800:220:1:248:1893:25c8:1946"));
+
+    assert!(!public("127.0.0.1"));
+    assert!(!public("10.1.2.3"));
+    assert!(!public("172.16.0.1"));
+    assert!(!public("192.168.1.1"));
+    assert!(!public("169.254.169.254"));
+    assert!(!public("100.64.0.1"));
+    assert!(!public("0.0.0.0"));
+    assert!(!public("::1"));
+    assert!(!public("::ffff:127.0.0.1"));
+    assert!(!public("fd00::1"));
+    assert!(!public("fe80::1"));
+    assert!(!public("198.18.0.1"));
+    assert!(!public("198.19.255.255"));
+    assert!(public("198.20.0.1"));
+    assert!(!public("240.0.0.1"));
+    assert!(!public("255.255.255.254"));
+
+    // NAT64 and 6to4 are as public as the IPv4 address inside them.
+    assert!(!public("64:ff9b::7f00:1"));
+    assert!(!public("64:ff9b::a9fe:a9fe"));
+    assert!(public("64:ff9b::5db8:d822"));
+    assert!(!public("2002:7f00:1::"));
+    assert!(!public("2002:c0a8:101::1"));
+    assert!(public("2002:5db8:d822::1"));
+}
+
+#[test]
+fn test_image_type() {
+    assert_eq!(
+        image_type("image/PNG; charset=binary")
+            .as_ref()
+            .map(|v| v.to_str().unwrap()),
+        Some("image/png")
+    );
+    assert_eq!(image_type("image/svg+xml"), None);
+    assert_eq!(image_type("text/html"), None);
+}
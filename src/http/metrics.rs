@@ -0,0 +1,84 @@
+use anyhow::Context;
+use axum::extract::Extension;
+use axum::routing::get;
+use axum::Router;
+use metrics::describe_counter;
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+
+use crate::db;
+use crate::http::{articles, cache, quota, rate_limit, ApiContext};
+
+/// The bucket boundaries for all our histograms, in seconds.
+///
+/// These go from a millisecond, which is about as fast as a query gets, up to ten seconds,
+/// by which point the client has most likely given up on us anyway.
+const BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub fn router() -> Router {
+    // Note that anyone who can reach the API can read these. They don't contain anything
+    // sensitive, but if you'd rather not share them, block this route at your load balancer
+    // and have Prometheus scrape the instances directly.
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+/// Install the global metrics recorder, so `metrics::counter!()` and friends
+/// anywhere in the application record to something.
+///
+/// Until this is called, all metrics are silently discarded.
+pub fn install() -> anyhow::Result<PrometheusHandle> {
+    // By default this exports histograms as summaries, which can't be aggregated
+    // across instances.
+    let handle = PrometheusBuilder::new()
+        .set_buckets(BUCKETS)
+        .context("invalid histogram buckets")?
+        .install_recorder()
+        .context("failed to install metrics recorder")?;
+
+    db::instrument::describe_metrics();
+    db::cancel::describe_metrics();
+    cache::describe_metrics();
+    quota::describe_metrics();
+    rate_limit::describe_metrics();
+    articles::restrictions::describe_metrics();
+    describe_activity_metrics();
+
+    Ok(handle)
+}
+
+/// Register descriptions for the counters of what users are doing, which the handlers increment
+/// with `metrics::increment_counter!()` once the thing they're counting has actually happened.
+///
+/// These are for product dashboards rather than for us: they count signups and articles,
+/// not requests. Each is a plain counter, so "signups per hour" is
+/// `sum(increase(users_registered_total[1h]))` across all instances.
+///
+/// None of them are incremented inside `Tx::retry()`, which may run more than once, so nothing
+/// is counted twice.
+fn describe_activity_metrics() {
+    describe_counter!("users_registered_total", "How many users have signed up.");
+    describe_counter!(
+        "logins_total",
+        "How many times users have logged in successfully."
+    );
+    describe_counter!(
+        "articles_published_total",
+        "How many articles have been published."
+    );
+    describe_counter!("comments_added_total", "How many comments have been added.");
+    // These count requests, which includes favoriting an article that was already favorited,
+    // so don't expect the difference to add up to the sum of `favoritesCount`.
+    describe_counter!(
+        "articles_favorited_total",
+        "How many times users have favorited an article."
+    );
+    describe_counter!(
+        "articles_unfavorited_total",
+        "How many times users have unfavorited an article."
+    );
+}
+
+async fn get_metrics(ctx: Extension<ApiContext>) -> String {
+    ctx.metrics.render()
+}
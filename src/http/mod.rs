@@ -1,9 +1,22 @@
-use crate::config::Config;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, DynamicConfig};
+use crate::db::{self, Db};
+use crate::events::EventHub;
+use crate::http::cache::{Cached, CachedMap};
+use crate::mail::{self, Mailer};
 use anyhow::Context;
+use arc_swap::ArcSwap;
+use axum::body::{boxed, Body, BoxBody};
+use axum::extract::extractor_middleware;
+use axum::http::{Request, Response};
 use axum::{AddExtensionLayer, Router};
-use sqlx::PgPool;
+use metrics_exporter_prometheus::PrometheusHandle;
+use std::convert::Infallible;
 use std::sync::Arc;
-use tower::ServiceBuilder;
+use std::time::Duration;
+use tower::make::Shared;
+use tower::util::{BoxCloneService, MapRequestLayer};
+use tower::{ServiceBuilder, ServiceExt};
 
 // Utility modules.
 
@@ -13,9 +26,34 @@ mod error;
 /// Contains definitions for application-specific parameters to handler functions,
 /// such as `AuthUser` which checks for the `Authorization: Token <token>` header in the request,
 /// verifies `<token>` as a JWT and checks the signature,
-/// then deserializes the information it contains.
+/// then deserializes the information it contains (or looks up the session, see `session`).
 mod extractor;
 
+/// An in-memory cache for the results of expensive queries.
+mod cache;
+
+/// Per-user quotas on how many articles and comments can be posted, see `Config::article_quota_per_day`.
+mod quota;
+
+/// Limits how fast clients can send requests, per user or per IP address, see
+/// `Config::rate_limit_requests`.
+mod rate_limit;
+
+/// Where sessions are kept when login tokens aren't JWTs, see `Config::session_store`, along
+/// with the `DELETE /api/user/session` route to end one. Not part of the Realworld spec.
+mod session;
+
+/// Provides the `Tx` extractor, a database transaction that's automatically committed
+/// or rolled back depending on the handler's response, along with the `TxLayer` that makes it work.
+mod tx;
+
+/// Wraps responses in a `{"data", "error", "meta"}` envelope for the clients that want one,
+/// see `Config::response_envelope`.
+mod envelope;
+
+/// Logs every request with who made it and how it went, see `trace::layer()`.
+mod trace;
+
 /// A catch-all module for other common types in the API. Arguably, the `error` and `extractor`
 /// modules could have been children of this one, but that's more of a subjective decision.
 mod types;
@@ -33,11 +71,49 @@ mod articles;
 mod profiles;
 mod users;
 
+/// The `/api/user/analytics` routes, with numbers for authors about their articles and
+/// followers. Not part of the Realworld spec.
+mod analytics;
+
+/// The `/api/onboarding` routes, which keep track of how far new users have got with
+/// onboarding. Not part of the Realworld spec.
+mod onboarding;
+
+/// The `/api/announcements` routes, for sitewide banners that admins manage and users can
+/// dismiss. Not part of the Realworld spec.
+mod announcements;
+
+/// The `/healthz` and `/readyz` routes for load balancers and orchestrators, and a more thorough
+/// health check for operators. Not part of the Realworld spec.
+mod health;
+
+/// The `/metrics` route for Prometheus to scrape. Also not part of the Realworld spec.
+mod metrics;
+
+/// The `/api/csp-report` route, which browsers send Content Security Policy violations to.
+/// Also not part of the Realworld spec.
+mod csp;
+
+/// The `/imgproxy` route, which serves external images in articles from our own origin.
+/// Also not part of the Realworld spec.
+mod imgproxy;
+
+/// The `/api/debug` routes, for frontend development. Off unless `Config::debug_routes` is set.
+mod debug;
+
+/// Entry points for the fuzz targets in `fuzz/`, for the parts of the API that parse
+/// untrusted input.
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+
+pub use articles::slugify;
 pub use error::{Error, ResultExt};
+pub use imgproxy::sign_image_url;
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-use tower_http::trace::TraceLayer;
+use extractor::MaintenanceGuard;
+use tx::TxLayer;
 
 /// The core type through which handler functions can access common API state.
 ///
@@ -55,46 +131,170 @@ use tower_http::trace::TraceLayer;
 #[derive(Clone)]
 struct ApiContext {
     config: Arc<Config>,
-    db: PgPool,
+    /// The settings that may change while we're running, see `DynamicConfig`.
+    ///
+    /// Use `ctx.dynamic.load()` to get the current settings. Note that it's a snapshot, so it's
+    /// best not to hold onto it for longer than a single request.
+    dynamic: Arc<ArcSwap<DynamicConfig>>,
+    db: Db,
+    /// Subscribe to this for articles and comments created through any instance of the API.
+    // Nothing in the Realworld spec needs live updates, so no routes subscribe to this yet.
+    #[allow(dead_code)]
+    events: EventHub,
+    /// Renders everything recorded with the `metrics` crate, for `GET /metrics`.
+    metrics: PrometheusHandle,
+    /// Use `ctx.clock.now()` rather than `OffsetDateTime::now_utc()`, see `crate::clock`.
+    clock: Arc<dyn Clock>,
+    /// Send emails with this, see `crate::mail`.
+    mailer: Arc<dyn Mailer>,
+    /// Counts requests for rate limiting, see `rate_limit::from_config()`.
+    rate_limiter: Arc<dyn rate_limit::Backend>,
+    /// Where sessions are kept, or `None` if login tokens are JWTs. See `session::from_config()`.
+    sessions: Option<Arc<dyn session::SessionStore>>,
+    /// The result of `GET /api/tags`, see `articles::tags_cache()`.
+    tags: Arc<Cached<Vec<db::tags::TagCount>>>,
+    /// Articles by slug, as seen by anonymous users. See `articles::article_cache()`.
+    articles: Arc<CachedMap<String, db::articles::Article>>,
+    /// Profiles by username, as seen by anonymous users. See `profiles::profile_cache()`.
+    profiles: Arc<CachedMap<String, db::users::VersionedProfile>>,
+    /// Images fetched by `GET /imgproxy`, by URL. See `imgproxy::image_cache()`.
+    images: Arc<CachedMap<String, imgproxy::Image>>,
 }
 
-pub async fn serve(config: Config, db: PgPool) -> anyhow::Result<()> {
+pub async fn serve(
+    config: Config,
+    db: Db,
+    dynamic: Arc<ArcSwap<DynamicConfig>>,
+    events: EventHub,
+) -> anyhow::Result<()> {
     // Bootstrapping an API is both more intuitive with Axum than Actix-web but also
     // a bit more confusing at the same time.
     //
     // Coming from Actix-web, I would expect to pass the router into `ServiceBuilder` and not
-    // the other way around.
+    // the other way around. As it turns out you can do both, but they're not the same thing:
+    // `Router::layer()` wraps each route individually, *after* the request has been routed,
+    // whereas `ServiceBuilder::service()` wraps the router as a whole.
+    //
+    // We do the latter for most of our layers, so that they see every request, including the
+    // ones that don't match any route. The few that need to know which route matched go on
+    // the router in `api_router()` instead.
     //
     // It does look nicer than the mess of `move || {}` closures you have to do with Actix-web,
     // which, I suspect, largely has to do with how it manages its own worker threads instead of
     // letting Tokio do it.
-    let app = api_router().layer(
-        ServiceBuilder::new()
-            // The other reason for using a single object is because `AddExtensionLayer::new()` is
-            // rather verbose compared to Actix-web's `Data::new()`.
-            //
-            // It seems very logically named, but that makes it a bit annoying to type over and over.
-            .layer(AddExtensionLayer::new(ApiContext {
-                config: Arc::new(config),
-                db,
-            }))
-            // Enables logging. Use `RUST_LOG=tower_http=debug`
-            .layer(TraceLayer::new_for_http()),
+    let port = config.port;
+    let http_keepalive = config.http_keepalive;
+    let tcp_keepalive = config.tcp_keepalive_secs.map(Duration::from_secs);
+    let tcp_nodelay = config.tcp_nodelay;
+
+    let metrics = metrics::install()?;
+    db::instrument::monitor_pools(db.clone());
+
+    let mailer = mail::from_config(&config)?;
+
+    let app = app(
+        config,
+        db,
+        dynamic,
+        events,
+        metrics,
+        Arc::new(SystemClock),
+        mailer,
     );
 
-    // We use 8080 as our default HTTP server port, it's pretty easy to remember.
+    // See `Config::port` for why this defaults to 8080.
+    //
+    // We only speak HTTP/1.1 (Axum's `http2` feature isn't enabled), so HTTP/2 to clients,
+    // and any HTTP/2 settings that go with it, are up to whatever terminates TLS in front of us.
     //
-    // Note that any port below 1024 needs superuser privileges to bind on Linux,
-    // so 80 isn't usually used as a default for that reason.
-    axum::Server::bind(&"0.0.0.0:8080".parse()?)
-        .serve(app.into_make_service())
+    // Hyper 0.14.15 has no keep-alive or header read timeout for HTTP/1.1 (those came in later
+    // versions), so there's nothing more to set here; see `Config::http_keepalive`.
+    axum::Server::bind(&([0, 0, 0, 0], port).into())
+        .http1_keepalive(http_keepalive)
+        .tcp_keepalive(tcp_keepalive)
+        .tcp_nodelay(tcp_nodelay)
+        .serve(Shared::new(app))
         .await
         .context("error running HTTP server")
 }
 
+/// The whole API as a single `Service`, see [`app()`].
+///
+/// The actual type is a tower of layers many lines long, so we box it up.
+pub type App = BoxCloneService<Request<Body>, Response<BoxBody>, Infallible>;
+
+/// Put together the API with all its middleware, ready to handle requests.
+///
+/// `serve()` is what you want normally. This is public for the integration tests in `tests/`,
+/// which send requests straight to the `App` with `tower::ServiceExt::oneshot()` instead of
+/// going through a socket.
+///
+/// This doesn't install `metrics` as the global recorder or start any background tasks,
+/// as each test builds its own `App` and those only work once per process.
+pub fn app(
+    config: Config,
+    db: Db,
+    dynamic: Arc<ArcSwap<DynamicConfig>>,
+    events: EventHub,
+    metrics: PrometheusHandle,
+    clock: Arc<dyn Clock>,
+    mailer: Arc<dyn Mailer>,
+) -> App {
+    ServiceBuilder::new()
+        // `TraceLayer` wraps the response body in its own type, so this has to go outside it.
+        .map_response(|res: Response<_>| res.map(boxed))
+        // Enables logging. Each request is logged at `info` once it's been handled; use
+        // `RUST_LOG=tower_http=debug` to also see when they come in.
+        .layer(trace::layer())
+        // This goes outside everything else, so that it sees every response as the client
+        // would, including errors from the layers below.
+        .layer(envelope::EnvelopeLayer {
+            mode: config.response_envelope,
+        })
+        // This goes inside `TraceLayer` so that a failure to commit shows up
+        // in the logged response status.
+        .layer(TxLayer)
+        // The other reason for using a single object is because `AddExtensionLayer::new()` is
+        // rather verbose compared to Actix-web's `Data::new()`.
+        //
+        // It seems very logically named, but that makes it a bit annoying to type over and over.
+        .layer(AddExtensionLayer::new(ApiContext {
+            tags: Arc::new(articles::tags_cache(&events)),
+            articles: Arc::new(articles::article_cache()),
+            profiles: Arc::new(profiles::profile_cache()),
+            images: Arc::new(imgproxy::image_cache()),
+            rate_limiter: rate_limit::from_config(&config),
+            sessions: session::from_config(&config),
+            config: Arc::new(config),
+            dynamic,
+            db,
+            events,
+            metrics,
+            clock,
+            mailer,
+        }))
+        // This has to go after `AddExtensionLayer` because it needs `ApiContext`.
+        .layer(extractor_middleware::<MaintenanceGuard>())
+        .service(api_router())
+        .boxed_clone()
+}
+
 fn api_router() -> Router {
     // This is the order that the modules were authored in.
     users::router()
         .merge(profiles::router())
         .merge(articles::router())
+        .merge(onboarding::router())
+        .merge(analytics::router())
+        .merge(announcements::router())
+        .merge(health::router())
+        .merge(metrics::router())
+        .merge(imgproxy::router())
+        .merge(csp::router())
+        .merge(debug::router())
+        .merge(session::router())
+        // This goes on the router for the same reason, since the limit depends on the route.
+        .layer(rate_limit::RateLimitLayer)
+        // This has to go on the router rather than in `app()`, see `trace::record_route()`.
+        .layer(MapRequestLayer::new(trace::record_route))
 }
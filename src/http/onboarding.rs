@@ -0,0 +1,90 @@
+use axum::extract::Extension;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use crate::db;
+use crate::db::user_settings::OnboardingStep;
+use crate::http::extractor::AuthUser;
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// Frontends walk new users through a few steps: picking some tags they're interested in,
+// following some authors and writing their first article. If someone signs up on their phone
+// and carries on at their desk, the frontend there needs to know where they got to, so we keep
+// track of it here rather than leaving it in local storage.
+//
+// What "picking tags" means is up to the frontend; we just remember that it happened. Following
+// someone or writing an article counts whether or not the frontend tells us, so those steps are
+// done even for users who found their own way around.
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/onboarding/state", get(get_onboarding_state))
+        .route("/api/onboarding/complete", post(complete_onboarding))
+}
+
+/// A wrapper type for all requests/responses from these routes.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct OnboardingBody<T> {
+    onboarding: T,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct CompleteOnboarding {
+    /// The step the user has just done, or `None` if they've finished (or skipped) onboarding.
+    step: Option<OnboardingStep>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OnboardingState {
+    picked_tags: bool,
+    followed_authors: bool,
+    wrote_first_article: bool,
+    completed: bool,
+}
+
+async fn get_onboarding_state(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+) -> Result<Json<OnboardingBody<OnboardingState>>> {
+    // The primary, so that a frontend reading this straight after `POST /api/onboarding/complete`
+    // doesn't send the user back a step.
+    let onboarding = db::user_settings::onboarding(ctx.db.primary(), auth_user.user_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(Json(OnboardingBody {
+        onboarding: OnboardingState {
+            picked_tags: onboarding.picked_tags,
+            followed_authors: onboarding.followed_authors,
+            wrote_first_article: onboarding.wrote_first_article,
+            completed: onboarding.completed,
+        },
+    }))
+}
+
+/// Responds with the same thing as `GET /api/onboarding/state`, after the change.
+async fn complete_onboarding(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Json(req): Json<OnboardingBody<CompleteOnboarding>>,
+) -> Result<Json<OnboardingBody<OnboardingState>>> {
+    db::user_settings::complete_onboarding(
+        ctx.db.primary(),
+        auth_user.user_id,
+        req.onboarding.step,
+    )
+    .await?;
+
+    get_onboarding_state(auth_user, ctx).await
+}
+
+/// Deserialize `data` as each of the request bodies above, the same way `Json` would,
+/// for the fuzz targets in `fuzz/`. Only errors are expected; anything else is a bug.
+#[cfg(feature = "fuzzing")]
+pub(in crate::http) fn fuzz_request_bodies(data: &[u8]) {
+    serde_json::from_slice::<OnboardingBody<CompleteOnboarding>>(data).ok();
+}
@@ -1,17 +1,38 @@
+use std::time::Duration;
+
+use crate::db;
+use crate::http::cache::CachedMap;
 use crate::http::error::ResultExt;
-use crate::http::extractor::{AuthUser, MaybeAuthUser};
+use crate::http::extractor::{AuthUser, MaybeAuthUser, Preconditions};
+use crate::http::tx::Tx;
+use crate::http::types::Username;
 use crate::http::ApiContext;
 use crate::http::{Error, Result};
-use axum::extract::{Extension, Path};
+use axum::extract::{Extension, Path, Query};
+use axum::http::HeaderMap;
 use axum::routing::{get, post};
 use axum::{Json, Router};
+use headers::{ETag, HeaderMapExt};
+use sha2::{Digest, Sha256};
 
 // The `profiles` routes are very similar to the `users` routes, except they allow looking up
 // other users' data.
 
+/// The usernames that the routes below would shadow, which nobody may register.
+/// See `users::check_username()`.
+pub(super) const ROUTE_USERNAMES: &[&str] = &["suggestions", "follow-batch"];
+
 pub fn router() -> Router {
     Router::new()
         .route("/api/profiles/:username", get(get_user_profile))
+        // Static segments take priority over parameters, so this doesn't conflict with the
+        // route above, though it does mean a user named `suggestions` couldn't be looked up
+        // at all. That's why these are in `ROUTE_USERNAMES`; add any new ones there too.
+        .route("/api/profiles/suggestions", get(suggestions))
+        .route(
+            "/api/profiles/follow-batch",
+            post(follow_batch).delete(unfollow_batch),
+        )
         .route(
             "/api/profiles/:username/follow",
             post(follow_user).delete(unfollow_user),
@@ -25,6 +46,45 @@ struct ProfileBody {
     profile: Profile,
 }
 
+#[derive(serde::Serialize)]
+struct ProfilesBody {
+    profiles: Vec<Profile>,
+}
+
+#[derive(serde::Deserialize)]
+struct UsernamesBody {
+    usernames: Vec<Username>,
+}
+
+#[derive(serde::Serialize)]
+struct BatchResultsBody {
+    results: Vec<BatchResult>,
+}
+
+#[derive(serde::Serialize)]
+struct BatchResult {
+    username: String,
+    result: BatchOutcome,
+}
+
+#[derive(serde::Serialize, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+enum BatchOutcome {
+    Followed,
+    Unfollowed,
+    /// Already followed, or already not followed.
+    Unchanged,
+    NotFound,
+    /// The user's own username, since they can't follow themselves.
+    Forbidden,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct SuggestionsQuery {
+    limit: Option<i64>,
+}
+
 #[derive(serde::Serialize)]
 pub struct Profile {
     pub username: String,
@@ -33,6 +93,39 @@ pub struct Profile {
     pub following: bool,
 }
 
+impl From<db::users::VersionedProfile> for Profile {
+    fn from(profile: db::users::VersionedProfile) -> Self {
+        Profile {
+            username: profile.username,
+            bio: profile.bio,
+            image: profile.image,
+            following: profile.following,
+        }
+    }
+}
+
+impl From<db::users::Profile> for Profile {
+    fn from(profile: db::users::Profile) -> Self {
+        Profile {
+            username: profile.username,
+            bio: profile.bio,
+            image: profile.image,
+            following: profile.following,
+        }
+    }
+}
+
+impl From<db::articles::Favoriter> for Profile {
+    fn from(favoriter: db::articles::Favoriter) -> Self {
+        Profile {
+            username: favoriter.username,
+            bio: favoriter.bio,
+            image: favoriter.image,
+            following: favoriter.following,
+        }
+    }
+}
+
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#get-profile
 async fn get_user_profile(
     // The Realworld spec says authentication is optional, but doesn't specify if it should be
@@ -44,39 +137,186 @@ async fn get_user_profile(
     // Destructuring `Path()` is something I've missed in Actix-web since it was removed
     // in the 4.0 beta: https://github.com/actix/actix-web/pull/2160
     // Needless to say, I'm delighted that Axum has it.
-    Path(username): Path<String>,
-) -> Result<Json<ProfileBody>> {
-    // Since our query columns directly match an existing struct definition,
-    // we can use `query_as!()` and save a bit of manual mapping.
-    let profile = sqlx::query_as!(
-        Profile,
-        r#"
-            select
-                username,
-                bio,
-                image,
-                exists(
-                    select 1 from follow 
-                    where followed_user_id = "user".user_id and following_user_id = $2
-                ) "following!" -- This tells SQLx that this column will never be null
-            from "user"
-            where username = $1
-        "#,
-        username,
-        maybe_auth_user.user_id()
-    )
-    .fetch_optional(&ctx.db)
-    .await?
+    Path(username): Path<Username>,
+    preconditions: Preconditions,
+) -> Result<(HeaderMap, Json<ProfileBody>)> {
+    let viewer = maybe_auth_user.user_id();
+    let find = || db::users::find_profile(ctx.db.read(), username.as_str(), viewer);
+
+    // Like `GET /api/articles/:slug`, anonymous visitors all see the same thing, so they can
+    // share a cache. Logged-in users each see their own `following`.
+    let profile = match viewer {
+        Some(_) => find().await?,
+        None => {
+            ctx.profiles
+                .get_or_refresh(&username.as_str().to_string(), find)
+                .await?
+        }
+    }
     .ok_or(Error::NotFound)?;
 
-    Ok(Json(ProfileBody { profile }))
+    let etag = profile_etag(&profile)?;
+    preconditions.evaluate(Some(&etag), None)?;
+
+    let mut headers = HeaderMap::new();
+    headers.typed_insert(etag);
+
+    Ok((
+        headers,
+        Json(ProfileBody {
+            profile: profile.into(),
+        }),
+    ))
+}
+
+/// An `ETag` for a profile, as seen by whoever asked for it.
+///
+/// Unlike an article, everything in a profile comes from the user's own row except `following`,
+/// so we can use its `updated_at` with that rather than hashing the whole response with
+/// `json_etag()`. `updated_at` only has microsecond resolution, but two changes to a profile
+/// within the same microsecond would be quite the feat.
+fn profile_etag(profile: &db::users::VersionedProfile) -> anyhow::Result<ETag> {
+    let mut hasher = Sha256::new();
+    hasher.update(profile.updated_at.unix_timestamp_nanos().to_be_bytes());
+    hasher.update([profile.following as u8]);
+
+    Ok(format!("\"{:x}\"", hasher.finalize()).parse()?)
+}
+
+/// How many profiles to keep in `profile_cache()`.
+const PROFILE_CACHE_CAPACITY: usize = 1000;
+
+/// How long `GET /api/profiles/:username` may be out of date for anonymous users.
+///
+/// Updating a profile through this instance refreshes it straight away, as long as the username
+/// stays the same. The TTL covers the rest: changes through other instances or the `admin`
+/// subcommand, a lookup by someone's old username after they changed it, and a refresh that
+/// read from a replica which hadn't caught up yet.
+///
+/// Following or unfollowing someone doesn't matter here, since anonymous users don't follow
+/// anyone. It does change the `ETag` that the follower sees.
+const PROFILE_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// The cache for `GET /api/profiles/:username` for anonymous users, for `ApiContext`.
+pub(in crate::http) fn profile_cache() -> CachedMap<String, db::users::VersionedProfile> {
+    CachedMap::new("profiles", PROFILE_CACHE_CAPACITY, PROFILE_CACHE_TTL)
+}
+
+/// Suggest authors for the user to follow.
+///
+/// Not in the Realworld spec. There's no recommendation engine behind this, just a query (see
+/// `db::users::suggestions()`) that scores authors the user doesn't follow yet by:
+///
+/// * how many of the people the user follows also follow them, and
+/// * how many of their recent articles share a tag with the articles the user has favorited,
+///   which is the closest thing we have to a reading history.
+///
+/// A new user has neither, so they get the most followed authors instead.
+async fn suggestions(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    query: Query<SuggestionsQuery>,
+) -> Result<Json<ProfilesBody>> {
+    let limit = query.limit.unwrap_or(10).clamp(1, 50);
+
+    let profiles = db::users::suggestions(ctx.db.read(), auth_user.user_id, limit).await?;
+
+    Ok(Json(ProfilesBody {
+        profiles: profiles.into_iter().map(Profile::from).collect(),
+    }))
+}
+
+/// The most usernames `follow_batch()` and `unfollow_batch()` take at once.
+///
+/// Onboarding suggests a screenful of authors, not a whole directory.
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Follow several users at once, e.g. from the suggestions in an onboarding flow.
+///
+/// Not in the Realworld spec. Unlike `POST /api/profiles/:username/follow`, a username that
+/// doesn't exist (or is the user's own) doesn't fail the whole request; the response has
+/// a result for each username instead, in the same order.
+async fn follow_batch(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Json(req): Json<UsernamesBody>,
+) -> Result<Json<BatchResultsBody>> {
+    let usernames = batch_usernames(req)?;
+    let results = db::users::follow_many(ctx.db.primary(), auth_user.user_id, &usernames).await?;
+
+    Ok(Json(batch_results(results, BatchOutcome::Followed)))
+}
+
+/// Unfollow several users at once. The counterpart to `follow_batch()`.
+async fn unfollow_batch(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Json(req): Json<UsernamesBody>,
+) -> Result<Json<BatchResultsBody>> {
+    let usernames = batch_usernames(req)?;
+    let results = db::users::unfollow_many(ctx.db.primary(), auth_user.user_id, &usernames).await?;
+
+    Ok(Json(batch_results(results, BatchOutcome::Unfollowed)))
+}
+
+/// Check the size of a batch, and drop any repeated usernames.
+fn batch_usernames(req: UsernamesBody) -> Result<Vec<String>> {
+    if req.usernames.len() > MAX_BATCH_SIZE {
+        return Err(Error::unprocessable_entity([(
+            "usernames",
+            format!("at most {} usernames at once", MAX_BATCH_SIZE),
+        )]));
+    }
+
+    let mut usernames: Vec<String> = Vec::with_capacity(req.usernames.len());
+
+    for username in req.usernames {
+        let username: String = username.into();
+
+        // Quadratic, but with at most 100 that's nothing.
+        if !usernames.contains(&username) {
+            usernames.push(username);
+        }
+    }
+
+    Ok(usernames)
+}
+
+/// Turn the results of `db::users::follow_many()` or `unfollow_many()` into a response, where
+/// `changed` is what to call a user whose follow was actually added or removed.
+fn batch_results(
+    results: Vec<db::users::BatchFollowResult>,
+    changed: BatchOutcome,
+) -> BatchResultsBody {
+    BatchResultsBody {
+        results: results
+            .into_iter()
+            .map(|result| BatchResult {
+                result: if !result.found {
+                    BatchOutcome::NotFound
+                } else if result.is_self {
+                    BatchOutcome::Forbidden
+                } else if result.changed {
+                    changed
+                } else {
+                    BatchOutcome::Unchanged
+                },
+                username: result.username,
+            })
+            .collect(),
+    }
 }
 
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#follow-user
 async fn follow_user(
     auth_user: AuthUser,
-    ctx: Extension<ApiContext>,
-    Path(username): Path<String>,
+    // Using a transaction gives us a consistent view of the database.
+    // This has the side-effect of checking out a connection for the whole function,
+    // which saves some overhead on subsequent queries.
+    //
+    // `Tx` is committed for us if we return successfully, and rolled back otherwise.
+    mut tx: Tx,
+    Path(username): Path<Username>,
 ) -> Result<Json<ProfileBody>> {
     // You can implement this either with a single query using Common Table Expressions (CTEs),
     // or multiple queries with a transaction.
@@ -93,34 +333,25 @@ async fn follow_user(
     //
     // Trust me, I've learned this the hard way.
 
-    // Begin a transaction so we have a consistent view of the database.
-    // This has the side-effect of checking out a connection for the whole function,
-    // which saves some overhead on subsequent queries.
-    //
-    // If an error occurs, this transaction will be rolled back on-drop.
-    let mut tx = ctx.db.begin().await?;
-
-    let user = sqlx::query!(
-        r#"select user_id, username, bio, image from "user" where username = $1"#,
-        username
-    )
-    .fetch_optional(&mut tx)
-    .await?
-    .ok_or(Error::NotFound)?;
+    let username = username.as_str();
+
+    // If this conflicts with another transaction, `retry()` runs it again, see `Tx`.
+    let user = tx
+        .retry(|mut tx| {
+            Box::pin(async move {
+                let user = db::users::find_by_username(&mut *tx, username)
+                    .await?
+                    .ok_or(Error::NotFound)?;
 
-    sqlx::query!(
-        "insert into follow(following_user_id, followed_user_id) values ($1, $2) \
-         on conflict do nothing", // If the row already exists, we don't need to do anything.
-        auth_user.user_id,
-        user.user_id
-    )
-    .execute(&mut tx)
-    .await
-    // Handle this check constraint
-    .on_constraint("user_cannot_follow_self", |_| Error::Forbidden)?;
+                db::users::follow(&mut *tx, auth_user.user_id, user.user_id)
+                    .await
+                    // Handle this check constraint
+                    .on_constraint("user_cannot_follow_self", |_| Error::Forbidden)?;
 
-    // IMPORTANT! Without this, the changes we just made will be dropped.
-    tx.commit().await?;
+                Ok(user)
+            })
+        })
+        .await?;
 
     Ok(Json(ProfileBody {
         profile: Profile {
@@ -136,31 +367,26 @@ async fn follow_user(
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#unfollow-user
 async fn unfollow_user(
     auth_user: AuthUser,
-    ctx: Extension<ApiContext>,
-    Path(username): Path<String>,
+    mut tx: Tx,
+    Path(username): Path<Username>,
 ) -> Result<Json<ProfileBody>> {
     // This is basically identical to `follow_user()` user except we're deleting from `follow`.
 
-    let mut tx = ctx.db.begin().await?;
+    let username = username.as_str();
 
-    let user = sqlx::query!(
-        r#"select user_id, username, bio, image from "user" where username = $1"#,
-        username
-    )
-    .fetch_optional(&mut tx)
-    .await?
-    .ok_or(Error::NotFound)?;
+    let user = tx
+        .retry(|mut tx| {
+            Box::pin(async move {
+                let user = db::users::find_by_username(&mut *tx, username)
+                    .await?
+                    .ok_or(Error::NotFound)?;
 
-    sqlx::query!(
-        "delete from follow where following_user_id = $1 and followed_user_id = $2",
-        auth_user.user_id,
-        user.user_id
-    )
-    .execute(&mut tx)
-    .await?;
+                db::users::unfollow(&mut *tx, auth_user.user_id, user.user_id).await?;
 
-    // IMPORTANT! Without this, the changes we just made will be dropped.
-    tx.commit().await?;
+                Ok(user)
+            })
+        })
+        .await?;
 
     Ok(Json(ProfileBody {
         profile: Profile {
@@ -172,3 +398,9 @@ async fn unfollow_user(
         },
     }))
 }
+
+/// See `articles::fuzz_request_bodies()`.
+#[cfg(feature = "fuzzing")]
+pub(in crate::http) fn fuzz_request_bodies(data: &[u8]) {
+    serde_json::from_slice::<UsernamesBody>(data).ok();
+}
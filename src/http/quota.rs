@@ -0,0 +1,266 @@
+use std::fmt;
+
+use axum::http::{HeaderMap, HeaderValue};
+use metrics::{describe_counter, increment_counter};
+use time::{Duration, OffsetDateTime};
+
+use crate::db;
+use crate::db::types::UserId;
+use crate::http::{ApiContext, Error, Result};
+
+// Quotas on how much each user may post, which are separate from (and much coarser than) any
+// limits on how fast requests can come in. Someone posting an article every ten minutes isn't
+// going to trip a rate limiter, but a hundred and forty of them a day is still a spammer.
+//
+// The windows roll: "20 articles per day" means in any 24 hours, not per calendar day, so
+// there's no midnight rush to post the next 20. That's easy to do because we can just count
+// the user's recent rows, which also means there's no separate counter that could disagree
+// with what's actually in the database, and deleting a post gives that use back.
+//
+// The count and the insert aren't atomic, so a user sending several posts at the exact same
+// moment could go over by a few. Closing that gap would take a lock per user on every post,
+// and a quota that's off by a few doesn't matter the way a balance that's off by a few does.
+//
+// Every response to a request that counts against a quota says where the user stands, the same
+// way GitHub's API does:
+//
+// * `X-Quota-Limit`: how many the user may post per window.
+// * `X-Quota-Remaining`: how many more they can post right now.
+// * `X-Quota-Reset`: in how many seconds the oldest post in the window drops out of it, freeing
+//   up another.
+//
+// Once a quota is used up, the response is `429 Too Many Requests` with those same headers
+// and a `Retry-After`.
+//
+// Registering accounts is limited the same way, except by IP address and device rather than by
+// user, since there isn't one yet. A burst of signups from one place is the most obvious sign
+// of a spam script, so every rejection is counted in `quota_exceeded_total` to be alerted on.
+
+const LIMIT: &str = "x-quota-limit";
+const REMAINING: &str = "x-quota-remaining";
+const RESET: &str = "x-quota-reset";
+
+/// What a quota limits, and how to describe it.
+#[derive(Debug)]
+struct Kind {
+    /// For the `quota` label on `quota_exceeded_total`.
+    name: &'static str,
+    /// The error message is "you may only {verb} {limit} {what} per {per}".
+    verb: &'static str,
+    what: &'static str,
+    per: &'static str,
+    window: Duration,
+}
+
+const ARTICLES: Kind = Kind {
+    name: "articles",
+    verb: "post",
+    what: "articles",
+    per: "day",
+    window: Duration::day(),
+};
+
+const COMMENTS: Kind = Kind {
+    name: "comments",
+    verb: "post",
+    what: "comments",
+    per: "hour",
+    window: Duration::hour(),
+};
+
+const SIGNUPS_PER_IP: Kind = Kind {
+    name: "signups_per_ip",
+    verb: "register",
+    what: "accounts",
+    per: "hour from the same IP address",
+    window: Duration::hour(),
+};
+
+const SIGNUPS_PER_DEVICE: Kind = Kind {
+    name: "signups_per_device",
+    verb: "register",
+    what: "accounts",
+    per: "hour from the same device",
+    window: Duration::hour(),
+};
+
+/// Register descriptions for the metrics in this module, see `db::instrument::describe_metrics()`.
+pub fn describe_metrics() {
+    describe_counter!(
+        "quota_exceeded_total",
+        "How many requests were rejected because a quota was used up, by quota."
+    );
+}
+
+/// Something a user may only do so many times per window, and how many times they've done it.
+#[derive(Debug)]
+pub struct Quota {
+    kind: &'static Kind,
+    limit: u32,
+    /// How many times the user has done it within the window, before this request.
+    used: u32,
+    /// When the window ends for the oldest of those, if there are any.
+    reset_at: Option<OffsetDateTime>,
+    now: OffsetDateTime,
+}
+
+/// Check `user_id`'s quota for publishing articles, see `Config::article_quota_per_day`.
+///
+/// Returns `Error::QuotaExceeded` if it's used up, otherwise the headers to add to the response
+/// once the article's been published. These are empty if there's no quota.
+pub async fn check_articles(ctx: &ApiContext, user_id: UserId) -> Result<HeaderMap> {
+    let limit = match ctx.dynamic.load().article_quota_per_day {
+        Some(limit) => limit,
+        None => return Ok(HeaderMap::new()),
+    };
+
+    let now = ctx.clock.now();
+
+    // This has to go to the primary, or the user's latest articles might not be counted yet.
+    let recent =
+        db::articles::count_recent(ctx.db.primary(), user_id, now - ARTICLES.window).await?;
+
+    Quota::new(&ARTICLES, limit, recent, now).check()
+}
+
+/// Check `user_id`'s quota for posting comments, see `Config::comment_quota_per_hour`.
+///
+/// See `check_articles()`.
+pub async fn check_comments(ctx: &ApiContext, user_id: UserId) -> Result<HeaderMap> {
+    let limit = match ctx.dynamic.load().comment_quota_per_hour {
+        Some(limit) => limit,
+        None => return Ok(HeaderMap::new()),
+    };
+
+    let now = ctx.clock.now();
+
+    let recent =
+        db::comments::count_recent(ctx.db.primary(), user_id, now - COMMENTS.window).await?;
+
+    Quota::new(&COMMENTS, limit, recent, now).check()
+}
+
+/// Check the caps on registering accounts from the same IP address and from the same device,
+/// see `Config::signups_per_ip_per_hour` and `Config::signups_per_device_per_hour`.
+///
+/// Either is skipped if it isn't set, or we don't know where the request came from.
+pub async fn check_signups(ctx: &ApiContext, origin: &db::users::SignupOrigin<'_>) -> Result<()> {
+    let dynamic = ctx.dynamic.load();
+    let now = ctx.clock.now();
+
+    if let (Some(limit), Some(ip)) = (dynamic.signups_per_ip_per_hour, origin.ip) {
+        let since = now - SIGNUPS_PER_IP.window;
+        let recent = db::users::count_recent_signups_by_ip(ctx.db.primary(), ip, since).await?;
+
+        Quota::new(&SIGNUPS_PER_IP, limit, recent, now).check()?;
+    }
+
+    if let (Some(limit), Some(device)) = (dynamic.signups_per_device_per_hour, origin.device) {
+        let since = now - SIGNUPS_PER_DEVICE.window;
+        let recent =
+            db::users::count_recent_signups_by_device(ctx.db.primary(), device, since).await?;
+
+        Quota::new(&SIGNUPS_PER_DEVICE, limit, recent, now).check()?;
+    }
+
+    Ok(())
+}
+
+impl Quota {
+    fn new(kind: &'static Kind, limit: u32, recent: db::Recent, now: OffsetDateTime) -> Self {
+        Quota {
+            kind,
+            limit,
+            used: recent.count.try_into().unwrap_or(u32::MAX),
+            reset_at: recent.oldest.map(|oldest| oldest + kind.window),
+            now,
+        }
+    }
+
+    /// Fail if the quota is used up, otherwise return the headers for after using one more.
+    fn check(mut self) -> Result<HeaderMap> {
+        if self.used >= self.limit {
+            increment_counter!("quota_exceeded_total", "quota" => self.kind.name);
+            return Err(Error::QuotaExceeded(self));
+        }
+
+        // If this is the first in the window, it's the one that'll be the oldest.
+        self.reset_at.get_or_insert(self.now + self.kind.window);
+        self.used += 1;
+
+        Ok(self.headers())
+    }
+
+    /// How many seconds until the oldest use drops out of the window, rounded up.
+    pub fn reset_secs(&self) -> i64 {
+        let until = self
+            .reset_at
+            .map_or(Duration::zero(), |reset_at| reset_at - self.now);
+
+        // It can be in the past if our clock is a little behind the database's.
+        until.as_seconds_f64().ceil().max(0.0) as i64
+    }
+
+    /// The `X-Quota-*` headers describing where the user stands.
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let remaining = self.limit.saturating_sub(self.used);
+
+        headers.insert(LIMIT, HeaderValue::from(self.limit));
+        headers.insert(REMAINING, HeaderValue::from(remaining));
+        headers.insert(RESET, HeaderValue::from(self.reset_secs()));
+        headers
+    }
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "you may only {} {} {} per {}",
+            self.kind.verb, self.limit, self.kind.what, self.kind.per
+        )
+    }
+}
+
+#[test]
+fn test_quota() {
+    let now = OffsetDateTime::now_utc();
+    let quota = |count: i64, oldest: Option<OffsetDateTime>| {
+        Quota::new(&ARTICLES, 2, db::Recent { count, oldest }, now)
+    };
+    let header = |headers: &HeaderMap, name: &str| headers[name].to_str().unwrap().to_string();
+
+    // The first use starts the window.
+    let headers = quota(0, None).check().unwrap();
+    assert_eq!(header(&headers, LIMIT), "2");
+    assert_eq!(header(&headers, REMAINING), "1");
+    assert_eq!(header(&headers, RESET), "86400");
+
+    let headers = quota(1, Some(now - Duration::hour())).check().unwrap();
+    assert_eq!(header(&headers, REMAINING), "0");
+    assert_eq!(header(&headers, RESET), "82800");
+
+    match quota(2, Some(now - Duration::hour())).check() {
+        Err(Error::QuotaExceeded(quota)) => {
+            assert_eq!(quota.reset_secs(), 82800);
+            assert_eq!(header(&quota.headers(), REMAINING), "0");
+            assert_eq!(quota.to_string(), "you may only post 2 articles per day");
+        }
+        other => panic!("expected QuotaExceeded, got {:?}", other.map(|_| ())),
+    }
+
+    let signups = Quota::new(
+        &SIGNUPS_PER_IP,
+        5,
+        db::Recent {
+            count: 5,
+            oldest: None,
+        },
+        now,
+    );
+    assert_eq!(
+        signups.to_string(),
+        "you may only register 5 accounts per hour from the same IP address"
+    );
+}
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use axum::body::{boxed, Body, BoxBody, Bytes, HttpBody};
+use axum::extract::MatchedPath;
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, HeaderValue, Method, Request, Response};
+use axum::response::IntoResponse;
+use axum::BoxError;
+use futures::future::BoxFuture;
+use metrics::{describe_counter, increment_counter};
+use time::{Duration, OffsetDateTime};
+use tower::{Layer, Service};
+
+use crate::config::{Config, DynamicConfig};
+use crate::http::extractor::{AuthUser, ClientIp};
+use crate::http::{ApiContext, Error};
+
+// Not part of the Realworld spec.
+//
+// Limits on how fast clients can send requests, as opposed to the quotas in `quota` on how much
+// a user can post. Every request to an `/api` route counts against the user who sent it, or the
+// IP address it came from if they aren't logged in (and we know it, see
+// `Config::client_ip_header`). Signing up and logging in have a much stricter limit of their
+// own, by IP address, since those are what password guessers and account farms hammer on.
+//
+// The counters are fixed windows: "300 per minute" means per minute on the clock, so a client
+// could get 300 in at the end of one minute and another 300 at the start of the next. A sliding
+// window would be fairer, but it needs a timestamp per request instead of a single counter,
+// and as a backstop against scripts, twice the limit for a moment is still a limit.
+//
+// Where the counters live is up to `Backend`. By default each instance of the API counts in
+// its own memory with `MemoryBackend`, which is all you need for one instance; with several,
+// set `Config::rate_limit_redis_url` so they share counters through `RedisBackend` instead.
+// If the backend fails, we let the request through: a rate limiter that takes the API down with
+// it does more damage than anyone it was keeping out.
+//
+// Every response to a limited request says where the client stands, like the headers for
+// quotas, errors included and however far the client is from the limit, so well-behaved
+// clients can pace themselves instead of waiting to be told no:
+//
+// * `X-RateLimit-Limit`: how many requests they may make per window.
+// * `X-RateLimit-Remaining`: how many more they can make in this one.
+// * `X-RateLimit-Reset`: in how many seconds this window ends.
+//
+// Over the limit, the response is `429 Too Many Requests` with those same headers and
+// a `Retry-After`, and is counted in `rate_limited_total`.
+//
+// This has to be applied with `Router::layer()`, like `trace::record_route()`, since the policy
+// depends on which route matched.
+
+/// The `Backend` for `Config::rate_limit_redis_url`.
+#[cfg(feature = "redis")]
+mod redis;
+
+#[cfg(feature = "redis")]
+pub use redis::RedisBackend;
+
+const LIMIT: &str = "x-ratelimit-limit";
+const REMAINING: &str = "x-ratelimit-remaining";
+const RESET: &str = "x-ratelimit-reset";
+
+/// Keeps count of requests, see `MemoryBackend` and, with the `redis` feature, `RedisBackend`.
+#[async_trait]
+pub trait Backend: Send + Sync + 'static {
+    /// Count one more request against `key`, returning how many there have been including it.
+    ///
+    /// The count only needs to be kept for `ttl` from `now`, after which nothing will ask for
+    /// that key again.
+    async fn hit(&self, key: &str, now: OffsetDateTime, ttl: Duration) -> anyhow::Result<u64>;
+}
+
+/// Counts requests in memory, for a single instance of the API, and for tests.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    counts: Mutex<Counts>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    /// The count for each key, and when it expires.
+    by_key: HashMap<String, (u64, OffsetDateTime)>,
+    /// When to next clear out the expired counts.
+    next_sweep: Option<OffsetDateTime>,
+}
+
+/// The `Backend` for `config`: `RedisBackend` if `rate_limit_redis_url` is set, `MemoryBackend`
+/// otherwise.
+pub(super) fn from_config(config: &Config) -> Arc<dyn Backend> {
+    match &config.rate_limit_redis_url {
+        #[cfg(feature = "redis")]
+        Some(url) => Arc::new(
+            RedisBackend::new(url)
+                .expect("BUG: rate_limit_redis_url should have been checked by Config::validate()"),
+        ),
+        #[cfg(not(feature = "redis"))]
+        Some(_) => panic!(
+            "BUG: rate_limit_redis_url is set without the `redis` feature, \
+             which Config::validate() should have caught"
+        ),
+        None => Arc::new(MemoryBackend::default()),
+    }
+}
+
+/// Register descriptions for the metrics in this module, see `db::instrument::describe_metrics()`.
+pub fn describe_metrics() {
+    describe_counter!(
+        "rate_limited_total",
+        "How many requests were rejected for going over a rate limit, by policy."
+    );
+    describe_counter!(
+        "rate_limit_errors_total",
+        "How many requests were let through without a rate limit because the backend failed."
+    );
+}
+
+#[async_trait]
+impl Backend for MemoryBackend {
+    async fn hit(&self, key: &str, now: OffsetDateTime, ttl: Duration) -> anyhow::Result<u64> {
+        let mut counts = self.counts.lock().unwrap();
+
+        // Each key is for a single window, so once that's over it's just taking up space.
+        // Clearing them out once per window keeps the map from growing forever.
+        if !matches!(counts.next_sweep, Some(next_sweep) if next_sweep > now) {
+            counts.by_key.retain(|_, (_, expires_at)| *expires_at > now);
+            counts.next_sweep = Some(now + ttl);
+        }
+
+        let (count, _) = counts
+            .by_key
+            .entry(key.to_string())
+            .or_insert((0, now + ttl));
+
+        *count += 1;
+
+        Ok(*count)
+    }
+}
+
+/// Which limit a request counts against.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Policy {
+    /// Any `/api` route, see `Config::rate_limit_requests`.
+    Default,
+    /// Signing up, logging in and resetting passwords, see `Config::rate_limit_auth_requests`.
+    Auth,
+}
+
+impl Policy {
+    /// The policy for a request to `route` (e.g. `/api/articles/:slug`), if it's limited at all.
+    ///
+    /// The health checks, `/metrics` and `/imgproxy` aren't: the first two are for our own
+    /// infrastructure, and a page with a lot of images in it fetches them all at once.
+    fn for_route(method: &Method, route: &str) -> Option<Self> {
+        match (method, route) {
+            (
+                &Method::POST,
+                "/api/users"
+                | "/api/users/login"
+                | "/api/users/forgot-password"
+                | "/api/users/reset-password",
+            ) => Some(Self::Auth),
+            (_, route) if route.starts_with("/api/") => Some(Self::Default),
+            _ => None,
+        }
+    }
+
+    /// For the keys, and the `policy` label on `rate_limited_total`.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Auth => "auth",
+        }
+    }
+
+    fn limit(self, dynamic: &DynamicConfig) -> u32 {
+        match self {
+            Self::Default => dynamic.rate_limit_requests,
+            Self::Auth => dynamic.rate_limit_auth_requests,
+        }
+    }
+
+    /// Who the request counts against, or `None` if we can't tell, in which case it isn't limited.
+    ///
+    /// The token is checked but the database isn't, so this doesn't know whether the user is
+    /// banned. They'll find out from the handler. With `Config::session_store`, checking the
+    /// token means looking up the session, which `AuthUser` then does again; we could pass it
+    /// along, but it's the cheapest lookup we make, and it would complicate both.
+    async fn subject(self, ctx: &ApiContext, headers: &HeaderMap) -> Option<String> {
+        if let (Self::Default, Some(auth_header)) = (self, headers.get(AUTHORIZATION)) {
+            if let Ok(user) = AuthUser::from_authorization(ctx, auth_header).await {
+                return Some(format!("user:{}", user.user_id.0));
+            }
+        }
+
+        let ClientIp(ip) = ClientIp::from_headers(&ctx.config, headers);
+
+        ip.map(|ip| format!("ip:{}", ip))
+    }
+}
+
+/// Where a client stands with a rate limit, for the headers on the response.
+#[derive(Debug)]
+pub struct RateLimit {
+    limit: u32,
+    /// How many requests the client has made in this window, including this one.
+    count: u64,
+    /// How many seconds until this window ends.
+    reset_secs: i64,
+}
+
+impl RateLimit {
+    /// How many seconds until the client can make more requests.
+    pub fn reset_secs(&self) -> i64 {
+        self.reset_secs
+    }
+
+    /// The `X-RateLimit-*` headers, see above.
+    pub fn headers(&self) -> HeaderMap {
+        let remaining = u64::from(self.limit).saturating_sub(self.count);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(LIMIT, HeaderValue::from(self.limit));
+        headers.insert(REMAINING, HeaderValue::from(remaining));
+        headers.insert(RESET, HeaderValue::from(self.reset_secs));
+        headers
+    }
+}
+
+/// Count each request against its rate limit, see above.
+#[derive(Clone)]
+pub(super) struct RateLimitLayer;
+
+#[derive(Clone)]
+pub(super) struct RateLimitService<S> {
+    inner: S,
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService { inner }
+    }
+}
+
+impl<S, ResBody> Service<Request<Body>> for RateLimitService<S>
+where
+    S: Service<Request<Body>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // See `TxService::call()` for why we swap in a clone.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let rate_limit = match check(&req).await {
+                Ok(rate_limit) => rate_limit,
+                Err(e) => return Ok(e.into_response().map(boxed)),
+            };
+
+            let mut res = inner.call(req).await?.map(boxed);
+
+            if let Some(rate_limit) = rate_limit {
+                res.headers_mut().extend(rate_limit.headers());
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+/// Count `req` against its rate limit, returning `Error::RateLimited` if that's used up,
+/// or `None` if the request isn't limited.
+async fn check(req: &Request<Body>) -> Result<Option<RateLimit>, Error> {
+    let ctx = req
+        .extensions()
+        .get::<ApiContext>()
+        .expect("BUG: ApiContext was not added as an extension");
+
+    let policy = match req
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|route| Policy::for_route(req.method(), route.as_str()))
+    {
+        Some(policy) => policy,
+        None => return Ok(None),
+    };
+
+    let limit = policy.limit(&ctx.dynamic.load());
+
+    if limit == 0 {
+        return Ok(None);
+    }
+
+    let subject = match policy.subject(ctx, req.headers()).await {
+        Some(subject) => subject,
+        None => return Ok(None),
+    };
+
+    // `Config::validate()` makes sure this is at least 1.
+    let window = ctx.config.rate_limit_window_secs as i64;
+    let now = ctx.clock.now();
+    let window_start = now.unix_timestamp() - now.unix_timestamp().rem_euclid(window);
+    let reset_secs = window_start + window - now.unix_timestamp();
+
+    let key = format!("ratelimit:{}:{}:{}", policy.name(), subject, window_start);
+
+    let count = match ctx
+        .rate_limiter
+        .hit(&key, now, Duration::seconds(reset_secs))
+        .await
+    {
+        Ok(count) => count,
+        Err(e) => {
+            log::warn!(
+                "failed to count request against its rate limit, letting it through: {:?}",
+                e
+            );
+            increment_counter!("rate_limit_errors_total");
+            return Ok(None);
+        }
+    };
+
+    let rate_limit = RateLimit {
+        limit,
+        count,
+        reset_secs,
+    };
+
+    if count > u64::from(limit) {
+        increment_counter!("rate_limited_total", "policy" => policy.name());
+        return Err(Error::RateLimited(rate_limit));
+    }
+
+    Ok(Some(rate_limit))
+}
+
+#[test]
+fn test_policy_for_route() {
+    assert_eq!(
+        Policy::for_route(&Method::POST, "/api/users"),
+        Some(Policy::Auth)
+    );
+    assert_eq!(
+        Policy::for_route(&Method::POST, "/api/users/login"),
+        Some(Policy::Auth)
+    );
+    assert_eq!(
+        Policy::for_route(&Method::GET, "/api/articles/:slug"),
+        Some(Policy::Default)
+    );
+    // Each of these sends an email, and guessing reset tokens is as bad as guessing passwords.
+    assert_eq!(
+        Policy::for_route(&Method::POST, "/api/users/forgot-password"),
+        Some(Policy::Auth)
+    );
+    assert_eq!(
+        Policy::for_route(&Method::POST, "/api/users/reset-password"),
+        Some(Policy::Auth)
+    );
+    assert_eq!(
+        Policy::for_route(&Method::PUT, "/api/user"),
+        Some(Policy::Default)
+    );
+    assert_eq!(Policy::for_route(&Method::GET, "/healthz"), None);
+    assert_eq!(Policy::for_route(&Method::GET, "/imgproxy"), None);
+}
+
+#[tokio::test]
+async fn test_memory_backend() {
+    let backend = MemoryBackend::default();
+    let now = OffsetDateTime::now_utc();
+    let ttl = Duration::minute();
+
+    assert_eq!(backend.hit("a", now, ttl).await.unwrap(), 1);
+    assert_eq!(backend.hit("a", now, ttl).await.unwrap(), 2);
+    assert_eq!(backend.hit("b", now, ttl).await.unwrap(), 1);
+
+    // The next window has keys of its own, and sweeps out the ones from the last.
+    let later = now + ttl;
+    assert_eq!(backend.hit("c", later, ttl).await.unwrap(), 1);
+    assert_eq!(backend.counts.lock().unwrap().by_key.len(), 1);
+}
@@ -0,0 +1,71 @@
+use anyhow::bail;
+use async_trait::async_trait;
+use time::{Duration, OffsetDateTime};
+
+use crate::http::rate_limit::Backend;
+use crate::redis::{RedisClient, Reply};
+
+// Each key is a counter in Redis, with an `INCR` and an `EXPIRE` for every request. See
+// `crate::redis` for the client, and the format of `Config::rate_limit_redis_url`.
+
+/// Keeps rate limiting counters in Redis, see `Config::rate_limit_redis_url`.
+#[derive(Debug)]
+pub struct RedisBackend {
+    client: RedisClient,
+}
+
+impl RedisBackend {
+    /// A backend for the server at `url`. This doesn't connect until the first request.
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        Ok(RedisBackend {
+            client: RedisClient::new(url)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Backend for RedisBackend {
+    async fn hit(&self, key: &str, _now: OffsetDateTime, ttl: Duration) -> anyhow::Result<u64> {
+        // Setting the expiry every time rather than only on the first is simpler than checking,
+        // and since each key is for one window, it always comes out the same anyway.
+        let ttl = ttl.whole_seconds().max(1).to_string();
+
+        let replies = self
+            .client
+            .pipeline(&[&["INCR", key], &["EXPIRE", key, &ttl]])
+            .await?;
+
+        match replies.first() {
+            Some(Reply::Integer(count)) => Ok((*count).try_into().unwrap_or(0)),
+            other => bail!("expected an integer reply to INCR, got {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_hit() {
+    let (port, server) =
+        crate::redis::fake_server(vec!["+OK\r\n", ":1\r\n", ":1\r\n", ":2\r\n", ":1\r\n"]).await;
+
+    let backend = RedisBackend::new(&format!("redis://:secret@127.0.0.1:{}", port)).unwrap();
+    let now = OffsetDateTime::now_utc();
+
+    assert_eq!(backend.hit("k", now, Duration::minute()).await.unwrap(), 1);
+    assert_eq!(
+        backend.hit("k", now, Duration::seconds(30)).await.unwrap(),
+        2
+    );
+
+    let received = server.await.unwrap();
+
+    assert_eq!(
+        received,
+        [
+            "AUTH secret",
+            "INCR k",
+            "EXPIRE k 60",
+            "INCR k",
+            "EXPIRE k 30",
+        ]
+    );
+}
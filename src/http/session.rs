@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use axum::extract::Extension;
+use axum::routing::delete;
+use axum::Router;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use time::{Duration, OffsetDateTime};
+
+use crate::config::{Config, SessionBackend};
+use crate::http::extractor::{AuthUser, DEFAULT_SESSION_LENGTH};
+use crate::http::types::UserId;
+use crate::http::{ApiContext, Error, Result};
+
+// Not part of the Realworld spec.
+//
+// The comments in `extractor` go into how JWTs fall short for long-lived sessions: once one is
+// out there, it's good until it expires, whatever happens. With `Config::session_store` set,
+// the token a user gets when they log in is a random string instead, which stands for a session
+// we keep in a `SessionStore`, and the session is over as soon as we delete it. That happens
+// when the user logs out with `DELETE /api/user/session`, and otherwise it expires after the
+// same two weeks as a JWT would.
+//
+// The token is only ever sent to the user. What we store is its SHA-256 hash, like the password
+// reset tokens in `users`, so anyone who gets a look inside Redis can see who's logged in but
+// can't log in as them. The tokens are 32 random bytes, so unlike passwords, there's no point
+// in a slow hash.
+//
+// Sessions are kept by token rather than by user, so there's no logging someone out everywhere
+// at once, e.g. when they change their password; that needs a list of each user's sessions kept
+// up to date alongside, which we haven't needed yet. Banning someone still takes effect straight
+// away, since `AuthUser` checks the database for that either way.
+//
+// Frontends can't tell the difference, as the Realworld spec only says to send the token back
+// in `Authorization: Token <token>`, not what's in it. With JWTs, `DELETE /api/user/session`
+// is a `404 Not Found`, since there's no session to end; the frontend just forgets the token.
+
+/// Keeps sessions, see `MemorySessionStore` and, with the `redis` feature, `RedisSessionStore`.
+#[async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    /// Start a session for `user_id` under `key`, which lasts for `ttl` from `now`.
+    async fn insert(
+        &self,
+        key: &str,
+        user_id: UserId,
+        now: OffsetDateTime,
+        ttl: Duration,
+    ) -> anyhow::Result<()>;
+
+    /// The user the session under `key` is for, unless it's ended or expired by `now`.
+    async fn get(&self, key: &str, now: OffsetDateTime) -> anyhow::Result<Option<UserId>>;
+
+    /// End the session under `key`, if there is one.
+    async fn remove(&self, key: &str) -> anyhow::Result<()>;
+}
+
+/// Keeps sessions in memory, for development and tests.
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    /// The user each session is for, and when it expires.
+    sessions: Mutex<HashMap<String, (UserId, OffsetDateTime)>>,
+}
+
+/// Keeps sessions in Redis, see `Config::session_redis_url`.
+#[cfg(feature = "redis")]
+#[derive(Debug)]
+pub struct RedisSessionStore {
+    client: crate::redis::RedisClient,
+}
+
+/// The `SessionStore` for `config`, or `None` if login tokens are JWTs.
+pub(super) fn from_config(config: &Config) -> Option<Arc<dyn SessionStore>> {
+    match config.session_store {
+        SessionBackend::Jwt => None,
+        SessionBackend::Memory => Some(Arc::new(MemorySessionStore::default())),
+        #[cfg(feature = "redis")]
+        SessionBackend::Redis => {
+            let url = config
+                .session_redis_url
+                .as_deref()
+                .expect("BUG: session_redis_url should have been checked by Config::validate()");
+
+            Some(Arc::new(RedisSessionStore {
+                client: crate::redis::RedisClient::new(url).expect(
+                    "BUG: session_redis_url should have been checked by Config::validate()",
+                ),
+            }))
+        }
+        #[cfg(not(feature = "redis"))]
+        SessionBackend::Redis => panic!(
+            "BUG: session_store is redis without the `redis` feature, \
+             which Config::validate() should have caught"
+        ),
+    }
+}
+
+pub fn router() -> Router {
+    Router::new().route("/api/user/session", delete(end_session))
+}
+
+/// A new token for `user_id`, who's just logged in: a session if `Config::session_store` says
+/// to keep them, or a JWT otherwise.
+pub(in crate::http) async fn issue_token(ctx: &ApiContext, user_id: UserId) -> Result<String> {
+    let sessions = match &ctx.sessions {
+        Some(sessions) => sessions,
+        None => {
+            return Ok(AuthUser {
+                user_id,
+                session: None,
+            }
+            .to_jwt(ctx))
+        }
+    };
+
+    let token = base64::encode_config(
+        rand::thread_rng().gen::<[u8; 32]>(),
+        base64::URL_SAFE_NO_PAD,
+    );
+
+    sessions
+        .insert(
+            &key(&token),
+            user_id,
+            ctx.clock.now(),
+            DEFAULT_SESSION_LENGTH,
+        )
+        .await?;
+
+    Ok(token)
+}
+
+/// The token to hand back to a user who's already logged in, e.g. from `GET /api/user`.
+///
+/// For a session, that's the one they're using: a new one every time would leave the old one
+/// lying around until it expired, still working after they'd logged out with the new one.
+/// A JWT is swapped for a fresh one, which extends the session if the frontend takes it.
+pub(in crate::http) fn current_token(ctx: &ApiContext, auth_user: &AuthUser) -> String {
+    match &auth_user.session {
+        Some(token) => token.clone(),
+        None => auth_user.to_jwt(ctx),
+    }
+}
+
+/// The user that `token` is a session for, for `AuthUser`.
+pub(in crate::http) async fn authenticate(
+    ctx: &ApiContext,
+    sessions: &dyn SessionStore,
+    token: &str,
+) -> Result<AuthUser> {
+    let user_id = sessions
+        .get(&key(token), ctx.clock.now())
+        .await?
+        .ok_or_else(|| {
+            log::debug!("no session for token, or it's expired");
+            Error::Unauthorized
+        })?;
+
+    Ok(AuthUser {
+        user_id,
+        session: Some(token.to_string()),
+    })
+}
+
+/// Log out, by ending the session that the request was made with. Any other sessions the
+/// user has are left alone.
+async fn end_session(auth_user: AuthUser, ctx: Extension<ApiContext>) -> Result<()> {
+    match (&ctx.sessions, &auth_user.session) {
+        (Some(sessions), Some(token)) => Ok(sessions.remove(&key(token)).await?),
+        _ => Err(Error::NotFound),
+    }
+}
+
+/// Where the session for `token` is kept, see above.
+fn key(token: &str) -> String {
+    format!("session:{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn insert(
+        &self,
+        key: &str,
+        user_id: UserId,
+        now: OffsetDateTime,
+        ttl: Duration,
+    ) -> anyhow::Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        // Sessions that were never ended would otherwise pile up forever. Going through all of
+        // them for every login is fine for the handful of users in development.
+        sessions.retain(|_, (_, expires_at)| *expires_at > now);
+        sessions.insert(key.to_string(), (user_id, now + ttl));
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, now: OffsetDateTime) -> anyhow::Result<Option<UserId>> {
+        Ok(match self.sessions.lock().unwrap().get(key) {
+            Some((user_id, expires_at)) if *expires_at > now => Some(*user_id),
+            _ => None,
+        })
+    }
+
+    async fn remove(&self, key: &str) -> anyhow::Result<()> {
+        self.sessions.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+// Redis expires the keys itself, by its own clock, so these ignore `now`.
+#[cfg(feature = "redis")]
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn insert(
+        &self,
+        key: &str,
+        user_id: UserId,
+        _now: OffsetDateTime,
+        ttl: Duration,
+    ) -> anyhow::Result<()> {
+        let user_id = user_id.0.to_string();
+        let ttl = ttl.whole_seconds().max(1).to_string();
+
+        self.client
+            .pipeline(&[&["SET", key, &user_id, "EX", &ttl]])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, _now: OffsetDateTime) -> anyhow::Result<Option<UserId>> {
+        use crate::redis::Reply;
+
+        match self.client.pipeline(&[&["GET", key]]).await?.pop() {
+            Some(Reply::Bulk(Some(user_id))) => Ok(Some(UserId(user_id.parse()?))),
+            Some(Reply::Bulk(None)) => Ok(None),
+            other => anyhow::bail!("expected a bulk string reply to GET, got {:?}", other),
+        }
+    }
+
+    async fn remove(&self, key: &str) -> anyhow::Result<()> {
+        self.client.pipeline(&[&["DEL", key]]).await?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_memory_session_store() {
+    let store = MemorySessionStore::default();
+    let now = OffsetDateTime::now_utc();
+    let user_id = UserId(uuid::Uuid::from_u128(rand::random()));
+
+    store
+        .insert("a", user_id, now, Duration::hour())
+        .await
+        .unwrap();
+    store
+        .insert("b", user_id, now, Duration::hour())
+        .await
+        .unwrap();
+
+    assert_eq!(store.get("a", now).await.unwrap(), Some(user_id));
+    assert_eq!(store.get("c", now).await.unwrap(), None);
+
+    // Ending one session leaves the other.
+    store.remove("a").await.unwrap();
+    assert_eq!(store.get("a", now).await.unwrap(), None);
+    assert_eq!(store.get("b", now).await.unwrap(), Some(user_id));
+
+    let later = now + Duration::hour();
+    assert_eq!(store.get("b", later).await.unwrap(), None);
+
+    // The next login sweeps out the expired ones.
+    store
+        .insert("c", user_id, later, Duration::hour())
+        .await
+        .unwrap();
+    assert_eq!(store.sessions.lock().unwrap().len(), 1);
+}
+
+#[cfg(feature = "redis")]
+#[tokio::test]
+async fn test_redis_session_store() {
+    let user_id = UserId(uuid::Uuid::from_u128(rand::random()));
+    let bulk = format!("$36\r\n{}\r\n", user_id.0);
+
+    let (port, server) = crate::redis::fake_server(vec![
+        "+OK\r\n".to_string(),
+        bulk,
+        ":1\r\n".to_string(),
+        "$-1\r\n".to_string(),
+    ])
+    .await;
+
+    let store = RedisSessionStore {
+        client: crate::redis::RedisClient::new(&format!("redis://127.0.0.1:{}", port)).unwrap(),
+    };
+    let now = OffsetDateTime::now_utc();
+
+    store
+        .insert("session:k", user_id, now, Duration::weeks(2))
+        .await
+        .unwrap();
+    assert_eq!(store.get("session:k", now).await.unwrap(), Some(user_id));
+    store.remove("session:k").await.unwrap();
+    assert_eq!(store.get("session:k", now).await.unwrap(), None);
+
+    assert_eq!(
+        server.await.unwrap(),
+        [
+            format!("SET session:k {} EX 1209600", user_id.0),
+            "GET session:k".to_string(),
+            "DEL session:k".to_string(),
+            "GET session:k".to_string(),
+        ]
+    );
+}
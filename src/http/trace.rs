@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use axum::body::HttpBody;
+use axum::extract::MatchedPath;
+use axum::http::{header, Request, Response};
+use tower_http::classify::{ServerErrorsAsFailures, SharedClassifier};
+use tower_http::trace::{DefaultOnRequest, MakeSpan, OnResponse, TraceLayer};
+use tracing::field::{display, Empty};
+use tracing::Span;
+
+use crate::db::types::UserId;
+
+// Logs a line for every request once it's been handled, with who made it and what happened,
+// so that when someone reports abuse we can see everything a user did from the logs alone
+// rather than having to piece it together from the database.
+//
+// Everything goes in the fields of the `request` span rather than the message, so with
+// `log_format = "json"` each one is a separate key you can filter on, e.g. every request by
+// one `user_id`, or every `route` that returned a 5xx.
+//
+// We deliberately never log request or response bodies, headers or query strings: they have
+// passwords and tokens in them, and logs end up in far more places than the database does.
+// We don't log the client's IP address either, since `ClientIp` is only trustworthy behind a
+// proxy that sets it; the proxy's own access logs are the place to look for those.
+
+/// The `TraceLayer` for `app()`.
+pub(super) fn layer(
+) -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, RequestSpan, DefaultOnRequest, LogResponse>
+{
+    TraceLayer::new_for_http()
+        .make_span_with(RequestSpan)
+        .on_response(LogResponse)
+}
+
+/// Record the route that matched the request (e.g. `/api/articles/:slug`) in the request span.
+///
+/// The path alone would have an article's slug or a username in it, which makes it much harder
+/// to count requests per route. Only the router knows which one matched, so this has to be
+/// applied with `Router::layer()`, which puts it in front of each route.
+pub(super) fn record_route<B>(req: Request<B>) -> Request<B> {
+    if let Some(route) = req.extensions().get::<MatchedPath>() {
+        Span::current().record("route", &route.as_str());
+    }
+
+    req
+}
+
+/// Record the authenticated user in the request span, called by `AuthUser` and `MaybeAuthUser`.
+///
+/// This happens as soon as the token checks out, so requests by banned users are logged
+/// as theirs too.
+pub(super) fn record_user(user_id: UserId) {
+    Span::current().record("user_id", &display(user_id.0));
+}
+
+/// Creates the span for each request, with fields that start out empty for everything we only
+/// find out while handling it.
+#[derive(Clone)]
+pub(super) struct RequestSpan;
+
+impl<B> MakeSpan<B> for RequestSpan {
+    fn make_span(&mut self, req: &Request<B>) -> Span {
+        tracing::info_span!(
+            "request",
+            method = %req.method(),
+            // Not the whole URI, which would include the query string.
+            path = req.uri().path(),
+            route = Empty,
+            user_id = Empty,
+            request_bytes = content_length(req.headers()),
+            status = Empty,
+            latency_ms = Empty,
+            response_bytes = Empty,
+        )
+    }
+}
+
+/// Logs each response at `info`, once the handler has finished and we know how it went.
+///
+/// The latency is up to when the response headers were ready; for the few routes that stream
+/// their response, sending the body may take longer.
+#[derive(Clone)]
+pub(super) struct LogResponse;
+
+impl<B: HttpBody> OnResponse<B> for LogResponse {
+    fn on_response(self, res: &Response<B>, latency: Duration, span: &Span) {
+        span.record("status", &res.status().as_u16());
+        span.record("latency_ms", &(latency.as_micros() as f64 / 1000.0));
+
+        // Almost all of our responses are a single buffer, so the body knows its own size.
+        if let Some(bytes) = res
+            .body()
+            .size_hint()
+            .exact()
+            .or_else(|| content_length(res.headers()))
+        {
+            span.record("response_bytes", &bytes);
+        }
+
+        tracing::info!("finished processing request");
+    }
+}
+
+/// The `Content-Length` of a request or response, if it has one.
+///
+/// A request body without one is still read, so `request_bytes` is empty for those. We'd have to
+/// wrap the body to count it, and chunked uploads aren't something our frontends do.
+fn content_length(headers: &header::HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
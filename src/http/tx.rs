@@ -0,0 +1,284 @@
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::body::{boxed, Body, BoxBody, Bytes, HttpBody};
+use axum::extract::{Extension, FromRequest, RequestParts};
+use axum::http::{Request, Response};
+use axum::response::IntoResponse;
+use axum::BoxError;
+use futures::future::BoxFuture;
+use rand::Rng;
+use sqlx::{PgPool, Postgres, Transaction};
+use tower::{Layer, Service};
+
+use crate::db;
+use crate::http::{ApiContext, Error, Result};
+
+/// Add this as a parameter to a handler function to get a database transaction scoped
+/// to the current request.
+///
+/// The transaction isn't begun until the handler first calls `conn()`, so routes that don't use
+/// this pay nothing more than an extra allocation per request, and neither does a handler that
+/// takes it but returns early, e.g. because the request failed validation. Only a handler that
+/// actually gets as far as the database holds a connection for the rest of the request.
+///
+/// Once the handler returns, `TxLayer` commits the transaction if the response was successful
+/// (anything that isn't a `4xx` or `5xx`) and rolls it back otherwise. This is a lot harder
+/// to mess up than remembering to call `tx.commit()` on every success path, which I've
+/// forgotten to do more times than I'd like to admit.
+///
+/// Use what `conn()` returns like any other transaction: `.fetch_one(tx.conn().await?)`.
+///
+/// If the transaction might conflict with another one (a deadlock or, at stricter isolation
+/// levels, a serialization failure), do the work in `retry()` instead, which runs it again
+/// from the start if that happens.
+///
+/// Anything that has to wait until the changes are visible to everyone else, like
+/// invalidating a cache, goes in `after_commit()`.
+///
+/// This was heavily inspired by the `axum-sqlx-tx` crate, which unfortunately didn't exist yet
+/// for the version of Axum we're using.
+pub struct Tx {
+    db: PgPool,
+    // This is `None` until the handler first asks for it.
+    tx: Option<Transaction<'static, Postgres>>,
+    slot: TxSlot,
+}
+
+/// The transaction as handed to the closure passed to `Tx::retry()`. Use it like the one from
+/// `Tx::conn()`, with a reborrow: `.fetch_one(&mut *tx)`.
+pub struct RetryTx<'t, 'a> {
+    tx: &'t mut Transaction<'static, Postgres>,
+    // The closure passed to `retry()` returns a future that borrows the transaction for `'t`,
+    // and usually whatever the closure borrowed from the handler too, for `'a`. Nothing in
+    // `retry()`'s signature would tell the compiler that the latter outlives the former, so
+    // it'd refuse to let the future borrow anything at all, except that this does.
+    _outlives: PhantomData<&'t &'a ()>,
+}
+
+/// How many times we'll run a transaction that keeps conflicting with others before giving up
+/// and returning the error.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// The most we'll wait before the first retry, which doubles for each one after that.
+const BASE_RETRY_DELAY_MS: u64 = 20;
+
+/// Shared between `TxService` and `Tx`, so the transaction can be taken back after the
+/// handler is done with it.
+#[derive(Clone, Default)]
+struct TxSlot(Arc<Mutex<TxState>>);
+
+#[derive(Default)]
+struct TxState {
+    tx: Option<Transaction<'static, Postgres>>,
+    after_commit: Vec<Box<dyn FnOnce() + Send>>,
+}
+
+/// Wraps the API router to provide the `Tx` extractor.
+#[derive(Clone)]
+pub struct TxLayer;
+
+#[derive(Clone)]
+pub struct TxService<S> {
+    inner: S,
+}
+
+impl<S> Layer<S> for TxLayer {
+    type Service = TxService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TxService { inner }
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for TxService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        // The service that was driven to readiness is the one we need to call,
+        // so we swap in a fresh clone for the next request.
+        // https://docs.rs/tower/0.4.11/tower/trait.Service.html#be-careful-when-cloning-inner-services
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let slot = TxSlot::default();
+            req.extensions_mut().insert(slot.clone());
+
+            let res = inner.call(req).await?;
+
+            let status = res.status();
+
+            // Dropping a `Transaction` rolls it back, and with it go the `after_commit()` hooks.
+            if status.is_client_error() || status.is_server_error() {
+                return Ok(res.map(boxed));
+            }
+
+            let TxState { tx, after_commit } = slot.take();
+
+            // If the handler never began the transaction then there's nothing to commit.
+            if let Some(tx) = tx {
+                if let Err(e) = tx.commit().await {
+                    // The handler may have already sent its response to us, but if the
+                    // commit fails then none of its changes actually happened, so we
+                    // can't let that response through.
+                    return Ok(Error::from(e).into_response().map(boxed));
+                }
+            }
+
+            for hook in after_commit {
+                hook();
+            }
+
+            Ok(res.map(boxed))
+        })
+    }
+}
+
+impl TxSlot {
+    fn lock(&self) -> MutexGuard<'_, TxState> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn take(&self) -> TxState {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn put(&self, tx: Transaction<'static, Postgres>) {
+        self.lock().tx = Some(tx);
+    }
+}
+
+#[async_trait]
+impl FromRequest for Tx {
+    type Rejection = Error;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        let ctx: Extension<ApiContext> = Extension::from_request(req)
+            .await
+            .expect("BUG: ApiContext was not added as an extension");
+
+        let slot: Extension<TxSlot> = Extension::from_request(req)
+            .await
+            .expect("BUG: TxLayer was not added to the router");
+
+        Ok(Tx {
+            db: ctx.db.primary().clone(),
+            tx: None,
+            slot: slot.0,
+        })
+    }
+}
+
+impl Tx {
+    /// The transaction, beginning it first if this is the first time it's been asked for.
+    pub async fn conn(&mut self) -> Result<&mut Transaction<'static, Postgres>, Error> {
+        if self.tx.is_none() {
+            self.tx = Some(self.db.begin().await?);
+        }
+
+        Ok(self.tx.as_mut().expect("we just began it"))
+    }
+
+    /// Call `f` once `TxLayer` has committed the transaction, or not at all if it's rolled back.
+    ///
+    /// Doing something like invalidating a cache straight away would leave a window before the
+    /// commit in which another request could fill it again with what's about to change.
+    pub fn after_commit(&mut self, f: impl FnOnce() + Send + 'static) {
+        self.slot.lock().after_commit.push(Box::new(f));
+    }
+
+    /// Run `f` in the transaction, e.g.
+    /// `tx.retry(|mut tx| Box::pin(async move { db::users::follow(&mut *tx, ...).await }))`.
+    ///
+    /// If it fails because the transaction conflicted with another one (see
+    /// `db::is_retryable()`), the transaction is rolled back and `f` is run again in a new one,
+    /// a few times at most. So do all of the work that belongs in the transaction in `f`, since
+    /// anything done before this is rolled back along with it, and none of the work that
+    /// doesn't, since `f` may run more than once.
+    ///
+    /// The conflict shows up on whichever query hit it, which is in `f` for deadlocks. A
+    /// serialization failure can also happen when `TxLayer` commits, which this can't retry,
+    /// but we don't use the isolation levels that have those.
+    pub async fn retry<'a, T, F>(&mut self, mut f: F) -> Result<T>
+    where
+        F: for<'t> FnMut(RetryTx<'t, 'a>) -> BoxFuture<'t, Result<T>>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            let tx = RetryTx {
+                tx: self.conn().await?,
+                _outlives: PhantomData,
+            };
+
+            match f(tx).await {
+                Err(Error::Sqlx(e)) if db::is_retryable(&e) && attempt < MAX_ATTEMPTS => {
+                    log::warn!(
+                        "transaction conflicted with another one, retrying (attempt {} of {}): {}",
+                        attempt + 1,
+                        MAX_ATTEMPTS,
+                        e
+                    );
+                }
+                res => return res,
+            }
+
+            // Postgres won't let us do anything more with an aborted transaction anyway.
+            if let Some(tx) = self.tx.take() {
+                tx.rollback().await?;
+            }
+
+            // If a bunch of transactions all conflicted with each other, retrying them all
+            // at once will likely just make them conflict again. Waiting a random amount
+            // of time, up to a limit that grows with each attempt, spreads them out.
+            let max_delay = BASE_RETRY_DELAY_MS << (attempt - 1);
+            let delay = rand::thread_rng().gen_range(0..=max_delay);
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+
+            attempt += 1;
+        }
+    }
+}
+
+impl Deref for RetryTx<'_, '_> {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        self.tx
+    }
+}
+
+impl DerefMut for RetryTx<'_, '_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.tx
+    }
+}
+
+impl Drop for Tx {
+    fn drop(&mut self) {
+        // Hand the transaction back to `TxService` so it can decide what to do with it.
+        if let Some(tx) = self.tx.take() {
+            self.slot.put(tx);
+        }
+    }
+}
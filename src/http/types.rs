@@ -1,7 +1,20 @@
+use crate::http::{ApiContext, Error};
+use axum::body::{Bytes, StreamBody};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::IntoResponse;
+use axum::BoxError;
+use futures::{future, stream, Stream, StreamExt};
+use headers::ETag;
+use hmac::{Hmac, Mac, NewMac};
 use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::fmt::Formatter;
-use time::{Format, OffsetDateTime};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::sync::OnceLock;
+use time::{Format, OffsetDateTime, UtcOffset};
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
 
 /// `OffsetDateTime` provides RFC-3339 (ISO-8601 subset) serialization, but the default
 /// `serde::Serialize` implementation produces array of integers, which is great for binary
@@ -18,15 +31,39 @@ use time::{Format, OffsetDateTime};
 /// * `cookie::CookieBuilder` (used by Actix-web and `tower-cookies`) bakes-in `time::Duration`
 ///   for setting the expiration
 ///     * not really Chrono's fault but certainly doesn't help.
-#[derive(sqlx::Type)]
+#[derive(sqlx::Type, Debug, PartialEq, Eq)]
 pub struct Timestamptz(pub OffsetDateTime);
 
+impl Timestamptz {
+    // Without the `large-dates` feature, `time` only supports years -9999 through 9999.
+    const MIN_MILLIS: i64 = -377_705_116_800_000;
+    const MAX_MILLIS: i64 = 253_402_300_799_999;
+}
+
+impl Display for Timestamptz {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // `Format::Rfc3339` drops the fractional seconds entirely, which makes it impossible
+        // to tell the order of two comments posted in the same second.
+        //
+        // This matches the format shown in the Realworld spec exactly, e.g.
+        // `2016-02-18T03:22:56.637Z`, and is what Javascript's `Date.toISOString()` produces.
+        let utc = self.0.to_offset(UtcOffset::UTC);
+
+        write!(
+            f,
+            "{}.{:03}Z",
+            utc.lazy_format("%Y-%m-%dT%H:%M:%S"),
+            utc.millisecond()
+        )
+    }
+}
+
 impl Serialize for Timestamptz {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        serializer.collect_str(&self.0.lazy_format(Format::Rfc3339))
+        serializer.collect_str(self)
     }
 }
 
@@ -35,7 +72,7 @@ impl<'de> Deserialize<'de> for Timestamptz {
     where
         D: Deserializer<'de>,
     {
-        struct StrVisitor;
+        struct TimestampVisitor;
 
         // By providing our own `Visitor` impl, we can access the string data without copying.
         //
@@ -49,23 +86,658 @@ impl<'de> Deserialize<'de> for Timestamptz {
         // to work with.
         //
         // However, I also wanted to demonstrate that it was possible to do this with Serde alone.
-        impl Visitor<'_> for StrVisitor {
+        //
+        // It also turned out to be useful as some Realworld frontends send timestamps as
+        // milliseconds since the Unix epoch (`Date.now()` in Javascript), which is easy
+        // to support with a `Visitor` but would have been a pain with `FromStr`.
+        impl Visitor<'_> for TimestampVisitor {
             type Value = Timestamptz;
 
             fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
-                f.pad("expected string")
+                f.pad("an RFC 3339 timestamp string or milliseconds since the Unix epoch")
             }
 
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
             where
                 E: serde::de::Error,
             {
+                // This also accepts fractional seconds.
                 OffsetDateTime::parse(v, Format::Rfc3339)
                     .map(Timestamptz)
                     .map_err(E::custom)
             }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                if !(Timestamptz::MIN_MILLIS..=Timestamptz::MAX_MILLIS).contains(&v) {
+                    return Err(E::custom(format_args!("timestamp out of range: {}", v)));
+                }
+
+                Ok(Timestamptz(OffsetDateTime::from_unix_timestamp_nanos(
+                    v as i128 * 1_000_000,
+                )))
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                let v = i64::try_from(v)
+                    .map_err(|_| E::custom(format_args!("timestamp out of range: {}", v)))?;
+
+                self.visit_i64(v)
+            }
         }
 
-        deserializer.deserialize_str(StrVisitor)
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+/// Generate a strong `ETag` for a response body by hashing its JSON serialization.
+///
+/// This is the laziest way to get an `ETag` that's guaranteed to change whenever the response
+/// does, since a lot of our responses include data from more than one row (e.g. `favoritesCount`
+/// and `author.following` for articles) so there isn't a single `updated_at` we could use.
+///
+/// It doesn't save us from running the query, but it does save the bandwidth of sending
+/// the response again.
+pub fn json_etag<T: Serialize>(value: &T) -> anyhow::Result<ETag> {
+    let json = serde_json::to_vec(value)?;
+
+    Ok(format!("\"{:x}\"", Sha256::digest(&json)).parse()?)
+}
+
+/// How many items `json_array_stream()` serializes into each chunk of the response body.
+///
+/// Writing each item as its own chunk works, but costs a few bytes of framing and a write
+/// to the socket apiece.
+const JSON_STREAM_CHUNK: usize = 64;
+
+/// A response of `{"<key>": [...]}`, with the items of `stream` serialized into the array
+/// as they come in, rather than collecting them all into a `Vec` first.
+///
+/// The catch is that we've already sent `200 OK` by the time we find out whether the stream
+/// fails partway through. If it does, we log the error and cut the response short, so the client
+/// sees a broken connection instead of a complete-looking response that's missing items.
+pub fn json_array_stream<S, T, E>(key: &'static str, stream: S) -> impl IntoResponse
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: Serialize + Send + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let start = stream::once(future::ready(Ok(Bytes::from(format!(r#"{{"{}":["#, key)))));
+    let end = stream::once(future::ready(Ok(Bytes::from_static(b"]}"))));
+
+    let items = stream
+        .ready_chunks(JSON_STREAM_CHUNK)
+        .enumerate()
+        .map(move |(chunk, items)| {
+            let mut buf = Vec::new();
+
+            for (i, item) in items.into_iter().enumerate() {
+                let item = item.map_err(|e| {
+                    log::error!("error streaming {}: {}", key, e);
+                    BoxError::from(e)
+                })?;
+
+                if chunk > 0 || i > 0 {
+                    buf.push(b',');
+                }
+
+                serde_json::to_writer(&mut buf, &item)?;
+            }
+
+            Ok::<_, BoxError>(Bytes::from(buf))
+        });
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    (headers, StreamBody::new(start.chain(items).chain(end)))
+}
+
+/// A username, validated and normalized when it's deserialized.
+///
+/// By parsing usernames into this type at the edge of the API, handlers can't accidentally
+/// forget to validate one before it hits the database.
+///
+/// Usernames are normalized to [Unicode Normalization Form C][nfc] first, so that two usernames
+/// that look identical but are encoded differently can't both be registered. The `unique`
+/// constraint in the database handles case-insensitivity for us.
+///
+/// [nfc]: https://unicode.org/reports/tr15/#Norm_Forms
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Username(String);
+
+/// An email address, validated and normalized when it's deserialized.
+///
+/// Actually validating email addresses against RFC 5322 is a fool's errand; the only way to know
+/// for sure that an address works is to send an email to it. We just check that it's
+/// shaped roughly like an email address and lowercase it.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Email(String);
+
+/// An article slug, validated and normalized when it's deserialized.
+///
+/// Slugs we generate with `articles::slugify()` are always lowercase so we lowercase these
+/// as well, which means `/api/articles/Some-Article` still finds `some-article`.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Slug(String);
+
+impl Username {
+    const MAX_LEN: usize = 64;
+
+    pub fn as_str(&self) -> &str {
+        &self.0
     }
 }
+
+impl TryFrom<String> for Username {
+    type Error = String;
+
+    fn try_from(username: String) -> Result<Self, Self::Error> {
+        let username: String = username.nfc().collect();
+
+        if username.is_empty() || username.chars().count() > Self::MAX_LEN {
+            return Err(format!(
+                "username must be between 1 and {} characters",
+                Self::MAX_LEN
+            ));
+        }
+
+        // This keeps usernames URL-safe (they're used in the `/api/profiles/:username` routes)
+        // and rules out whitespace and invisible characters that could be used for impersonation.
+        if !username
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            return Err(
+                "username may only contain letters, numbers, underscores, hyphens and periods"
+                    .into(),
+            );
+        }
+
+        Ok(Self(username))
+    }
+}
+
+impl From<Username> for String {
+    fn from(username: Username) -> Self {
+        username.0
+    }
+}
+
+impl Email {
+    // The maximum length of a forward or reverse path in SMTP:
+    // https://datatracker.ietf.org/doc/html/rfc5321#section-4.5.3.1.3
+    const MAX_LEN: usize = 254;
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Email {
+    type Error = String;
+
+    fn try_from(email: String) -> Result<Self, Self::Error> {
+        let email = email.trim().to_lowercase();
+
+        if email.len() > Self::MAX_LEN {
+            return Err(format!(
+                "email must be no longer than {} bytes",
+                Self::MAX_LEN
+            ));
+        }
+
+        let valid = match email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.is_empty()
+                    && !domain.contains('@')
+                    && !email.contains(|c: char| c.is_whitespace() || c.is_control())
+            }
+            None => false,
+        };
+
+        if !valid {
+            return Err("email must be a valid email address".into());
+        }
+
+        Ok(Self(email))
+    }
+}
+
+impl From<Email> for String {
+    fn from(email: Email) -> Self {
+        email.0
+    }
+}
+
+impl Slug {
+    const MAX_LEN: usize = 256;
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Slug {
+    type Error = String;
+
+    fn try_from(mut slug: String) -> Result<Self, Self::Error> {
+        if slug.is_empty() || slug.len() > Self::MAX_LEN {
+            return Err(format!(
+                "slug must be between 1 and {} bytes",
+                Self::MAX_LEN
+            ));
+        }
+
+        if !slug.chars().all(|c| c.is_alphanumeric() || c == '-') {
+            return Err("slug may only contain letters, numbers and hyphens".into());
+        }
+
+        slug.make_ascii_lowercase();
+
+        Ok(Self(slug))
+    }
+}
+
+impl From<Slug> for String {
+    fn from(slug: Slug) -> Self {
+        slug.0
+    }
+}
+
+/// A common response envelope for paginated list endpoints.
+///
+/// The Realworld spec gives every list its own top-level key (`articles`, `comments`, etc.)
+/// and we have to stick with that for the routes it defines. For routes we add ourselves,
+/// this keeps pagination working the same way everywhere so the frontend only has to
+/// implement it once:
+///
+/// ```json
+/// { "items": [...], "total": 42, "nextCursor": "..." }
+/// ```
+///
+/// `nextCursor` is always present, and is `null` on the last page.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The total number of items across all pages.
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Build a page from the results of a query that was run with `limit`.
+    ///
+    /// If the query returned fewer than `limit` items then we know this is the last page,
+    /// otherwise `next_cursor` is called with the last item to produce the cursor for the next one.
+    pub fn new(
+        items: Vec<T>,
+        total: i64,
+        limit: i64,
+        next_cursor: impl FnOnce(&T) -> String,
+    ) -> Self {
+        let next_cursor = if (items.len() as i64) < limit {
+            None
+        } else {
+            items.last().map(next_cursor)
+        };
+
+        Page {
+            items,
+            total,
+            next_cursor,
+        }
+    }
+}
+
+/// An opaque, tamper-proof cursor for keyset pagination.
+///
+/// This encodes the sort key of the last item on a page (a timestamp and an ID), which is all
+/// we need to fetch the next page with a `where (sort_key, id) < ($1, $2)` clause. Which
+/// timestamp that is depends on the listing, e.g. `starts_at` for announcements.
+///
+/// We sign it with the same HMAC key as our JWTs so that clients can't craft their own cursors.
+/// That isn't a security issue in and of itself since the query would still be filtered
+/// normally, but it means we're free to change what goes in the cursor later without worrying
+/// about clients that started relying on its contents.
+///
+/// The `Listing` it's for is signed along with it, so a cursor from one listing can't be passed
+/// to another, where its timestamp and ID would mean something else entirely.
+///
+/// The format is `base64url(payload || hmac_sha256("cursor" || payload))`, where `payload` is
+/// `listing || timestamp_micros || id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub sort_key: OffsetDateTime,
+    pub id: Uuid,
+}
+
+/// The listings that hand out a `Cursor`, for telling their cursors apart.
+///
+/// These are what goes in the cursor, so don't renumber them, or every cursor that's out there
+/// will stop working.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Listing {
+    /// `GET /api/articles`.
+    Articles = 1,
+    /// `GET /api/articles/feed`. It's sorted the same way as `Articles`, but it's a different
+    /// list, so a cursor from one is no good for the other.
+    Feed = 2,
+    /// `GET /api/articles/:slug/favoriters`.
+    Favoriters = 3,
+    /// `GET /api/admin/announcements`.
+    Announcements = 4,
+}
+
+impl Cursor {
+    // Postgres only stores timestamps with microsecond resolution, so that's what we encode.
+    const PAYLOAD_LEN: usize = 1 + 8 + 16;
+
+    /// Encode and sign this cursor for returning to the client from `listing`.
+    pub fn format(&self, ctx: &ApiContext, listing: Listing) -> String {
+        self.format_with_key(ctx.config.hmac_key.as_bytes(), listing)
+    }
+
+    /// Decode a cursor from the client, checking its signature and that it's from `listing`.
+    ///
+    /// Returns `422 Unprocessable Entity` if the cursor was malformed or tampered with, or was
+    /// handed out by a different listing.
+    pub fn parse(ctx: &ApiContext, listing: Listing, cursor: &str) -> Result<Self, Error> {
+        Self::parse_with_key(ctx.config.hmac_key.as_bytes(), listing, cursor)
+            .ok_or_else(|| Error::unprocessable_entity([("cursor", "invalid cursor")]))
+    }
+
+    fn format_with_key(&self, key: &[u8], listing: Listing) -> String {
+        let mut bytes = self.payload(listing).to_vec();
+        bytes.extend_from_slice(&Self::mac(key, &bytes).finalize().into_bytes());
+
+        base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn parse_with_key(key: &[u8], listing: Listing, cursor: &str) -> Option<Self> {
+        let bytes = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+
+        if bytes.len() <= Self::PAYLOAD_LEN {
+            return None;
+        }
+
+        let (payload, tag) = bytes.split_at(Self::PAYLOAD_LEN);
+
+        // `verify()` does a constant-time comparison.
+        Self::mac(key, payload).verify(tag).ok()?;
+
+        // Checked after the signature, so all a client can learn from this is that a cursor we
+        // gave them was for somewhere else, which they knew.
+        if payload[0] != listing as u8 {
+            return None;
+        }
+
+        let (micros, id) = payload[1..].split_at(8);
+        let micros = i64::from_be_bytes(micros.try_into().ok()?);
+
+        Some(Cursor {
+            sort_key: OffsetDateTime::from_unix_timestamp_nanos(micros as i128 * 1000),
+            id: Uuid::from_slice(id).ok()?,
+        })
+    }
+
+    fn payload(&self, listing: Listing) -> [u8; Self::PAYLOAD_LEN] {
+        let micros = (self.sort_key.unix_timestamp_nanos() / 1000) as i64;
+
+        let mut payload = [0u8; Self::PAYLOAD_LEN];
+        payload[0] = listing as u8;
+        payload[1..9].copy_from_slice(&micros.to_be_bytes());
+        payload[9..].copy_from_slice(self.id.as_bytes());
+        payload
+    }
+
+    fn mac(key: &[u8], payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(key).expect("HMAC-SHA-256 can accept any key length");
+        // Since the key is shared with the JWTs, this makes sure a signature for one
+        // can never be valid for the other.
+        mac.update(b"cursor");
+        mac.update(payload);
+        mac
+    }
+}
+
+#[test]
+fn test_cursor_rejects_tampering() {
+    let key = b"a very secret key";
+
+    let cursor = Cursor {
+        sort_key: OffsetDateTime::from_unix_timestamp(1_640_000_000)
+            + time::Duration::microseconds(123_456),
+        id: Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef),
+    };
+
+    let formatted = cursor.format_with_key(key, Listing::Articles);
+    assert_eq!(
+        Cursor::parse_with_key(key, Listing::Articles, &formatted),
+        Some(cursor)
+    );
+
+    // Wrong key
+    assert_eq!(
+        Cursor::parse_with_key(b"some other key", Listing::Articles, &formatted),
+        None
+    );
+
+    // Wrong listing
+    assert_eq!(
+        Cursor::parse_with_key(key, Listing::Announcements, &formatted),
+        None
+    );
+
+    // Flip a bit in the payload, including turning it into a cursor for another listing
+    for i in [0, 1] {
+        let mut bytes = base64::decode_config(&formatted, base64::URL_SAFE_NO_PAD).unwrap();
+        bytes[i] ^= 1;
+        let tampered = base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+        assert_eq!(
+            Cursor::parse_with_key(key, Listing::Articles, &tampered),
+            None
+        );
+        assert_eq!(Cursor::parse_with_key(key, Listing::Feed, &tampered), None);
+    }
+
+    // Garbage
+    assert_eq!(
+        Cursor::parse_with_key(key, Listing::Articles, "not a cursor"),
+        None
+    );
+    assert_eq!(Cursor::parse_with_key(key, Listing::Articles, ""), None);
+}
+
+// The typed IDs live with the queries in `db`, since that's where they come from,
+// but they show up in the API too.
+pub use crate::db::types::{ArticleId, CommentId, UserId};
+
+#[test]
+fn test_timestamptz_round_trip() {
+    let timestamp = Timestamptz(
+        OffsetDateTime::from_unix_timestamp(1_455_765_776) + time::Duration::milliseconds(637),
+    );
+
+    let json = serde_json::to_string(&timestamp).unwrap();
+    assert_eq!(json, r#""2016-02-18T03:22:56.637Z""#);
+    assert_eq!(
+        serde_json::from_str::<Timestamptz>(&json).unwrap(),
+        timestamp
+    );
+
+    // Epoch milliseconds
+    assert_eq!(
+        serde_json::from_str::<Timestamptz>("1455765776637").unwrap(),
+        timestamp
+    );
+
+    // Timestamps with an offset should be converted to UTC when serialized.
+    let offset: Timestamptz = serde_json::from_str(r#""2016-02-17T22:22:56.637-05:00""#).unwrap();
+    assert_eq!(offset, timestamp);
+    assert_eq!(serde_json::to_string(&offset).unwrap(), json);
+
+    // No fractional seconds
+    assert_eq!(
+        serde_json::from_str::<Timestamptz>(r#""2016-02-18T03:22:56Z""#).unwrap(),
+        Timestamptz(OffsetDateTime::from_unix_timestamp(1_455_765_776))
+    );
+
+    assert!(serde_json::from_str::<Timestamptz>("1e100").is_err());
+    assert!(serde_json::from_str::<Timestamptz>(&i64::MAX.to_string()).is_err());
+    assert!(serde_json::from_str::<Timestamptz>(r#""yesterday""#).is_err());
+}
+
+/// The body of an article or comment, which the Realworld spec says is Markdown.
+///
+/// The spec leaves rendering to the frontend, but that means every frontend needs its own
+/// Markdown renderer *and* has to remember to sanitize the output, or else it's an XSS vector.
+/// Doing it once on the server side is a lot harder to get wrong.
+///
+/// Deserializes from a plain string. Serializes as a map with both the `body` and `bodyHtml`
+/// keys, so use it with `#[serde(flatten)]`:
+///
+/// ```rust,ignore
+/// #[derive(serde::Serialize)]
+/// struct Comment {
+///     #[serde(flatten)]
+///     body: MarkdownBody,
+/// }
+/// ```
+///
+/// The HTML is only rendered the first time it's needed, and is then cached.
+// Nothing uses this yet; articles and comments will once we turn on server-side rendering.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct MarkdownBody {
+    raw: String,
+    html: OnceLock<String>,
+}
+
+#[allow(dead_code)]
+impl MarkdownBody {
+    pub fn new(raw: String) -> Self {
+        MarkdownBody {
+            raw,
+            html: OnceLock::new(),
+        }
+    }
+
+    /// The Markdown source as it was submitted.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// The rendered and sanitized HTML.
+    pub fn html(&self) -> &str {
+        self.html.get_or_init(|| {
+            let mut html = String::with_capacity(self.raw.len());
+            pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(&self.raw));
+
+            // `pulldown-cmark` passes through any raw HTML in the source as-is, so it's
+            // important that we sanitize *after* rendering, not before.
+            ammonia::clean(&html)
+        })
+    }
+
+    pub fn into_raw(self) -> String {
+        self.raw
+    }
+}
+
+impl Serialize for MarkdownBody {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        // This needs to be a map and not a struct for `#[serde(flatten)]` to work.
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("body", self.raw())?;
+        map.serialize_entry("bodyHtml", self.html())?;
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for MarkdownBody {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer).map(MarkdownBody::new)
+    }
+}
+
+#[test]
+fn test_markdown_body_is_sanitized() {
+    #[derive(serde::Serialize)]
+    struct Comment {
+        id: i64,
+        #[serde(flatten)]
+        body: MarkdownBody,
+    }
+
+    let comment = Comment {
+        id: 1,
+        body: MarkdownBody::new("Hello, *world*!<script>alert('pwned')</script>".to_string()),
+    };
+
+    assert_eq!(
+        serde_json::to_value(&comment).unwrap(),
+        serde_json::json!({
+            "id": 1,
+            "body": "Hello, *world*!<script>alert('pwned')</script>",
+            "bodyHtml": "<p>Hello, <em>world</em>!</p>\n",
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_json_array_stream() {
+    use axum::body::HttpBody;
+
+    async fn collect<S>(stream: S) -> Result<serde_json::Value, ()>
+    where
+        S: Stream<Item = Result<u32, std::io::Error>> + Send + 'static,
+    {
+        let mut body = Box::pin(
+            json_array_stream("items", stream)
+                .into_response()
+                .into_body(),
+        );
+        let mut buf = Vec::new();
+
+        while let Some(chunk) = body.data().await {
+            buf.extend_from_slice(&chunk.map_err(|_| ())?);
+        }
+
+        Ok(serde_json::from_slice(&buf).unwrap())
+    }
+
+    assert_eq!(
+        collect(stream::iter(vec![])).await,
+        Ok(serde_json::json!({ "items": [] }))
+    );
+
+    // More than one chunk's worth, to check the commas between them.
+    let items: Vec<u32> = (0..200).collect();
+    assert_eq!(
+        collect(stream::iter(items.clone()).map(Ok)).await,
+        Ok(serde_json::json!({ "items": items }))
+    );
+
+    let failing = stream::iter(vec![Ok(1), Err(std::io::Error::other("oops"))]);
+    assert_eq!(collect(failing).await, Err(()));
+}
@@ -1,13 +1,22 @@
+use crate::db;
 use crate::http::{ApiContext, Result};
+use crate::mail::Message;
 use anyhow::Context;
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHash};
 use axum::extract::Extension;
 use axum::routing::{get, post};
 use axum::{Json, Router};
+use metrics::increment_counter;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use url::Url;
 
 use crate::http::error::{Error, ResultExt};
-use crate::http::extractor::AuthUser;
+use crate::http::extractor::{AuthUser, ClientIp, DeviceFingerprint};
+use crate::http::tx::Tx;
+use crate::http::types::{Email, Username};
+use crate::http::{profiles, quota, session};
 
 pub fn router() -> Router {
     // By having each module responsible for setting up its own routing,
@@ -16,6 +25,9 @@ pub fn router() -> Router {
         .route("/api/users", post(create_user))
         .route("/api/users/login", post(login_user))
         .route("/api/user", get(get_current_user).put(update_user))
+        .route("/api/user/anonymize", post(anonymize_user))
+        .route("/api/users/forgot-password", post(forgot_password))
+        .route("/api/users/reset-password", post(reset_password))
 }
 
 /// A wrapper type for all requests/responses from these routes.
@@ -26,27 +38,43 @@ struct UserBody<T> {
 
 #[derive(serde::Deserialize)]
 struct NewUser {
-    username: String,
-    email: String,
+    username: Username,
+    email: Email,
     password: String,
 }
 
 #[derive(serde::Deserialize)]
 struct LoginUser {
-    email: String,
+    email: Email,
     password: String,
 }
 
 #[derive(serde::Deserialize, Default, PartialEq, Eq)]
 #[serde(default)] // fill in any missing fields with `..UpdateUser::default()`
 struct UpdateUser {
-    email: Option<String>,
-    username: Option<String>,
+    email: Option<Email>,
+    username: Option<Username>,
     password: Option<String>,
     bio: Option<String>,
     image: Option<String>,
 }
 
+#[derive(serde::Deserialize)]
+struct ConfirmPassword {
+    password: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ForgotPassword {
+    email: Email,
+}
+
+#[derive(serde::Deserialize)]
+struct ResetPassword {
+    token: String,
+    password: String,
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 struct User {
     email: String,
@@ -59,23 +87,30 @@ struct User {
 // https://realworld-docs.netlify.app/docs/specs/backend-specs/endpoints#registration
 async fn create_user(
     ctx: Extension<ApiContext>,
+    ClientIp(ip): ClientIp,
+    DeviceFingerprint(device): DeviceFingerprint,
     Json(req): Json<UserBody<NewUser>>,
 ) -> Result<Json<UserBody<User>>> {
+    let ip = ip.map(|ip| ip.to_string());
+    let origin = db::users::SignupOrigin {
+        ip: ip.as_deref(),
+        device: device.as_deref(),
+    };
+
+    check_username(&ctx, &req.user.username)?;
+
+    // Before hashing the password, so a script hammering this doesn't get to make us do that.
+    quota::check_signups(&ctx, &origin).await?;
+
     let password_hash = hash_password(req.user.password).await?;
 
-    // I personally prefer using queries inline in request handlers as it's easier to understand the
-    // query's semantics in the wider context of where it's invoked.
-    //
-    // Sometimes queries just get too darn big, though. In that case it may be a good idea
-    // to move the query to a separate module.
-    let user_id = sqlx::query_scalar!(
-        // language=PostgreSQL
-        r#"insert into "user" (username, email, password_hash) values ($1, $2, $3) returning user_id"#,
-        req.user.username,
-        req.user.email,
-        password_hash
+    let user_id = db::users::create(
+        ctx.db.primary(),
+        req.user.username.as_str(),
+        req.user.email.as_str(),
+        &password_hash,
+        &origin,
     )
-    .fetch_one(&ctx.db)
     .await
     .on_constraint("user_username_key", |_| {
         Error::unprocessable_entity([("username", "username taken")])
@@ -84,11 +119,13 @@ async fn create_user(
         Error::unprocessable_entity([("email", "email taken")])
     })?;
 
+    increment_counter!("users_registered_total");
+
     Ok(Json(UserBody {
         user: User {
-            email: req.user.email,
-            token: AuthUser { user_id }.to_jwt(&ctx),
-            username: req.user.username,
+            email: req.user.email.into(),
+            token: session::issue_token(&ctx, user_id).await?,
+            username: req.user.username.into(),
             bio: "".to_string(),
             image: None,
         },
@@ -100,26 +137,24 @@ async fn login_user(
     ctx: Extension<ApiContext>,
     Json(req): Json<UserBody<LoginUser>>,
 ) -> Result<Json<UserBody<User>>> {
-    let user = sqlx::query!(
-        r#"
-            select user_id, email, username, bio, image, password_hash 
-            from "user" where email = $1
-        "#,
-        req.user.email,
-    )
-    .fetch_optional(&ctx.db)
-    .await?
-    .ok_or(Error::unprocessable_entity([("email", "does not exist")]))?;
+    let (user, credentials) =
+        db::users::find_by_email_with_password(ctx.db.primary(), req.user.email.as_str())
+            .await?
+            .ok_or(Error::unprocessable_entity([("email", "does not exist")]))?;
+
+    verify_password(req.user.password, credentials.password_hash).await?;
+
+    // Only after checking the password, so this doesn't tell anyone else they're banned.
+    if credentials.banned {
+        return Err(Error::Forbidden);
+    }
 
-    verify_password(req.user.password, user.password_hash).await?;
+    increment_counter!("logins_total");
 
     Ok(Json(UserBody {
         user: User {
             email: user.email,
-            token: AuthUser {
-                user_id: user.user_id,
-            }
-            .to_jwt(&ctx),
+            token: session::issue_token(&ctx, user.user_id).await?,
             username: user.username,
             bio: user.bio,
             image: user.image,
@@ -132,12 +167,9 @@ async fn get_current_user(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
 ) -> Result<Json<UserBody<User>>> {
-    let user = sqlx::query!(
-        r#"select email, username, bio, image from "user" where user_id = $1"#,
-        auth_user.user_id
-    )
-    .fetch_one(&ctx.db)
-    .await?;
+    let user = db::users::find_by_id(ctx.db.primary(), auth_user.user_id)
+        .await?
+        .ok_or(Error::NotFound)?;
 
     Ok(Json(UserBody {
         user: User {
@@ -146,8 +178,9 @@ async fn get_current_user(
             // or generate a new one. Generating a new one is easier the way the code is structured.
             //
             // This has the side-effect of automatically refreshing the session if the frontend
-            // updates its token based on this response.
-            token: auth_user.to_jwt(&ctx),
+            // updates its token based on this response. Sessions from `Config::session_store` are
+            // a different story, see `current_token()`.
+            token: session::current_token(&ctx, &auth_user),
             username: user.username,
             bio: user.bio,
             image: user.image,
@@ -168,6 +201,10 @@ async fn update_user(
         return get_current_user(auth_user, ctx).await;
     }
 
+    if let Some(username) = &req.user.username {
+        check_username(&ctx, username)?;
+    }
+
     // WTB `Option::map_async()`
     let password_hash = if let Some(password) = req.user.password {
         Some(hash_password(password).await?)
@@ -175,27 +212,17 @@ async fn update_user(
         None
     };
 
-    let user = sqlx::query!(
-        // This is how we do optional updates of fields without needing a separate query for each.
-        // language=PostgreSQL
-        r#"
-            update "user"
-            set email = coalesce($1, "user".email),
-                username = coalesce($2, "user".username),
-                password_hash = coalesce($3, "user".password_hash),
-                bio = coalesce($4, "user".bio),
-                image = coalesce($5, "user".image)
-            where user_id = $6
-            returning email, username, bio, image
-        "#,
-        req.user.email,
-        req.user.username,
-        password_hash,
-        req.user.bio,
-        req.user.image,
-        auth_user.user_id
+    let user = db::users::update(
+        ctx.db.primary(),
+        auth_user.user_id,
+        db::users::UserUpdate {
+            email: req.user.email.as_ref().map(Email::as_str),
+            username: req.user.username.as_ref().map(Username::as_str),
+            password_hash: password_hash.as_deref(),
+            bio: req.user.bio.as_deref(),
+            image: req.user.image.as_deref(),
+        },
     )
-    .fetch_one(&ctx.db)
     .await
     .on_constraint("user_username_key", |_| {
         Error::unprocessable_entity([("username", "username taken")])
@@ -204,10 +231,182 @@ async fn update_user(
         Error::unprocessable_entity([("email", "email taken")])
     })?;
 
+    // If they changed their username, anonymous visitors may still see the profile under the old
+    // one until it expires from the cache, see `profiles::profile_cache()`.
+    ctx.profiles.invalidate(&user.username);
+
+    Ok(Json(UserBody {
+        user: User {
+            email: user.email,
+            token: session::current_token(&ctx, &auth_user),
+            username: user.username,
+            bio: user.bio,
+            image: user.image,
+        },
+    }))
+}
+
+/// Delete the user's account, but keep their articles and comments under `[deleted]`.
+/// See `db::users::anonymize()`.
+///
+/// Not in the Realworld spec. This is the only way for users to leave; simply deleting their
+/// content would leave holes in other people's comment threads.
+///
+/// They have to give their password again, so a token left in someone else's browser isn't
+/// enough to do something this drastic.
+async fn anonymize_user(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Json(req): Json<UserBody<ConfirmPassword>>,
+) -> Result<()> {
+    let credentials = db::users::find_credentials(ctx.db.primary(), auth_user.user_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    verify_password(req.user.password, credentials.password_hash).await?;
+
+    db::users::anonymize(ctx.db.primary(), auth_user.user_id).await?;
+
+    Ok(())
+}
+
+/// How long the token in a password reset email can be used for.
+const RESET_TOKEN_TTL: time::Duration = time::Duration::hours(1);
+
+/// How many password reset emails we'll send any one user in an hour, so nobody can fill up
+/// someone else's inbox with them.
+const MAX_RESET_EMAILS_PER_HOUR: i64 = 3;
+
+/// Email the user a token to reset their password with, if there's an account with this email.
+///
+/// Not in the Realworld spec. The response is the same whether or not there's such an account,
+/// and whether or not we actually sent anything. That doesn't stop anyone finding out whether
+/// an email address has an account, as `POST /api/users` tells them anyway, but there's no
+/// sense in adding another way.
+///
+/// The token can be used once, within `RESET_TOKEN_TTL`, with `POST /api/users/reset-password`.
+/// See `Config::password_reset_url` for what the email looks like.
+async fn forgot_password(
+    ctx: Extension<ApiContext>,
+    Json(req): Json<UserBody<ForgotPassword>>,
+) -> Result<()> {
+    let user =
+        db::users::find_by_email_with_password(ctx.db.primary(), req.user.email.as_str()).await?;
+
+    // Being able to reset their password wouldn't get a banned user anywhere, as they still
+    // couldn't log in.
+    let user = match user {
+        Some((user, credentials)) if !credentials.banned => user,
+        _ => return Ok(()),
+    };
+
+    let token = base64::encode_config(
+        rand::thread_rng().gen::<[u8; 32]>(),
+        base64::URL_SAFE_NO_PAD,
+    );
+
+    let stored = db::password_resets::create(
+        ctx.db.primary(),
+        user.user_id,
+        &Sha256::digest(token.as_bytes()),
+        ctx.clock.now(),
+        ctx.clock.now() + RESET_TOKEN_TTL,
+        MAX_RESET_EMAILS_PER_HOUR,
+    )
+    .await?;
+
+    if !stored {
+        log::debug!(
+            "not sending another password reset email to {}",
+            user.username
+        );
+        return Ok(());
+    }
+
+    let instructions = match &ctx.config.password_reset_url {
+        Some(url) => {
+            // `Config::validate()` has checked this is a valid URL.
+            let mut url = Url::parse(url).context("invalid password_reset_url")?;
+            url.query_pairs_mut().append_pair("token", &token);
+            format!("To choose a new one, go to:\n\n{}", url)
+        }
+        None => format!(
+            "To choose a new one, enter this code where you asked for it:\n\n{}",
+            token
+        ),
+    };
+
+    // If this fails, the token we just stored goes unused, which is harmless.
+    ctx.mailer
+        .send(Message {
+            to: user.email,
+            subject: "Reset your password".into(),
+            body: format!(
+                "Hi {},\n\n\
+                 Someone, hopefully you, asked to reset the password for your account. {}\n\n\
+                 This works once, within the next hour. If it wasn't you, you can ignore this \
+                 email; your password hasn't changed.\n",
+                user.username, instructions
+            ),
+        })
+        .await?;
+
+    increment_counter!("password_reset_emails_total");
+
+    Ok(())
+}
+
+/// Set a new password with the token from a password reset email, which logs the user in.
+///
+/// Not in the Realworld spec. The token is used up along with any others the user was sent,
+/// but only if the rest succeeds too: it's all one transaction, so if hashing the password fails
+/// or the user turns out to be banned, the token is left as it was and can be tried again.
+async fn reset_password(
+    ctx: Extension<ApiContext>,
+    mut tx: Tx,
+    Json(req): Json<UserBody<ResetPassword>>,
+) -> Result<Json<UserBody<User>>> {
+    // We check the token before hashing the password, so guessing tokens doesn't get to make
+    // us do that.
+    let user_id = db::password_resets::consume(
+        tx.conn().await?,
+        &Sha256::digest(req.user.token.as_bytes()),
+        ctx.clock.now(),
+    )
+    .await?
+    .ok_or_else(|| Error::unprocessable_entity([("token", "invalid or expired")]))?;
+
+    let password_hash = hash_password(req.user.password).await?;
+
+    let user = db::users::update(
+        tx.conn().await?,
+        user_id,
+        db::users::UserUpdate {
+            email: None,
+            username: None,
+            password_hash: Some(&password_hash),
+            bio: None,
+            image: None,
+        },
+    )
+    .await?;
+
+    let credentials = db::users::find_credentials(tx.conn().await?, user_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    // They could have been banned since we sent the email. Returning the error rolls back the
+    // transaction, so their password stays as it was.
+    if credentials.banned {
+        return Err(Error::Forbidden);
+    }
+
+    tx.after_commit(|| increment_counter!("password_resets_total"));
+
     Ok(Json(UserBody {
         user: User {
             email: user.email,
-            token: auth_user.to_jwt(&ctx),
+            token: session::issue_token(&ctx, user_id).await?,
             username: user.username,
             bio: user.bio,
             image: user.image,
@@ -215,10 +414,81 @@ async fn update_user(
     }))
 }
 
+/// Check that `username` isn't reserved and doesn't contain a blocked word,
+/// see `Config::reserved_usernames` and `Config::blocked_username_words`.
+///
+/// This is on top of what `Username` itself checks, because it depends on the configuration.
+fn check_username(ctx: &ApiContext, username: &Username) -> Result<()> {
+    username_problem(
+        &ctx.config.reserved_usernames,
+        &ctx.config.blocked_username_words,
+        username.as_str(),
+    )
+    .map_or(Ok(()), |problem| {
+        Err(Error::unprocessable_entity([("username", problem)]))
+    })
+}
+
+/// The guts of `check_username()`, without the `ApiContext`.
+fn username_problem(
+    reserved: &[String],
+    blocked: &[String],
+    username: &str,
+) -> Option<&'static str> {
+    // Ignoring the separators means `ad.min` and `ad_min` can't pass themselves off as `admin`,
+    // and a blocked word can't be split up to sneak it past us.
+    let fold = |s: &str| -> String {
+        s.chars()
+            .filter(|c| !matches!(c, '_' | '-' | '.'))
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+
+    let folded = fold(username);
+
+    let is_reserved = profiles::ROUTE_USERNAMES
+        .iter()
+        .copied()
+        .chain(reserved.iter().map(String::as_str))
+        .any(|name| fold(name) == folded);
+
+    if is_reserved {
+        return Some("username is reserved");
+    }
+
+    // Only for blocked words; `l33t` is a perfectly good username, but not if it spells out
+    // something it shouldn't.
+    let unleet: String = folded
+        .chars()
+        .map(|c| match c {
+            '0' => 'o',
+            '1' => 'i',
+            '3' => 'e',
+            '4' => 'a',
+            '5' => 's',
+            '7' => 't',
+            '8' => 'b',
+            c => c,
+        })
+        .collect();
+
+    let is_blocked = blocked
+        .iter()
+        .map(|word| fold(word))
+        .filter(|word| !word.is_empty())
+        .any(|word| folded.contains(&word) || unleet.contains(&word));
+
+    if is_blocked {
+        return Some("username is not allowed");
+    }
+
+    None
+}
+
 async fn hash_password(password: String) -> Result<String> {
     // Argon2 hashing is designed to be computationally intensive,
     // so we need to do this on a blocking thread.
-    Ok(tokio::task::spawn_blocking(move || -> Result<String> {
+    tokio::task::spawn_blocking(move || -> Result<String> {
         let salt = SaltString::generate(rand::thread_rng());
         Ok(
             PasswordHash::generate(Argon2::default(), password, salt.as_str())
@@ -227,11 +497,11 @@ async fn hash_password(password: String) -> Result<String> {
         )
     })
     .await
-    .context("panic in generating password hash")??)
+    .context("panic in generating password hash")?
 }
 
 async fn verify_password(password: String, password_hash: String) -> Result<()> {
-    Ok(tokio::task::spawn_blocking(move || -> Result<()> {
+    tokio::task::spawn_blocking(move || -> Result<()> {
         let hash = PasswordHash::new(&password_hash)
             .map_err(|e| anyhow::anyhow!("invalid password hash: {}", e))?;
 
@@ -242,5 +512,48 @@ async fn verify_password(password: String, password_hash: String) -> Result<()>
             })
     })
     .await
-    .context("panic in verifying password hash")??)
+    .context("panic in verifying password hash")?
+}
+
+/// Deserialize `data` as each of the request bodies above, the same way `Json` would,
+/// for the fuzz targets in `fuzz/`. Only errors are expected; anything else is a bug.
+#[cfg(feature = "fuzzing")]
+pub(in crate::http) fn fuzz_request_bodies(data: &[u8]) {
+    serde_json::from_slice::<UserBody<NewUser>>(data).ok();
+    serde_json::from_slice::<UserBody<LoginUser>>(data).ok();
+    serde_json::from_slice::<UserBody<UpdateUser>>(data).ok();
+    serde_json::from_slice::<UserBody<ConfirmPassword>>(data).ok();
+    serde_json::from_slice::<UserBody<ForgotPassword>>(data).ok();
+    serde_json::from_slice::<UserBody<ResetPassword>>(data).ok();
+}
+
+#[test]
+fn test_username_problem() {
+    let reserved = ["admin".to_string(), "login".to_string()];
+    let blocked = ["badword".to_string()];
+
+    let problem = |username| username_problem(&reserved, &blocked, username);
+
+    assert_eq!(problem("alice"), None);
+    assert_eq!(problem("admin"), Some("username is reserved"));
+    assert_eq!(problem("Ad_Min"), Some("username is reserved"));
+    assert_eq!(problem("log.in"), Some("username is reserved"));
+    // Only the whole name is reserved.
+    assert_eq!(problem("admiral"), None);
+    assert_eq!(problem("login_bob"), None);
+
+    // These would collide with our routes whatever the configuration says.
+    assert_eq!(
+        username_problem(&[], &[], "suggestions"),
+        Some("username is reserved")
+    );
+    assert_eq!(
+        username_problem(&[], &[], "Follow-Batch"),
+        Some("username is reserved")
+    );
+
+    assert_eq!(problem("my_badword_99"), Some("username is not allowed"));
+    assert_eq!(problem("B4d-W0rd"), Some("username is not allowed"));
+    assert_eq!(problem("l33t"), None);
+    assert_eq!(username_problem(&[], &["".to_string()], "alice"), None);
 }
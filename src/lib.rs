@@ -4,12 +4,40 @@
 // However, this style better facilitates a guided exploration of the code, so it's the one
 // we'll be using in this project.
 
-/// Defines the arguments required to start the server application using [`clap`].
+/// The `admin` subcommands, for operators to manage users and content from the command line.
+pub mod admin;
+
+/// The `Clock` that everything time-dependent asks for the current time, so tests can control it.
+pub mod clock;
+
+/// Defines the arguments required to start the server application using [`clap`],
+/// and merges them with the other configuration sources using [`figment`].
 ///
 /// [`clap`]: https://github.com/clap-rs/clap/
+/// [`figment`]: https://github.com/SergioBenitez/Figment
 pub mod config;
 
+/// Provides the `Db` facade over our connection pools, which routes read-only queries to a
+/// read replica if one is configured.
+pub mod db;
+
+/// Relays changes that are announced with Postgres `NOTIFY` to subscribers within
+/// the application, for features like live updates over WebSockets or SSE.
+pub mod events;
+
+/// The `Mailer` that sends emails, through an SMTP server or just to the logs.
+pub mod mail;
+
 /// Contains the setup code for the API build with Axum.
 ///
 /// The Realworld API routes exist in child modules of this.
 pub mod http;
+
+/// A small Redis client, for the rate limiting counters and sessions that can be kept there.
+#[cfg(feature = "redis")]
+pub mod redis;
+
+/// The harness for the integration tests in `tests/`: a `TestApp` with a database of its own,
+/// and a client for making requests to it as different users.
+#[cfg(feature = "test-util")]
+pub mod test_util;
@@ -0,0 +1,96 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::config::Config;
+
+// Anything that needs to send an email asks the `Mailer` in `ApiContext`, the same way it asks
+// the `Clock` what time it is. Which one that is depends on the configuration: with `smtp_url`
+// set it's `SmtpMailer`, which needs the `smtp` feature, and otherwise it's `LogMailer`, which
+// just logs what it would have sent. That's all you want in development anyway. The tests use
+// `MockMailer`, so they can fish the emails back out.
+//
+// Nothing here retries, or queues emails to send later: if the mail server is down, sending
+// fails and whoever asked can try again. For a password reset (see
+// `http::users::forgot_password()`) that's the user clicking the button again, which they
+// would do anyway. Invitations to co-author an article (see `http::articles::authors`) don't
+// depend on the email getting through, so those just log the failure.
+
+/// The SMTP client for `Config::smtp_url`.
+#[cfg(feature = "smtp")]
+mod smtp;
+
+#[cfg(feature = "smtp")]
+pub use smtp::SmtpMailer;
+
+/// An email to one recipient, in plain text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Sends emails. See `LogMailer`, `MockMailer` and, with the `smtp` feature, `SmtpMailer`.
+#[async_trait]
+pub trait Mailer: Send + Sync + 'static {
+    async fn send(&self, message: Message) -> anyhow::Result<()>;
+}
+
+/// Logs every message instead of sending it, for development.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct LogMailer;
+
+/// Keeps every message it's asked to send, for tests.
+///
+/// Share it with the application in an `Arc` and keep a handle to call `take()` on.
+#[derive(Debug, Default)]
+pub struct MockMailer {
+    sent: Mutex<Vec<Message>>,
+}
+
+/// The `Mailer` for `config`: `SmtpMailer` if `smtp_url` is set, `LogMailer` otherwise.
+pub fn from_config(config: &Config) -> anyhow::Result<Arc<dyn Mailer>> {
+    match &config.smtp_url {
+        #[cfg(feature = "smtp")]
+        Some(url) => Ok(Arc::new(SmtpMailer::new(url, &config.mail_from)?)),
+        // `Config::validate()` should have caught this already.
+        #[cfg(not(feature = "smtp"))]
+        Some(_) => anyhow::bail!("smtp_url is set, but we were built without the `smtp` feature"),
+        None => {
+            log::info!("smtp_url is not set, so emails will be logged instead of sent");
+            Ok(Arc::new(LogMailer))
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, message: Message) -> anyhow::Result<()> {
+        // The body is usually the whole point (e.g. a link with a token in it), so it's logged
+        // too. That's fine for development, and why this is never used when `smtp_url` is set.
+        log::info!(
+            "not sending email to {} (smtp_url is not set)\nSubject: {}\n\n{}",
+            message.to,
+            message.subject,
+            message.body
+        );
+
+        Ok(())
+    }
+}
+
+impl MockMailer {
+    /// Take all the messages sent so far, oldest first.
+    pub fn take(&self) -> Vec<Message> {
+        std::mem::take(&mut *self.sent.lock().unwrap())
+    }
+}
+
+#[async_trait]
+impl Mailer for MockMailer {
+    async fn send(&self, message: Message) -> anyhow::Result<()> {
+        self.sent.lock().unwrap().push(message);
+        Ok(())
+    }
+}
@@ -6,11 +6,13 @@
 // to put the application bootstrap logic here is an open question. Both approaches have their
 // upsides and their downsides. Your input is welcome!
 
-use anyhow::Context;
 use clap::Parser;
-use sqlx::postgres::PgPoolOptions;
+use rand::RngCore;
 
-use realworld_axum_sqlx::config::Config;
+use realworld_axum_sqlx::admin;
+use realworld_axum_sqlx::config::{Args, Command, Config};
+use realworld_axum_sqlx::db::{self, Db};
+use realworld_axum_sqlx::events::EventHub;
 use realworld_axum_sqlx::http;
 
 #[tokio::main]
@@ -19,33 +21,104 @@ async fn main() -> anyhow::Result<()> {
     // since we're not going to use a `.env` file if we deploy this application.
     dotenv::dotenv().ok();
 
+    // Parse our command-line arguments.
+    // This will exit with a help message if something is wrong.
+    let args = Args::parse();
+
+    let command = args.command.clone().unwrap_or_default();
+
+    // This is the one command that doesn't need any configuration, which is kind of the point:
+    // it's for generating the configuration.
+    if let Command::GenKey = command {
+        let mut key = [0u8; 48];
+        rand::thread_rng().fill_bytes(&mut key);
+        println!("{}", base64::encode(key));
+        return Ok(());
+    }
+
+    // Then merge them with our other configuration sources.
+    let config = Config::load(&args)?;
+
+    // This goes before validation, because a configuration that doesn't validate
+    // is exactly when you'd want to see it.
+    if let Some(format) = args.print_config {
+        return config.print(format);
+    }
+
+    config.validate()?;
+
     // Initialize the logger.
-    env_logger::init();
+    config.init_logging()?;
 
-    // Parse our configuration from the environment.
-    // This will exit with a help message if something is wrong.
-    let config = Config::parse();
-
-    // We create a single connection pool for SQLx that's shared across the whole application.
-    // This saves us from opening a new connection for every API call, which is wasteful.
-    let db = PgPoolOptions::new()
-        // The default connection limit for a Postgres server is 100 connections, minus 3 for superusers.
-        // Since we're using the default superuser we don't have to worry about this too much,
-        // although we should leave some connections available for manual access.
-        //
-        // If you're deploying your application with multiple replicas, then the total
-        // across all replicas should not exceed the Postgres connection limit.
-        .max_connections(50)
-        .connect(&config.database_url)
-        .await
-        .context("could not connect to database_url")?;
-
-    // This embeds database migrations in the application binary so we can ensure the database
-    // is migrated correctly on startup
-    sqlx::migrate!().run(&db).await?;
-
-    // Finally, we spin up our API.
-    http::serve(config, db).await?;
+    let db = Db::connect(&config).await?;
+
+    match command {
+        Command::Serve => {
+            if config.run_migrations {
+                db.migrate().await?;
+
+                // If the replica is behind on migrations, queries against it would fail
+                // in confusing ways.
+                db.check_replica_migrations().await?;
+            } else {
+                // Presumably, the migrations are being applied by something else right now.
+                // We start anyway so that `/readyz` can tell the orchestrator when we're
+                // ready to go.
+                let check = async {
+                    db.check_migrations().await?;
+                    db.check_replica_migrations().await
+                };
+
+                if let Err(e) = check.await {
+                    log::warn!("{:#}; not ready until migrations are applied", e);
+                }
+            }
+
+            let dynamic = config.watch(args.clone());
+
+            if config.pgbouncer_mode {
+                // `database_url` presumably points at the pooler then, but we can't tell for sure.
+                log::warn!(
+                    "live updates rely on LISTEN, which doesn't work through a connection pooler \
+                     in transaction mode; make sure database_url connects directly to Postgres"
+                );
+            }
+
+            if config.debug_routes {
+                log::warn!(
+                    "debug_routes is on, so anyone can log in as any user; \
+                     don't use this configuration in production"
+                );
+            }
+
+            let events = EventHub::listen(&db).await?;
+
+            db::feed::prune_periodically(db.clone());
+
+            // Finally, we spin up our API.
+            http::serve(config, db, dynamic, events).await?;
+        }
+        Command::Migrate => {
+            db.migrate().await?;
+            log::info!("database is up to date");
+        }
+        Command::Seed(options) => {
+            db.migrate().await?;
+            db::seed::seed(db.primary(), &options).await?;
+        }
+        Command::CheckConfig => {
+            // `Config::load()` and `Db::connect()` have already done most of the work.
+            db.check_replica_migrations().await?;
+            log::info!("configuration is valid");
+        }
+        Command::Admin(command) => {
+            // Same as `seed`, these need the schema to be up to date, but unlike `seed` this is
+            // likely to be run against production, where applying migrations is someone else's job.
+            db.check_migrations().await?;
+            admin::run(&db, command).await?;
+        }
+        Command::GenKey => unreachable!(),
+    }
 
     Ok(())
 }
@@ -0,0 +1,632 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use axum::body::Body;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use serde_json::{json, Value};
+use sqlx::{Connection, Executor, PgConnection};
+use time::OffsetDateTime;
+use tower::ServiceExt;
+
+use crate::clock::MockClock;
+use crate::config::Config;
+use crate::db::Db;
+use crate::events::EventHub;
+use crate::http;
+use crate::mail::MockMailer;
+
+// The harness for the integration tests, which put the whole API through its paces against
+// a real database, the same way the Postman collection does but a lot faster.
+//
+// Each test gets a fresh database of its own with all the migrations applied, so tests can run
+// in parallel without tripping over each other's data. SQLx 0.6 and later can do this for you
+// with `#[sqlx::test(migrations = "migrations")]`; until we upgrade, `TestApp` does it by hand.
+//
+// We don't bind a port, either: the tests call the `http::App` service directly with
+// `tower::ServiceExt::oneshot()`, which goes through all the same middleware.
+//
+// These need `DATABASE_URL` to point at a Postgres server where the user can create databases.
+// The database in the URL itself is only used to connect, so it's fine to use the same one
+// as for development (which is also what the query macros need to compile anyway).
+//
+// If you don't have Postgres installed but do have Docker, set `TEST_POSTGRES=docker` instead
+// and every test starts a throwaway Postgres container of its own, which is removed again when
+// the test finishes. That's a few seconds slower per test, but needs no setup at all.
+//
+// This is what `testcontainers` does, but all it takes is a couple of `docker` commands,
+// which didn't seem worth a dependency (and its async API) for.
+//
+// A typical test reads like this:
+//
+// ```rust,ignore
+// let app = TestApp::new().await;
+// let alice = app.create_user("alice").await;
+//
+// let article = alice
+//     .post_json("/api/articles", json!({ "article": { ... } }))
+//     .await
+//     .assert_ok();
+//
+// app.get("/api/articles/no-such-article").await.assert_status(StatusCode::NOT_FOUND);
+// ```
+
+/// The password `TestApp::create_user()` gives everyone.
+pub const PASSWORD: &str = "hunter2hunter2";
+
+/// Set to `docker` to run each test against a Postgres container of its own, see above.
+pub const TEST_POSTGRES_VAR: &str = "TEST_POSTGRES";
+
+/// The image for `TEST_POSTGRES=docker`, pinned so a new major version can't break the tests
+/// out from under us.
+const POSTGRES_IMAGE: &str = "postgres:14";
+
+/// How long we give a new container to start accepting connections.
+const CONTAINER_STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+// A key that passes `Config::validate()`, not that we call it. Don't use this for anything real.
+const HMAC_KEY: &str = "0IoOXEW1SXjKsbhgZoQrUdcmb7XWlYHlhNx9ek3mDE1lGnKGPZbNAE/5D5BWW9Ar";
+
+/// An instance of the API with a database of its own, which is dropped along with it.
+///
+/// Requests made through this directly are anonymous; use `create_user()` or `login()` to get
+/// a `TestUser` that makes requests as a particular user.
+pub struct TestApp {
+    app: http::App,
+    db: Db,
+    clock: Arc<MockClock>,
+    mailer: Arc<MockMailer>,
+    database_url: String,
+    database_name: String,
+    /// Declared last so it's dropped last, after the pools connected to it.
+    container: Option<Container>,
+}
+
+/// A Postgres container for `TEST_POSTGRES=docker`, which is removed when this is dropped.
+struct Container {
+    id: String,
+}
+
+/// A logged-in user, whose requests all carry their token.
+pub struct TestUser<'a> {
+    app: &'a TestApp,
+    pub username: String,
+    pub email: String,
+    pub token: String,
+}
+
+/// The status and body of a response, with some helpers to make assertions on them.
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// The body as JSON. An empty body comes out as `Value::Null`, and one that isn't JSON as
+    /// a `Value::String`, since most errors other than `422 Unprocessable Entity` are just
+    /// a plain message.
+    pub body: Value,
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        Self::with_config(json!({})).await
+    }
+
+    /// Like `new()`, but with the settings in `overrides` on top of the defaults, using the same
+    /// names as in a configuration file, e.g. `json!({ "comment_quota_per_hour": 2 })`.
+    pub async fn with_config(overrides: Value) -> Self {
+        dotenv::dotenv().ok();
+
+        let (database_url, container) = match std::env::var(TEST_POSTGRES_VAR).as_deref() {
+            Ok("docker") => {
+                let (container, database_url) = Container::start().await;
+                (database_url, Some(container))
+            }
+            Ok(other) => panic!(
+                "unknown {}: {:?}, expected \"docker\"",
+                TEST_POSTGRES_VAR, other
+            ),
+            Err(_) => (
+                std::env::var("DATABASE_URL").expect(
+                    "DATABASE_URL or TEST_POSTGRES=docker must be set to run the integration tests",
+                ),
+                None,
+            ),
+        };
+
+        // Postgres folds unquoted identifiers to lowercase, and lowercase hex never needs quoting.
+        let database_name = format!("realworld_test_{:016x}", rand::random::<u64>());
+
+        let mut conn = PgConnection::connect(&database_url)
+            .await
+            .expect("failed to connect to DATABASE_URL");
+
+        conn.execute(&*format!("create database {}", database_name))
+            .await
+            .expect("failed to create the test database");
+
+        let mut test_url = url::Url::parse(&database_url).expect("DATABASE_URL is not a valid URL");
+        test_url.set_path(&database_name);
+
+        // Going through `serde` means every setting we don't care about gets its default,
+        // without having to list them all here.
+        let mut config = json!({
+            "database_url": test_url.as_str(),
+            "hmac_key": HMAC_KEY,
+        });
+
+        if let Value::Object(overrides) = overrides {
+            config.as_object_mut().unwrap().extend(overrides);
+        }
+
+        let config: Config = serde_json::from_value(config).expect("invalid test configuration");
+
+        let db = Db::connect(&config).await.expect("failed to connect");
+        db.migrate().await.expect("failed to run migrations");
+
+        let events = EventHub::listen(&db).await.expect("failed to listen");
+        let dynamic = Arc::new(ArcSwap::from_pointee(config.dynamic()));
+        // A recorder that isn't installed, so nothing is ever recorded to it; the global
+        // recorder can only be installed once per process, and every test builds an `App`.
+        let metrics = PrometheusBuilder::new().build_recorder().handle();
+        // Starts at the real time, so timestamps from Postgres line up with it until a test
+        // moves it.
+        let clock = Arc::new(MockClock::new(OffsetDateTime::now_utc()));
+        let mailer = Arc::new(MockMailer::default());
+
+        TestApp {
+            app: http::app(
+                config,
+                db.clone(),
+                dynamic,
+                events,
+                metrics,
+                clock.clone(),
+                mailer.clone(),
+            ),
+            db,
+            clock,
+            mailer,
+            database_url,
+            database_name,
+            container,
+        }
+    }
+
+    /// The test database, for setting up data directly with `db::fixtures` rather than through
+    /// the API, or checking on it afterwards.
+    ///
+    /// Users inserted that way have the password `fixtures::DEMO_PASSWORD`, not `PASSWORD`.
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+
+    /// The clock the app tells the time by, which only moves when you `advance()` it.
+    pub fn clock(&self) -> &MockClock {
+        &self.clock
+    }
+
+    /// The emails the app has sent, which only ever go here.
+    pub fn mailer(&self) -> &MockMailer {
+        &self.mailer
+    }
+
+    /// Register a user with the password `PASSWORD` and an email address based on `username`.
+    pub async fn create_user(&self, username: &str) -> TestUser<'_> {
+        let email = format!("{}@example.com", username);
+
+        let body = self
+            .post_json(
+                "/api/users",
+                json!({
+                    "user": {
+                        "username": username,
+                        "email": email,
+                        "password": PASSWORD,
+                    }
+                }),
+            )
+            .await
+            .assert_ok();
+
+        self.user_from(&body)
+    }
+
+    /// Log in as an existing user.
+    pub async fn login(&self, email: &str, password: &str) -> TestUser<'_> {
+        let body = self
+            .post_json(
+                "/api/users/login",
+                json!({ "user": { "email": email, "password": password } }),
+            )
+            .await
+            .assert_ok();
+
+        self.user_from(&body)
+    }
+
+    fn user_from(&self, body: &Value) -> TestUser<'_> {
+        let field = |name: &str| {
+            body["user"][name]
+                .as_str()
+                .unwrap_or_else(|| panic!("no {} in {}", name, body))
+                .to_string()
+        };
+
+        TestUser {
+            app: self,
+            username: field("username"),
+            email: field("email"),
+            token: field("token"),
+        }
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.request(Method::GET, uri, None, None).await
+    }
+
+    pub async fn post_json(&self, uri: &str, body: Value) -> TestResponse {
+        self.request(Method::POST, uri, None, Some(body)).await
+    }
+
+    pub async fn put_json(&self, uri: &str, body: Value) -> TestResponse {
+        self.request(Method::PUT, uri, None, Some(body)).await
+    }
+
+    pub async fn delete(&self, uri: &str) -> TestResponse {
+        self.request(Method::DELETE, uri, None, None).await
+    }
+
+    /// Send a request, with a JSON body if there is one.
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> TestResponse {
+        let mut req = Request::builder().method(method).uri(uri);
+
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Token {}", token));
+        }
+
+        let req = match body {
+            Some(body) => req
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => req.body(Body::empty()),
+        }
+        .unwrap();
+
+        self.send(req).await
+    }
+
+    /// Send a request built by hand, for when it needs headers that `request()` doesn't add.
+    pub async fn send(&self, req: Request<Body>) -> TestResponse {
+        let res = self.app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let headers = res.headers().clone();
+
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+        let body = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+        };
+
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+}
+
+impl TestUser<'_> {
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.request(Method::GET, uri, None).await
+    }
+
+    /// `POST` without a body, which is how favoriting and following work.
+    pub async fn post(&self, uri: &str) -> TestResponse {
+        self.request(Method::POST, uri, None).await
+    }
+
+    pub async fn post_json(&self, uri: &str, body: Value) -> TestResponse {
+        self.request(Method::POST, uri, Some(body)).await
+    }
+
+    pub async fn put_json(&self, uri: &str, body: Value) -> TestResponse {
+        self.request(Method::PUT, uri, Some(body)).await
+    }
+
+    pub async fn delete(&self, uri: &str) -> TestResponse {
+        self.request(Method::DELETE, uri, None).await
+    }
+
+    pub async fn request(&self, method: Method, uri: &str, body: Option<Value>) -> TestResponse {
+        self.app.request(method, uri, Some(&self.token), body).await
+    }
+
+    /// Publish an article titled `title` and return its slug.
+    pub async fn create_article(&self, title: &str, tags: &[&str]) -> String {
+        let body = self
+            .post_json(
+                "/api/articles",
+                json!({
+                    "article": {
+                        "title": title,
+                        "description": "A test article",
+                        "body": "Hello, world!",
+                        "tagList": tags,
+                    }
+                }),
+            )
+            .await
+            .assert_ok();
+
+        body["article"]["slug"].as_str().unwrap().to_string()
+    }
+}
+
+impl TestResponse {
+    /// Assert that the status is `status`, showing the body if it isn't.
+    #[track_caller]
+    pub fn assert_status(self, status: StatusCode) -> Self {
+        assert_eq!(
+            self.status, status,
+            "unexpected status, body: {}",
+            self.body
+        );
+        self
+    }
+
+    /// The value of the header `name`, panicking if it's missing or not valid UTF-8.
+    #[track_caller]
+    pub fn header(&self, name: &str) -> &str {
+        self.headers
+            .get(name)
+            .unwrap_or_else(|| panic!("no {} header in the response", name))
+            .to_str()
+            .unwrap()
+    }
+
+    /// Assert that the status is `200 OK`, and return the body.
+    #[track_caller]
+    pub fn assert_ok(self) -> Value {
+        self.assert_status(StatusCode::OK).body
+    }
+
+    /// Assert that the response is a `422 Unprocessable Entity` with `message` among the errors
+    /// for `field`.
+    #[track_caller]
+    pub fn assert_unprocessable(self, field: &str, message: &str) {
+        let res = self.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+        let messages = res.body["errors"][field].as_array();
+        assert!(
+            messages.into_iter().flatten().any(|m| m == message),
+            "expected {:?} for {:?}, body: {}",
+            message,
+            field,
+            res.body
+        );
+    }
+
+    /// Assert that the status and body match the snapshot in `tests/snapshots/<name>.json`.
+    ///
+    /// Values that change from run to run, like timestamps and IDs, are replaced with
+    /// placeholders first; see `redact()`.
+    ///
+    /// If the snapshot doesn't exist yet, it's written and the assertion passes, except in CI
+    /// (when `CI` is set) where a missing snapshot is an error. To accept a change to an existing
+    /// snapshot, run the tests with `UPDATE_SNAPSHOTS=1` and review the diff like any other.
+    #[track_caller]
+    pub fn assert_snapshot(self, name: &str) {
+        let mut body = self.body;
+        redact(&mut body);
+
+        let actual = serde_json::to_string_pretty(&json!({
+            "status": self.status.as_u16(),
+            "body": body,
+        }))
+        .unwrap()
+            + "\n";
+
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/snapshots")
+            .join(format!("{}.json", name));
+
+        let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+
+        match std::fs::read_to_string(&path) {
+            Ok(expected) if expected == actual => return,
+            Ok(expected) if !update => panic!(
+                "response doesn't match snapshot {}\n\
+                 (run with UPDATE_SNAPSHOTS=1 to accept the change)\n\
+                 --- expected\n{}\n+++ actual\n{}",
+                path.display(),
+                expected,
+                actual
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                assert!(
+                    std::env::var_os("CI").is_none(),
+                    "snapshot {} is missing; run the tests locally to create it\n{}",
+                    path.display(),
+                    actual
+                );
+            }
+            Err(e) if !update => panic!("failed to read {}: {}", path.display(), e),
+            _ => (),
+        }
+
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        eprintln!("wrote snapshot {}", path.display());
+    }
+}
+
+/// Replace the values in a response that differ between runs with placeholders, in place.
+///
+/// That's timestamps, tokens and cursors by the name of their field, and anything that looks
+/// like a UUID wherever it is.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let placeholder = match key.as_str() {
+                    "createdAt" | "updatedAt" => "[timestamp]",
+                    "token" => "[token]",
+                    "nextCursor" => "[cursor]",
+                    "shortUrl" => "[url]",
+                    _ => {
+                        redact(value);
+                        continue;
+                    }
+                };
+
+                if !value.is_null() {
+                    *value = placeholder.into();
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        Value::String(s) if uuid::Uuid::parse_str(s).is_ok() => *value = "[uuid]".into(),
+        _ => (),
+    }
+}
+
+impl Container {
+    /// Start a container and wait for Postgres in it to accept connections.
+    ///
+    /// Returns the container along with a URL to connect to it.
+    async fn start() -> (Self, String) {
+        let id = docker(&[
+            "run",
+            "--detach",
+            // In case we never get to remove it ourselves, e.g. because the test was killed.
+            "--rm",
+            "--env",
+            "POSTGRES_PASSWORD=password",
+            // Let Docker pick a free port, so tests can run in parallel.
+            "--publish",
+            "127.0.0.1::5432",
+            POSTGRES_IMAGE,
+        ])
+        .await;
+
+        // From here on, dropping `container` removes it even if we panic.
+        let container = Container { id };
+
+        // Prints e.g. `127.0.0.1:49153`.
+        let address = docker(&["port", &container.id, "5432/tcp"]).await;
+        let address = address
+            .lines()
+            .next()
+            .expect("container has no published port");
+        let database_url = format!("postgresql://postgres:password@{}/postgres", address);
+
+        // The image runs `initdb` and restarts Postgres before it's ready, and only listens on TCP
+        // after that, so the first connection that succeeds is the real thing.
+        let deadline = tokio::time::Instant::now() + CONTAINER_STARTUP_TIMEOUT;
+
+        loop {
+            match PgConnection::connect(&database_url).await {
+                Ok(conn) => {
+                    conn.close().await.ok();
+                    break;
+                }
+                Err(e) if tokio::time::Instant::now() > deadline => {
+                    panic!(
+                        "Postgres in container {} never started: {}",
+                        container.id, e
+                    )
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(250)).await,
+            }
+        }
+
+        (container, database_url)
+    }
+}
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        // Blocking is fine here, for the same reason it's fine in `TestApp::drop()`:
+        // this doesn't touch the runtime at all.
+        let res = std::process::Command::new("docker")
+            .args(["rm", "--force", "--volumes", &self.id])
+            .output();
+
+        match res {
+            Ok(output) if output.status.success() => (),
+            Ok(output) => eprintln!(
+                "failed to remove container {}: {}",
+                self.id,
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(e) => eprintln!("failed to remove container {}: {}", self.id, e),
+        }
+    }
+}
+
+/// Run `docker` with `args` and return its output, trimmed, panicking if it fails.
+async fn docker(args: &[&str]) -> String {
+    let output = tokio::process::Command::new("docker")
+        .args(args)
+        .output()
+        .await
+        .expect("failed to run `docker`; is it installed?");
+
+    assert!(
+        output.status.success(),
+        "`docker {}` failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        // Removing the container takes the database with it.
+        if self.container.is_some() {
+            return;
+        }
+
+        // We're most likely being dropped on the test's runtime, which we can't block on, and
+        // `Drop` can't be async. So we drop the database from a thread with a runtime of its own.
+        //
+        // `with (force)` disconnects our pools first, which would otherwise keep the database
+        // in use until the test's runtime shuts down. This runs even if the test panicked, so
+        // failed tests don't leave databases lying around either.
+        let database_url = self.database_url.clone();
+        let database_name = self.database_name.clone();
+
+        let res = std::thread::spawn(move || -> anyhow::Result<()> {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+
+            rt.block_on(async {
+                let mut conn = PgConnection::connect(&database_url).await?;
+                conn.execute(&*format!(
+                    "drop database if exists {} with (force)",
+                    database_name
+                ))
+                .await?;
+                Ok::<_, anyhow::Error>(())
+            })
+        })
+        .join();
+
+        if let Ok(Err(e)) = res {
+            eprintln!("failed to drop {}: {:#}", self.database_name, e);
+        }
+    }
+}
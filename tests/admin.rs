@@ -0,0 +1,116 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use realworld_axum_sqlx::admin::{self, AdminCommand, ArticleCommand, UserCommand};
+use realworld_axum_sqlx::db::users::Role;
+use realworld_axum_sqlx::test_util::{TestApp, PASSWORD};
+
+fn ban(username: &str) -> AdminCommand {
+    AdminCommand::User(UserCommand::Ban {
+        username: username.into(),
+    })
+}
+
+#[tokio::test]
+async fn test_ban() {
+    let app = TestApp::new().await;
+    let alice = app.create_user("alice").await;
+
+    admin::run(app.db(), ban("alice")).await.unwrap();
+
+    // Her token stops working straight away...
+    alice
+        .get("/api/user")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    // ...and she can't get a new one.
+    let login = json!({ "user": { "email": "alice@example.com", "password": PASSWORD } });
+    app.post_json("/api/users/login", login.clone())
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    // Banning twice is fine.
+    admin::run(app.db(), ban("alice")).await.unwrap();
+
+    admin::run(
+        app.db(),
+        AdminCommand::User(UserCommand::Unban {
+            username: "alice".into(),
+        }),
+    )
+    .await
+    .unwrap();
+
+    alice.get("/api/user").await.assert_ok();
+    app.post_json("/api/users/login", login).await.assert_ok();
+
+    assert!(admin::run(app.db(), ban("nobody")).await.is_err());
+}
+
+#[tokio::test]
+async fn test_set_role() {
+    let app = TestApp::new().await;
+    app.create_user("alice").await;
+
+    admin::run(
+        app.db(),
+        AdminCommand::User(UserCommand::SetRole {
+            username: "alice".into(),
+            role: Role::Moderator,
+        }),
+    )
+    .await
+    .unwrap();
+
+    let role: Role = sqlx::query_scalar(r#"select role from "user" where username = 'alice'"#)
+        .fetch_one(app.db().primary())
+        .await
+        .unwrap();
+    assert_eq!(role, Role::Moderator);
+}
+
+#[tokio::test]
+async fn test_delete_article() {
+    let app = TestApp::new().await;
+    let alice = app.create_user("alice").await;
+    let slug = alice.create_article("Spam", &[]).await;
+
+    let delete = |slug: &str| {
+        AdminCommand::Article(ArticleCommand::Delete {
+            slug: slug.to_string(),
+        })
+    };
+
+    admin::run(app.db(), delete(&slug)).await.unwrap();
+
+    app.get(&format!("/api/articles/{}", slug))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    assert!(admin::run(app.db(), delete(&slug)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_anonymize() {
+    let app = TestApp::new().await;
+    let alice = app.create_user("alice").await;
+    let slug = alice.create_article("Keep me", &[]).await;
+
+    let anonymize = |username: &str| {
+        AdminCommand::User(UserCommand::Anonymize {
+            username: username.into(),
+        })
+    };
+
+    admin::run(app.db(), anonymize("alice")).await.unwrap();
+
+    let body = app
+        .get(&format!("/api/articles/{}", slug))
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["author"]["username"], "[deleted]");
+
+    assert!(admin::run(app.db(), anonymize("alice")).await.is_err());
+    assert!(admin::run(app.db(), anonymize("[deleted]")).await.is_err());
+}
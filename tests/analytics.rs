@@ -0,0 +1,152 @@
+use axum::http::StatusCode;
+use serde_json::json;
+use time::OffsetDateTime;
+
+use realworld_axum_sqlx::test_util::TestApp;
+
+#[tokio::test]
+async fn test_analytics_export() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+    let carol = app.create_user("carol").await;
+
+    let popular = alice.create_article("Popular", &[]).await;
+    let unpopular = alice.create_article("Unpopular", &[]).await;
+    bob.create_article("Not Alice's", &[]).await;
+
+    bob.post("/api/profiles/alice/follow").await.assert_ok();
+    carol.post("/api/profiles/alice/follow").await.assert_ok();
+
+    bob.post(&format!("/api/articles/{}/favorite", popular))
+        .await
+        .assert_ok();
+    carol
+        .post(&format!("/api/articles/{}/favorite", popular))
+        .await
+        .assert_ok();
+
+    let res = alice
+        .get("/api/user/analytics/export")
+        .await
+        .assert_status(StatusCode::OK);
+
+    assert!(res.header("content-type").starts_with("text/csv"));
+    assert_eq!(
+        res.header("content-disposition"),
+        "attachment; filename=\"analytics.csv\""
+    );
+
+    // Follows are timestamped by Postgres, not the mock clock.
+    let today = OffsetDateTime::now_utc().date().format("%F");
+
+    assert_eq!(
+        res.body.as_str().unwrap(),
+        format!(
+            "metric,date,slug,value\r\n\
+             followers,{},,2\r\n\
+             favorites,,{},2\r\n\
+             favorites,,{},0\r\n",
+            today, popular, unpopular
+        )
+    );
+
+    // Carol has nothing to report.
+    let res = carol
+        .get("/api/user/analytics/export")
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(res.body, "metric,date,slug,value\r\n");
+
+    app.get("/api/user/analytics/export")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_analytics() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+    let carol = app.create_user("carol").await;
+
+    let slug = alice.create_article("Hello", &[]).await;
+
+    bob.post("/api/profiles/alice/follow").await.assert_ok();
+    carol.post("/api/profiles/alice/follow").await.assert_ok();
+
+    bob.post(&format!("/api/articles/{}/favorite", slug))
+        .await
+        .assert_ok();
+
+    bob.post_json(
+        &format!("/api/articles/{}/comments", slug),
+        json!({ "comment": { "body": "Nice" } }),
+    )
+    .await
+    .assert_ok();
+
+    // Bob followed Alice a while ago, outside the shortest window.
+    sqlx::query(
+        r#"
+            update follow set created_at = now() - interval '10 days'
+            where following_user_id = (select user_id from "user" where username = 'bob')
+        "#,
+    )
+    .execute(app.db().primary())
+    .await
+    .unwrap();
+
+    let body = alice.get("/api/user/analytics").await.assert_ok();
+    let analytics = &body["analytics"];
+
+    assert_eq!(analytics["window"], 30);
+
+    let days = analytics["days"].as_array().unwrap();
+    assert_eq!(days.len(), 30);
+
+    let today = OffsetDateTime::now_utc().date();
+    assert_eq!(days[29]["date"], today.format("%F"));
+    assert_eq!(
+        days[0]["date"],
+        (today - time::Duration::days(29)).format("%F")
+    );
+
+    assert_eq!(days[19]["newFollowers"], 1);
+    assert_eq!(days[19]["followers"], 1);
+    assert_eq!(days[18]["followers"], 0);
+
+    assert_eq!(
+        days[29],
+        json!({
+            "date": today.format("%F"),
+            "newFollowers": 1,
+            "followers": 2,
+            "favorites": 1,
+            "comments": 1,
+        })
+    );
+
+    assert_eq!(
+        analytics["articles"],
+        json!([{
+            "slug": slug,
+            "title": "Hello",
+            "favorites": 1,
+            "comments": 1,
+            "favoritesCount": 1,
+        }])
+    );
+
+    let body = alice.get("/api/user/analytics?window=7").await.assert_ok();
+    let days = body["analytics"]["days"].as_array().unwrap();
+    assert_eq!(days.len(), 7);
+    assert_eq!(days[0]["followers"], 1);
+
+    alice
+        .get("/api/user/analytics?window=365")
+        .await
+        .assert_unprocessable("window", "must be 7, 30 or 90 days");
+}
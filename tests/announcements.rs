@@ -0,0 +1,208 @@
+use axum::http::StatusCode;
+use serde_json::json;
+use time::{Duration, Format, OffsetDateTime};
+
+use realworld_axum_sqlx::clock::Clock;
+use realworld_axum_sqlx::db;
+use realworld_axum_sqlx::db::users::Role;
+use realworld_axum_sqlx::test_util::TestApp;
+
+fn timestamp(t: OffsetDateTime) -> String {
+    t.format(Format::Rfc3339)
+}
+
+#[tokio::test]
+async fn test_announcements() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let ada = app.create_user("ada").await;
+
+    db::users::set_role(app.db().primary(), "ada", Role::Admin)
+        .await
+        .unwrap();
+
+    let now = app.clock().now();
+
+    // Only admins can post them; moderators can't either.
+    db::users::set_role(app.db().primary(), "alice", Role::Moderator)
+        .await
+        .unwrap();
+
+    alice
+        .post_json(
+            "/api/admin/announcements",
+            json!({ "announcement": { "message": "Hello" } }),
+        )
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let body = ada
+        .post_json(
+            "/api/admin/announcements",
+            json!({ "announcement": { "message": "  New terms of service  " } }),
+        )
+        .await
+        .assert_ok();
+
+    let terms = &body["announcement"];
+    assert_eq!(terms["message"], "New terms of service");
+    assert_eq!(terms["severity"], "info");
+    assert_eq!(terms["endsAt"], json!(null));
+    let terms_id = terms["id"].as_str().unwrap().to_string();
+
+    // Maintenance tomorrow, announced an hour ahead.
+    let body = ada
+        .post_json(
+            "/api/admin/announcements",
+            json!({
+                "announcement": {
+                    "message": "Down for maintenance",
+                    "severity": "critical",
+                    "startsAt": timestamp(now + Duration::day() - Duration::hour()),
+                    "endsAt": timestamp(now + Duration::day() + Duration::hour()),
+                }
+            }),
+        )
+        .await
+        .assert_ok();
+
+    let maintenance_id = body["announcement"]["id"].as_str().unwrap().to_string();
+
+    ada.post_json(
+        "/api/admin/announcements",
+        json!({
+            "announcement": {
+                "message": "Backwards",
+                "startsAt": timestamp(now),
+                "endsAt": timestamp(now - Duration::hour()),
+            }
+        }),
+    )
+    .await
+    .assert_unprocessable("endsAt", "must be after startsAt");
+
+    ada.post_json(
+        "/api/admin/announcements",
+        json!({ "announcement": { "message": " " } }),
+    )
+    .await
+    .assert_unprocessable("message", "must be between 1 and 500 characters");
+
+    let body = app.get("/api/announcements/active").await.assert_ok();
+    assert_eq!(body["announcements"].as_array().unwrap().len(), 1);
+    assert_eq!(body["announcements"][0]["id"], terms_id.as_str());
+
+    // Admins see the scheduled one too.
+    let body = ada.get("/api/admin/announcements").await.assert_ok();
+    assert_eq!(body["items"][0]["id"], maintenance_id.as_str());
+    assert_eq!(body["items"][1]["id"], terms_id.as_str());
+    assert_eq!(body["total"], 2);
+    assert_eq!(body["nextCursor"], json!(null));
+
+    // One at a time.
+    let body = ada
+        .get("/api/admin/announcements?limit=1")
+        .await
+        .assert_ok();
+    assert_eq!(body["items"][0]["id"], maintenance_id.as_str());
+    assert_eq!(body["total"], 2);
+    let cursor = body["nextCursor"].as_str().unwrap();
+
+    let body = ada
+        .get(&format!(
+            "/api/admin/announcements?limit=1&cursor={}",
+            cursor
+        ))
+        .await
+        .assert_ok();
+    assert_eq!(body["items"][0]["id"], terms_id.as_str());
+    let cursor = body["nextCursor"].as_str().unwrap();
+
+    let body = ada
+        .get(&format!(
+            "/api/admin/announcements?limit=1&cursor={}",
+            cursor
+        ))
+        .await
+        .assert_ok();
+    assert_eq!(body["items"], json!([]));
+    assert_eq!(body["nextCursor"], json!(null));
+
+    ada.get("/api/admin/announcements?cursor=nonsense")
+        .await
+        .assert_unprocessable("cursor", "invalid cursor");
+
+    // A cursor from another listing is no good either, even though it's one we signed.
+    ada.create_article("Scheduled maintenance", &[]).await;
+    let body = app.get("/api/articles?limit=1").await.assert_ok();
+    let cursor = body["nextCursor"].as_str().unwrap();
+
+    ada.get(&format!("/api/admin/announcements?cursor={}", cursor))
+        .await
+        .assert_unprocessable("cursor", "invalid cursor");
+
+    // Dismissing one only hides it from that user.
+    alice
+        .post(&format!("/api/announcements/{}/dismiss", terms_id))
+        .await
+        .assert_ok();
+
+    // Twice is fine.
+    alice
+        .post(&format!("/api/announcements/{}/dismiss", terms_id))
+        .await
+        .assert_ok();
+
+    let body = alice.get("/api/announcements/active").await.assert_ok();
+    assert_eq!(body["announcements"], json!([]));
+
+    let body = ada.get("/api/announcements/active").await.assert_ok();
+    assert_eq!(body["announcements"].as_array().unwrap().len(), 1);
+
+    app.post_json(
+        "/api/announcements/00000000-0000-0000-0000-000000000000/dismiss",
+        json!({}),
+    )
+    .await
+    .assert_status(StatusCode::UNAUTHORIZED);
+
+    alice
+        .post("/api/announcements/00000000-0000-0000-0000-000000000000/dismiss")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    // Once the maintenance window starts, it goes up, ahead of the less severe one...
+    app.clock().advance(Duration::day());
+
+    let body = app.get("/api/announcements/active").await.assert_ok();
+    assert_eq!(body["announcements"][0]["id"], maintenance_id.as_str());
+    assert_eq!(body["announcements"][1]["id"], terms_id.as_str());
+
+    // ...and comes down by itself once it's over.
+    app.clock().advance(Duration::hour() * 2);
+
+    let body = app.get("/api/announcements/active").await.assert_ok();
+    assert_eq!(body["announcements"].as_array().unwrap().len(), 1);
+
+    let body = ada
+        .put_json(
+            &format!("/api/admin/announcements/{}", terms_id),
+            json!({ "announcement": { "message": "Updated terms", "severity": "warning" } }),
+        )
+        .await
+        .assert_ok();
+    assert_eq!(body["announcement"]["message"], "Updated terms");
+    assert_eq!(body["announcement"]["severity"], "warning");
+
+    ada.delete(&format!("/api/admin/announcements/{}", terms_id))
+        .await
+        .assert_ok();
+
+    ada.delete(&format!("/api/admin/announcements/{}", terms_id))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let body = app.get("/api/announcements/active").await.assert_ok();
+    assert_eq!(body["announcements"], json!([]));
+}
@@ -0,0 +1,1455 @@
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use realworld_axum_sqlx::db;
+use realworld_axum_sqlx::db::fixtures::{Fixtures, DEMO_PASSWORD};
+use realworld_axum_sqlx::db::users::Role;
+use realworld_axum_sqlx::test_util::TestApp;
+
+fn titles(body: &Value) -> Vec<&str> {
+    body["articles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|article| article["title"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_article_crud() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    let slug = alice
+        .create_article("How to train your dragon", &["dragons", "training"])
+        .await;
+    assert_eq!(slug, "how-to-train-your-dragon");
+
+    let body = app
+        .get("/api/articles/how-to-train-your-dragon")
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["title"], "How to train your dragon");
+    assert_eq!(body["article"]["author"]["username"], "alice");
+    assert_eq!(body["article"]["tagList"], json!(["dragons", "training"]));
+
+    let body = alice
+        .put_json(
+            "/api/articles/how-to-train-your-dragon",
+            json!({ "article": { "title": "How to tame your dragon" } }),
+        )
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["slug"], "how-to-tame-your-dragon");
+
+    bob.put_json(
+        "/api/articles/how-to-tame-your-dragon",
+        json!({ "article": { "title": "Mine now" } }),
+    )
+    .await
+    .assert_status(StatusCode::FORBIDDEN);
+
+    let body = app.get("/api/tags").await.assert_ok();
+    assert_eq!(body["tags"], json!(["dragons", "training"]));
+
+    bob.delete("/api/articles/how-to-tame-your-dragon")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    alice
+        .delete("/api/articles/how-to-tame-your-dragon")
+        .await
+        .assert_ok();
+
+    app.get("/api/articles/how-to-tame-your-dragon")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_list_articles() {
+    let app = TestApp::new().await;
+    let db = app.db().primary();
+
+    // None of this is what's under test, so it goes straight into the database.
+    let mut fixtures = Fixtures::new(0);
+    let alice = fixtures.user().username("alice").insert(db).await.unwrap();
+    let bob = fixtures.user().username("bob").insert(db).await.unwrap();
+
+    for (author, title, tags, age_secs) in [
+        (&alice, "First", &["rust"][..], 300.0),
+        (&bob, "Second", &["sql"], 200.0),
+        (&alice, "Third", &["rust", "sql"], 100.0),
+    ] {
+        fixtures
+            .article(author)
+            .title(title)
+            .with_tags(tags)
+            .age_secs(age_secs)
+            .insert(db)
+            .await
+            .unwrap();
+    }
+
+    let body = app.get("/api/articles").await.assert_ok();
+    assert_eq!(titles(&body), ["Third", "Second", "First"]);
+    assert_eq!(body["articlesCount"], 3);
+
+    let body = app.get("/api/articles?tag=rust").await.assert_ok();
+    assert_eq!(titles(&body), ["Third", "First"]);
+    assert_eq!(body["articlesCount"], 2);
+
+    let body = app.get("/api/articles?author=bob").await.assert_ok();
+    assert_eq!(titles(&body), ["Second"]);
+
+    let body = app.get("/api/articles?limit=1&offset=1").await.assert_ok();
+    assert_eq!(titles(&body), ["Second"]);
+    assert_eq!(body["articlesCount"], 3);
+
+    // Users from the factories can log in like anyone else.
+    let alice = app.login(&alice.email, DEMO_PASSWORD).await;
+    let body = alice.get("/api/articles?author=alice").await.assert_ok();
+    assert_eq!(titles(&body), ["Third", "First"]);
+}
+
+#[tokio::test]
+async fn test_list_articles_cursor() {
+    let app = TestApp::new().await;
+    let db = app.db().primary();
+
+    let mut fixtures = Fixtures::new(0);
+    let alice = fixtures.user().username("alice").insert(db).await.unwrap();
+
+    let mut expected = vec![];
+
+    for age_secs in 1..=5 {
+        let article = fixtures
+            .article(&alice)
+            .title(&format!("Article {}", age_secs))
+            .with_tags(&["rust"])
+            .age_secs(age_secs as f64)
+            .insert(db)
+            .await
+            .unwrap();
+        expected.push(article.title);
+    }
+
+    // Paging with `offset` and with the cursor give the same pages, like the feed.
+    let mut by_offset = vec![];
+    let mut by_cursor = vec![];
+    let mut cursor: Option<String> = None;
+
+    for offset in (0..6).step_by(2) {
+        let body = app
+            .get(&format!("/api/articles?tag=rust&limit=2&offset={}", offset))
+            .await
+            .assert_ok();
+        by_offset.extend(titles(&body).into_iter().map(String::from));
+
+        let uri = match &cursor {
+            Some(cursor) => format!("/api/articles?tag=rust&limit=2&cursor={}", cursor),
+            None => "/api/articles?tag=rust&limit=2".to_string(),
+        };
+        let body = app.get(&uri).await.assert_ok();
+        by_cursor.extend(titles(&body).into_iter().map(String::from));
+        cursor = body["nextCursor"].as_str().map(String::from);
+
+        // Still the total, not what's left after the cursor.
+        assert_eq!(body["articlesCount"], 5);
+    }
+
+    assert_eq!(by_offset, expected);
+    assert_eq!(by_cursor, expected);
+    assert_eq!(cursor, None);
+
+    // `after` is the same as `cursor`.
+    let body = app.get("/api/articles?limit=3").await.assert_ok();
+    let next = body["nextCursor"].as_str().unwrap();
+    let body = app
+        .get(&format!("/api/articles?after={}", next))
+        .await
+        .assert_ok();
+    assert_eq!(titles(&body), &expected[3..]);
+
+    app.get("/api/articles?cursor=nonsense")
+        .await
+        .assert_unprocessable("cursor", "invalid cursor");
+
+    // Too far in the future to be a date at all.
+    app.get("/api/articles?cursor=99999999999999999999999999_0bd6b8b44b8a11ee9a385b0a6a1d0e3c")
+        .await
+        .assert_unprocessable("cursor", "invalid cursor");
+}
+
+#[tokio::test]
+async fn test_content_limits() {
+    let app = TestApp::with_config(json!({
+        "max_title_len": 10,
+        "max_body_bytes": 8,
+        "max_tags_per_article": 2,
+        "max_tag_len": 4,
+    }))
+    .await;
+    let alice = app.create_user("alice").await;
+
+    let article = |title: &str, body: &str, tags: &[&str]| {
+        json!({
+            "article": {
+                "title": title,
+                "description": "A test article",
+                "body": body,
+                "tagList": tags,
+            }
+        })
+    };
+
+    // Right at the limits is fine. Titles count characters, bodies count bytes.
+    alice
+        .post_json(
+            "/api/articles",
+            article("Ünïcödé ok", "12345678", &["rust", "sql"]),
+        )
+        .await
+        .assert_ok();
+
+    alice
+        .post_json("/api/articles", article("Title", "ünïcödé", &[]))
+        .await
+        .assert_unprocessable("body", "body must be at most 8 bytes");
+
+    alice
+        .post_json("/api/articles", article("Much too long", "Body", &[]))
+        .await
+        .assert_unprocessable("title", "title must be at most 10 characters");
+
+    alice
+        .post_json("/api/articles", article("Title", "Body", &["a", "b", "c"]))
+        .await
+        .assert_unprocessable("tagList", "an article may have at most 2 tags");
+
+    alice
+        .post_json("/api/articles", article("Title", "Body", &["python"]))
+        .await
+        .assert_unprocessable("tagList", "tags must be at most 4 characters");
+
+    // Edits are held to the same limits.
+    alice
+        .put_json(
+            "/api/articles/unicode-ok",
+            json!({ "article": { "body": "Far too long now" } }),
+        )
+        .await
+        .assert_unprocessable("body", "body must be at most 8 bytes");
+}
+
+#[tokio::test]
+async fn test_favorites() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    alice.create_article("Favorite me", &[]).await;
+
+    let body = bob
+        .post("/api/articles/favorite-me/favorite")
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["favorited"], true);
+    assert_eq!(body["article"]["favoritesCount"], 1);
+
+    // Favoriting twice doesn't count twice.
+    let body = bob
+        .post("/api/articles/favorite-me/favorite")
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["favoritesCount"], 1);
+
+    let body = alice
+        .post("/api/articles/favorite-me/favorite")
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["favoritesCount"], 2);
+
+    let body = bob
+        .delete("/api/articles/favorite-me/favorite")
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["favorited"], false);
+    assert_eq!(body["article"]["favoritesCount"], 1);
+
+    // Nor does unfavoriting twice.
+    let body = bob
+        .delete("/api/articles/favorite-me/favorite")
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["favorited"], false);
+    assert_eq!(body["article"]["favoritesCount"], 1);
+
+    // The counts we return match what the trigger actually wrote.
+    let body = bob.get("/api/articles/favorite-me").await.assert_ok();
+    assert_eq!(body["article"]["favoritesCount"], 1);
+
+    bob.delete("/api/articles/no-such-article/favorite")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    bob.post("/api/articles/no-such-article/favorite")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_favoriters() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+    let carol = app.create_user("carol").await;
+
+    let slug = alice.create_article("Popular", &[]).await;
+    let path = format!("/api/articles/{}/favoriters", slug);
+
+    let body = app.get(&path).await.assert_ok();
+    assert_eq!(body["items"], json!([]));
+    assert_eq!(body["total"], 0);
+    assert_eq!(body["nextCursor"], Value::Null);
+
+    bob.post(&format!("/api/articles/{}/favorite", slug))
+        .await
+        .assert_ok();
+    carol
+        .post(&format!("/api/articles/{}/favorite", slug))
+        .await
+        .assert_ok();
+    alice.post("/api/profiles/bob/follow").await.assert_ok();
+
+    let usernames = |body: &Value| -> Vec<String> {
+        body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|profile| profile["username"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    // Most recent first, with whether the caller follows each of them.
+    let body = alice.get(&path).await.assert_ok();
+    assert_eq!(usernames(&body), ["carol", "bob"]);
+    assert_eq!(body["items"][0]["following"], false);
+    assert_eq!(body["items"][1]["following"], true);
+    assert_eq!(body["total"], 2);
+
+    // A page at a time.
+    let body = app.get(&format!("{}?limit=1", path)).await.assert_ok();
+    assert_eq!(usernames(&body), ["carol"]);
+    assert_eq!(body["total"], 2);
+
+    let cursor = body["nextCursor"].as_str().unwrap();
+    let body = app
+        .get(&format!("{}?limit=1&cursor={}", path, cursor))
+        .await
+        .assert_ok();
+    assert_eq!(usernames(&body), ["bob"]);
+    assert_eq!(body["items"][0]["following"], false);
+
+    let cursor = body["nextCursor"].as_str().unwrap();
+    let body = app
+        .get(&format!("{}?limit=1&cursor={}", path, cursor))
+        .await
+        .assert_ok();
+    assert_eq!(body["items"], json!([]));
+    assert_eq!(body["nextCursor"], Value::Null);
+
+    app.get(&format!("{}?cursor=garbage", path))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+    app.get("/api/articles/no-such-article/favoriters")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_favorited_is_per_article() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    alice.create_article("Liked", &[]).await;
+    alice.create_article("Not liked", &[]).await;
+
+    bob.post("/api/articles/liked/favorite").await.assert_ok();
+    bob.post("/api/profiles/alice/follow").await.assert_ok();
+
+    // Favoriting one article used to make every article look favorited.
+    let favorited = |body: &Value| -> Vec<(String, bool)> {
+        body["articles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|article| {
+                (
+                    article["slug"].as_str().unwrap().to_string(),
+                    article["favorited"].as_bool().unwrap(),
+                )
+            })
+            .collect()
+    };
+
+    let expected = vec![
+        ("not-liked".to_string(), false),
+        ("liked".to_string(), true),
+    ];
+
+    let body = bob.get("/api/articles").await.assert_ok();
+    assert_eq!(favorited(&body), expected);
+
+    let body = bob.get("/api/articles/feed").await.assert_ok();
+    assert_eq!(favorited(&body), expected);
+
+    let body = bob.get("/api/articles/not-liked").await.assert_ok();
+    assert_eq!(body["article"]["favorited"], false);
+
+    // Likewise, the filter only matches the articles they actually favorited.
+    let body = app.get("/api/articles?favorited=bob").await.assert_ok();
+    assert_eq!(titles(&body), ["Liked"]);
+    assert_eq!(body["articlesCount"], 1);
+}
+
+#[tokio::test]
+async fn test_search_articles() {
+    let app = TestApp::new().await;
+    let db = app.db().primary();
+
+    let mut fixtures = Fixtures::new(0);
+    let alice = fixtures.user().username("alice").insert(db).await.unwrap();
+
+    for (title, description, body, tags) in [
+        (
+            "Borrowing",
+            "About lifetimes",
+            "The borrow checker, again.",
+            &["rust"][..],
+        ),
+        (
+            "Cooking",
+            "Dinner tonight",
+            "Slow-cooked beans, and a word on borrowing pans.",
+            &[],
+        ),
+        ("Unrelated", "Nothing to see", "Really nothing.", &[]),
+        (
+            "Indexes",
+            "Borrowed from the manual",
+            "GIN and GiST.",
+            &["sql"],
+        ),
+    ] {
+        fixtures
+            .article(&alice)
+            .title(title)
+            .description(description)
+            .body(body)
+            .with_tags(tags)
+            .insert(db)
+            .await
+            .unwrap();
+    }
+
+    // Stemming matches "borrowing" and "borrowed" both, and a match in the title beats one in
+    // the description, which beats one in the body.
+    let body = app.get("/api/articles/search?q=borrow").await.assert_ok();
+    assert_eq!(titles(&body), ["Borrowing", "Indexes", "Cooking"]);
+    assert_eq!(body["articlesCount"], 3);
+
+    let body = app
+        .get("/api/articles/search?q=borrow&limit=1&offset=1")
+        .await
+        .assert_ok();
+    assert_eq!(titles(&body), ["Indexes"]);
+    assert_eq!(body["articlesCount"], 3);
+
+    let body = app
+        .get("/api/articles/search?q=borrow%20-beans")
+        .await
+        .assert_ok();
+    assert_eq!(body["articlesCount"], 2);
+
+    // The best match, for bob to favorite.
+    let body = app.get("/api/articles/search?q=borrow").await.assert_ok();
+
+    let bob = app.create_user("bob").await;
+    let slug = body["articles"][0]["slug"].as_str().unwrap();
+    bob.post(&format!("/api/articles/{}/favorite", slug))
+        .await
+        .assert_ok();
+    bob.post("/api/profiles/alice/follow").await.assert_ok();
+    bob.post("/api/tags/sql/mute").await.assert_ok();
+
+    let body = bob.get("/api/articles/search?q=borrow").await.assert_ok();
+    assert_eq!(titles(&body), ["Borrowing", "Cooking"]);
+    assert_eq!(body["articles"][0]["favorited"], true);
+    assert_eq!(body["articles"][1]["favorited"], false);
+    assert_eq!(body["articles"][0]["author"]["following"], true);
+
+    app.get("/api/articles/search?q=%20")
+        .await
+        .assert_unprocessable("q", "search query must be between 1 and 200 characters");
+
+    let body = app.get("/api/articles/search?q=nonsense").await.assert_ok();
+    assert_eq!(titles(&body), Vec::<&str>::new());
+    assert_eq!(body["articlesCount"], 0);
+    assert_eq!(body["suggestions"], json!([]));
+
+    // A search that finds enough doesn't bother with suggestions.
+    let body = app.get("/api/articles/search?q=borrow").await.assert_ok();
+    assert!(body.get("suggestions").is_none(), "{}", body);
+
+    // A typo in a title, or in a tag.
+    let body = app.get("/api/articles/search?q=indxes").await.assert_ok();
+    assert_eq!(body["articlesCount"], 0);
+    assert_eq!(body["suggestions"], json!(["Indexes"]));
+
+    let body = app.get("/api/articles/search?q=sqll").await.assert_ok();
+    assert_eq!(body["suggestions"], json!(["sql"]));
+
+    // Bob muted `sql`, so it'd be no use to Bob.
+    let body = bob.get("/api/articles/search?q=sqll").await.assert_ok();
+    assert_eq!(body["suggestions"], json!([]));
+}
+
+#[tokio::test]
+async fn test_anonymous_article_cache() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    let slug = alice.create_article("Cache me", &[]).await;
+    let path = format!("/api/articles/{}", slug);
+
+    // Fill the cache.
+    let body = app.get(&path).await.assert_ok();
+    assert_eq!(body["article"]["favoritesCount"], 0);
+
+    // Anything that changes the article through this instance shows up straight away.
+    bob.post(&format!("{}/favorite", path)).await.assert_ok();
+
+    let body = app.get(&path).await.assert_ok();
+    assert_eq!(body["article"]["favoritesCount"], 1);
+    // Anonymous users don't get to see who favorited it.
+    assert_eq!(body["article"]["favorited"], false);
+
+    // Whereas logged-in users bypass the cache.
+    let body = bob.get(&path).await.assert_ok();
+    assert_eq!(body["article"]["favorited"], true);
+
+    // Edits too, once they've been committed.
+    alice
+        .put_json(&path, json!({ "article": { "description": "Edited" } }))
+        .await
+        .assert_ok();
+
+    let body = app.get(&path).await.assert_ok();
+    assert_eq!(body["article"]["description"], "Edited");
+
+    // Under the old slug and the new one.
+    alice
+        .put_json(&path, json!({ "article": { "title": "Cached" } }))
+        .await
+        .assert_ok();
+
+    app.get(&path).await.assert_status(StatusCode::NOT_FOUND);
+
+    let body = app.get("/api/articles/cached").await.assert_ok();
+    assert_eq!(body["article"]["title"], "Cached");
+
+    alice.delete("/api/articles/cached").await.assert_ok();
+
+    app.get("/api/articles/cached")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_permalink() {
+    let app = TestApp::with_config(json!({ "base_url": "https://api.example.com/" })).await;
+    let alice = app.create_user("alice").await;
+
+    let body = alice
+        .post_json(
+            "/api/articles",
+            json!({
+                "article": {
+                    "title": "First draft",
+                    "description": "A test article",
+                    "body": "Hello, world!",
+                    "tagList": [],
+                }
+            }),
+        )
+        .await
+        .assert_ok();
+
+    let short_url = body["article"]["shortUrl"].as_str().unwrap();
+    let path = short_url
+        .strip_prefix("https://api.example.com")
+        .unwrap_or_else(|| panic!("unexpected shortUrl: {}", short_url));
+
+    // The short URL stays the same when the slug changes, and follows it.
+    let body = alice
+        .put_json(
+            "/api/articles/first-draft",
+            json!({ "article": { "title": "Final version" } }),
+        )
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["shortUrl"], short_url);
+
+    let res = app
+        .get(path)
+        .await
+        .assert_status(StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        res.header("location"),
+        "https://api.example.com/api/articles/final-version"
+    );
+
+    alice
+        .delete("/api/articles/final-version")
+        .await
+        .assert_ok();
+
+    app.get(path).await.assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_edit_lock() {
+    let app = TestApp::new().await;
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    let path = format!(
+        "/api/articles/{}",
+        alice.create_article("Locked", &[]).await
+    );
+    let lock = format!("{}/lock", path);
+    let edit = json!({ "article": { "body": "Edited" } });
+
+    let body = alice.post(&lock).await.assert_ok();
+    assert_eq!(body["lock"]["username"], "alice");
+
+    // Holding the lock doesn't get in your own way.
+    alice.put_json(&path, edit.clone()).await.assert_ok();
+
+    // Only someone who could edit the article can lock it.
+    bob.post(&lock).await.assert_status(StatusCode::FORBIDDEN);
+
+    // Bob isn't a co-author (see `test_coauthors()`), so pretend they have it open anyway.
+    alice.delete(&lock).await.assert_ok();
+
+    let expires_at = time::OffsetDateTime::now_utc() + time::Duration::minutes(5);
+    sqlx::query(
+        r#"
+            insert into article_lock (article_id, user_id, expires_at)
+            select article_id, (select user_id from "user" where username = 'bob'), $1
+            from article where slug = 'locked'
+        "#,
+    )
+    .bind(expires_at)
+    .execute(app.db().primary())
+    .await
+    .unwrap();
+
+    let res = alice
+        .put_json(&path, edit.clone())
+        .await
+        .assert_status(StatusCode::LOCKED);
+    assert_eq!(res.body["lock"]["username"], "bob");
+
+    let res = alice.post(&lock).await.assert_status(StatusCode::LOCKED);
+    assert_eq!(res.body["lock"]["username"], "bob");
+
+    // Releasing a lock you don't hold does nothing.
+    alice.delete(&lock).await.assert_ok();
+    alice
+        .put_json(&path, edit.clone())
+        .await
+        .assert_status(StatusCode::LOCKED);
+
+    // Once it expires, it's as good as gone.
+    app.clock().advance(time::Duration::minutes(6));
+    alice.put_json(&path, edit).await.assert_ok();
+
+    let body = alice.post(&lock).await.assert_ok();
+    assert_eq!(body["lock"]["username"], "alice");
+
+    app.post_json(&lock, json!({}))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    alice
+        .post("/api/articles/no-such-article/lock")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_coauthors() {
+    let app = TestApp::new().await;
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+    let carol = app.create_user("carol").await;
+
+    let path = format!(
+        "/api/articles/{}",
+        alice.create_article("Written together", &[]).await
+    );
+    let invite = format!("{}/authors/invite", path);
+    let edit = json!({ "article": { "body": "Edited by Bob" } });
+
+    bob.put_json(&path, edit.clone())
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let body = alice
+        .post_json(&invite, json!({ "invitation": { "username": "bob" } }))
+        .await
+        .assert_ok();
+    assert_eq!(body["invitation"]["inviter"], "alice");
+    assert_eq!(body["invitation"]["invitee"], "bob");
+    assert_eq!(body["invitation"]["title"], "Written together");
+
+    let sent = app.mailer().take();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "bob@example.com");
+    assert!(
+        sent[0].body.contains("Written together"),
+        "{}",
+        sent[0].body
+    );
+
+    // Being invited isn't enough to edit it.
+    bob.put_json(&path, edit.clone())
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    alice
+        .post_json(&invite, json!({ "invitation": { "username": "bob" } }))
+        .await
+        .assert_unprocessable("username", "has already been invited");
+    alice
+        .post_json(&invite, json!({ "invitation": { "username": "alice" } }))
+        .await
+        .assert_unprocessable("username", "is already an author of this article");
+    alice
+        .post_json(&invite, json!({ "invitation": { "username": "nobody" } }))
+        .await
+        .assert_unprocessable("username", "no such user");
+    bob.post_json(&invite, json!({ "invitation": { "username": "carol" } }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let body = bob.get("/api/invitations").await.assert_ok();
+    let id = body["invitations"][0]["id"].as_str().unwrap().to_string();
+    assert_eq!(body["invitations"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        carol.get("/api/invitations").await.assert_ok()["invitations"],
+        json!([])
+    );
+
+    // Only Bob can accept it.
+    let accept = format!("/api/invitations/{}/accept", id);
+    carol
+        .post(&accept)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    alice
+        .post(&accept)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let body = bob.post(&accept).await.assert_ok();
+    assert_eq!(body["article"]["author"]["username"], "alice");
+    assert_eq!(
+        bob.get("/api/invitations").await.assert_ok()["invitations"],
+        json!([])
+    );
+    bob.post(&accept).await.assert_status(StatusCode::NOT_FOUND);
+
+    // Now Bob can edit it and lock it, but it's still Alice's.
+    let body = bob.put_json(&path, edit).await.assert_ok();
+    assert_eq!(body["article"]["body"], "Edited by Bob");
+    assert_eq!(body["article"]["author"]["username"], "alice");
+
+    let body = bob.post(&format!("{}/lock", path)).await.assert_ok();
+    assert_eq!(body["lock"]["username"], "bob");
+    alice
+        .put_json(&path, json!({ "article": { "body": "Edited by Alice" } }))
+        .await
+        .assert_status(StatusCode::LOCKED);
+    bob.delete(&format!("{}/lock", path)).await.assert_ok();
+
+    bob.delete(&path).await.assert_status(StatusCode::FORBIDDEN);
+
+    alice
+        .post_json(&invite, json!({ "invitation": { "username": "bob" } }))
+        .await
+        .assert_unprocessable("username", "is already an author of this article");
+
+    // Carol declines, and the next invitation Alice takes back.
+    alice
+        .post_json(&invite, json!({ "invitation": { "username": "carol" } }))
+        .await
+        .assert_ok();
+    let body = carol.get("/api/invitations").await.assert_ok();
+    let id = body["invitations"][0]["id"].as_str().unwrap().to_string();
+    bob.delete(&format!("/api/invitations/{}", id))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    carol
+        .delete(&format!("/api/invitations/{}", id))
+        .await
+        .assert_ok();
+
+    let body = alice
+        .post_json(&invite, json!({ "invitation": { "username": "carol" } }))
+        .await
+        .assert_ok();
+    let id = body["invitation"]["id"].as_str().unwrap().to_string();
+    alice
+        .delete(&format!("/api/invitations/{}", id))
+        .await
+        .assert_ok();
+    carol
+        .post(&format!("/api/invitations/{}/accept", id))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    carol
+        .put_json(&path, json!({ "article": { "body": "Edited by Carol" } }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_comments() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    alice.create_article("Comment on me", &[]).await;
+
+    let body = bob
+        .post_json(
+            "/api/articles/comment-on-me/comments",
+            json!({ "comment": { "body": "First!" } }),
+        )
+        .await
+        .assert_ok();
+    assert_eq!(body["comment"]["author"]["username"], "bob");
+    let comment_uri = format!(
+        "/api/articles/comment-on-me/comments/{}",
+        body["comment"]["id"]
+    );
+
+    let body = app
+        .get("/api/articles/comment-on-me/comments")
+        .await
+        .assert_ok();
+    assert_eq!(body["comments"].as_array().unwrap().len(), 1);
+    assert_eq!(body["comments"][0]["body"], "First!");
+
+    alice
+        .delete(&comment_uri)
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    bob.delete(&comment_uri).await.assert_ok();
+
+    bob.delete(&comment_uri)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let body = app
+        .get("/api/articles/comment-on-me/comments")
+        .await
+        .assert_ok();
+    assert!(body["comments"].as_array().unwrap().is_empty());
+
+    // Not an empty list.
+    app.get("/api/articles/no-such-article/comments")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_tags() {
+    let app = TestApp::new().await;
+    let alice = app.create_user("alice").await;
+
+    let body = app.get("/api/tags").await.assert_ok();
+    assert_eq!(body["tags"], json!([]));
+
+    let first = alice.create_article("First", &["rust", "sql"]).await;
+    alice.create_article("Second", &["axum", "rust"]).await;
+
+    // The tags are cached, but publishing an article refreshes them straight away.
+    let body = app.get("/api/tags").await.assert_ok();
+    assert_eq!(body["tags"], json!(["axum", "rust", "sql"]));
+    assert_eq!(
+        body["articlesCount"],
+        json!({ "axum": 1, "rust": 2, "sql": 1 })
+    );
+
+    // And so does deleting one. A tag nobody uses any more is left out, not listed with 0.
+    alice
+        .delete(&format!("/api/articles/{}", first))
+        .await
+        .assert_ok();
+
+    let body = app.get("/api/tags").await.assert_ok();
+    assert_eq!(body["tags"], json!(["axum", "rust"]));
+    assert_eq!(body["articlesCount"], json!({ "axum": 1, "rust": 1 }));
+
+    // Each tag is only stored once per article, however many times it's given.
+    let body = alice
+        .post_json(
+            "/api/articles",
+            json!({
+                "article": {
+                    "title": "Third",
+                    "description": "Again and again",
+                    "body": "And again.",
+                    "tagList": ["rust", "axum", "rust"],
+                }
+            }),
+        )
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["tagList"], json!(["axum", "rust"]));
+
+    let body = app.get("/api/tags").await.assert_ok();
+    assert_eq!(body["articlesCount"], json!({ "axum": 2, "rust": 2 }));
+}
+
+#[tokio::test]
+async fn test_muted_tags() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    bob.create_article("Dragons", &["dragons"]).await;
+    bob.create_article("Dragons and knights", &["dragons", "knights"])
+        .await;
+    bob.create_article("Knights", &["knights"]).await;
+    alice.post("/api/profiles/bob/follow").await.assert_ok();
+
+    let body = alice.post("/api/tags/dragons/mute").await.assert_ok();
+    assert_eq!(body["tags"], json!(["dragons"]));
+    // Muting it again is fine.
+    alice.post("/api/tags/dragons/mute").await.assert_ok();
+
+    let body = alice.get("/api/articles").await.assert_ok();
+    assert_eq!(titles(&body), ["Knights"]);
+    assert_eq!(body["articlesCount"], 1);
+
+    let body = alice.get("/api/articles/feed").await.assert_ok();
+    assert_eq!(titles(&body), ["Knights"]);
+    assert_eq!(body["articlesCount"], 1);
+
+    // Asking for a muted tag by name still shows it.
+    let body = alice.get("/api/articles?tag=dragons").await.assert_ok();
+    assert_eq!(titles(&body), ["Dragons and knights", "Dragons"]);
+
+    // Other filters don't.
+    let body = alice.get("/api/articles?tag=knights").await.assert_ok();
+    assert_eq!(titles(&body), ["Knights"]);
+
+    // It's only muted for Alice.
+    let body = bob.get("/api/articles").await.assert_ok();
+    assert_eq!(body["articlesCount"], 3);
+    let body = app.get("/api/articles").await.assert_ok();
+    assert_eq!(body["articlesCount"], 3);
+
+    let body = alice.get("/api/tags/muted").await.assert_ok();
+    assert_eq!(body["tags"], json!(["dragons"]));
+
+    let body = alice.delete("/api/tags/dragons/mute").await.assert_ok();
+    assert_eq!(body["tags"], json!([]));
+
+    let body = alice.get("/api/articles/feed").await.assert_ok();
+    assert_eq!(body["articlesCount"], 3);
+
+    alice
+        .post(&format!("/api/tags/{}/mute", "x".repeat(51)))
+        .await
+        .assert_unprocessable("tag", "tags must be between 1 and 50 characters");
+
+    app.post_json("/api/tags/dragons/mute", json!({}))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_merge_tags() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+    let moderator = app.create_user("morgan").await;
+
+    db::users::set_role(app.db().primary(), "morgan", Role::Moderator)
+        .await
+        .unwrap();
+
+    let both = alice
+        .create_article("Rust and SQL", &["Rust", "rustlang", "sql"])
+        .await;
+    alice.create_article("Rust", &["rust"]).await;
+    alice.create_article("SQL", &["sql"]).await;
+    bob.post("/api/tags/rustlang/mute").await.assert_ok();
+
+    let merge = |dry_run: bool| json!({ "merge": { "from": ["Rust", "rustlang", "rust"], "to": "rust", "dryRun": dry_run } });
+
+    // Only moderators can merge tags.
+    alice
+        .post_json("/api/admin/tags/merge", merge(false))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    // A dry run only counts the articles that would change.
+    let body = moderator
+        .post_json("/api/admin/tags/merge", merge(true))
+        .await
+        .assert_ok();
+    assert_eq!(body["merge"]["articlesCount"], 1);
+    assert_eq!(body["merge"]["from"], json!(["Rust", "rustlang"]));
+
+    let body = app.get("/api/tags").await.assert_ok();
+    assert_eq!(body["tags"], json!(["Rust", "rust", "rustlang", "sql"]));
+
+    // Warm the cache for anonymous visitors, so we know merging clears it.
+    app.get(&format!("/api/articles/{}", both))
+        .await
+        .assert_ok();
+
+    let body = moderator
+        .post_json("/api/admin/tags/merge", merge(false))
+        .await
+        .assert_ok();
+    assert_eq!(body["merge"]["articlesCount"], 1);
+    assert_eq!(body["merge"]["dryRun"], false);
+
+    let body = app.get("/api/tags").await.assert_ok();
+    assert_eq!(body["tags"], json!(["rust", "sql"]));
+    assert_eq!(body["articlesCount"], json!({ "rust": 2, "sql": 2 }));
+
+    let body = app
+        .get(&format!("/api/articles/{}", both))
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["tagList"], json!(["rust", "sql"]));
+
+    let body = app.get("/api/articles?tag=rust").await.assert_ok();
+    assert_eq!(titles(&body), ["Rust", "Rust and SQL"]);
+
+    // Bob had muted one of the old tags, so now the new one is muted for Bob instead.
+    let body = bob.get("/api/tags/muted").await.assert_ok();
+    assert_eq!(body["tags"], json!(["rust"]));
+
+    moderator
+        .post_json(
+            "/api/admin/tags/merge",
+            json!({ "merge": { "from": ["rust"], "to": "rust" } }),
+        )
+        .await
+        .assert_unprocessable("from", "must list at least one tag other than `to`");
+
+    moderator
+        .post_json(
+            "/api/admin/tags/merge",
+            json!({ "merge": { "from": ["rust"], "to": "" } }),
+        )
+        .await
+        .assert_unprocessable("to", "tags must be between 1 and 50 characters");
+}
+
+#[tokio::test]
+async fn test_tag_metadata() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+    let moderator = app.create_user("morgan").await;
+
+    db::users::set_role(app.db().primary(), "morgan", Role::Moderator)
+        .await
+        .unwrap();
+
+    alice.create_article("First", &["rust"]).await;
+    alice.create_article("Second", &["rust", "sql"]).await;
+    bob.create_article("Third", &["rust"]).await;
+
+    let body = app.get("/api/tags/rust").await.assert_ok();
+    assert_eq!(
+        body["tag"],
+        json!({
+            "name": "rust",
+            "description": null,
+            "image": null,
+            "articlesCount": 3,
+            "authorsCount": 2,
+        })
+    );
+
+    app.get("/api/tags/dragons")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    // This isn't a tag.
+    let body = alice.get("/api/tags/muted").await.assert_ok();
+    assert_eq!(body["tags"], json!([]));
+
+    let metadata = json!({
+        "tag": {
+            "description": "  A language empowering everyone to build reliable software.  ",
+            "image": "https://example.com/ferris.png",
+        }
+    });
+
+    alice
+        .put_json("/api/admin/tags/rust", metadata.clone())
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let body = moderator
+        .put_json("/api/admin/tags/rust", metadata)
+        .await
+        .assert_ok();
+    assert_eq!(
+        body["tag"]["description"],
+        "A language empowering everyone to build reliable software."
+    );
+
+    let body = app.get("/api/tags/rust").await.assert_ok();
+    assert_eq!(body["tag"]["image"], "https://example.com/ferris.png");
+    assert_eq!(body["tag"]["articlesCount"], 3);
+
+    // Tags can have a page before anyone uses them.
+    moderator
+        .put_json(
+            "/api/admin/tags/dragons",
+            json!({ "tag": { "description": "Here be dragons." } }),
+        )
+        .await
+        .assert_ok();
+
+    let body = app.get("/api/tags/dragons").await.assert_ok();
+    assert_eq!(body["tag"]["description"], "Here be dragons.");
+    assert_eq!(body["tag"]["image"], json!(null));
+    assert_eq!(body["tag"]["articlesCount"], 0);
+
+    // Merging a tag into one without metadata takes its metadata along.
+    moderator
+        .post_json(
+            "/api/admin/tags/merge",
+            json!({ "merge": { "from": ["dragons"], "to": "wyverns" } }),
+        )
+        .await
+        .assert_ok();
+
+    app.get("/api/tags/dragons")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    let body = app.get("/api/tags/wyverns").await.assert_ok();
+    assert_eq!(body["tag"]["description"], "Here be dragons.");
+
+    moderator
+        .put_json(
+            "/api/admin/tags/rust",
+            json!({ "tag": { "image": "javascript:alert(1)" } }),
+        )
+        .await
+        .assert_unprocessable("image", "image must be an http:// or https:// URL");
+
+    moderator
+        .put_json(
+            "/api/admin/tags/rust",
+            json!({ "tag": { "description": "x".repeat(1001) } }),
+        )
+        .await
+        .assert_unprocessable("description", "description must be at most 1000 characters");
+}
+
+#[tokio::test]
+async fn test_takedown() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+    let moderator = app.create_user("morgan").await;
+
+    db::users::set_role(app.db().primary(), "morgan", Role::Moderator)
+        .await
+        .unwrap();
+
+    let slug = alice.create_article("Spam", &["spam"]).await;
+    bob.post("/api/profiles/alice/follow").await.assert_ok();
+
+    let path = format!("/api/articles/{}", slug);
+    let takedown = format!("/api/admin/articles/{}/takedown", slug);
+    let appeal = format!("{}/appeal", path);
+    let reason = json!({ "takedown": { "reason": "This is spam." } });
+
+    // Only moderators can take articles down.
+    bob.post_json(&takedown, reason.clone())
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    // Warm the cache for anonymous visitors, so we know taking it down clears it.
+    app.get(&path).await.assert_ok();
+
+    let body = moderator.post_json(&takedown, reason).await.assert_ok();
+    assert_eq!(body["takedown"]["reason"], "This is spam.");
+
+    // Now only Alice can see it, along with why.
+    app.get(&path).await.assert_status(StatusCode::NOT_FOUND);
+    bob.get(&path).await.assert_status(StatusCode::NOT_FOUND);
+    bob.post(&format!("{}/favorite", path))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let body = alice.get(&path).await.assert_ok();
+    assert_eq!(body["article"]["takedownReason"], "This is spam.");
+
+    for user in [&alice, &bob] {
+        let body = user.get("/api/articles").await.assert_ok();
+        assert_eq!(body["articlesCount"], 0);
+        assert!(titles(&body).is_empty());
+    }
+
+    let body = bob.get("/api/articles/feed").await.assert_ok();
+    assert!(titles(&body).is_empty());
+
+    // Only Alice can appeal.
+    let appeal_body = json!({ "appeal": { "body": "It's not spam, it's a recipe." } });
+    bob.post_json(&appeal, appeal_body.clone())
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let body = moderator.get("/api/admin/appeals").await.assert_ok();
+    assert_eq!(body["takedowns"], json!([]));
+
+    let body = alice.post_json(&appeal, appeal_body).await.assert_ok();
+    assert_eq!(body["takedown"]["appeal"], "It's not spam, it's a recipe.");
+
+    let body = moderator.get("/api/admin/appeals").await.assert_ok();
+    assert_eq!(body["takedowns"][0]["slug"], slug);
+    bob.get("/api/admin/appeals")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    // Upholding the takedown dismisses the appeal.
+    let body = moderator
+        .post_json(
+            &takedown,
+            json!({ "takedown": { "reason": "It's still spam." } }),
+        )
+        .await
+        .assert_ok();
+    assert!(body["takedown"].get("appeal").is_none());
+
+    let body = moderator.get("/api/admin/appeals").await.assert_ok();
+    assert_eq!(body["takedowns"], json!([]));
+
+    moderator.delete(&takedown).await.assert_ok();
+
+    let body = app.get(&path).await.assert_ok();
+    assert!(body["article"].get("takedownReason").is_none());
+    let body = bob.get("/api/articles/feed").await.assert_ok();
+    assert_eq!(titles(&body), ["Spam"]);
+
+    // It's back up, so there's nothing to restore or appeal.
+    moderator
+        .delete(&takedown)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    alice
+        .post_json(&appeal, json!({ "appeal": { "body": "Hello?" } }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_article_preview() {
+    let app = TestApp::with_config(json!({ "base_url": "https://example.com" })).await;
+    let alice = app.create_user("alice").await;
+
+    let slug = alice
+        .create_article("Cats & <dogs>", &["pets", "cats"])
+        .await;
+
+    let res = app.get(&format!("/og/{}", slug)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.header("content-type").starts_with("text/html"));
+    assert_eq!(res.header("cache-control"), "public, max-age=300");
+
+    let html = res.body.as_str().unwrap();
+    assert!(html.contains(r#"<meta property="og:title" content="Cats &amp; &lt;dogs&gt;">"#));
+    assert!(html.contains(r#"<meta property="article:author" content="alice">"#));
+    assert!(html.contains(r#"<meta property="article:tag" content="pets">"#));
+    assert!(html.contains(r#"<meta property="og:url" content="https://example.com/a/"#));
+    // Alice has no avatar.
+    assert!(!html.contains("og:image"));
+
+    app.get("/og/no-such-article")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_oembed() {
+    let app = TestApp::with_config(json!({ "base_url": "https://example.com" })).await;
+    let alice = app.create_user("alice").await;
+
+    let slug = alice.create_article("Cats & <dogs>", &["pets"]).await;
+
+    let short_url = app.get(&format!("/api/articles/{}", slug)).await.body["article"]["shortUrl"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let oembed = |url: &str| {
+        format!(
+            "/api/oembed?{}",
+            url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("url", url)
+                .finish()
+        )
+    };
+
+    for url in [
+        short_url.clone(),
+        format!("https://example.com/api/articles/{}", slug),
+        format!("https://example.com/og/{}", slug),
+    ] {
+        let body = app.get(&oembed(&url)).await.assert_ok();
+        assert_eq!(body["version"], "1.0");
+        assert_eq!(body["type"], "rich");
+        assert_eq!(body["title"], "Cats & <dogs>");
+        assert_eq!(body["author_name"], "alice");
+        assert_eq!(body["author_url"], "https://example.com/api/profiles/alice");
+        assert_eq!(body["provider_url"], "https://example.com");
+        assert_eq!(body["width"], 600);
+
+        let html = body["html"].as_str().unwrap();
+        assert!(html.contains(&format!(r#"<a href="{}">"#, short_url)));
+        assert!(html.contains("Cats &amp; &lt;dogs&gt;"));
+    }
+
+    let body = app
+        .get(&format!("{}&maxwidth=320", oembed(&short_url)))
+        .await
+        .assert_ok();
+    assert_eq!(body["width"], 320);
+
+    app.get(&format!("{}&format=xml", oembed(&short_url)))
+        .await
+        .assert_status(StatusCode::NOT_IMPLEMENTED);
+
+    // Someone else's site.
+    app.get(&oembed(&format!("https://example.org/og/{}", slug)))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    app.get(&oembed("https://example.com/og/no-such-article"))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_article_links() {
+    let app = TestApp::with_config(json!({ "base_url": "https://example.com" })).await;
+
+    let alice = app.create_user("alice").await;
+    let moderator = app.create_user("morgan").await;
+
+    db::users::set_role(app.db().primary(), "morgan", Role::Moderator)
+        .await
+        .unwrap();
+
+    let article = alice
+        .post_json(
+            "/api/articles",
+            json!({
+                "article": {
+                    "title": "Great deals",
+                    "description": "You won't believe them",
+                    "body": "[Buy now](https://spam.example/buy) ![](https://cdn.example/ad.png) \
+                        [more](https://example.com/a/123)",
+                    "tagList": [],
+                }
+            }),
+        )
+        .await
+        .assert_ok();
+
+    let slug = article["article"]["slug"].as_str().unwrap();
+
+    // Only moderators can list them.
+    alice
+        .get("/api/admin/links")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let urls = |body: &Value| {
+        let mut urls: Vec<String> = body["links"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|link| {
+                assert_eq!(link["slug"], slug);
+                format!(
+                    "{} {}",
+                    link["kind"].as_str().unwrap(),
+                    link["url"].as_str().unwrap()
+                )
+            })
+            .collect();
+        urls.sort();
+        urls
+    };
+
+    // Links back to us aren't external.
+    let body = moderator.get("/api/admin/links").await.assert_ok();
+    assert_eq!(
+        urls(&body),
+        [
+            "image https://cdn.example/ad.png",
+            "link https://spam.example/buy"
+        ]
+    );
+
+    let body = moderator
+        .get("/api/admin/links?host=SPAM.example")
+        .await
+        .assert_ok();
+    assert_eq!(urls(&body), ["link https://spam.example/buy"]);
+
+    // Editing the body replaces them.
+    alice
+        .put_json(
+            &format!("/api/articles/{}", slug),
+            json!({ "article": { "body": "[Sorry](https://spam.example/buy) for the spam" } }),
+        )
+        .await
+        .assert_ok();
+
+    let body = moderator.get("/api/admin/links").await.assert_ok();
+    assert_eq!(urls(&body), ["link https://spam.example/buy"]);
+}
@@ -0,0 +1,94 @@
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use serde_json::{json, Value};
+
+use realworld_axum_sqlx::db;
+use realworld_axum_sqlx::db::users::Role;
+use realworld_axum_sqlx::test_util::TestApp;
+
+fn report(content_type: &str, body: Value) -> Request<Body> {
+    let body = body.to_string();
+
+    Request::post("/api/csp-report")
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_csp_reports() {
+    let app = TestApp::new().await;
+
+    let legacy = |page: &str| {
+        report(
+            "application/csp-report",
+            json!({
+                "csp-report": {
+                    "document-uri": format!("https://example.com/article/{}", page),
+                    "violated-directive": "img-src 'self'",
+                    "effective-directive": "img-src",
+                    "blocked-uri": "https://tracker.example/pixel.gif",
+                }
+            }),
+        )
+    };
+
+    app.send(legacy("hello"))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    app.send(legacy("hello?ref=home"))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    app.send(report(
+        "application/reports+json",
+        json!([{
+            "type": "csp-violation",
+            "url": "https://example.com/settings",
+            "body": {
+                "documentURL": "https://example.com/settings",
+                "effectiveDirective": "script-src-elem",
+                "blockedURL": "inline",
+            }
+        }]),
+    ))
+    .await
+    .assert_status(StatusCode::NO_CONTENT);
+
+    app.send(report("application/json", json!({ "hello": "world" })))
+        .await
+        .assert_unprocessable("report", "not a CSP violation report");
+
+    let alice = app.create_user("alice").await;
+    let morgan = app.create_user("morgan").await;
+
+    db::users::set_role(app.db().primary(), "morgan", Role::Moderator)
+        .await
+        .unwrap();
+
+    alice
+        .get("/api/admin/csp-reports")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let body = morgan.get("/api/admin/csp-reports").await.assert_ok();
+    let reports = body["cspReports"].as_array().unwrap();
+    assert_eq!(reports.len(), 2, "{}", body);
+
+    assert_eq!(reports[0]["documentUri"], "https://example.com/settings");
+    assert_eq!(reports[0]["violatedDirective"], "script-src-elem");
+    assert_eq!(reports[0]["blockedUri"], "inline");
+    assert_eq!(reports[0]["count"], 1);
+
+    // The same violation on the same page, whatever the query string.
+    assert_eq!(
+        reports[1]["documentUri"],
+        "https://example.com/article/hello"
+    );
+    assert_eq!(reports[1]["count"], 2);
+    assert_eq!(
+        reports[1]["sample"]["csp-report"]["document-uri"],
+        "https://example.com/article/hello?ref=home"
+    );
+}
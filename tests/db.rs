@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use futures::future::FutureExt;
+use serde_json::json;
+
+use realworld_axum_sqlx::test_util::TestApp;
+
+#[tokio::test]
+async fn test_statement_timeout() {
+    // The migrations still ran, or this wouldn't get any further.
+    let app = TestApp::with_config(json!({ "statement_timeout_ms": 100 })).await;
+
+    let err = sqlx::query("select pg_sleep(1)")
+        .execute(app.db().primary())
+        .await
+        .unwrap_err();
+
+    // `query_canceled`, which is what Postgres calls running out of time, too.
+    assert_eq!(
+        err.as_database_error().unwrap().code().as_deref(),
+        Some("57014")
+    );
+
+    sqlx::query("select pg_sleep(0.01)")
+        .execute(app.db().primary())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_cancel_on_drop() {
+    let app = TestApp::new().await;
+    let db = app.db();
+
+    let mut conn = db.cancellable(db.primary()).await.unwrap();
+
+    // Stands in for a client going away, which drops the request's future the same way.
+    let query = async move {
+        conn.run(|conn| {
+            sqlx::query("select pg_sleep(30)")
+                .execute(conn)
+                .map(|res| res.map(drop))
+                .boxed()
+        })
+        .await
+    };
+    tokio::time::timeout(Duration::from_millis(500), query)
+        .await
+        .unwrap_err();
+
+    // The cancel is sent in the background, so give it a moment.
+    for _ in 0..50 {
+        let running: i64 = sqlx::query_scalar(
+            r#"
+                select count(*) from pg_stat_activity
+                where datname = current_database() and query = 'select pg_sleep(30)'
+                    and state = 'active'
+            "#,
+        )
+        .fetch_one(db.primary())
+        .await
+        .unwrap();
+
+        if running == 0 {
+            return;
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    panic!("the query is still running");
+}
@@ -0,0 +1,139 @@
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use serde_json::json;
+
+use realworld_axum_sqlx::test_util::TestApp;
+
+#[tokio::test]
+async fn test_debug_routes_disabled() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+
+    alice
+        .get("/api/debug/token")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    app.post_json(
+        "/api/debug/token",
+        json!({ "userId": "7c1e3a52-9d4b-4f0e-8a61-2b5c9e0d4f17" }),
+    )
+    .await
+    .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_debug_token() {
+    let app = TestApp::with_config(json!({ "debug_routes": true })).await;
+
+    let alice = app.create_user("alice").await;
+
+    let body = alice.get("/api/debug/token").await.assert_ok();
+    assert_eq!(body["session"], false);
+    assert_eq!(body["valid"], true);
+    assert_eq!(body["expired"], false);
+    assert!(body["expiresAt"].is_string());
+
+    let user_id = body["claims"]["user_id"].clone();
+    assert!(user_id.is_string());
+
+    // Log in as Alice without a password.
+    let body = app
+        .post_json("/api/debug/token", json!({ "userId": user_id }))
+        .await
+        .assert_ok();
+
+    let token = body["token"].as_str().unwrap();
+
+    let body = app
+        .send(
+            Request::get("/api/user")
+                .header(header::AUTHORIZATION, format!("Token {}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .assert_ok();
+    assert_eq!(body["user"]["username"], "alice");
+
+    app.post_json(
+        "/api/debug/token",
+        json!({ "userId": "7c1e3a52-9d4b-4f0e-8a61-2b5c9e0d4f17" }),
+    )
+    .await
+    .assert_unprocessable("userId", "no such user");
+
+    // Tokens that wouldn't be accepted are still decoded.
+    app.clock().advance(time::Duration::weeks(3));
+
+    let body = alice.get("/api/debug/token").await.assert_ok();
+    assert_eq!(body["valid"], false);
+    assert_eq!(body["expired"], true);
+    assert_eq!(body["claims"]["user_id"], user_id);
+
+    // Change a character in the middle of the signature.
+    let mut forged = alice.token.clone().into_bytes();
+    let i = forged.len() - 10;
+    forged[i] = if forged[i] == b'A' { b'B' } else { b'A' };
+    let forged = String::from_utf8(forged).unwrap();
+
+    let body = app
+        .send(
+            Request::get("/api/debug/token")
+                .header(header::AUTHORIZATION, format!("Token {}", forged))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .assert_ok();
+    assert_eq!(body["valid"], false);
+
+    app.get("/api/debug/token")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_debug_session_token() {
+    let app =
+        TestApp::with_config(json!({ "debug_routes": true, "session_store": "memory" })).await;
+
+    let alice = app.create_user("alice").await;
+
+    // There's nothing in a session token to decode, so all we can say is whether it works.
+    let body = alice.get("/api/debug/token").await.assert_ok();
+    assert_eq!(body, json!({ "session": true, "valid": true }));
+
+    alice.delete("/api/user/session").await.assert_ok();
+
+    let body = alice.get("/api/debug/token").await.assert_ok();
+    assert_eq!(body, json!({ "session": true, "valid": false }));
+
+    // Minting one starts a session.
+    let user_id: uuid::Uuid =
+        sqlx::query_scalar(r#"select user_id from "user" where username = 'alice'"#)
+            .fetch_one(app.db().primary())
+            .await
+            .unwrap();
+
+    let body = app
+        .post_json("/api/debug/token", json!({ "userId": user_id.to_string() }))
+        .await
+        .assert_ok();
+    assert!(body["expiresAt"].is_string());
+
+    let token = body["token"].as_str().unwrap();
+    assert!(!token.contains('.'), "{}", token);
+
+    let body = app
+        .send(
+            Request::get("/api/debug/token")
+                .header(header::AUTHORIZATION, format!("Token {}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .assert_ok();
+    assert_eq!(body["valid"], true);
+}
@@ -0,0 +1,114 @@
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use serde_json::json;
+
+use realworld_axum_sqlx::test_util::TestApp;
+
+const ENVELOPE: &str = "application/vnd.realworld.envelope+json";
+
+fn get(uri: &str, token: Option<&str>, accept: Option<&str>) -> Request<Body> {
+    let mut req = Request::get(uri);
+
+    if let Some(token) = token {
+        req = req.header(header::AUTHORIZATION, format!("Token {}", token));
+    }
+
+    if let Some(accept) = accept {
+        req = req.header(header::ACCEPT, accept);
+    }
+
+    req.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn test_envelope_negotiated() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+
+    // The Realworld format is the default.
+    let res = app
+        .send(get("/api/user", Some(&alice.token), None))
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(res.body["user"]["username"], "alice");
+    assert_eq!(res.header("vary"), "accept");
+
+    let res = app
+        .send(get("/api/user", Some(&alice.token), Some(ENVELOPE)))
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(res.header("content-type"), ENVELOPE);
+    assert_eq!(res.body["data"]["user"]["username"], "alice");
+    assert_eq!(res.body["error"], json!(null));
+    assert_eq!(res.body["meta"], json!({ "status": 200 }));
+
+    let res = app
+        .send(get("/api/user", None, Some(ENVELOPE)))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        res.body,
+        json!({
+            "data": null,
+            "error": { "message": "authentication required", "details": null },
+            "meta": { "status": 401 },
+        })
+    );
+
+    let res = app
+        .send(
+            Request::post("/api/users")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::ACCEPT, ENVELOPE)
+                .body(Body::from(
+                    json!({
+                        "user": {
+                            "username": "alice",
+                            "email": "alice2@example.com",
+                            "password": "hunter2hunter2",
+                        }
+                    })
+                    .to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(res.body["error"]["message"], "unprocessable entity");
+    assert_eq!(
+        res.body["error"]["details"],
+        json!({ "errors": { "username": ["username taken"] } })
+    );
+
+    // Anything that isn't JSON is left alone.
+    let res = app
+        .send(get(
+            "/api/user/analytics/export",
+            Some(&alice.token),
+            Some(ENVELOPE),
+        ))
+        .await
+        .assert_status(StatusCode::OK);
+    assert!(res.header("content-type").starts_with("text/csv"));
+}
+
+#[tokio::test]
+async fn test_envelope_modes() {
+    let app = TestApp::with_config(json!({ "response_envelope": "always" })).await;
+
+    let res = app
+        .send(get("/api/tags", None, None))
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(res.body["data"], json!({ "tags": [], "articlesCount": {} }));
+    assert!(res.headers.get("vary").is_none());
+
+    let app = TestApp::with_config(json!({ "response_envelope": "off" })).await;
+
+    let res = app
+        .send(get("/api/tags", None, Some(ENVELOPE)))
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(res.body, json!({ "tags": [], "articlesCount": {} }));
+}
@@ -0,0 +1,68 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use realworld_axum_sqlx::http::sign_image_url;
+use realworld_axum_sqlx::test_util::TestApp;
+
+const KEY: &str = "nFVcbUIzvJ8Q9Bw0XhTXI0ZWpU3VBbZIOJqnxeExxj7nBnPB9Nv8IJm6EYI6zQWW";
+
+fn proxy_path(url: &str, sig: &str) -> String {
+    format!(
+        "/imgproxy?{}",
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("url", url)
+            .append_pair("sig", sig)
+            .finish()
+    )
+}
+
+// We can't test fetching an actual image without a server on the public internet, since
+// anything we could start here is on a private network as far as the proxy is concerned.
+// See the unit tests in `src/http/imgproxy.rs` for the parts of that we can test.
+
+#[tokio::test]
+async fn test_imgproxy_disabled() {
+    let app = TestApp::new().await;
+
+    let url = "https://images.example/cat.png";
+
+    app.get(&proxy_path(url, &sign_image_url(KEY, url)))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_imgproxy_rejects() {
+    let app = TestApp::with_config(json!({ "image_proxy_key": KEY })).await;
+
+    let url = "https://images.example/cat.png";
+
+    // Signed with the wrong key, for a different URL, or not at all.
+    for sig in [
+        sign_image_url("some other key", url),
+        sign_image_url(KEY, "https://images.example/dog.png"),
+        String::new(),
+    ] {
+        app.get(&proxy_path(url, &sig))
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    // Anything on a private network is off limits, even with a valid signature.
+    for url in [
+        "http://127.0.0.1:8080/metrics",
+        "http://169.254.169.254/latest/meta-data/",
+        "http://[::1]/",
+        "http://localhost/cat.png",
+    ] {
+        app.get(&proxy_path(url, &sign_image_url(KEY, url)))
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    let url = "file:///etc/passwd";
+
+    app.get(&proxy_path(url, &sign_image_url(KEY, url)))
+        .await
+        .assert_unprocessable("url", "must be an http:// or https:// URL");
+}
@@ -0,0 +1,88 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+use tracing_subscriber::fmt::MakeWriter;
+
+use realworld_axum_sqlx::test_util::TestApp;
+
+/// Collects everything logged while it's the default subscriber.
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Logs {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self {
+        self.clone()
+    }
+}
+
+impl Logs {
+    /// The `request` span of each request that finished, as JSON.
+    fn requests(&self) -> Vec<Value> {
+        let logs = self.0.lock().unwrap();
+
+        String::from_utf8_lossy(&logs)
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .filter(|line| line["fields"]["message"] == "finished processing request")
+            .map(|line| line["span"].clone())
+            .collect()
+    }
+
+    fn contains(&self, needle: &str) -> bool {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).contains(needle)
+    }
+}
+
+#[tokio::test]
+async fn test_request_logs() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let slug = alice.create_article("Hello", &[]).await;
+
+    let logs = Logs::default();
+
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_writer(logs.clone())
+        .finish();
+
+    // `#[tokio::test]` runs everything on this thread, so this sees all of it.
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    alice
+        .get(&format!("/api/articles/{}", slug))
+        .await
+        .assert_ok();
+    app.get("/api/articles/nope").await;
+
+    let requests = logs.requests();
+    assert_eq!(requests.len(), 2, "{:?}", requests);
+
+    assert_eq!(requests[0]["method"], "GET");
+    assert_eq!(requests[0]["path"], format!("/api/articles/{}", slug));
+    assert_eq!(requests[0]["route"], "/api/articles/:slug");
+    assert_eq!(requests[0]["status"], 200);
+    assert!(requests[0]["user_id"].is_string());
+    assert!(requests[0]["latency_ms"].is_number());
+    assert!(requests[0]["response_bytes"].as_u64().unwrap() > 0);
+
+    assert_eq!(requests[1]["status"], 404);
+    assert!(requests[1].get("user_id").is_none());
+
+    assert!(!logs.contains(&alice.token));
+}
@@ -0,0 +1,73 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use realworld_axum_sqlx::test_util::TestApp;
+
+#[tokio::test]
+async fn test_onboarding() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    app.create_user("bob").await;
+
+    let state = |picked_tags: bool, followed_authors: bool, wrote_first_article, completed| {
+        json!({
+            "onboarding": {
+                "pickedTags": picked_tags,
+                "followedAuthors": followed_authors,
+                "wroteFirstArticle": wrote_first_article,
+                "completed": completed,
+            }
+        })
+    };
+
+    let body = alice.get("/api/onboarding/state").await.assert_ok();
+    assert_eq!(body, state(false, false, false, false));
+
+    let body = alice
+        .post_json(
+            "/api/onboarding/complete",
+            json!({ "onboarding": { "step": "pickedTags" } }),
+        )
+        .await
+        .assert_ok();
+    assert_eq!(body, state(true, false, false, false));
+
+    // Following someone counts without the frontend saying so.
+    alice.post("/api/profiles/bob/follow").await.assert_ok();
+
+    let body = alice.get("/api/onboarding/state").await.assert_ok();
+    assert_eq!(body, state(true, true, false, false));
+
+    // Doing a step twice is fine.
+    alice
+        .post_json(
+            "/api/onboarding/complete",
+            json!({ "onboarding": { "step": "pickedTags" } }),
+        )
+        .await
+        .assert_ok();
+
+    let body = alice
+        .post_json("/api/onboarding/complete", json!({ "onboarding": {} }))
+        .await
+        .assert_ok();
+    assert_eq!(body, state(true, true, false, true));
+
+    alice.create_article("Hello", &[]).await;
+
+    let body = alice.get("/api/onboarding/state").await.assert_ok();
+    assert_eq!(body, state(true, true, true, true));
+
+    alice
+        .post_json(
+            "/api/onboarding/complete",
+            json!({ "onboarding": { "step": "tookANap" } }),
+        )
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    app.get("/api/onboarding/state")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,359 @@
+use serde_json::{json, Value};
+
+use realworld_axum_sqlx::test_util::{TestApp, TestUser};
+
+// This is the flow of the official Realworld Postman collection (`api/Conduit.postman_collection.json`
+// in https://github.com/gothinkster/realworld), translated request for request, with the same
+// assertions. It used to run in CI with `newman` against a running server, which needed Node.js
+// and a checkout of the Realworld repo, and only caught regressions after they were pushed.
+//
+// The collection mostly checks the *shape* of each response rather than the values, so the
+// helpers at the bottom do the same. Where it checks a value, so do we.
+//
+// If the upstream collection changes, this should be updated to match; the folder and request
+// names in the comments are the ones it uses.
+
+const EMAIL: &str = "postman@example.com";
+const PASSWORD: &str = "postman-password";
+const USERNAME: &str = "postman";
+
+#[tokio::test]
+async fn test_postman_collection() {
+    let app = TestApp::new().await;
+
+    let user = auth(&app).await;
+    articles_favorite_comments(&app, &user).await;
+    profiles(&app, &user).await;
+    tags(&app).await;
+}
+
+/// The "Auth" folder.
+async fn auth(app: &TestApp) -> TestUser<'_> {
+    // Register
+    let body = app
+        .post_json(
+            "/api/users",
+            json!({ "user": { "email": EMAIL, "password": PASSWORD, "username": USERNAME } }),
+        )
+        .await
+        .assert_ok();
+    assert_user(&body);
+
+    // Login
+    let body = app
+        .post_json(
+            "/api/users/login",
+            json!({ "user": { "email": EMAIL, "password": PASSWORD } }),
+        )
+        .await
+        .assert_ok();
+    assert_user(&body);
+
+    // Login and Remember Token
+    let user = app.login(EMAIL, PASSWORD).await;
+
+    // Current User
+    let body = user.get("/api/user").await.assert_ok();
+    assert_user(&body);
+
+    // Update User
+    let body = user
+        .put_json("/api/user", json!({ "user": { "email": EMAIL } }))
+        .await
+        .assert_ok();
+    assert_user(&body);
+
+    user
+}
+
+/// The "Articles" folder, which is just the anonymous listings, and then the
+/// "Articles, Favorite, Comments" folder.
+async fn articles_favorite_comments(app: &TestApp, user: &TestUser<'_>) {
+    // All Articles
+    assert_articles(&app.get("/api/articles").await.assert_ok());
+    // Articles by Author
+    assert_articles(&app.get("/api/articles?author=johnjacob").await.assert_ok());
+    // Articles Favorited by Username
+    assert_articles(&app.get("/api/articles?favorited=jane").await.assert_ok());
+    // Articles by Tag
+    assert_articles(&app.get("/api/articles?tag=dragons").await.assert_ok());
+
+    // Create Article
+    let body = user
+        .post_json(
+            "/api/articles",
+            json!({
+                "article": {
+                    "title": "How to train your dragon",
+                    "description": "Ever wonder how?",
+                    "body": "Very carefully.",
+                    "tagList": ["training", "dragons"],
+                }
+            }),
+        )
+        .await
+        .assert_ok();
+    assert_article(&body["article"]);
+    let article_uri = format!(
+        "/api/articles/{}",
+        body["article"]["slug"].as_str().unwrap()
+    );
+
+    // Feed
+    assert_articles(&user.get("/api/articles/feed").await.assert_ok());
+
+    // All Articles with auth
+    assert_articles(&user.get("/api/articles").await.assert_ok());
+    // Articles by Author with auth
+    let body = user
+        .get(&format!("/api/articles?author={}", USERNAME))
+        .await
+        .assert_ok();
+    assert_articles(&body);
+    assert_eq!(body["articlesCount"], 1);
+    // Articles Favorited by Username with auth
+    assert_articles(&user.get("/api/articles?favorited=jane").await.assert_ok());
+
+    // Single Article by slug
+    let body = user.get(&article_uri).await.assert_ok();
+    assert_article(&body["article"]);
+
+    // Articles by Tag
+    let body = user.get("/api/articles?tag=dragons").await.assert_ok();
+    assert_articles(&body);
+    let tags = body["articles"][0]["tagList"].as_array().unwrap();
+    assert!(tags.contains(&json!("dragons")), "{}", body);
+
+    // Update Article
+    let body = user
+        .put_json(
+            &article_uri,
+            json!({ "article": { "body": "With two hands" } }),
+        )
+        .await
+        .assert_ok();
+    assert_article(&body["article"]);
+    assert_eq!(body["article"]["body"], "With two hands");
+
+    // Favorite Article
+    let body = user
+        .post(&format!("{}/favorite", article_uri))
+        .await
+        .assert_ok();
+    assert_article(&body["article"]);
+    assert_eq!(body["article"]["favorited"], true);
+    assert!(body["article"]["favoritesCount"].as_i64().unwrap() > 0);
+
+    // Articles Favorited by Username
+    let body = app
+        .get(&format!("/api/articles?favorited={}", USERNAME))
+        .await
+        .assert_ok();
+    assert_articles(&body);
+    assert_eq!(body["articles"][0]["favorited"], false);
+    assert_eq!(body["articles"][0]["favoritesCount"], 1);
+
+    // Articles Favorited by Username with auth
+    let body = user
+        .get(&format!("/api/articles?favorited={}", USERNAME))
+        .await
+        .assert_ok();
+    assert_articles(&body);
+    assert_eq!(body["articles"][0]["favorited"], true);
+    assert_eq!(body["articles"][0]["favoritesCount"], 1);
+
+    // Unfavorite Article
+    let body = user
+        .delete(&format!("{}/favorite", article_uri))
+        .await
+        .assert_ok();
+    assert_article(&body["article"]);
+    assert_eq!(body["article"]["favorited"], false);
+
+    // Create Comment for Article
+    let body = user
+        .post_json(
+            &format!("{}/comments", article_uri),
+            json!({ "comment": { "body": "Thank you so much!" } }),
+        )
+        .await
+        .assert_ok();
+    assert_comment(&body["comment"]);
+    let comment_id = body["comment"]["id"].as_i64().unwrap();
+
+    // All Comments for Article
+    let body = user
+        .get(&format!("{}/comments", article_uri))
+        .await
+        .assert_ok();
+    body["comments"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .for_each(assert_comment);
+
+    // All Comments for Article without login
+    let body = app
+        .get(&format!("{}/comments", article_uri))
+        .await
+        .assert_ok();
+    body["comments"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .for_each(assert_comment);
+
+    // Delete Comment for Article
+    user.delete(&format!("{}/comments/{}", article_uri, comment_id))
+        .await
+        .assert_ok();
+
+    // Delete Article
+    user.delete(&article_uri).await.assert_ok();
+}
+
+/// The "Profiles" folder.
+async fn profiles(app: &TestApp, user: &TestUser<'_>) {
+    // Register Celeb
+    let celeb = format!("celeb_{}", USERNAME);
+
+    let body = app
+        .post_json(
+            "/api/users",
+            json!({
+                "user": {
+                    "email": format!("celeb_{}", EMAIL),
+                    "password": PASSWORD,
+                    "username": celeb,
+                }
+            }),
+        )
+        .await
+        .assert_ok();
+    assert_user(&body);
+
+    let profile_uri = format!("/api/profiles/{}", celeb);
+
+    // Profile
+    let body = user.get(&profile_uri).await.assert_ok();
+    assert_profile(&body["profile"]);
+
+    // Follow Profile
+    let body = user
+        .post(&format!("{}/follow", profile_uri))
+        .await
+        .assert_ok();
+    assert_profile(&body["profile"]);
+    assert_eq!(body["profile"]["following"], true);
+
+    // Unfollow Profile
+    let body = user
+        .delete(&format!("{}/follow", profile_uri))
+        .await
+        .assert_ok();
+    assert_profile(&body["profile"]);
+    assert_eq!(body["profile"]["following"], false);
+}
+
+/// The "Tags" folder.
+async fn tags(app: &TestApp) {
+    // All Tags
+    let body = app.get("/api/tags").await.assert_ok();
+    assert!(body["tags"].is_array(), "{}", body);
+}
+
+#[track_caller]
+fn assert_user(body: &Value) {
+    let user = &body["user"];
+
+    for field in ["email", "username", "bio", "image", "token"] {
+        assert!(
+            user.get(field).is_some(),
+            "user has no {:?}: {}",
+            field,
+            body
+        );
+    }
+}
+
+#[track_caller]
+fn assert_articles(body: &Value) {
+    let articles = body["articles"]
+        .as_array()
+        .unwrap_or_else(|| panic!("no articles: {}", body));
+    assert!(body["articlesCount"].is_i64(), "no articlesCount: {}", body);
+
+    articles.iter().for_each(assert_article);
+}
+
+#[track_caller]
+fn assert_article(article: &Value) {
+    for field in ["title", "slug", "body", "description", "author"] {
+        assert!(
+            article.get(field).is_some(),
+            "article has no {:?}: {}",
+            field,
+            article
+        );
+    }
+
+    assert_iso8601(&article["createdAt"]);
+    assert_iso8601(&article["updatedAt"]);
+    assert!(article["tagList"].is_array(), "{}", article);
+    assert!(article["favorited"].is_boolean(), "{}", article);
+    assert!(article["favoritesCount"].is_i64(), "{}", article);
+}
+
+#[track_caller]
+fn assert_comment(comment: &Value) {
+    assert!(comment["id"].is_i64(), "{}", comment);
+    assert!(comment["body"].is_string(), "{}", comment);
+    assert!(comment.get("author").is_some(), "{}", comment);
+    assert_iso8601(&comment["createdAt"]);
+    assert_iso8601(&comment["updatedAt"]);
+}
+
+#[track_caller]
+fn assert_profile(profile: &Value) {
+    for field in ["username", "bio", "image", "following"] {
+        assert!(
+            profile.get(field).is_some(),
+            "profile has no {:?}: {}",
+            field,
+            profile
+        );
+    }
+}
+
+/// The same check as the collection does, which is the regex
+/// `^\d{4,}-[01]\d-[0-3]\dT[0-2]\d:[0-5]\d:[0-5]\d.\d+(?:[+-][0-2]\d:[0-5]\d|Z)$`.
+#[track_caller]
+fn assert_iso8601(value: &Value) {
+    let s = value
+        .as_str()
+        .unwrap_or_else(|| panic!("not a timestamp: {}", value));
+
+    let valid = (|| {
+        let (date, time) = s.split_once('T')?;
+        let date: Vec<&str> = date.split('-').collect();
+        let time = time.strip_suffix('Z')?;
+        let (hms, fraction) = time.split_once('.')?;
+        let hms: Vec<&str> = hms.split(':').collect();
+
+        let digits = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit());
+
+        Some(
+            date.len() == 3
+                && date[0].len() >= 4
+                && digits(date[0], date[0].len())
+                && digits(date[1], 2)
+                && digits(date[2], 2)
+                && hms.len() == 3
+                && hms.iter().all(|part| digits(part, 2))
+                && !fraction.is_empty()
+                && digits(fraction, fraction.len()),
+        )
+    })();
+
+    assert_eq!(valid, Some(true), "not an ISO 8601 timestamp: {:?}", s);
+}
@@ -0,0 +1,317 @@
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use serde_json::{json, Value};
+
+use realworld_axum_sqlx::db::feed::MAX_ENTRIES_PER_USER;
+use realworld_axum_sqlx::db::fixtures::Fixtures;
+use realworld_axum_sqlx::test_util::TestApp;
+
+fn slugs(body: &Value) -> Vec<&str> {
+    body["articles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|article| article["slug"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_follow_and_feed() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    let older = alice.create_article("Before the follow", &[]).await;
+
+    let body = bob.post("/api/profiles/alice/follow").await.assert_ok();
+    assert_eq!(body["profile"]["following"], true);
+
+    let body = bob.get("/api/profiles/alice").await.assert_ok();
+    assert_eq!(body["profile"]["following"], true);
+
+    let newer = alice.create_article("After the follow", &[]).await;
+
+    // Articles from before the follow are backfilled into the feed.
+    let body = bob.get("/api/articles/feed").await.assert_ok();
+    assert_eq!(slugs(&body), [newer.as_str(), older.as_str()]);
+
+    // Alice's own feed doesn't include her own articles.
+    let body = alice.get("/api/articles/feed").await.assert_ok();
+    assert!(slugs(&body).is_empty());
+
+    let body = bob.delete("/api/profiles/alice/follow").await.assert_ok();
+    assert_eq!(body["profile"]["following"], false);
+
+    let body = bob.get("/api/articles/feed").await.assert_ok();
+    assert!(slugs(&body).is_empty());
+}
+
+#[tokio::test]
+async fn test_feed_pagination() {
+    let app = TestApp::new().await;
+    let db = app.db().primary();
+
+    let mut fixtures = Fixtures::new(0);
+    let alice = fixtures.user().username("alice").insert(db).await.unwrap();
+
+    let mut expected = vec![];
+
+    for age_secs in 1..=5 {
+        let article = fixtures
+            .article(&alice)
+            .age_secs(age_secs as f64)
+            .insert(db)
+            .await
+            .unwrap();
+        expected.push(article.slug);
+    }
+
+    let bob = app.create_user("bob").await;
+    bob.post("/api/profiles/alice/follow").await.assert_ok();
+
+    // Paging with `offset` and with the cursor give the same pages.
+    let mut by_offset = vec![];
+    let mut by_cursor = vec![];
+    let mut cursor: Option<String> = None;
+
+    for offset in (0..6).step_by(2) {
+        let body = bob
+            .get(&format!("/api/articles/feed?limit=2&offset={}", offset))
+            .await
+            .assert_ok();
+        by_offset.extend(slugs(&body).into_iter().map(String::from));
+
+        let uri = match &cursor {
+            Some(cursor) => format!("/api/articles/feed?limit=2&cursor={}", cursor),
+            None => "/api/articles/feed?limit=2".to_string(),
+        };
+        let body = bob.get(&uri).await.assert_ok();
+        by_cursor.extend(slugs(&body).into_iter().map(String::from));
+        cursor = body["nextCursor"].as_str().map(String::from);
+
+        // The whole feed, not just this page.
+        assert_eq!(body["articlesCount"], 5);
+    }
+
+    assert_eq!(by_offset, expected);
+    assert_eq!(by_cursor, expected);
+    // The last page was short, so there's nothing after it.
+    assert_eq!(cursor, None);
+
+    // Nothing is kept past the cap, so there's no need to look.
+    let body = bob
+        .get(&format!(
+            "/api/articles/feed?offset={}",
+            MAX_ENTRIES_PER_USER
+        ))
+        .await
+        .assert_ok();
+    assert!(slugs(&body).is_empty());
+}
+
+#[tokio::test]
+async fn test_follow_errors() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+
+    alice
+        .post("/api/profiles/alice/follow")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    alice
+        .post("/api/profiles/nobody/follow")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    app.get("/api/profiles/nobody")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_suggestions() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+    let carol = app.create_user("carol").await;
+    let dave = app.create_user("dave").await;
+    let erin = app.create_user("erin").await;
+    let frank = app.create_user("frank").await;
+
+    bob.create_article("Bob's article", &["rust"]).await;
+    carol.create_article("Carol's article", &["rust"]).await;
+    let soup = dave.create_article("Soup", &["cooking"]).await;
+
+    alice.post("/api/profiles/bob/follow").await.assert_ok();
+    bob.post("/api/profiles/carol/follow").await.assert_ok();
+    bob.post("/api/profiles/erin/follow").await.assert_ok();
+    dave.post("/api/profiles/carol/follow").await.assert_ok();
+    erin.post("/api/profiles/carol/follow").await.assert_ok();
+
+    let usernames = |body: &Value| -> Vec<String> {
+        body["profiles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|profile| profile["username"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    // Frank hasn't followed or favorited anything, so they get the most followed authors. Erin
+    // has a follower but hasn't written anything.
+    let body = frank.get("/api/profiles/suggestions").await.assert_ok();
+    assert_eq!(usernames(&body), ["carol", "bob"]);
+    assert_eq!(body["profiles"][0]["following"], false);
+
+    // Alice follows Bob, who follows Carol, and favorited an article about cooking.
+    alice
+        .post(&format!("/api/articles/{}/favorite", soup))
+        .await
+        .assert_ok();
+
+    let body = alice.get("/api/profiles/suggestions").await.assert_ok();
+    assert_eq!(usernames(&body), ["carol", "dave"]);
+
+    let body = alice
+        .get("/api/profiles/suggestions?limit=1")
+        .await
+        .assert_ok();
+    assert_eq!(usernames(&body), ["carol"]);
+
+    app.get("/api/profiles/suggestions")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_profile_etag() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    // `If-None-Match` for Alice's profile, as Bob if `token` is given.
+    let get_if_none_match = |etag: &str, token: Option<&str>| {
+        let mut req = Request::get("/api/profiles/alice").header(header::IF_NONE_MATCH, etag);
+
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Token {}", token));
+        }
+
+        app.send(req.body(Body::empty()).unwrap())
+    };
+
+    let res = app.get("/api/profiles/alice").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let anonymous_etag = res.header("etag").to_string();
+
+    let res = get_if_none_match(&anonymous_etag, None).await;
+    assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+    assert_eq!(res.header("etag"), anonymous_etag);
+
+    // Bob doesn't follow Alice yet, so Bob sees the same thing.
+    let res = get_if_none_match(&anonymous_etag, Some(&bob.token)).await;
+    assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+
+    // Now Bob does, so it's changed for Bob, but not for anyone else.
+    bob.post("/api/profiles/alice/follow").await.assert_ok();
+
+    let res = get_if_none_match(&anonymous_etag, Some(&bob.token)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["profile"]["following"], true);
+
+    let res = get_if_none_match(&anonymous_etag, None).await;
+    assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+
+    // Changing Alice's profile changes it for everyone, straight away, even though anonymous
+    // visitors are served from a cache.
+    alice
+        .put_json("/api/user", json!({ "user": { "bio": "Hello!" } }))
+        .await
+        .assert_ok();
+
+    let res = get_if_none_match(&anonymous_etag, None).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["profile"]["bio"], "Hello!");
+    assert_ne!(res.header("etag"), anonymous_etag);
+}
+
+#[tokio::test]
+async fn test_follow_batch() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+    app.create_user("bob").await;
+    app.create_user("carol").await;
+
+    alice.post("/api/profiles/bob/follow").await.assert_ok();
+
+    let results = |body: &Value| -> Vec<(String, String)> {
+        body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| {
+                (
+                    result["username"].as_str().unwrap().to_string(),
+                    result["result"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    };
+
+    let pair = |username: &str, result: &str| (username.to_string(), result.to_string());
+
+    let body = alice
+        .post_json(
+            "/api/profiles/follow-batch",
+            json!({ "usernames": ["carol", "bob", "nobody", "alice", "carol"] }),
+        )
+        .await
+        .assert_ok();
+
+    assert_eq!(
+        results(&body),
+        [
+            pair("carol", "followed"),
+            pair("bob", "unchanged"),
+            pair("nobody", "notFound"),
+            pair("alice", "forbidden"),
+        ]
+    );
+
+    let body = alice.get("/api/profiles/carol").await.assert_ok();
+    assert_eq!(body["profile"]["following"], true);
+
+    let body = alice
+        .request(
+            Method::DELETE,
+            "/api/profiles/follow-batch",
+            Some(json!({ "usernames": ["Carol", "dave"] })),
+        )
+        .await
+        .assert_ok();
+
+    // Usernames are case-insensitive, as everywhere else.
+    assert_eq!(
+        results(&body),
+        [pair("Carol", "unfollowed"), pair("dave", "notFound")]
+    );
+
+    let body = alice.get("/api/profiles/carol").await.assert_ok();
+    assert_eq!(body["profile"]["following"], false);
+
+    let too_many: Vec<String> = (0..101).map(|i| format!("user{}", i)).collect();
+
+    alice
+        .post_json(
+            "/api/profiles/follow-batch",
+            json!({ "usernames": too_many }),
+        )
+        .await
+        .assert_unprocessable("usernames", "at most 100 usernames at once");
+}
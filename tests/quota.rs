@@ -0,0 +1,290 @@
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use serde_json::json;
+use time::Duration;
+
+use realworld_axum_sqlx::test_util::TestApp;
+
+#[tokio::test]
+async fn test_article_quota() {
+    let app = TestApp::with_config(json!({ "article_quota_per_day": 2 })).await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    let article = |title: &str| {
+        json!({
+            "article": {
+                "title": title,
+                "description": "A test article",
+                "body": "Hello, world!",
+                "tagList": [],
+            }
+        })
+    };
+
+    let res = alice.post_json("/api/articles", article("First")).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.header("x-quota-limit"), "2");
+    assert_eq!(res.header("x-quota-remaining"), "1");
+    assert_eq!(res.header("x-quota-reset"), "86400");
+
+    let res = alice.post_json("/api/articles", article("Second")).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.header("x-quota-remaining"), "0");
+
+    let res = alice
+        .post_json("/api/articles", article("Third"))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.header("x-quota-remaining"), "0");
+    assert!(res.header("retry-after").parse::<i64>().unwrap() > 0);
+    assert_eq!(
+        res.body,
+        "quota exceeded: you may only post 2 articles per day"
+    );
+
+    // It's per user.
+    bob.create_article("Bob's first", &[]).await;
+
+    // Deleting one gives it back.
+    alice.delete("/api/articles/second").await.assert_ok();
+    alice.create_article("Third", &[]).await;
+
+    alice
+        .post_json("/api/articles", article("Fourth"))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+    // A day later, the window has moved past all of them.
+    app.clock().advance(Duration::day() + Duration::minute());
+    alice.create_article("Fourth", &[]).await;
+}
+
+#[tokio::test]
+async fn test_comment_quota() {
+    let app = TestApp::with_config(json!({ "comment_quota_per_hour": 1 })).await;
+
+    let alice = app.create_user("alice").await;
+    let first = alice.create_article("First", &[]).await;
+    let second = alice.create_article("Second", &[]).await;
+
+    let comment = json!({ "comment": { "body": "Nice!" } });
+
+    // Articles aren't limited, so they don't get the headers.
+    let res = alice
+        .post_json(
+            "/api/articles",
+            json!({
+                "article": {
+                    "title": "Third",
+                    "description": "A test article",
+                    "body": "Hello, world!",
+                    "tagList": [],
+                }
+            }),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.headers.get("x-quota-limit").is_none());
+
+    let res = alice
+        .post_json(
+            &format!("/api/articles/{}/comments", first),
+            comment.clone(),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.header("x-quota-remaining"), "0");
+    assert_eq!(res.header("x-quota-reset"), "3600");
+
+    // The quota covers comments on every article.
+    alice
+        .post_json(
+            &format!("/api/articles/{}/comments", second),
+            comment.clone(),
+        )
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+    app.clock().advance(Duration::hour() + Duration::minute());
+
+    alice
+        .post_json(&format!("/api/articles/{}/comments", second), comment)
+        .await
+        .assert_ok();
+}
+
+#[tokio::test]
+async fn test_signup_caps() {
+    let app = TestApp::with_config(json!({
+        "client_ip_header": "x-forwarded-for",
+        "signups_per_ip_per_hour": 2,
+        "signups_per_device_per_hour": 1,
+    }))
+    .await;
+
+    let signup = |username: &str, ip: &str, device: Option<&str>| {
+        let mut req = Request::post("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            // Only the last address counts, the one our proxy added.
+            .header("x-forwarded-for", format!("10.0.0.1, {}", ip));
+
+        if let Some(device) = device {
+            req = req.header("x-device-fingerprint", device);
+        }
+
+        let body = json!({
+            "user": {
+                "username": username,
+                "email": format!("{}@example.com", username),
+                "password": "password123",
+            }
+        });
+
+        req.body(Body::from(body.to_string())).unwrap()
+    };
+
+    app.send(signup("alice", "192.0.2.1", Some("laptop")))
+        .await
+        .assert_ok();
+
+    // Same device, different address.
+    let res = app
+        .send(signup("bob", "192.0.2.2", Some("laptop")))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        res.body,
+        "quota exceeded: you may only register 1 accounts per hour from the same device"
+    );
+
+    app.send(signup("bob", "192.0.2.1", Some("phone")))
+        .await
+        .assert_ok();
+
+    // Same address, and no fingerprint at all.
+    let res = app
+        .send(signup("carol", "192.0.2.1", None))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.header("x-quota-limit"), "2");
+
+    app.send(signup("carol", "192.0.2.3", None))
+        .await
+        .assert_ok();
+
+    // Without a client IP at all, only the device is checked.
+    app.create_user("dave").await;
+
+    app.clock().advance(Duration::hour() + Duration::minute());
+
+    app.send(signup("erin", "192.0.2.1", Some("laptop")))
+        .await
+        .assert_ok();
+}
+
+#[tokio::test]
+async fn test_new_account_restrictions() {
+    let app = TestApp::with_config(json!({
+        "new_account_hours": 24,
+        "new_account_max_articles": 1,
+        "new_account_max_comments": 1,
+        "new_account_links": false,
+    }))
+    .await;
+
+    let alice = app.create_user("alice").await;
+
+    let article = |title: &str, body: &str| {
+        json!({
+            "article": {
+                "title": title,
+                "description": "A test article",
+                "body": body,
+                "tagList": [],
+            }
+        })
+    };
+
+    let comment = |body: &str| json!({ "comment": { "body": body } });
+
+    let res = alice
+        .post_json(
+            "/api/articles",
+            article("Deals", "Check out [this](https://spam.example/deals)!"),
+        )
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(res.body["restriction"]["code"], "new_account_links");
+    assert_eq!(
+        res.body["restriction"]["message"],
+        "accounts less than 24 hours old may not post links to other sites"
+    );
+    assert!(res.body["restriction"]["liftsAt"].is_string());
+
+    // Links back to us are fine.
+    let slug = alice
+        .post_json(
+            "/api/articles",
+            article(
+                "Hello",
+                "See [my other article](http://localhost:8080/a/123).",
+            ),
+        )
+        .await
+        .assert_ok()["article"]["slug"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let res = alice
+        .post_json("/api/articles", article("Again", "Hello again!"))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(res.body["restriction"]["code"], "new_account_articles");
+
+    // Nor can links be edited in afterwards.
+    let res = alice
+        .put_json(
+            &format!("/api/articles/{}", slug),
+            json!({ "article": { "body": "![](https://spam.example/banner.png)" } }),
+        )
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(res.body["restriction"]["code"], "new_account_links");
+
+    let comments = format!("/api/articles/{}/comments", slug);
+
+    let res = alice
+        .post_json(&comments, comment("<https://spam.example>"))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(res.body["restriction"]["code"], "new_account_links");
+
+    alice
+        .post_json(&comments, comment("First!"))
+        .await
+        .assert_ok();
+
+    let res = alice
+        .post_json(&comments, comment("Second!"))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(res.body["restriction"]["code"], "new_account_comments");
+
+    // Once the account is a day old, none of it applies.
+    app.clock().advance(Duration::day() + Duration::minute());
+
+    alice
+        .post_json(
+            "/api/articles",
+            article("Deals", "Check out [this](https://spam.example/deals)!"),
+        )
+        .await
+        .assert_ok();
+    alice
+        .post_json(&comments, comment("<https://example.com>"))
+        .await
+        .assert_ok();
+}
@@ -0,0 +1,165 @@
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use serde_json::json;
+use time::Duration;
+
+use realworld_axum_sqlx::test_util::TestApp;
+
+#[tokio::test]
+async fn test_rate_limit_per_user() {
+    let app = TestApp::with_config(json!({
+        "client_ip_header": "x-real-ip",
+        "rate_limit_requests": 3,
+    }))
+    .await;
+
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    let res = alice.get("/api/user").await.assert_status(StatusCode::OK);
+    assert_eq!(res.header("x-ratelimit-limit"), "3");
+    assert_eq!(res.header("x-ratelimit-remaining"), "2");
+
+    let reset: i64 = res.header("x-ratelimit-reset").parse().unwrap();
+    assert!((1..=60).contains(&reset), "reset: {}", reset);
+
+    alice.get("/api/user").await.assert_ok();
+    alice.get("/api/articles").await.assert_ok();
+
+    let res = alice
+        .get("/api/user")
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.header("x-ratelimit-remaining"), "0");
+    assert!(res.header("retry-after").parse::<i64>().unwrap() > 0);
+
+    // It's per user.
+    bob.get("/api/user").await.assert_ok();
+
+    // Routes outside `/api` aren't limited.
+    alice.get("/healthz").await.assert_status(StatusCode::OK);
+
+    // Without a token or a client IP, there's nobody to count against.
+    for _ in 0..4 {
+        app.get("/api/articles").await.assert_ok();
+    }
+
+    let anonymous = |ip: &str| {
+        Request::get("/api/articles")
+            .header("x-real-ip", ip)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    for _ in 0..3 {
+        app.send(anonymous("192.0.2.1")).await.assert_ok();
+    }
+
+    app.send(anonymous("192.0.2.1"))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+    app.send(anonymous("192.0.2.2")).await.assert_ok();
+
+    // The next window starts from scratch.
+    app.clock().advance(Duration::minute());
+
+    alice.get("/api/user").await.assert_ok();
+}
+
+#[tokio::test]
+async fn test_rate_limit_auth() {
+    let app = TestApp::with_config(json!({
+        "client_ip_header": "x-real-ip",
+        "rate_limit_auth_requests": 2,
+    }))
+    .await;
+
+    // Not from any address, so this doesn't count.
+    app.create_user("alice").await;
+
+    let login = |ip: &str| {
+        let body = json!({
+            "user": {
+                "email": "alice@example.com",
+                "password": "not the password",
+            }
+        });
+
+        Request::post("/api/users/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-real-ip", ip)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    for _ in 0..2 {
+        app.send(login("192.0.2.1"))
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    let res = app
+        .send(login("192.0.2.1"))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.header("x-ratelimit-limit"), "2");
+
+    // Another address still gets to try.
+    app.send(login("192.0.2.2"))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    // And the rest of the API is counted separately.
+    let res = app
+        .send(
+            Request::get("/api/tags")
+                .header("x-real-ip", "192.0.2.1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(res.header("x-ratelimit-limit"), "300");
+}
+
+#[tokio::test]
+async fn test_rate_limit_headers_when_authenticated() {
+    let app = TestApp::with_config(json!({ "rate_limit_requests": 100 })).await;
+
+    let alice = app.create_user("alice").await;
+
+    // Every response to a logged-in user says where they stand, long before they're throttled,
+    // errors included, so clients can slow down by themselves.
+    let responses = [
+        alice.get("/api/user").await.assert_status(StatusCode::OK),
+        alice
+            .post_json(
+                "/api/articles",
+                json!({
+                    "article": {
+                        "title": "Headers",
+                        "description": "Headers",
+                        "body": "Headers",
+                        "tagList": [],
+                    }
+                }),
+            )
+            .await
+            .assert_status(StatusCode::OK),
+        alice
+            .get("/api/articles/no-such-article")
+            .await
+            .assert_status(StatusCode::NOT_FOUND),
+        alice
+            .post("/api/profiles/nobody/follow")
+            .await
+            .assert_status(StatusCode::NOT_FOUND),
+    ];
+
+    for (i, res) in responses.iter().enumerate() {
+        assert_eq!(res.header("x-ratelimit-limit"), "100");
+        assert_eq!(res.header("x-ratelimit-remaining"), (99 - i).to_string());
+        assert!(!res.header("x-ratelimit-reset").is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use time::Duration;
+
+use realworld_axum_sqlx::test_util::{TestApp, PASSWORD};
+
+#[tokio::test]
+async fn test_sessions() {
+    let app = TestApp::with_config(json!({ "session_store": "memory" })).await;
+
+    let alice = app.create_user("alice").await;
+
+    // An opaque token, not a JWT.
+    assert!(!alice.token.contains('.'), "{}", alice.token);
+
+    // Hands back the same token rather than starting another session.
+    let body = alice.get("/api/user").await.assert_ok();
+    assert_eq!(body["user"]["token"], alice.token.as_str());
+
+    let body = alice
+        .put_json("/api/user", json!({ "user": { "bio": "Hi" } }))
+        .await
+        .assert_ok();
+    assert_eq!(body["user"]["token"], alice.token.as_str());
+
+    // Logging in again is another session.
+    let laptop = app.login("alice@example.com", PASSWORD).await;
+    assert_ne!(laptop.token, alice.token);
+
+    // Logging out ends that session and no other.
+    alice.delete("/api/user/session").await.assert_ok();
+
+    alice
+        .get("/api/user")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    // Including for routes where logging in is optional.
+    alice
+        .get("/api/articles")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    alice
+        .delete("/api/user/session")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    laptop.get("/api/user").await.assert_ok();
+
+    // Nor is a made-up token any good.
+    app.request(Method::GET, "/api/user", Some("not-a-session"), None)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    // Sessions expire like JWTs do.
+    app.clock().advance(Duration::weeks(2) + Duration::second());
+
+    laptop
+        .get("/api/user")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_end_session_with_jwt() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+
+    // There's no session to end.
+    alice
+        .delete("/api/user/session")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    alice.get("/api/user").await.assert_ok();
+}
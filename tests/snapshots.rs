@@ -0,0 +1,108 @@
+use serde_json::json;
+
+use realworld_axum_sqlx::test_util::TestApp;
+
+// Snapshots of representative responses, in `tests/snapshots/`, so that any change to what we
+// serialize (a renamed field, a missing `#[serde(rename_all = "camelCase")]`, a `null` where
+// there used to be an empty string) shows up as a diff in review rather than a broken frontend.
+//
+// If you change a response on purpose, run `UPDATE_SNAPSHOTS=1 cargo test --test snapshots`
+// and commit the updated files.
+
+#[tokio::test]
+async fn test_snapshot_users_and_profiles() {
+    let app = TestApp::new().await;
+
+    app.post_json(
+        "/api/users",
+        json!({
+            "user": {
+                "username": "alice",
+                "email": "alice@example.com",
+                "password": "hunter2hunter2",
+            }
+        }),
+    )
+    .await
+    .assert_snapshot("user");
+
+    let bob = app.create_user("bob").await;
+
+    bob.post("/api/profiles/alice/follow")
+        .await
+        .assert_snapshot("profile");
+}
+
+#[tokio::test]
+async fn test_snapshot_articles_and_comments() {
+    let app = TestApp::new().await;
+    let alice = app.create_user("alice").await;
+
+    alice
+        .post_json(
+            "/api/articles",
+            json!({
+                "article": {
+                    "title": "How to train your dragon",
+                    "description": "Ever wonder how?",
+                    "body": "You have to believe",
+                    "tagList": ["reactjs", "angularjs", "dragons"],
+                }
+            }),
+        )
+        .await
+        .assert_snapshot("article");
+
+    app.get("/api/articles").await.assert_snapshot("articles");
+
+    alice
+        .post_json(
+            "/api/articles/how-to-train-your-dragon/comments",
+            json!({ "comment": { "body": "Thank you so much!" } }),
+        )
+        .await
+        .assert_snapshot("comment");
+
+    app.get("/api/tags").await.assert_snapshot("tags");
+}
+
+#[tokio::test]
+async fn test_snapshot_errors() {
+    let app = TestApp::new().await;
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    app.post_json(
+        "/api/users",
+        json!({ "user": { "username": "alice", "email": "not an email", "password": "x" } }),
+    )
+    .await
+    .assert_snapshot("error_bad_request");
+
+    app.post_json(
+        "/api/users",
+        json!({
+            "user": {
+                "username": "alice",
+                "email": "alice2@example.com",
+                "password": "hunter2hunter2",
+            }
+        }),
+    )
+    .await
+    .assert_snapshot("error_unprocessable");
+
+    app.get("/api/user")
+        .await
+        .assert_snapshot("error_unauthorized");
+
+    app.get("/api/articles/no-such-article")
+        .await
+        .assert_snapshot("error_not_found");
+
+    let slug = alice.create_article("Mine", &[]).await;
+
+    bob.delete(&format!("/api/articles/{}", slug))
+        .await
+        .assert_snapshot("error_forbidden");
+}
@@ -0,0 +1,301 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use realworld_axum_sqlx::clock::Clock;
+use realworld_axum_sqlx::db;
+use realworld_axum_sqlx::test_util::{TestApp, PASSWORD};
+
+#[tokio::test]
+async fn test_register_and_login() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+
+    let body = alice.get("/api/user").await.assert_ok();
+    assert_eq!(body["user"]["username"], "alice");
+    assert_eq!(body["user"]["email"], "alice@example.com");
+
+    let alice = app.login("alice@example.com", PASSWORD).await;
+    assert_eq!(alice.username, "alice");
+
+    app.post_json(
+        "/api/users/login",
+        json!({ "user": { "email": "alice@example.com", "password": "hunter3hunter3" } }),
+    )
+    .await
+    .assert_status(StatusCode::UNAUTHORIZED);
+
+    app.post_json(
+        "/api/users/login",
+        json!({ "user": { "email": "bob@example.com", "password": PASSWORD } }),
+    )
+    .await
+    .assert_unprocessable("email", "does not exist");
+
+    app.get("/api/user")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_token_expiry() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+
+    // Sessions last two weeks.
+    app.clock().advance(time::Duration::weeks(2));
+    alice.get("/api/user").await.assert_ok();
+
+    app.clock().advance(time::Duration::seconds(1));
+    alice
+        .get("/api/user")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    // Logging in again gives a token that's good for another two weeks from "now".
+    let alice = app.login("alice@example.com", PASSWORD).await;
+    alice.get("/api/user").await.assert_ok();
+}
+
+#[tokio::test]
+async fn test_register_duplicate() {
+    let app = TestApp::new().await;
+
+    app.create_user("alice").await;
+
+    app.post_json(
+        "/api/users",
+        json!({
+            "user": {
+                "username": "alice",
+                "email": "someone.else@example.com",
+                "password": PASSWORD,
+            }
+        }),
+    )
+    .await
+    .assert_unprocessable("username", "username taken");
+}
+
+#[tokio::test]
+async fn test_update_user() {
+    let app = TestApp::new().await;
+
+    let alice = app.create_user("alice").await;
+
+    let body = alice
+        .put_json(
+            "/api/user",
+            json!({ "user": { "bio": "I like to skate", "password": "correct horse battery" } }),
+        )
+        .await
+        .assert_ok();
+    assert_eq!(body["user"]["bio"], "I like to skate");
+
+    app.login("alice@example.com", "correct horse battery")
+        .await;
+}
+
+#[tokio::test]
+async fn test_anonymize() {
+    let app = TestApp::new().await;
+    let alice = app.create_user("alice").await;
+    let bob = app.create_user("bob").await;
+
+    let slug = alice.create_article("Still here", &[]).await;
+    alice
+        .post_json(
+            &format!("/api/articles/{}/comments", slug),
+            json!({ "comment": { "body": "Me too" } }),
+        )
+        .await
+        .assert_ok();
+    bob.post("/api/profiles/alice/follow").await.assert_ok();
+
+    let confirm = |password: &str| json!({ "user": { "password": password } });
+
+    alice
+        .post_json("/api/user/anonymize", confirm("wrong password"))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    alice
+        .post_json("/api/user/anonymize", confirm(PASSWORD))
+        .await
+        .assert_ok();
+
+    // The account is gone, along with the token...
+    alice
+        .get("/api/user")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    app.get("/api/profiles/alice")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    // ...but what they wrote is still there, credited to nobody in particular.
+    let body = app
+        .get(&format!("/api/articles/{}", slug))
+        .await
+        .assert_ok();
+    assert_eq!(body["article"]["author"]["username"], "[deleted]");
+
+    let body = app
+        .get(&format!("/api/articles/{}/comments", slug))
+        .await
+        .assert_ok();
+    assert_eq!(body["comments"][0]["author"]["username"], "[deleted]");
+
+    // It's not in the feed of someone who followed them, since they can't follow `[deleted]`.
+    let body = bob.get("/api/articles/feed").await.assert_ok();
+    assert_eq!(body["articles"], json!([]));
+
+    // The name is free again.
+    app.create_user("alice").await;
+}
+
+#[tokio::test]
+async fn test_reserved_usernames() {
+    let app = TestApp::with_config(json!({ "blocked_username_words": ["badword"] })).await;
+
+    let register = |username: &str| {
+        app.post_json(
+            "/api/users",
+            json!({
+                "user": {
+                    "username": username,
+                    "email": format!("{}@example.com", username.to_lowercase()),
+                    "password": PASSWORD,
+                }
+            }),
+        )
+    };
+
+    register("Admin")
+        .await
+        .assert_unprocessable("username", "username is reserved");
+
+    register("suggestions")
+        .await
+        .assert_unprocessable("username", "username is reserved");
+
+    register("the_b4dw0rd")
+        .await
+        .assert_unprocessable("username", "username is not allowed");
+
+    let alice = app.create_user("alice").await;
+
+    alice
+        .put_json("/api/user", json!({ "user": { "username": "log-in" } }))
+        .await
+        .assert_unprocessable("username", "username is reserved");
+
+    // Changing anything else is still fine.
+    alice
+        .put_json("/api/user", json!({ "user": { "bio": "Hi" } }))
+        .await
+        .assert_ok();
+}
+
+#[tokio::test]
+async fn test_password_reset() {
+    let app = TestApp::with_config(json!({
+        "password_reset_url": "https://example.com/reset-password",
+    }))
+    .await;
+
+    app.create_user("alice").await;
+
+    let forgot = |email: &str| {
+        app.post_json(
+            "/api/users/forgot-password",
+            json!({ "user": { "email": email } }),
+        )
+    };
+
+    let reset = |token: &str, password: &str| {
+        app.post_json(
+            "/api/users/reset-password",
+            json!({ "user": { "token": token, "password": password } }),
+        )
+    };
+
+    // The token is at the end of the link in the email.
+    let token_from = |body: &str| {
+        let (_, rest) = body
+            .split_once("https://example.com/reset-password?token=")
+            .unwrap_or_else(|| panic!("no link in {:?}", body));
+        rest.split_whitespace().next().unwrap().to_string()
+    };
+
+    // Nobody's told whether there's an account, but only an account gets an email.
+    forgot("nobody@example.com").await.assert_ok();
+    assert!(app.mailer().take().is_empty());
+
+    forgot("Alice@example.com").await.assert_ok();
+    let sent = app.mailer().take();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "alice@example.com");
+    let token = token_from(&sent[0].body);
+
+    reset("not-the-token", "new password")
+        .await
+        .assert_unprocessable("token", "invalid or expired");
+
+    let body = reset(&token, "new password").await.assert_ok();
+    assert_eq!(body["user"]["username"], "alice");
+
+    app.login("alice@example.com", "new password").await;
+    app.post_json(
+        "/api/users/login",
+        json!({ "user": { "email": "alice@example.com", "password": PASSWORD } }),
+    )
+    .await
+    .assert_status(StatusCode::UNAUTHORIZED);
+
+    // Each token works once.
+    reset(&token, "another password")
+        .await
+        .assert_unprocessable("token", "invalid or expired");
+
+    // And only for an hour.
+    forgot("alice@example.com").await.assert_ok();
+    let token = token_from(&app.mailer().take()[0].body);
+    app.clock().advance(time::Duration::minutes(61));
+    reset(&token, "another password")
+        .await
+        .assert_unprocessable("token", "invalid or expired");
+
+    // Using one token uses up any others from before it.
+    forgot("alice@example.com").await.assert_ok();
+    let older = token_from(&app.mailer().take()[0].body);
+    forgot("alice@example.com").await.assert_ok();
+    let newer = token_from(&app.mailer().take()[0].body);
+
+    reset(&newer, "another password").await.assert_ok();
+    reset(&older, "yet another password")
+        .await
+        .assert_unprocessable("token", "invalid or expired");
+
+    app.login("alice@example.com", "another password").await;
+
+    // The third this hour is all anyone gets, used up or not.
+    forgot("alice@example.com").await.assert_ok();
+    assert_eq!(app.mailer().take().len(), 1);
+    forgot("alice@example.com").await.assert_ok();
+    assert!(app.mailer().take().is_empty());
+
+    // An hour later, there's room for another.
+    app.clock().advance(time::Duration::minutes(61));
+    forgot("alice@example.com").await.assert_ok();
+    assert_eq!(app.mailer().take().len(), 1);
+
+    // The tokens are cleared out an hour after they expire, used or not: by now, that's the
+    // first two.
+    let prune = || db::password_resets::prune(app.db().primary(), app.clock().now());
+    assert_eq!(prune().await.unwrap(), 2);
+
+    app.clock().advance(time::Duration::hours(3));
+    assert_eq!(prune().await.unwrap(), 4);
+}